@@ -0,0 +1,62 @@
+use std::{env, fs, io, path::{Path, PathBuf}};
+
+const BLOCK_SIZE: usize = 512;
+
+fn header(name: &str, mode: u32, size: usize, type_flag: u8, link: &str) -> [u8; BLOCK_SIZE] {
+    assert!(name.len() <= 100 && link.len() <= 100, "initramfs path is too long: {}", name);
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(format!("{:07o}", mode).as_bytes());
+    header[108..115].copy_from_slice(b"0000000");
+    header[116..123].copy_from_slice(b"0000000");
+    header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+    header[136..147].copy_from_slice(b"00000000000");
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = type_flag;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    header
+}
+
+/// Appends `directory` contents to ustar `archive`, sorted so output is reproducible
+fn pack(root: &Path, directory: &Path, archive: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for path in entries {
+        let name = path.strip_prefix(root).unwrap().to_str().expect("initramfs path is not UTF-8").replace('\\', "/");
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+
+        if file_type.is_symlink() {
+            let link = fs::read_link(&path)?;
+            archive.extend_from_slice(&header(&name, 0o777, 0, b'2', link.to_str().expect("symlink target is not UTF-8")));
+        } else if file_type.is_dir() {
+            archive.extend_from_slice(&header(&(name + "/"), 0o755, 0, b'5', ""));
+            pack(root, &path, archive)?;
+        } else {
+            let data = fs::read(&path)?;
+            archive.extend_from_slice(&header(&name, 0o644, data.len(), b'0', ""));
+            archive.extend_from_slice(&data);
+            archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let root = Path::new("initramfs");
+    let mut archive = Vec::new();
+    println!("cargo:rerun-if-changed=initramfs");
+
+    if root.is_dir() {
+        pack(root, root, &mut archive).expect("Failed to pack initramfs");
+    }
+
+    archive.resize(archive.len() + BLOCK_SIZE * 2, 0);
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out.join("initramfs.tar"), archive).expect("Failed to write initramfs archive");
+}
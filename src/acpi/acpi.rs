@@ -1,8 +1,13 @@
+use alloc::vec::Vec;
+use x86_64::{instructions::port::Port, PhysAddr};
+
+use crate::memory;
+
 #[repr(C, packed)]
 pub struct Xsdp {
-    pub signature: [char; 8],
+    pub signature: [u8; 8],
     pub checksum: u8,
-    pub oemiud: [char; 6],
+    pub oemiud: [u8; 6],
     pub revision: u8,
     pub rsdt_address: u32,
     pub length: u32,
@@ -11,14 +16,18 @@ pub struct Xsdp {
     pub _reserved: [u8; 3]
 }
 
-pub unsafe fn check_xsdp(xsdp_ptr: *const Xsdp) -> bool {
-    let mut sum: u8 = 0;
+/// Size of header shared by all system description tables
+const SDT_HEADER_SIZE: usize = 36;
+const MADT_ENTRIES: usize = SDT_HEADER_SIZE + 8;
 
-    for i in 0..20 {
-        unsafe { sum += *(xsdp_ptr.add(i) as *const u8); }
-    }
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
 
-    if sum != 0 {
+pub unsafe fn check_xsdp(xsdp_ptr: *const Xsdp) -> bool {
+    let bytes = xsdp_ptr as *const u8;
+
+    if checksum(core::slice::from_raw_parts(bytes, 20)) != 0 {
         return false;
     }
 
@@ -27,27 +36,224 @@ pub unsafe fn check_xsdp(xsdp_ptr: *const Xsdp) -> bool {
         return true;
     }
 
-    let mut sum2: u8 = 0;
+    checksum(core::slice::from_raw_parts(bytes, 36)) == 0
+}
+
+/// Searches BIOS area for XSDP, the area has to be identity mapped
+pub fn find_xsdp_bios() -> Option<*const Xsdp> {
+    for mem in (0xE0000..0xFFFFF - 20).step_by(16) { // 20 is the size of ACPI 1.0 XSDP (RSDP) table, it's always 16 byte aligned
+        unsafe {
+            let signature = mem as *const [u8; 8];
 
-    for i in 0..36 {
-        unsafe { sum2 += *(xsdp_ptr.add(i) as *const u8); }
+            if *signature == *b"RSD PTR " && check_xsdp(signature as *const Xsdp) {
+                return Some(signature as *const Xsdp);
+            }
+        }
     }
 
-    return sum2 == 0;
+    None
 }
 
-pub fn find_xsdp_bios() -> Option<*const Xsdp> {
-    for mem in 0xE0000..0xFFFFF - 20 { // 20 is the size of ACPI 1.0 XSDP (RSDP) table
-        unsafe {
-            let string: *const char = mem as *const char;
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// System description table at physical `address`, read through physical memory mapping
+unsafe fn table_at(address: u64) -> Option<&'static [u8]> {
+    let header = memory::physical_to_virtual(PhysAddr::new(address)).as_ptr::<u8>();
+    let length = read_u32(core::slice::from_raw_parts(header, SDT_HEADER_SIZE), 4) as usize;
+
+    if length < SDT_HEADER_SIZE {
+        return None;
+    }
+
+    let table = core::slice::from_raw_parts(header, length);
+    (checksum(table) == 0).then_some(table)
+}
+
+/// Tables listed in XSDT (or RSDT for ACPI 1.0). Needs identity mapped BIOS area and global
+/// memory mapping.
+pub fn tables() -> Vec<&'static [u8]> {
+    let Some(xsdp) = find_xsdp_bios() else {
+        return Vec::new();
+    };
+
+    unsafe {
+        let root = if (*xsdp).revision >= 2 && (*xsdp).xsdt_address != 0 {
+            table_at((*xsdp).xsdt_address).map(|table| (table, 8))
+        } else {
+            table_at((*xsdp).rsdt_address as u64).map(|table| (table, 4))
+        };
+
+        let Some((root, entry_size)) = root else {
+            return Vec::new();
+        };
+
+        root[SDT_HEADER_SIZE..].chunks_exact(entry_size)
+            .map(|entry| if entry_size == 8 { read_u64(entry, 0) } else { read_u32(entry, 0) as u64 })
+            .filter_map(|address| table_at(address))
+            .collect()
+    }
+}
+
+/// Finds table with `signature`, see `tables`
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    tables().into_iter().find(|table| table[..4] == *signature)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8
+}
+
+/// Interrupt controller layout from Multiple APIC Description Table
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// Processors that are enabled or can be brought online
+    pub processors: Vec<Processor>
+}
+
+impl Madt {
+    pub fn parse(table: &[u8]) -> Madt {
+        let mut madt = Madt { local_apic_address: read_u32(table, SDT_HEADER_SIZE) as u64, processors: Vec::new() };
+        let mut offset = MADT_ENTRIES;
+
+        while offset + 2 <= table.len() {
+            let (kind, length) = (table[offset], table[offset + 1] as usize);
+
+            if length < 2 || offset + length > table.len() {
+                break;
+            }
+
+            let entry = &table[offset..offset + length];
+
+            match kind {
+                0 if length >= 8 && read_u32(entry, 4) & 0b11 != 0 => madt.processors.push(Processor { processor_id: entry[2], apic_id: entry[3] }),
+                5 if length >= 12 => madt.local_apic_address = read_u64(entry, 4),
+                _ => {}
+            }
+
+            offset += length;
+        }
+
+        madt
+    }
+}
+
+pub fn find_madt() -> Option<Madt> {
+    find_table(b"APIC").map(Madt::parse)
+}
+
+/// Fixed ACPI Description Table offsets
+const FADT_DSDT: usize = 40;
+const FADT_SMI_COMMAND: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_PM1A_CONTROL: usize = 64;
+const FADT_PM1B_CONTROL: usize = 68;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REGISTER: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+const FADT_X_DSDT: usize = 140;
+
+/// FADT flag of a usable reset register
+const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+/// Address space of the reset register that is I/O ports
+const SYSTEM_IO: u8 = 1;
+/// PM1 control bits
+const SCI_ENABLE: u16 = 1 << 0;
+const SLEEP_ENABLE: u16 = 1 << 13;
+
+/// Sleep type values of `_S5_`, the soft off state, from the DSDT. Expects the usual AML of a
+/// `Name` with a package of plain numbers.
+fn soft_off_types(fadt: &[u8]) -> Option<(u16, u16)> {
+    let x_dsdt = if fadt.len() >= FADT_X_DSDT + 8 { read_u64(fadt, FADT_X_DSDT) } else { 0 };
+    let address = if x_dsdt != 0 { x_dsdt } else { read_u32(fadt, FADT_DSDT) as u64 };
+    let dsdt = unsafe { table_at(address)? };
+
+    let name = dsdt.windows(4).position(|window| window == b"_S5_")?;
+    let mut offset = name + 4;
+
+    // Package op, package length of 1 to 4 bytes, element count
+    if *dsdt.get(offset)? != 0x12 {
+        return None;
+    }
+
+    offset += 1;
+    offset += (*dsdt.get(offset)? >> 6) as usize + 1;
+    offset += 1;
+
+    let mut element = || {
+        // Byte prefix, plain zero and one ops are the value themselves
+        if *dsdt.get(offset)? == 0x0A {
+            offset += 1;
+        }
 
-            if *string == 'R' && *string.add(1) == 'S' && *string.add(2) == 'D' && *string.add(3) == ' ' && *string.add(4) == 'P' && *string.add(5) == 'T' && *string.add(6) == 'R' && *string.add(7) == ' ' {
-                if check_xsdp(string as *const Xsdp) {
-                    return Some(string as *const Xsdp);
+        let value = *dsdt.get(offset)? as u16;
+        offset += 1;
+        Some(value)
+    };
+
+    Some((element()?, element()?))
+}
+
+/// Puts the machine in the soft off state, returns if ACPI can't
+pub fn enter_soft_off() {
+    let Some(fadt) = find_table(b"FACP") else {
+        return;
+    };
+
+    let Some((type_a, type_b)) = soft_off_types(fadt) else {
+        return;
+    };
+
+    let pm1a = read_u32(fadt, FADT_PM1A_CONTROL) as u16;
+    let pm1b = read_u32(fadt, FADT_PM1B_CONTROL) as u16;
+
+    if pm1a == 0 {
+        return;
+    }
+
+    unsafe {
+        let mut control: Port<u16> = Port::new(pm1a);
+        let smi_command = read_u32(fadt, FADT_SMI_COMMAND) as u16;
+
+        // Firmware hands the power management registers over once ACPI is enabled
+        if control.read() & SCI_ENABLE == 0 && smi_command != 0 {
+            Port::<u8>::new(smi_command).write(fadt[FADT_ACPI_ENABLE]);
+
+            for _ in 0..1_000_000 {
+                if control.read() & SCI_ENABLE != 0 {
+                    break;
                 }
+
+                core::hint::spin_loop();
             }
         }
+
+        control.write(type_a << 10 | SLEEP_ENABLE);
+
+        if pm1b != 0 {
+            Port::<u16>::new(pm1b).write(type_b << 10 | SLEEP_ENABLE);
+        }
     }
+}
 
-    None
+/// Resets the machine through the FADT reset register, returns if there's no usable one
+pub fn reset() {
+    let Some(fadt) = find_table(b"FACP") else {
+        return;
+    };
+
+    if fadt.len() < FADT_RESET_VALUE + 1 || read_u32(fadt, FADT_FLAGS) & RESET_REGISTER_SUPPORTED == 0 || fadt[FADT_RESET_REGISTER] != SYSTEM_IO {
+        return;
+    }
+
+    let port = read_u64(fadt, FADT_RESET_REGISTER + 4) as u16;
+    unsafe { Port::<u8>::new(port).write(fadt[FADT_RESET_VALUE]) };
 }
@@ -1,7 +1,10 @@
 pub mod linked_list;
 
+use core::alloc::{GlobalAlloc, Layout};
+
 use spin::{Mutex, MutexGuard};
 use x86_64::{
+    instructions::interrupts,
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, PhysFrame
     },
@@ -14,8 +17,22 @@ use crate::memory::EmptyFrameAllocator;
 pub const HEAP_START: usize = 0x44444444_0000;
 pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
 
+/// Heap lock is taken with interrupts disabled, so thread holding it can't be preempted and
+/// interrupt handlers (including scheduler) can allocate without deadlocking
+struct InterruptSafeHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for InterruptSafeHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: InterruptSafeHeap = InterruptSafeHeap(LockedHeap::empty());
 
 pub fn map_range(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>, start: usize, size: usize) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
@@ -32,7 +49,7 @@ pub fn map_range(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
-    unsafe { ALLOCATOR.lock().init(start as *mut u8, size); }
+    unsafe { ALLOCATOR.0.lock().init(start as *mut u8, size); }
 
     Ok(())
 }
@@ -61,6 +78,14 @@ pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl
     map_range(mapper, frame_allocator, HEAP_START, HEAP_SIZE)
 }
 
+/// Bytes of the kernel heap in use and bytes still free
+pub fn heap_usage() -> (usize, usize) {
+    interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.0.lock();
+        (heap.used(), heap.free())
+    })
+}
+
 pub struct Locked<A> {
     inner: Mutex<A>
 }
@@ -0,0 +1,227 @@
+//! Virtual consoles: the keyboard and a VGA screen each, Alt+F1 to Alt+F6 switch between them.
+//! Each is a terminal, see `tty`. The first is the console, it also reads `COM1` input and
+//! writes to `COM1`. Processes get it as descriptors 0, 1 and 2.
+//!
+//! Keyboard scancodes are turned into bytes in the interrupt handler and kept in a fixed buffer
+//! of the console that is shown, arrow keys become ANSI escape sequences. `COM1` input is kept
+//! by the `serial` module. Neither wakes readers, they poll.
+
+use alloc::{format, string::String, sync::Arc};
+
+use crate::{
+    fs::{file::{File, OpenFlags}, page_cache::FileId, vfs::Dentry, FileType, FsError, Inode, Stat},
+    serial::{self, ComPort},
+    sync::SpinLock,
+    thread,
+    tty::{InputBuffer, Line, Received, Tty},
+    vga::{self, SCREENS}
+};
+
+pub const CONSOLES: usize = SCREENS;
+/// Inode number and `FileId` of the console, pipes count from 1
+const CONSOLE_INODE: u64 = 0;
+/// Inode numbers of the other virtual consoles are this plus their index, far above those of
+/// pipes and below those of serial ports
+const VIRTUAL_INODE: u64 = 1 << 62;
+
+/// Scancode set 1 make codes up to space, 0 for keys without a byte
+const NORMAL: &[u8; 58] = b"\0\x1B1234567890-=\x7F\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFTED: &[u8; 58] = b"\0\x1B!@#$%^&*()_+\x7F\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+const LEFT_CTRL: u8 = 0x1D;
+const LEFT_SHIFT: u8 = 0x2A;
+const RIGHT_SHIFT: u8 = 0x36;
+const LEFT_ALT: u8 = 0x38;
+const CAPS_LOCK: u8 = 0x3A;
+const F1: u8 = 0x3B;
+const EXTENDED: u8 = 0xE0;
+const RELEASED: u8 = 0x80;
+
+/// Turns scancode set 1 into bytes, tracking shift, ctrl, alt and caps lock
+#[derive(Debug, Default)]
+pub struct Decoder {
+    shift: u8,
+    ctrl: u8,
+    alt: u8,
+    caps_lock: bool,
+    /// Previous scancode was `EXTENDED`
+    extended: bool
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder { shift: 0, ctrl: 0, alt: 0, caps_lock: false, extended: false }
+    }
+
+    /// Passes bytes typed by `scancode` to `output`, nothing for releases and modifiers. Returns
+    /// the index of the console to switch to for Alt+F1 to Alt+F6.
+    pub fn decode(&mut self, scancode: u8, mut output: impl FnMut(u8)) -> Option<usize> {
+        if scancode == EXTENDED {
+            self.extended = true;
+            return None;
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let pressed = scancode & RELEASED == 0;
+        let key = scancode & !RELEASED;
+
+        match (key, extended) {
+            (LEFT_SHIFT | RIGHT_SHIFT, false) => self.shift = count(self.shift, pressed),
+            // Right ctrl is the extended left ctrl
+            (LEFT_CTRL, _) => self.ctrl = count(self.ctrl, pressed),
+            // Right alt is the extended left alt
+            (LEFT_ALT, _) => self.alt = count(self.alt, pressed),
+            (CAPS_LOCK, false) if pressed => self.caps_lock = !self.caps_lock,
+            (F1.., false) if pressed && self.alt > 0 && ((key - F1) as usize) < CONSOLES => return Some((key - F1) as usize),
+            (_, true) if pressed => {
+                let arrow = match key {
+                    0x48 => b'A',
+                    0x50 => b'B',
+                    0x4D => b'C',
+                    0x4B => b'D',
+                    _ => return None
+                };
+
+                [0x1B, b'[', arrow].into_iter().for_each(output);
+            }
+            (_, false) if pressed && (key as usize) < NORMAL.len() => {
+                let letter = NORMAL[key as usize].is_ascii_alphabetic();
+                let shifted = (self.shift > 0) != (letter && self.caps_lock);
+                let byte = if shifted { SHIFTED[key as usize] } else { NORMAL[key as usize] };
+
+                match byte {
+                    0 => {}
+                    byte if self.ctrl > 0 && byte.is_ascii_alphabetic() => output(byte & 0x1F),
+                    byte => output(byte)
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+/// Number of held keys of a modifier after one of them is pressed or released
+fn count(held: u8, pressed: bool) -> u8 {
+    if pressed { held.saturating_add(1).min(2) } else { held.saturating_sub(1) }
+}
+
+static DECODER: SpinLock<Decoder> = SpinLock::new(Decoder::new());
+static INPUTS: [SpinLock<InputBuffer>; CONSOLES] = [const { SpinLock::new(InputBuffer::new()) }; CONSOLES];
+static TTYS: [Tty; CONSOLES] = [Tty::new(file_id(0)), Tty::new(file_id(1)), Tty::new(file_id(2)), Tty::new(file_id(3)), Tty::new(file_id(4)), Tty::new(file_id(5))];
+
+/// Adds bytes typed by `scancode` to input of the console that is shown, or switches consoles.
+/// Called by the keyboard interrupt handler.
+pub(crate) fn add_scancode(scancode: u8) {
+    let mut typed = [0u8; 3];
+    let mut count = 0;
+
+    let switch = DECODER.lock().decode(scancode, |byte| {
+        typed[count] = byte;
+        count += 1;
+    });
+
+    if let Some(index) = switch {
+        vga::show(index);
+        return;
+    }
+
+    let index = vga::visible();
+
+    for &byte in &typed[..count] {
+        let received = TTYS[index].arrive(byte);
+        INPUTS[index].lock().push(received);
+    }
+}
+
+/// Adds `bytes` to input of console `index` as if they were typed
+pub fn push_input(index: usize, bytes: &[u8]) {
+    for &byte in bytes {
+        let received = TTYS[index].arrive(byte);
+        INPUTS[index].lock().push(received);
+    }
+}
+
+/// Terminal of console `index`
+pub fn tty(index: usize) -> &'static Tty {
+    &TTYS[index]
+}
+
+struct Console {
+    index: usize
+}
+
+impl Line for Console {
+    fn receive(&self) -> Option<Received> {
+        let received = INPUTS[self.index].lock().pop();
+
+        match self.index {
+            0 => received.or_else(|| serial::receive(ComPort::Com1)),
+            _ => received
+        }
+    }
+
+    fn wait(&self) {
+        thread::sleep(10);
+    }
+
+    fn send(&self, bytes: &[u8]) {
+        vga::writer(self.index).lock().write_bytes(bytes);
+
+        if self.index == 0 {
+            serial::write(ComPort::Com1, bytes);
+        }
+    }
+}
+
+impl Inode for Console {
+    fn stat(&self) -> Result<Stat, FsError> {
+        Ok(Stat::new(file_id(self.index).inode, FileType::CharDevice, 0))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        TTYS[self.index].read(self, buf)
+    }
+
+    fn try_read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match TTYS[self.index].try_read(self, buf) {
+            None if !buf.is_empty() => Err(FsError::WouldBlock),
+            count => Ok(count.unwrap_or(0))
+        }
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        TTYS[self.index].write(self, buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    fn terminal(&self) -> Option<&Tty> {
+        Some(&TTYS[self.index])
+    }
+}
+
+/// Console as an open file with `flags`
+pub fn open(flags: OpenFlags) -> File {
+    let dentry = Dentry { path: String::from("/dev/console"), inode: Arc::new(Console { index: 0 }) };
+    File::new(dentry, file_id(0), flags)
+}
+
+/// Virtual console `index`, from 0 to `CONSOLES - 1`, as an open file with `flags`. The first
+/// is the console.
+pub fn open_virtual(index: usize, flags: OpenFlags) -> File {
+    let dentry = Dentry { path: format!("/dev/tty{}", index + 1), inode: Arc::new(Console { index }) };
+    File::new(dentry, file_id(index), flags)
+}
+
+/// Id of files of virtual console `index`
+pub const fn file_id(index: usize) -> FileId {
+    match index {
+        0 => FileId { fs: 0, inode: CONSOLE_INODE },
+        index => FileId { fs: 0, inode: VIRTUAL_INODE + index as u64 }
+    }
+}
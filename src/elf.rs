@@ -0,0 +1,190 @@
+//! ELF64 executables for x86_64. Only what loading a static program needs: file header and
+//! program headers. Everything is read with bounds checks, the file may be anything.
+
+const MAGIC: &[u8; 4] = b"\x7FELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3E;
+const HEADER_SIZE: usize = 64;
+
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+pub const PT_LOAD: u32 = 1;
+pub const PT_INTERP: u32 = 3;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// File ends in the middle of a header
+    Truncated,
+    BadMagic,
+    /// Not a 64 bit little endian ELF of the current version
+    Unsupported,
+    /// Not an executable, shared objects and relocatable files can't be loaded
+    NotExecutable,
+    WrongMachine,
+    /// Program header describes a segment that doesn't fit the file or address space
+    BadSegment,
+    /// Program needs a dynamic linker
+    Dynamic,
+    /// Entry point is not in an executable segment
+    BadEntry
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64
+}
+
+impl ProgramHeader {
+    /// End of the segment in memory, checked in `Elf::parse` for loadable segments
+    pub fn end(&self) -> u64 {
+        self.virtual_address + self.memory_size
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        (self.virtual_address..self.end()).contains(&address)
+    }
+}
+
+/// Checked executable, segments only have to be mapped
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry: u64,
+    program_header_offset: u64,
+    program_header_count: u16
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        let header = data.get(..HEADER_SIZE).ok_or(ElfError::Truncated)?;
+
+        if &header[..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+
+        if header[4] != CLASS_64 || header[5] != DATA_LITTLE_ENDIAN || header[6] != VERSION_CURRENT {
+            return Err(ElfError::Unsupported);
+        }
+
+        if read_u16(header, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+
+        if read_u16(header, 18) != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+
+        let program_header_offset = read_u64(header, 32);
+        let program_header_size = read_u16(header, 54);
+        let program_header_count = read_u16(header, 56);
+
+        if program_header_count > 0 && program_header_size as usize != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::Unsupported);
+        }
+
+        let table_size = program_header_count as u64 * PROGRAM_HEADER_SIZE as u64;
+
+        if program_header_offset.checked_add(table_size).is_none_or(|end| end > data.len() as u64) {
+            return Err(ElfError::Truncated);
+        }
+
+        let elf = Elf { data, entry: read_u64(header, 24), program_header_offset, program_header_count };
+
+        for header in elf.program_headers() {
+            match header.kind {
+                PT_INTERP => return Err(ElfError::Dynamic),
+                PT_LOAD => elf.check_segment(&header)?,
+                _ => {}
+            }
+        }
+
+        if !elf.segments().any(|segment| segment.flags & PF_X != 0 && segment.contains(elf.entry)) {
+            return Err(ElfError::BadEntry);
+        }
+
+        Ok(elf)
+    }
+
+    fn check_segment(&self, segment: &ProgramHeader) -> Result<(), ElfError> {
+        let file_end = segment.offset.checked_add(segment.file_size).ok_or(ElfError::BadSegment)?;
+
+        if segment.file_size > segment.memory_size || file_end > self.data.len() as u64 || segment.virtual_address.checked_add(segment.memory_size).is_none() {
+            return Err(ElfError::BadSegment);
+        }
+
+        // Offset and address have to agree within a page, or the file can't be mapped page by page
+        if segment.align > 1 && (!segment.align.is_power_of_two() || segment.virtual_address % segment.align != segment.offset % segment.align) {
+            return Err(ElfError::BadSegment);
+        }
+
+        Ok(())
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let start = self.program_header_offset as usize;
+
+        (0..self.program_header_count as usize).map(move |index| {
+            let header = &data[start + index * PROGRAM_HEADER_SIZE..start + (index + 1) * PROGRAM_HEADER_SIZE];
+
+            ProgramHeader {
+                kind: read_u32(header, 0),
+                flags: read_u32(header, 4),
+                offset: read_u64(header, 8),
+                virtual_address: read_u64(header, 16),
+                file_size: read_u64(header, 32),
+                memory_size: read_u64(header, 40),
+                align: read_u64(header, 48)
+            }
+        })
+    }
+
+    /// Loadable segments
+    pub fn segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers().filter(|header| header.kind == PT_LOAD)
+    }
+
+    /// Bytes of `segment` stored in the file, the rest of its memory is zeroed
+    pub fn segment_data(&self, segment: &ProgramHeader) -> &'a [u8] {
+        &self.data[segment.offset as usize..(segment.offset + segment.file_size) as usize]
+    }
+
+    /// Where program headers are in memory once loaded, for `AT_PHDR`
+    pub fn program_headers_address(&self) -> Option<u64> {
+        let offset = self.program_header_offset;
+
+        self.segments()
+            .find(|segment| segment.offset <= offset && offset + self.program_header_count as u64 * PROGRAM_HEADER_SIZE as u64 <= segment.offset + segment.file_size)
+            .map(|segment| segment.virtual_address + offset - segment.offset)
+    }
+
+    pub fn program_header_count(&self) -> u16 {
+        self.program_header_count
+    }
+}
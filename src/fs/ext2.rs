@@ -0,0 +1,1285 @@
+use alloc::{collections::BTreeMap, format, string::String, sync::{Arc, Weak}, vec, vec::Vec};
+use spin::Mutex;
+
+use crate::block::BlockDevice;
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESCRIPTOR_SIZE: u64 = 32;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFIFO: u16 = 0x1000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFBLK: u16 = 0x6000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xA000;
+
+const INDEX_FLAG: u32 = 0x1000;
+
+const DIRECT_BLOCKS: u64 = 12;
+const SINGLE_INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+const FAST_SYMLINK_MAX: usize = 60;
+
+const DIR_ENTRY_HEADER: usize = 8;
+const MAX_LINKS: u16 = 32000;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn set_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn dir_entry_size(name_length: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_length).next_multiple_of(4)
+}
+
+fn file_type_code(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::Regular => 1,
+        FileType::Directory => 2,
+        FileType::CharDevice => 3,
+        FileType::BlockDevice => 4,
+        FileType::Fifo => 5,
+        FileType::Symlink => 7
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Superblock {
+    inodes_count: u32,
+    blocks_count: u32,
+    first_data_block: u32,
+    block_size: usize,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    first_inode: u32,
+    inode_size: usize,
+    filetype: bool,
+    large_file: bool,
+    read_only: bool,
+    write_time: u32
+}
+
+impl Superblock {
+    fn parse(data: &[u8]) -> Result<Superblock, FsError> {
+        if u16_at(data, 56) != MAGIC {
+            return Err(FsError::Corrupted);
+        }
+
+        let log_block_size = u32_at(data, 24);
+
+        if log_block_size > 6 {
+            return Err(FsError::Corrupted);
+        }
+
+        let revision = u32_at(data, 76);
+        let (first_inode, inode_size, incompat, ro_compat) = if revision == 0 {
+            (GOOD_OLD_FIRST_INODE, GOOD_OLD_INODE_SIZE, 0, 0)
+        } else {
+            (u32_at(data, 84), u16_at(data, 88) as usize, u32_at(data, 96), u32_at(data, 100))
+        };
+
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            return Err(FsError::Unsupported);
+        }
+
+        let superblock = Superblock {
+            inodes_count: u32_at(data, 0),
+            blocks_count: u32_at(data, 4),
+            first_data_block: u32_at(data, 20),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: u32_at(data, 32),
+            inodes_per_group: u32_at(data, 40),
+            first_inode,
+            inode_size,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            large_file: ro_compat & RO_COMPAT_LARGE_FILE != 0,
+            read_only: ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) != 0,
+            write_time: u32_at(data, 48)
+        };
+
+        if superblock.blocks_per_group == 0 || superblock.inodes_per_group == 0 || superblock.inode_size < GOOD_OLD_INODE_SIZE
+            || !superblock.inode_size.is_power_of_two() || superblock.inode_size > superblock.block_size {
+            return Err(FsError::Corrupted);
+        }
+
+        Ok(superblock)
+    }
+
+    fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    fn pointers_per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size / 512) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GroupDescriptor {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16
+}
+
+/// Raw on-disk inode; fields not used by the driver are preserved on write
+#[derive(Clone)]
+struct RawInode {
+    raw: Vec<u8>
+}
+
+impl RawInode {
+    fn new(size: usize, mode: u16) -> RawInode {
+        let mut inode = RawInode { raw: vec![0; size] };
+        set_u16(&mut inode.raw, 0, mode);
+
+        if size > GOOD_OLD_INODE_SIZE {
+            set_u16(&mut inode.raw, 128, 32); // i_extra_isize
+        }
+
+        inode
+    }
+
+    fn mode(&self) -> u16 {
+        u16_at(&self.raw, 0)
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode() & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFIFO => FileType::Fifo,
+            _ => FileType::Regular
+        }
+    }
+
+    fn size(&self) -> u64 {
+        let high = if self.file_type() == FileType::Regular { u32_at(&self.raw, 108) as u64 } else { 0 };
+        u32_at(&self.raw, 4) as u64 | high << 32
+    }
+
+    fn set_size(&mut self, size: u64) {
+        set_u32(&mut self.raw, 4, size as u32);
+
+        if self.file_type() == FileType::Regular {
+            set_u32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    fn links(&self) -> u16 {
+        u16_at(&self.raw, 26)
+    }
+
+    fn set_links(&mut self, links: u16) {
+        set_u16(&mut self.raw, 26, links);
+    }
+
+    fn sectors(&self) -> u32 {
+        u32_at(&self.raw, 28)
+    }
+
+    fn set_sectors(&mut self, sectors: u32) {
+        set_u32(&mut self.raw, 28, sectors);
+    }
+
+    fn flags(&self) -> u32 {
+        u32_at(&self.raw, 32)
+    }
+
+    fn set_flags(&mut self, flags: u32) {
+        set_u32(&mut self.raw, 32, flags);
+    }
+
+    fn block(&self, index: usize) -> u32 {
+        u32_at(&self.raw, 40 + index * 4)
+    }
+
+    fn set_block(&mut self, index: usize, block: u32) {
+        set_u32(&mut self.raw, 40 + index * 4, block);
+    }
+
+    fn file_acl(&self) -> u32 {
+        u32_at(&self.raw, 104)
+    }
+
+    /// Fast symlinks keep target inside block pointer array
+    fn is_fast_symlink(&self, sectors_per_block: u32) -> bool {
+        let acl_sectors = if self.file_acl() != 0 { sectors_per_block } else { 0 };
+        self.file_type() == FileType::Symlink && self.sectors() == acl_sectors
+    }
+}
+
+struct RawDirEntry {
+    offset: usize,
+    inode: u32,
+    rec_len: usize,
+    name: String,
+    file_type: u8
+}
+
+/// Parses directory block entries; `None` if block is malformed
+fn parse_dir_block(block: &[u8]) -> Option<Vec<RawDirEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset < block.len() {
+        if block.len() - offset < DIR_ENTRY_HEADER {
+            return None;
+        }
+
+        let inode = u32_at(block, offset);
+        let rec_len = u16_at(block, offset + 4) as usize;
+        let name_length = block[offset + 6] as usize;
+
+        if rec_len < DIR_ENTRY_HEADER || rec_len % 4 != 0 || offset + rec_len > block.len() || DIR_ENTRY_HEADER + name_length > rec_len {
+            return None;
+        }
+
+        let name = String::from_utf8_lossy(&block[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name_length]).into_owned();
+        entries.push(RawDirEntry { offset, inode, rec_len, name, file_type: block[offset + 7] });
+        offset += rec_len;
+    }
+
+    Some(entries)
+}
+
+pub struct Ext2Fs {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    groups: Mutex<Vec<GroupDescriptor>>,
+    /// Serializes every operation touching bitmaps, inodes or directories
+    lock: Mutex<()>,
+    this: Weak<Ext2Fs>
+}
+
+impl Ext2Fs {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Ext2Fs>, FsError> {
+        let mut data = [0u8; 1024];
+        device.read_bytes(SUPERBLOCK_OFFSET, &mut data)?;
+        let superblock = Superblock::parse(&data)?;
+
+        if superblock.blocks_count as u64 * superblock.block_size as u64 > device.sector_count() * device.sector_size() as u64 {
+            return Err(FsError::Corrupted);
+        }
+
+        let count = superblock.group_count() as usize;
+        let mut table = vec![0u8; count * GROUP_DESCRIPTOR_SIZE as usize];
+        device.read_bytes((superblock.first_data_block as u64 + 1) * superblock.block_size as u64, &mut table)?;
+
+        let groups = table.chunks_exact(GROUP_DESCRIPTOR_SIZE as usize).map(|raw| GroupDescriptor {
+            block_bitmap: u32_at(raw, 0),
+            inode_bitmap: u32_at(raw, 4),
+            inode_table: u32_at(raw, 8),
+            free_blocks: u16_at(raw, 12),
+            free_inodes: u16_at(raw, 14),
+            used_dirs: u16_at(raw, 16)
+        }).collect();
+
+        Ok(Arc::new_cyclic(|this| Ext2Fs { device, superblock, groups: Mutex::new(groups), lock: Mutex::new(()), this: this.clone() }))
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.superblock.read_only
+    }
+
+    /// Kernel has no wall clock yet, so timestamps are taken from the last superblock write.
+    /// Must stay above inode count: fsck treats smaller deletion times as orphan list links.
+    fn timestamp(&self) -> u32 {
+        self.superblock.write_time.max(self.superblock.inodes_count)
+    }
+
+    fn check_writable(&self) -> Result<(), FsError> {
+        if self.superblock.read_only {
+            return Err(FsError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.superblock.block_size as u64
+    }
+
+    fn read_block(&self, block: u32) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0u8; self.superblock.block_size];
+        self.device.read_bytes(self.block_offset(block), &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&self, block: u32, data: &[u8]) -> Result<(), FsError> {
+        self.device.write_bytes(self.block_offset(block), data)?;
+        Ok(())
+    }
+
+    fn inode_offset(&self, ino: u32) -> Result<u64, FsError> {
+        if ino == 0 || ino > self.superblock.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+
+        let group = (ino - 1) / self.superblock.inodes_per_group;
+        let index = (ino - 1) % self.superblock.inodes_per_group;
+        let table = self.groups.lock()[group as usize].inode_table;
+        Ok(self.block_offset(table) + index as u64 * self.superblock.inode_size as u64)
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<RawInode, FsError> {
+        let mut raw = vec![0u8; self.superblock.inode_size];
+        self.device.read_bytes(self.inode_offset(ino)?, &mut raw)?;
+        Ok(RawInode { raw })
+    }
+
+    fn write_inode(&self, ino: u32, inode: &RawInode) -> Result<(), FsError> {
+        self.device.write_bytes(self.inode_offset(ino)?, &inode.raw)?;
+        Ok(())
+    }
+
+    fn write_group(&self, index: usize, group: &GroupDescriptor) -> Result<(), FsError> {
+        let offset = (self.superblock.first_data_block as u64 + 1) * self.superblock.block_size as u64 + index as u64 * GROUP_DESCRIPTOR_SIZE;
+        let mut raw = [0u8; 6];
+        set_u16(&mut raw, 0, group.free_blocks);
+        set_u16(&mut raw, 2, group.free_inodes);
+        set_u16(&mut raw, 4, group.used_dirs);
+        self.device.write_bytes(offset + 12, &raw)?;
+        Ok(())
+    }
+
+    /// Adjusts superblock free block and free inode counters
+    fn update_superblock_counts(&self, blocks: i64, inodes: i64) -> Result<(), FsError> {
+        let mut raw = [0u8; 8];
+        self.device.read_bytes(SUPERBLOCK_OFFSET + 12, &mut raw)?;
+        let free_blocks = (u32_at(&raw, 0) as i64 + blocks).max(0) as u32;
+        let free_inodes = (u32_at(&raw, 4) as i64 + inodes).max(0) as u32;
+        set_u32(&mut raw, 0, free_blocks);
+        set_u32(&mut raw, 4, free_inodes);
+        self.device.write_bytes(SUPERBLOCK_OFFSET + 12, &raw)?;
+        Ok(())
+    }
+
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = self.superblock.first_data_block + group * self.superblock.blocks_per_group;
+        (self.superblock.blocks_count - start).min(self.superblock.blocks_per_group)
+    }
+
+    fn inodes_in_group(&self, group: u32) -> u32 {
+        (self.superblock.inodes_count - group * self.superblock.inodes_per_group).min(self.superblock.inodes_per_group)
+    }
+
+    /// Finds and sets first clear bit in bitmap block, scanning `bits` bits
+    fn take_bit(&self, bitmap_block: u32, bits: u32, first: u32) -> Result<Option<u32>, FsError> {
+        let mut bitmap = self.read_block(bitmap_block)?;
+
+        for bit in first..bits {
+            let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+
+            if bitmap[byte] & mask == 0 {
+                bitmap[byte] |= mask;
+                self.write_block(bitmap_block, &bitmap)?;
+                return Ok(Some(bit));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn clear_bit(&self, bitmap_block: u32, bit: u32) -> Result<bool, FsError> {
+        let mut bitmap = self.read_block(bitmap_block)?;
+        let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+        let was_set = bitmap[byte] & mask != 0;
+        bitmap[byte] &= !mask;
+        self.write_block(bitmap_block, &bitmap)?;
+        Ok(was_set)
+    }
+
+    /// Allocates zeroed block, preferring group `goal`
+    fn allocate_block(&self, goal: u32) -> Result<u32, FsError> {
+        let group_count = self.superblock.group_count();
+
+        for i in 0..group_count {
+            let index = (goal + i) % group_count;
+            let mut group = self.groups.lock()[index as usize];
+
+            if group.free_blocks == 0 {
+                continue;
+            }
+
+            if let Some(bit) = self.take_bit(group.block_bitmap, self.blocks_in_group(index), 0)? {
+                group.free_blocks -= 1;
+                self.groups.lock()[index as usize] = group;
+                self.write_group(index as usize, &group)?;
+                self.update_superblock_counts(-1, 0)?;
+                let block = self.superblock.first_data_block + index * self.superblock.blocks_per_group + bit;
+                self.write_block(block, &vec![0u8; self.superblock.block_size])?;
+                return Ok(block);
+            }
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    fn free_block(&self, block: u32) -> Result<(), FsError> {
+        if block < self.superblock.first_data_block || block >= self.superblock.blocks_count {
+            return Err(FsError::Corrupted);
+        }
+
+        let relative = block - self.superblock.first_data_block;
+        let index = relative / self.superblock.blocks_per_group;
+        let mut group = self.groups.lock()[index as usize];
+
+        if self.clear_bit(group.block_bitmap, relative % self.superblock.blocks_per_group)? {
+            group.free_blocks += 1;
+            self.groups.lock()[index as usize] = group;
+            self.write_group(index as usize, &group)?;
+            self.update_superblock_counts(1, 0)?;
+        }
+
+        Ok(())
+    }
+
+    fn allocate_inode(&self, goal: u32, directory: bool) -> Result<u32, FsError> {
+        let group_count = self.superblock.group_count();
+
+        for i in 0..group_count {
+            let index = (goal + i) % group_count;
+            let mut group = self.groups.lock()[index as usize];
+
+            if group.free_inodes == 0 {
+                continue;
+            }
+
+            // Inodes below first_inode are reserved, they all live in group 0
+            let first = if index == 0 { self.superblock.first_inode - 1 } else { 0 };
+
+            if let Some(bit) = self.take_bit(group.inode_bitmap, self.inodes_in_group(index), first)? {
+                group.free_inodes -= 1;
+
+                if directory {
+                    group.used_dirs += 1;
+                }
+
+                self.groups.lock()[index as usize] = group;
+                self.write_group(index as usize, &group)?;
+                self.update_superblock_counts(0, -1)?;
+                return Ok(index * self.superblock.inodes_per_group + bit + 1);
+            }
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    fn free_inode(&self, ino: u32, directory: bool) -> Result<(), FsError> {
+        let index = (ino - 1) / self.superblock.inodes_per_group;
+        let mut group = self.groups.lock()[index as usize];
+
+        if self.clear_bit(group.inode_bitmap, (ino - 1) % self.superblock.inodes_per_group)? {
+            group.free_inodes += 1;
+
+            if directory {
+                group.used_dirs = group.used_dirs.saturating_sub(1);
+            }
+
+            self.groups.lock()[index as usize] = group;
+            self.write_group(index as usize, &group)?;
+            self.update_superblock_counts(0, 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn inode_group(&self, ino: u32) -> u32 {
+        (ino - 1) / self.superblock.inodes_per_group
+    }
+
+    /// Splits logical block index into the root pointer slot and path of indexes through indirect blocks
+    fn block_path(&self, index: u64) -> Result<(usize, Vec<usize>), FsError> {
+        let per = self.superblock.pointers_per_block();
+
+        if index < DIRECT_BLOCKS {
+            return Ok((index as usize, Vec::new()));
+        }
+
+        let index = index - DIRECT_BLOCKS;
+
+        if index < per {
+            return Ok((SINGLE_INDIRECT, vec![index as usize]));
+        }
+
+        let index = index - per;
+
+        if index < per * per {
+            return Ok((DOUBLE_INDIRECT, vec![(index / per) as usize, (index % per) as usize]));
+        }
+
+        let index = index - per * per;
+
+        if index < per * per * per {
+            return Ok((TRIPLE_INDIRECT, vec![(index / (per * per)) as usize, (index / per % per) as usize, (index % per) as usize]));
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    /// Maps logical block of inode to disk block, allocating missing blocks if `allocate` is set.
+    /// Returns 0 for holes when not allocating.
+    fn map_block(&self, ino: u32, inode: &mut RawInode, index: u64, allocate: bool) -> Result<u32, FsError> {
+        let (slot, path) = self.block_path(index)?;
+        let goal = self.inode_group(ino);
+        let mut block = inode.block(slot);
+
+        if block == 0 {
+            if !allocate {
+                return Ok(0);
+            }
+
+            block = self.allocate_block(goal)?;
+            inode.set_block(slot, block);
+            inode.set_sectors(inode.sectors() + self.superblock.sectors_per_block());
+        }
+
+        for position in path {
+            let mut pointers = self.read_block(block)?;
+            let mut next = u32_at(&pointers, position * 4);
+
+            if next == 0 {
+                if !allocate {
+                    return Ok(0);
+                }
+
+                next = self.allocate_block(goal)?;
+                set_u32(&mut pointers, position * 4, next);
+                self.write_block(block, &pointers)?;
+                inode.set_sectors(inode.sectors() + self.superblock.sectors_per_block());
+            }
+
+            block = next;
+        }
+
+        Ok(block)
+    }
+
+    /// Frees data blocks with relative index >= `keep` under indirect `block` of `level`.
+    /// Returns true if the indirect block itself was freed.
+    fn truncate_indirect(&self, inode: &mut RawInode, block: u32, level: u32, keep: u64) -> Result<bool, FsError> {
+        let per = self.superblock.pointers_per_block();
+        let span = per.pow(level - 1);
+        let mut pointers = self.read_block(block)?;
+        let mut changed = false;
+
+        for i in 0..per as usize {
+            let pointer = u32_at(&pointers, i * 4);
+            let start = i as u64 * span;
+
+            if pointer == 0 || start + span <= keep {
+                continue;
+            }
+
+            let freed = if level == 1 {
+                self.free_block(pointer)?;
+                inode.set_sectors(inode.sectors().saturating_sub(self.superblock.sectors_per_block()));
+                true
+            } else {
+                self.truncate_indirect(inode, pointer, level - 1, keep.saturating_sub(start))?
+            };
+
+            if freed {
+                set_u32(&mut pointers, i * 4, 0);
+                changed = true;
+            }
+        }
+
+        if pointers.iter().all(|&byte| byte == 0) {
+            self.free_block(block)?;
+            inode.set_sectors(inode.sectors().saturating_sub(self.superblock.sectors_per_block()));
+            return Ok(true);
+        }
+
+        if changed {
+            self.write_block(block, &pointers)?;
+        }
+
+        Ok(false)
+    }
+
+    /// Frees all blocks past `size` bytes and sets inode size
+    fn truncate_blocks(&self, inode: &mut RawInode, size: u64) -> Result<(), FsError> {
+        let keep = size.div_ceil(self.superblock.block_size as u64);
+        let per = self.superblock.pointers_per_block();
+
+        for slot in keep.min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+            let block = inode.block(slot as usize);
+
+            if block != 0 {
+                self.free_block(block)?;
+                inode.set_block(slot as usize, 0);
+                inode.set_sectors(inode.sectors().saturating_sub(self.superblock.sectors_per_block()));
+            }
+        }
+
+        let mut base = DIRECT_BLOCKS;
+
+        for (slot, level) in [(SINGLE_INDIRECT, 1), (DOUBLE_INDIRECT, 2), (TRIPLE_INDIRECT, 3)] {
+            let span = per.pow(level);
+            let block = inode.block(slot);
+
+            if block != 0 && keep < base + span && self.truncate_indirect(inode, block, level, keep.saturating_sub(base))? {
+                inode.set_block(slot, 0);
+            }
+
+            base += span;
+        }
+
+        inode.set_size(size);
+        Ok(())
+    }
+
+    fn read_data(&self, ino: u32, inode: &mut RawInode, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let size = inode.size();
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let length = buf.len().min((size - offset) as usize);
+        let block_size = self.superblock.block_size as u64;
+        let mut done = 0;
+
+        while done < length {
+            let position = offset + done as u64;
+            let in_block = position % block_size;
+            let count = ((block_size - in_block) as usize).min(length - done);
+            let block = self.map_block(ino, inode, position / block_size, false)?;
+
+            if block == 0 {
+                buf[done..done + count].fill(0);
+            } else {
+                self.device.read_bytes(self.block_offset(block) + in_block, &mut buf[done..done + count])?;
+            }
+
+            done += count;
+        }
+
+        Ok(length)
+    }
+
+    fn write_data(&self, ino: u32, inode: &mut RawInode, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let end = offset.checked_add(buf.len() as u64).ok_or(FsError::NoSpace)?;
+
+        if end > u32::MAX as u64 && !self.superblock.large_file {
+            return Err(FsError::NoSpace);
+        }
+
+        let block_size = self.superblock.block_size as u64;
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let in_block = position % block_size;
+            let count = ((block_size - in_block) as usize).min(buf.len() - done);
+            let block = self.map_block(ino, inode, position / block_size, true)?;
+            self.device.write_bytes(self.block_offset(block) + in_block, &buf[done..done + count])?;
+            done += count;
+        }
+
+        if end > inode.size() {
+            inode.set_size(end);
+        }
+
+        self.write_inode(ino, inode)?;
+        Ok(buf.len())
+    }
+
+    fn dir_blocks(&self, ino: u32, inode: &mut RawInode) -> Result<Vec<u32>, FsError> {
+        let count = inode.size().div_ceil(self.superblock.block_size as u64);
+        let mut blocks = Vec::new();
+
+        for index in 0..count {
+            let block = self.map_block(ino, inode, index, false)?;
+
+            if block == 0 {
+                return Err(FsError::Corrupted);
+            }
+
+            blocks.push(block);
+        }
+
+        Ok(blocks)
+    }
+
+    fn read_dir_entries(&self, ino: u32) -> Result<Vec<RawDirEntry>, FsError> {
+        let mut inode = self.read_inode(ino)?;
+
+        if inode.file_type() != FileType::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let mut entries = Vec::new();
+
+        for block in self.dir_blocks(ino, &mut inode)? {
+            let data = self.read_block(block)?;
+            entries.extend(parse_dir_block(&data).ok_or(FsError::Corrupted)?.into_iter().filter(|entry| entry.inode != 0));
+        }
+
+        Ok(entries)
+    }
+
+    fn find_entry(&self, dir: u32, name: &str) -> Result<u32, FsError> {
+        self.read_dir_entries(dir)?.into_iter().find(|entry| entry.name == name).map(|entry| entry.inode).ok_or(FsError::NotFound)
+    }
+
+    fn add_dir_entry(&self, dir: u32, name: &str, ino: u32, file_type: FileType) -> Result<(), FsError> {
+        let mut dir_inode = self.read_inode(dir)?;
+        let needed = dir_entry_size(name.len());
+        let type_code = if self.superblock.filetype { file_type_code(file_type) } else { 0 };
+
+        // Hash tree index isn't maintained, so clear the flag like pre-htree kernels do
+        if dir_inode.flags() & INDEX_FLAG != 0 {
+            dir_inode.set_flags(dir_inode.flags() & !INDEX_FLAG);
+            self.write_inode(dir, &dir_inode)?;
+        }
+
+        let write_entry = |block: &mut [u8], offset: usize, rec_len: usize| {
+            set_u32(block, offset, ino);
+            set_u16(block, offset + 4, rec_len as u16);
+            block[offset + 6] = name.len() as u8;
+            block[offset + 7] = type_code;
+            block[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name.len()].copy_from_slice(name.as_bytes());
+        };
+
+        for block in self.dir_blocks(dir, &mut dir_inode)? {
+            let mut data = self.read_block(block)?;
+
+            for entry in parse_dir_block(&data).ok_or(FsError::Corrupted)? {
+                let used = if entry.inode == 0 { 0 } else { dir_entry_size(entry.name.len()) };
+
+                if entry.rec_len - used < needed {
+                    continue;
+                }
+
+                if used == 0 {
+                    write_entry(&mut data, entry.offset, entry.rec_len);
+                } else {
+                    set_u16(&mut data, entry.offset + 4, used as u16);
+                    write_entry(&mut data, entry.offset + used, entry.rec_len - used);
+                }
+
+                return self.write_block(block, &data);
+            }
+        }
+
+        let block_size = self.superblock.block_size;
+        let index = dir_inode.size() / block_size as u64;
+        let block = self.map_block(dir, &mut dir_inode, index, true)?;
+        let mut data = vec![0u8; block_size];
+        write_entry(&mut data, 0, block_size);
+        self.write_block(block, &data)?;
+        dir_inode.set_size(dir_inode.size() + block_size as u64);
+        self.write_inode(dir, &dir_inode)
+    }
+
+    fn remove_dir_entry(&self, dir: u32, name: &str) -> Result<u32, FsError> {
+        let mut dir_inode = self.read_inode(dir)?;
+
+        if dir_inode.flags() & INDEX_FLAG != 0 {
+            dir_inode.set_flags(dir_inode.flags() & !INDEX_FLAG);
+            self.write_inode(dir, &dir_inode)?;
+        }
+
+        for block in self.dir_blocks(dir, &mut dir_inode)? {
+            let mut data = self.read_block(block)?;
+            let entries = parse_dir_block(&data).ok_or(FsError::Corrupted)?;
+
+            for (i, entry) in entries.iter().enumerate() {
+                if entry.inode == 0 || entry.name != name {
+                    continue;
+                }
+
+                if i == 0 {
+                    set_u32(&mut data, entry.offset, 0);
+                } else {
+                    let previous = &entries[i - 1];
+                    set_u16(&mut data, previous.offset + 4, (previous.rec_len + entry.rec_len) as u16);
+                }
+
+                self.write_block(block, &data)?;
+                return Ok(entry.inode);
+            }
+        }
+
+        Err(FsError::NotFound)
+    }
+
+    fn create_inode(&self, dir: u32, name: &str, file_type: FileType, symlink_target: Option<&str>) -> Result<u32, FsError> {
+        self.check_writable()?;
+
+        if name.is_empty() || name.len() > 255 || name.contains('/') || name.contains('\0') {
+            return Err(FsError::InvalidPath);
+        }
+
+        if self.find_entry(dir, name).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let mode = match file_type {
+            FileType::Regular => S_IFREG | 0o644,
+            FileType::Directory => S_IFDIR | 0o755,
+            FileType::Symlink => S_IFLNK | 0o777,
+            _ => return Err(FsError::Unsupported)
+        };
+
+        let directory = file_type == FileType::Directory;
+        let mut parent = self.read_inode(dir)?;
+
+        if directory && parent.links() >= MAX_LINKS {
+            return Err(FsError::TooManyLinks);
+        }
+
+        let ino = self.allocate_inode(self.inode_group(dir), directory)?;
+        let mut inode = RawInode::new(self.superblock.inode_size, mode);
+        inode.set_links(if directory { 2 } else { 1 });
+
+        for offset in [8, 12, 16] {
+            set_u32(&mut inode.raw, offset, self.timestamp());
+        }
+
+        if directory {
+            let block_size = self.superblock.block_size;
+            let block = self.map_block(ino, &mut inode, 0, true)?;
+            let mut data = vec![0u8; block_size];
+            set_u32(&mut data, 0, ino);
+            set_u16(&mut data, 4, 12);
+            data[6] = 1;
+            data[7] = if self.superblock.filetype { 2 } else { 0 };
+            data[8] = b'.';
+            set_u32(&mut data, 12, dir);
+            set_u16(&mut data, 16, (block_size - 12) as u16);
+            data[18] = 2;
+            data[19] = data[7];
+            data[20] = b'.';
+            data[21] = b'.';
+            self.write_block(block, &data)?;
+            inode.set_size(block_size as u64);
+        }
+
+        if let Some(target) = symlink_target {
+            if target.len() < FAST_SYMLINK_MAX {
+                inode.raw[40..40 + target.len()].copy_from_slice(target.as_bytes());
+                inode.set_size(target.len() as u64);
+            } else {
+                self.write_data(ino, &mut inode, 0, target.as_bytes())?;
+            }
+        }
+
+        self.write_inode(ino, &inode)?;
+        self.add_dir_entry(dir, name, ino, file_type)?;
+
+        if directory {
+            parent = self.read_inode(dir)?;
+            parent.set_links(parent.links() + 1);
+            self.write_inode(dir, &parent)?;
+        }
+
+        Ok(ino)
+    }
+
+    fn unlink_inode(&self, dir: u32, name: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let ino = self.find_entry(dir, name)?;
+        let mut inode = self.read_inode(ino)?;
+        let directory = inode.file_type() == FileType::Directory;
+
+        if directory && self.read_dir_entries(ino)?.iter().any(|entry| entry.name != "." && entry.name != "..") {
+            return Err(FsError::NotEmpty);
+        }
+
+        self.remove_dir_entry(dir, name)?;
+
+        if directory {
+            let mut parent = self.read_inode(dir)?;
+            parent.set_links(parent.links().saturating_sub(1));
+            self.write_inode(dir, &parent)?;
+            inode.set_links(0);
+        } else {
+            inode.set_links(inode.links().saturating_sub(1));
+        }
+
+        if inode.links() == 0 {
+            set_u32(&mut inode.raw, 20, self.timestamp());
+            if !inode.is_fast_symlink(self.superblock.sectors_per_block()) {
+                self.truncate_blocks(&mut inode, 0)?;
+            }
+
+            self.write_inode(ino, &inode)?;
+            self.free_inode(ino, directory)?;
+        } else {
+            self.write_inode(ino, &inode)?;
+        }
+
+        Ok(())
+    }
+
+    /// Walks the filesystem from the root and cross-checks it with bitmaps and counters.
+    /// Returns list of found problems, empty if filesystem is consistent.
+    pub fn check(&self) -> Result<Vec<String>, FsError> {
+        let _guard = self.lock.lock();
+        let mut problems = Vec::new();
+        let mut used_blocks: BTreeMap<u32, u32> = BTreeMap::new();
+        let mut references: BTreeMap<u32, u32> = BTreeMap::new();
+        let mut directories: Vec<(u32, u32)> = vec![(ROOT_INODE, ROOT_INODE)];
+        let mut visited: Vec<u32> = Vec::new();
+
+        while let Some((ino, parent)) = directories.pop() {
+            if visited.contains(&ino) {
+                problems.push(format!("directory {} reachable twice", ino));
+                continue;
+            }
+
+            visited.push(ino);
+            let entries = match self.read_dir_entries(ino) {
+                Ok(entries) => entries,
+                Err(error) => {
+                    problems.push(format!("directory {} unreadable: {:?}", ino, error));
+                    continue;
+                }
+            };
+
+            if entries.first().map(|entry| (entry.name.as_str(), entry.inode)) != Some((".", ino)) {
+                problems.push(format!("directory {} has no valid '.' entry", ino));
+            }
+
+            if entries.get(1).map(|entry| (entry.name.as_str(), entry.inode)) != Some(("..", parent)) {
+                problems.push(format!("directory {} has no valid '..' entry", ino));
+            }
+
+            for entry in entries {
+                *references.entry(entry.inode).or_insert(0) += 1;
+
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+
+                let child = self.read_inode(entry.inode)?;
+
+                if self.superblock.filetype && entry.file_type != file_type_code(child.file_type()) {
+                    problems.push(format!("entry '{}' in {} has wrong file type", entry.name, ino));
+                }
+
+                if child.file_type() == FileType::Directory {
+                    directories.push((entry.inode, ino));
+                }
+            }
+        }
+
+        for (&ino, &count) in references.iter() {
+            let mut inode = self.read_inode(ino)?;
+
+            if inode.links() as u32 != count {
+                problems.push(format!("inode {} has {} links, {} references", ino, inode.links(), count));
+            }
+
+            let group = self.groups.lock()[self.inode_group(ino) as usize];
+            let bitmap = self.read_block(group.inode_bitmap)?;
+            let bit = (ino - 1) % self.superblock.inodes_per_group;
+
+            if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0 {
+                problems.push(format!("inode {} in use but free in bitmap", ino));
+            }
+
+            if !inode.is_fast_symlink(self.superblock.sectors_per_block()) {
+                let mut blocks = Vec::new();
+                self.collect_blocks(&mut inode, &mut blocks)?;
+
+                if blocks.len() as u32 * self.superblock.sectors_per_block() + if inode.file_acl() != 0 { self.superblock.sectors_per_block() } else { 0 } != inode.sectors() {
+                    problems.push(format!("inode {} block count is wrong", ino));
+                }
+
+                for block in blocks {
+                    if let Some(owner) = used_blocks.insert(block, ino) {
+                        problems.push(format!("block {} used by inodes {} and {}", block, owner, ino));
+                    }
+                }
+            }
+        }
+
+        let mut total_free_blocks = 0u64;
+        let mut total_free_inodes = 0u64;
+        let groups = self.groups.lock().clone();
+
+        for (index, group) in groups.iter().enumerate() {
+            let block_bitmap = self.read_block(group.block_bitmap)?;
+            let inode_bitmap = self.read_block(group.inode_bitmap)?;
+            let count_free = |bitmap: &[u8], bits: u32| (0..bits).filter(|bit| bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0).count() as u64;
+            let free_blocks = count_free(&block_bitmap, self.blocks_in_group(index as u32));
+            let free_inodes = count_free(&inode_bitmap, self.inodes_in_group(index as u32));
+
+            if free_blocks != group.free_blocks as u64 {
+                problems.push(format!("group {} free block count {} != {}", index, group.free_blocks, free_blocks));
+            }
+
+            if free_inodes != group.free_inodes as u64 {
+                problems.push(format!("group {} free inode count {} != {}", index, group.free_inodes, free_inodes));
+            }
+
+            total_free_blocks += free_blocks;
+            total_free_inodes += free_inodes;
+        }
+
+        for (&block, &ino) in used_blocks.iter() {
+            let relative = block - self.superblock.first_data_block;
+            let group = groups[(relative / self.superblock.blocks_per_group) as usize];
+            let bitmap = self.read_block(group.block_bitmap)?;
+            let bit = relative % self.superblock.blocks_per_group;
+
+            if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0 {
+                problems.push(format!("block {} of inode {} free in bitmap", block, ino));
+            }
+        }
+
+        let mut raw = [0u8; 8];
+        self.device.read_bytes(SUPERBLOCK_OFFSET + 12, &mut raw)?;
+
+        if u32_at(&raw, 0) as u64 != total_free_blocks || u32_at(&raw, 4) as u64 != total_free_inodes {
+            problems.push(String::from("superblock free counts are wrong"));
+        }
+
+        Ok(problems)
+    }
+
+    /// Collects data and indirect blocks of inode
+    fn collect_blocks(&self, inode: &mut RawInode, blocks: &mut Vec<u32>) -> Result<(), FsError> {
+        fn walk(fs: &Ext2Fs, block: u32, level: u32, blocks: &mut Vec<u32>) -> Result<(), FsError> {
+            if block == 0 {
+                return Ok(());
+            }
+
+            blocks.push(block);
+
+            if level > 0 {
+                let pointers = fs.read_block(block)?;
+
+                for i in 0..fs.superblock.pointers_per_block() as usize {
+                    walk(fs, u32_at(&pointers, i * 4), level - 1, blocks)?;
+                }
+            }
+
+            Ok(())
+        }
+
+        for slot in 0..DIRECT_BLOCKS as usize {
+            walk(self, inode.block(slot), 0, blocks)?;
+        }
+
+        walk(self, inode.block(SINGLE_INDIRECT), 1, blocks)?;
+        walk(self, inode.block(DOUBLE_INDIRECT), 2, blocks)?;
+        walk(self, inode.block(TRIPLE_INDIRECT), 3, blocks)
+    }
+
+    fn inode(&self, ino: u32) -> Result<Arc<dyn Inode>, FsError> {
+        let fs = self.this.upgrade().ok_or(FsError::Io)?;
+        Ok(Arc::new(Ext2Inode { fs, ino }))
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.inode(ROOT_INODE).expect("ext2 filesystem dropped")
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.device.flush()?;
+        Ok(())
+    }
+}
+
+pub struct Ext2Inode {
+    fs: Arc<Ext2Fs>,
+    ino: u32
+}
+
+impl Ext2Inode {
+    fn read(&self) -> Result<RawInode, FsError> {
+        self.fs.read_inode(self.ino)
+    }
+
+    fn read_typed(&self, expected: FileType) -> Result<RawInode, FsError> {
+        let inode = self.read()?;
+
+        match (inode.file_type(), expected) {
+            (actual, expected) if actual == expected => Ok(inode),
+            (FileType::Directory, _) => Err(FsError::IsDirectory),
+            (_, FileType::Directory) => Err(FsError::NotDirectory),
+            _ => Err(FsError::InvalidArgument)
+        }
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn stat(&self) -> Result<Stat, FsError> {
+        let _guard = self.fs.lock.lock();
+        let inode = self.read()?;
+        let raw = &inode.raw;
+
+        Ok(Stat {
+            inode: self.ino as u64,
+            file_type: inode.file_type(),
+            mode: inode.mode() & 0o7777,
+            nlink: inode.links() as u32,
+            uid: u16_at(raw, 2) as u32 | (u16_at(raw, 120) as u32) << 16,
+            gid: u16_at(raw, 24) as u32 | (u16_at(raw, 122) as u32) << 16,
+            size: inode.size(),
+            blocks: inode.sectors() as u64,
+            atime: u32_at(raw, 8) as u64,
+            ctime: u32_at(raw, 12) as u64,
+            mtime: u32_at(raw, 16) as u64
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let _guard = self.fs.lock.lock();
+        let ino = self.fs.find_entry(self.ino, name)?;
+        self.fs.inode(ino)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let _guard = self.fs.lock.lock();
+        let mut inode = self.read_typed(FileType::Regular)?;
+        self.fs.read_data(self.ino, &mut inode, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.fs.check_writable()?;
+        let _guard = self.fs.lock.lock();
+        let mut inode = self.read_typed(FileType::Regular)?;
+        self.fs.write_data(self.ino, &mut inode, offset, buf)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.fs.check_writable()?;
+        let _guard = self.fs.lock.lock();
+        let mut inode = self.read_typed(FileType::Regular)?;
+
+        if size < inode.size() {
+            self.fs.truncate_blocks(&mut inode, size)?;
+            // Zero the tail of the last kept block so growing the file later reads zeroes
+            let block_size = self.fs.superblock.block_size as u64;
+
+            if size % block_size != 0 {
+                let block = self.fs.map_block(self.ino, &mut inode, size / block_size, false)?;
+
+                if block != 0 {
+                    let tail = vec![0u8; (block_size - size % block_size) as usize];
+                    self.fs.device.write_bytes(self.fs.block_offset(block) + size % block_size, &tail)?;
+                }
+            }
+        } else {
+            inode.set_size(size);
+        }
+
+        self.fs.write_inode(self.ino, &inode)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let _guard = self.fs.lock.lock();
+        let mut entries = Vec::new();
+
+        for entry in self.fs.read_dir_entries(self.ino)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+
+            let file_type = if self.fs.superblock.filetype && entry.file_type != 0 {
+                match entry.file_type {
+                    2 => FileType::Directory,
+                    3 => FileType::CharDevice,
+                    4 => FileType::BlockDevice,
+                    5 => FileType::Fifo,
+                    7 => FileType::Symlink,
+                    _ => FileType::Regular
+                }
+            } else {
+                self.fs.read_inode(entry.inode)?.file_type()
+            };
+
+            entries.push(DirEntry { name: entry.name, inode: entry.inode as u64, file_type });
+        }
+
+        Ok(entries)
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        let _guard = self.fs.lock.lock();
+        self.read_typed(FileType::Directory)?;
+        let ino = self.fs.create_inode(self.ino, name, file_type, None)?;
+        self.fs.inode(ino)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let _guard = self.fs.lock.lock();
+        self.read_typed(FileType::Directory)?;
+        self.fs.unlink_inode(self.ino, name)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let _guard = self.fs.lock.lock();
+        self.read_typed(FileType::Directory)?;
+        let ino = self.fs.create_inode(self.ino, name, FileType::Symlink, Some(target))?;
+        self.fs.inode(ino)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        let _guard = self.fs.lock.lock();
+        let mut inode = self.read_typed(FileType::Symlink)?;
+        let size = inode.size() as usize;
+
+        let target = if inode.is_fast_symlink(self.fs.superblock.sectors_per_block()) {
+            inode.raw[40..40 + size.min(FAST_SYMLINK_MAX)].to_vec()
+        } else {
+            let mut data = vec![0u8; size];
+            self.fs.read_data(self.ino, &mut inode, 0, &mut data)?;
+            data
+        };
+
+        String::from_utf8(target).map_err(|_| FsError::Corrupted)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.fs.sync()
+    }
+}
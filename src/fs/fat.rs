@@ -0,0 +1,1136 @@
+use alloc::{collections::BTreeMap, format, string::String, sync::{Arc, Weak}, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::block::BlockDevice;
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32
+}
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+const ENTRY_SIZE: usize = 32;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+const DEFAULT_DATE: u16 = (1 << 5) | 1; // 1980-01-01
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xAA550000;
+const FSINFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    fat_type: FatType,
+    cluster_size: usize,
+    fat_start: u64,
+    fat_size: u64,
+    fat_count: u64,
+    root_start: u64,
+    root_entries: usize,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    fsinfo_offset: Option<u64>
+}
+
+impl Geometry {
+    fn parse(boot: &[u8]) -> Result<Geometry, FsError> {
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as u64;
+        let u32_at = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap()) as u64;
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = u16_at(14);
+        let fat_count = boot[16] as u64;
+        let root_entries = u16_at(17);
+        let fat_sectors = if u16_at(22) != 0 { u16_at(22) } else { u32_at(36) };
+        let total_sectors = if u16_at(19) != 0 { u16_at(19) } else { u32_at(32) };
+
+        if boot[510..512] != [0x55, 0xAA] || ![512, 1024, 2048, 4096].contains(&bytes_per_sector) || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0 || fat_count == 0 || fat_sectors == 0 {
+            return Err(FsError::Corrupted);
+        }
+
+        let root_sectors = (root_entries * ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+        let data_sector = reserved_sectors + fat_count * fat_sectors + root_sectors;
+
+        if data_sector >= total_sectors {
+            return Err(FsError::Corrupted);
+        }
+
+        let cluster_count = (total_sectors - data_sector) / sectors_per_cluster;
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let (root_cluster, fsinfo_offset) = if fat_type == FatType::Fat32 {
+            let fsinfo_sector = u16_at(48);
+            let fsinfo = if fsinfo_sector != 0 && fsinfo_sector != 0xFFFF { Some(fsinfo_sector * bytes_per_sector) } else { None };
+            (u32_at(44) as u32, fsinfo)
+        } else {
+            (0, None)
+        };
+
+        Ok(Geometry {
+            fat_type,
+            cluster_size: (bytes_per_sector * sectors_per_cluster) as usize,
+            fat_start: reserved_sectors * bytes_per_sector,
+            fat_size: fat_sectors * bytes_per_sector,
+            fat_count,
+            root_start: (reserved_sectors + fat_count * fat_sectors) * bytes_per_sector,
+            root_entries: root_entries as usize,
+            data_start: data_sector * bytes_per_sector,
+            cluster_count: cluster_count as u32,
+            root_cluster,
+            fsinfo_offset
+        })
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFFFFFF
+        }
+    }
+
+    fn is_end_of_chain(&self, value: u32) -> bool {
+        value >= self.end_of_chain() & !7
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.cluster_size as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Dir {
+    /// FAT12/16 root directory, stored in a fixed region before data area
+    FixedRoot,
+    Chain(u32)
+}
+
+/// Position of the short directory entry describing a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Location {
+    dir: Dir,
+    slot: usize
+}
+
+struct RawEntry {
+    name: String,
+    short_name: [u8; 11],
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    write_time: u16,
+    write_date: u16,
+    first_slot: usize,
+    slot: usize
+}
+
+impl RawEntry {
+    fn parse_short(slot: usize, data: &[u8]) -> RawEntry {
+        RawEntry {
+            name: format_short_name(data[0..11].try_into().unwrap(), data[12]),
+            short_name: data[0..11].try_into().unwrap(),
+            attr: data[11],
+            cluster: (u16::from_le_bytes([data[20], data[21]]) as u32) << 16 | u16::from_le_bytes([data[26], data[27]]) as u32,
+            size: u32::from_le_bytes(data[28..32].try_into().unwrap()),
+            write_time: u16::from_le_bytes([data[22], data[23]]),
+            write_date: u16::from_le_bytes([data[24], data[25]]),
+            first_slot: slot,
+            slot
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    fn is_dot(&self) -> bool {
+        self.short_name == *b".          " || self.short_name == *b"..         "
+    }
+}
+
+fn format_short_name(short_name: &[u8; 11], case: u8) -> String {
+    let mut name = String::new();
+    let base = &short_name[0..8];
+    let ext = &short_name[8..11];
+    let push_part = |name: &mut String, part: &[u8], lower: bool| {
+        for (i, &byte) in part.iter().enumerate() {
+            if byte == b' ' && part[i..].iter().all(|&rest| rest == b' ') {
+                break;
+            }
+
+            let byte = if i == 0 && byte == 0x05 { ENTRY_DELETED } else { byte }; // 0x05 escapes leading 0xE5
+            let c = if byte.is_ascii() { byte as char } else { char::REPLACEMENT_CHARACTER };
+            name.push(if lower { c.to_ascii_lowercase() } else { c });
+        }
+    };
+
+    push_part(&mut name, base, case & CASE_LOWER_BASE != 0);
+
+    if ext.iter().any(|&byte| byte != b' ') {
+        name.push('.');
+        push_part(&mut name, ext, case & CASE_LOWER_EXT != 0);
+    }
+
+    name
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte))
+}
+
+/// Decodes directory contents into entries, pairing long name entries with their short entry
+fn parse_directory(data: &[u8]) -> Vec<RawEntry> {
+    let mut entries = Vec::new();
+    let mut lfn: Vec<u16> = Vec::new();
+    let mut lfn_start = 0;
+    let mut lfn_expected = 0; // next expected sequence number, 0 if no valid long name in progress
+    let mut lfn_checksum = 0;
+
+    for (slot, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        match raw[0] {
+            ENTRY_END => break,
+            ENTRY_DELETED => {
+                lfn_expected = 0;
+                continue;
+            },
+            _ => {}
+        }
+
+        if raw[11] & 0x3F == ATTR_LONG_NAME {
+            let sequence = raw[0] & 0x1F;
+
+            if raw[0] & LFN_LAST != 0 {
+                lfn = vec![0xFFFF; sequence as usize * LFN_CHARS];
+                lfn_start = slot;
+                lfn_expected = sequence;
+                lfn_checksum = raw[13];
+            }
+
+            if sequence == 0 || sequence != lfn_expected || raw[13] != lfn_checksum {
+                lfn_expected = 0;
+                continue;
+            }
+
+            let base = (sequence as usize - 1) * LFN_CHARS;
+
+            for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                lfn[base + i] = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+            }
+
+            lfn_expected -= 1;
+
+            if lfn_expected == 0 {
+                lfn_expected = u8::MAX; // complete, waiting for short entry
+            }
+
+            continue;
+        }
+
+        let mut entry = RawEntry::parse_short(slot, raw);
+
+        if lfn_expected == u8::MAX && short_name_checksum(&entry.short_name) == lfn_checksum {
+            let units = lfn.iter().copied().take_while(|&unit| unit != 0 && unit != 0xFFFF);
+            entry.name = char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+            entry.first_slot = lfn_start;
+        }
+
+        lfn_expected = 0;
+
+        if entry.attr & ATTR_VOLUME_ID == 0 {
+            entries.push(entry);
+        }
+    }
+
+    entries
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.encode_utf16().count() <= 255 && !name.ends_with(['.', ' '])
+        && name.chars().all(|c| c >= ' ' && !"\"*/:<>?\\|".contains(c))
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// Returns short name and case flags if `name` can be stored without long name entries
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, "")
+    };
+
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || !base.chars().chain(ext.chars()).all(is_short_char) {
+        return None;
+    }
+
+    let mut case = 0;
+
+    for (part, flag) in [(base, CASE_LOWER_BASE), (ext, CASE_LOWER_EXT)] {
+        let has_lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = part.chars().any(|c| c.is_ascii_uppercase());
+
+        match (has_lower, has_upper) {
+            (true, true) => return None,
+            (true, false) => case |= flag,
+            _ => {}
+        }
+    }
+
+    let mut short_name = [b' '; 11];
+
+    for (i, byte) in base.bytes().enumerate() {
+        short_name[i] = byte.to_ascii_uppercase();
+    }
+
+    for (i, byte) in ext.bytes().enumerate() {
+        short_name[8 + i] = byte.to_ascii_uppercase();
+    }
+
+    Some((short_name, case))
+}
+
+/// Generates unique "BASIS~N.EXT" short name for a name that needs long name entries
+fn generate_short_name(name: &str, existing: &[RawEntry]) -> Result<[u8; 11], FsError> {
+    let clean = |part: &str, limit: usize| -> Vec<u8> {
+        part.chars().filter(|&c| c != ' ' && c != '.').map(|c| {
+            let c = c.to_ascii_uppercase();
+            if is_short_char(c) { c as u8 } else { b'_' }
+        }).take(limit).collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(dot) => (clean(&trimmed[..dot], 8), clean(&trimmed[dot + 1..], 3)),
+        None => (clean(trimmed, 8), Vec::new())
+    };
+    let base = if base.is_empty() { vec![b'_'] } else { base };
+
+    for number in 1..1_000_000u32 {
+        let tail = format!("~{}", number);
+        let base_len = base.len().min(8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+
+        if !existing.iter().any(|entry| entry.short_name == short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(FsError::NoSpace)
+}
+
+fn make_short_entry(short_name: &[u8; 11], case: u8, attr: u8, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0u8; ENTRY_SIZE];
+    raw[0..11].copy_from_slice(short_name);
+    raw[11] = attr;
+    raw[12] = case;
+    raw[16..18].copy_from_slice(&DEFAULT_DATE.to_le_bytes()); // creation date
+    raw[18..20].copy_from_slice(&DEFAULT_DATE.to_le_bytes()); // access date
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[24..26].copy_from_slice(&DEFAULT_DATE.to_le_bytes()); // write date
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw
+}
+
+fn make_lfn_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+
+    if units.len() % LFN_CHARS != 0 {
+        units.push(0);
+    }
+
+    units.resize(count * LFN_CHARS, 0xFFFF);
+
+    // Stored in reverse: entry with highest sequence number comes first
+    (1..=count).rev().map(|sequence| {
+        let mut raw = [0u8; ENTRY_SIZE];
+        raw[0] = sequence as u8 | if sequence == count { LFN_LAST } else { 0 };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum;
+
+        for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            raw[offset..offset + 2].copy_from_slice(&units[(sequence - 1) * LFN_CHARS + i].to_le_bytes());
+        }
+
+        raw
+    }).collect()
+}
+
+/// Converts FAT date and time to seconds since Unix epoch
+fn fat_time_to_unix(date: u16, time: u16) -> u64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0F).clamp(1, 12) as i64;
+    let day = (date & 0x1F).max(1) as i64;
+
+    // Days from civil algorithm, March-based years
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    (days * 86400 + seconds).max(0) as u64
+}
+
+struct FsInfo {
+    free_count: u32,
+    next_free: u32,
+    dirty: bool
+}
+
+/// Inodes of directory entries. FAT has no inode numbers, entries get one when they're first
+/// seen, and an entry created in the slot of a removed one gets a new one.
+struct Inodes {
+    numbers: BTreeMap<Location, u64>,
+    /// Inodes handed out, they're marked removed when their entry is
+    live: BTreeMap<Location, Weak<FatInode>>,
+    /// Root directory is 1
+    next: u64
+}
+
+impl Inodes {
+    fn number(&mut self, location: Location) -> u64 {
+        let next = &mut self.next;
+
+        *self.numbers.entry(location).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+}
+
+pub struct FatFs {
+    device: Arc<dyn BlockDevice>,
+    geometry: Geometry,
+    fsinfo: Mutex<FsInfo>,
+    inodes: Mutex<Inodes>,
+    /// Serializes every operation touching FAT or directories
+    lock: Mutex<()>,
+    this: Weak<FatFs>
+}
+
+impl FatFs {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<FatFs>, FsError> {
+        let mut boot = [0u8; 512];
+        device.read_bytes(0, &mut boot)?;
+        let geometry = Geometry::parse(&boot)?;
+        let mut fsinfo = FsInfo { free_count: FSINFO_UNKNOWN, next_free: FSINFO_UNKNOWN, dirty: false };
+
+        if let Some(offset) = geometry.fsinfo_offset {
+            let mut sector = [0u8; 512];
+            device.read_bytes(offset, &mut sector)?;
+            let u32_at = |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+            if u32_at(0) == FSINFO_LEAD_SIGNATURE && u32_at(484) == FSINFO_STRUCT_SIGNATURE && u32_at(508) == FSINFO_TRAIL_SIGNATURE {
+                fsinfo.free_count = u32_at(488);
+                fsinfo.next_free = u32_at(492);
+            }
+        }
+
+        if geometry.fat_type == FatType::Fat32 && !geometry.is_valid_cluster(geometry.root_cluster) {
+            return Err(FsError::Corrupted);
+        }
+
+        Ok(Arc::new_cyclic(|this| FatFs {
+            device,
+            geometry,
+            fsinfo: Mutex::new(fsinfo),
+            inodes: Mutex::new(Inodes { numbers: BTreeMap::new(), live: BTreeMap::new(), next: 2 }),
+            lock: Mutex::new(()),
+            this: this.clone()
+        }))
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.geometry.fat_type
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.geometry.cluster_size
+    }
+
+    /// Free cluster count, taken from FSInfo hint when it is available
+    pub fn free_clusters(&self) -> Result<u32, FsError> {
+        let _guard = self.lock.lock();
+        let hint = self.fsinfo.lock().free_count;
+
+        if hint != FSINFO_UNKNOWN && hint <= self.geometry.cluster_count {
+            return Ok(hint);
+        }
+
+        let mut free = 0;
+
+        for cluster in 2..self.geometry.cluster_count + 2 {
+            if self.read_fat(cluster)? == 0 {
+                free += 1;
+            }
+        }
+
+        self.fsinfo.lock().free_count = free;
+        Ok(free)
+    }
+
+    fn fat_entry_offset(&self, cluster: u32) -> u64 {
+        let cluster = cluster as u64;
+
+        match self.geometry.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4
+        }
+    }
+
+    fn read_fat(&self, cluster: u32) -> Result<u32, FsError> {
+        let offset = self.geometry.fat_start + self.fat_entry_offset(cluster);
+
+        Ok(match self.geometry.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0u8; 2];
+                self.device.read_bytes(offset, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes) as u32;
+                if cluster & 1 != 0 { value >> 4 } else { value & 0xFFF }
+            },
+            FatType::Fat16 => {
+                let mut bytes = [0u8; 2];
+                self.device.read_bytes(offset, &mut bytes)?;
+                u16::from_le_bytes(bytes) as u32
+            },
+            FatType::Fat32 => {
+                let mut bytes = [0u8; 4];
+                self.device.read_bytes(offset, &mut bytes)?;
+                u32::from_le_bytes(bytes) & 0x0FFFFFFF
+            }
+        })
+    }
+
+    /// Writes FAT entry to every FAT copy
+    fn write_fat(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        for fat in 0..self.geometry.fat_count {
+            let offset = self.geometry.fat_start + fat * self.geometry.fat_size + self.fat_entry_offset(cluster);
+
+            match self.geometry.fat_type {
+                FatType::Fat12 => {
+                    let mut bytes = [0u8; 2];
+                    self.device.read_bytes(offset, &mut bytes)?;
+                    let old = u16::from_le_bytes(bytes);
+                    let new = if cluster & 1 != 0 {
+                        (old & 0x000F) | ((value as u16) << 4)
+                    } else {
+                        (old & 0xF000) | (value as u16 & 0x0FFF)
+                    };
+                    self.device.write_bytes(offset, &new.to_le_bytes())?;
+                },
+                FatType::Fat16 => self.device.write_bytes(offset, &(value as u16).to_le_bytes())?,
+                FatType::Fat32 => {
+                    let mut bytes = [0u8; 4];
+                    self.device.read_bytes(offset, &mut bytes)?;
+                    let new = (u32::from_le_bytes(bytes) & 0xF0000000) | (value & 0x0FFFFFFF);
+                    self.device.write_bytes(offset, &new.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns clusters of chain starting at `start`, empty for `start == 0`
+    fn chain(&self, start: u32) -> Result<Vec<u32>, FsError> {
+        let mut clusters = Vec::new();
+        let mut cluster = start;
+
+        if start == 0 {
+            return Ok(clusters);
+        }
+
+        loop {
+            if !self.geometry.is_valid_cluster(cluster) || clusters.len() > self.geometry.cluster_count as usize {
+                return Err(FsError::Corrupted);
+            }
+
+            clusters.push(cluster);
+            let next = self.read_fat(cluster)?;
+
+            if self.geometry.is_end_of_chain(next) {
+                return Ok(clusters);
+            }
+
+            cluster = next;
+        }
+    }
+
+    /// Allocates zeroed cluster, linking it after `previous` if given
+    fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32, FsError> {
+        let mut fsinfo = self.fsinfo.lock();
+        let count = self.geometry.cluster_count;
+        let start = if self.geometry.is_valid_cluster(fsinfo.next_free) { fsinfo.next_free } else { 2 };
+
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+
+            if self.read_fat(cluster)? != 0 {
+                continue;
+            }
+
+            self.write_fat(cluster, self.geometry.end_of_chain())?;
+
+            if let Some(previous) = previous {
+                self.write_fat(previous, cluster)?;
+            }
+
+            let zeroes = vec![0u8; self.geometry.cluster_size];
+            self.device.write_bytes(self.geometry.cluster_offset(cluster), &zeroes)?;
+
+            if fsinfo.free_count != FSINFO_UNKNOWN {
+                fsinfo.free_count = fsinfo.free_count.saturating_sub(1);
+            }
+
+            fsinfo.next_free = cluster + 1;
+            fsinfo.dirty = true;
+            return Ok(cluster);
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    fn free_chain(&self, clusters: &[u32]) -> Result<(), FsError> {
+        let mut fsinfo = self.fsinfo.lock();
+
+        for &cluster in clusters {
+            self.write_fat(cluster, 0)?;
+
+            if fsinfo.free_count != FSINFO_UNKNOWN {
+                fsinfo.free_count += 1;
+            }
+        }
+
+        fsinfo.dirty = true;
+        Ok(())
+    }
+
+    fn write_fsinfo(&self) -> Result<(), FsError> {
+        let mut fsinfo = self.fsinfo.lock();
+
+        if let (Some(offset), true) = (self.geometry.fsinfo_offset, fsinfo.dirty) {
+            let mut values = [0u8; 8];
+            values[0..4].copy_from_slice(&fsinfo.free_count.to_le_bytes());
+            values[4..8].copy_from_slice(&fsinfo.next_free.to_le_bytes());
+            self.device.write_bytes(offset + 488, &values)?;
+        }
+
+        fsinfo.dirty = false;
+        Ok(())
+    }
+
+    fn read_directory(&self, dir: Dir) -> Result<Vec<u8>, FsError> {
+        match dir {
+            Dir::FixedRoot => {
+                let mut data = vec![0u8; self.geometry.root_entries * ENTRY_SIZE];
+                self.device.read_bytes(self.geometry.root_start, &mut data)?;
+                Ok(data)
+            },
+            Dir::Chain(start) => {
+                let clusters = self.chain(start)?;
+                let mut data = vec![0u8; clusters.len() * self.geometry.cluster_size];
+
+                for (cluster, chunk) in clusters.iter().zip(data.chunks_exact_mut(self.geometry.cluster_size)) {
+                    self.device.read_bytes(self.geometry.cluster_offset(*cluster), chunk)?;
+                }
+
+                Ok(data)
+            }
+        }
+    }
+
+    fn slot_offset(&self, dir: Dir, slot: usize) -> Result<u64, FsError> {
+        match dir {
+            Dir::FixedRoot if slot < self.geometry.root_entries => Ok(self.geometry.root_start + (slot * ENTRY_SIZE) as u64),
+            Dir::FixedRoot => Err(FsError::NoSpace),
+            Dir::Chain(start) => {
+                let per_cluster = self.geometry.cluster_size / ENTRY_SIZE;
+                let cluster = *self.chain(start)?.get(slot / per_cluster).ok_or(FsError::Corrupted)?;
+                Ok(self.geometry.cluster_offset(cluster) + ((slot % per_cluster) * ENTRY_SIZE) as u64)
+            }
+        }
+    }
+
+    fn read_slot(&self, location: Location) -> Result<RawEntry, FsError> {
+        let mut raw = [0u8; ENTRY_SIZE];
+        self.device.read_bytes(self.slot_offset(location.dir, location.slot)?, &mut raw)?;
+
+        if raw[0] == ENTRY_END || raw[0] == ENTRY_DELETED {
+            return Err(FsError::NotFound);
+        }
+
+        Ok(RawEntry::parse_short(location.slot, &raw))
+    }
+
+    fn update_slot(&self, location: Location, cluster: u32, size: u32) -> Result<(), FsError> {
+        let offset = self.slot_offset(location.dir, location.slot)?;
+        let mut raw = [0u8; ENTRY_SIZE];
+        self.device.read_bytes(offset, &mut raw)?;
+        raw[11] |= ATTR_ARCHIVE;
+        raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&size.to_le_bytes());
+        self.device.write_bytes(offset, &raw)?;
+        Ok(())
+    }
+
+    fn find(&self, dir: Dir, name: &str) -> Result<RawEntry, FsError> {
+        parse_directory(&self.read_directory(dir)?).into_iter()
+            .find(|entry| !entry.is_dot() && (entry.name.eq_ignore_ascii_case(name) || format_short_name(&entry.short_name, 0).eq_ignore_ascii_case(name)))
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Finds `count` consecutive free slots, growing directory by one cluster if needed
+    fn find_free_slots(&self, dir: Dir, count: usize) -> Result<usize, FsError> {
+        let data = self.read_directory(dir)?;
+        let mut run = 0;
+
+        for (slot, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+            if raw[0] == ENTRY_END {
+                let available = data.len() / ENTRY_SIZE - slot;
+
+                if run + available >= count {
+                    return Ok(slot - run);
+                }
+
+                break;
+            }
+
+            run = if raw[0] == ENTRY_DELETED { run + 1 } else { 0 };
+
+            if run == count {
+                return Ok(slot + 1 - count);
+            }
+        }
+
+        let Dir::Chain(start) = dir else {
+            return Err(FsError::NoSpace);
+        };
+
+        // Trailing free slots before the end marker continue into the new cluster
+        let total_slots = data.len() / ENTRY_SIZE;
+        let trailing = data.chunks_exact(ENTRY_SIZE).rev().take_while(|raw| raw[0] == ENTRY_END || raw[0] == ENTRY_DELETED).count();
+        let mut last = *self.chain(start)?.last().ok_or(FsError::Corrupted)?;
+        let per_cluster = self.geometry.cluster_size / ENTRY_SIZE;
+        let mut added = 0;
+
+        while trailing + added < count {
+            last = self.allocate_cluster(Some(last))?;
+            added += per_cluster;
+        }
+
+        Ok(total_slots - trailing)
+    }
+
+    fn create_entry(&self, dir: Dir, name: &str, file_type: FileType) -> Result<Location, FsError> {
+        if !is_valid_name(name) {
+            return Err(FsError::InvalidPath);
+        }
+
+        let entries = parse_directory(&self.read_directory(dir)?);
+
+        if entries.iter().any(|entry| entry.name.eq_ignore_ascii_case(name) || format_short_name(&entry.short_name, 0).eq_ignore_ascii_case(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (short_name, case, lfn) = match exact_short_name(name) {
+            Some((short_name, case)) if !entries.iter().any(|entry| entry.short_name == short_name) => (short_name, case, Vec::new()),
+            _ => {
+                let short_name = generate_short_name(name, &entries)?;
+                (short_name, 0, make_lfn_entries(name, short_name_checksum(&short_name)))
+            }
+        };
+
+        let first_slot = self.find_free_slots(dir, lfn.len() + 1)?;
+
+        let (attr, cluster) = if file_type == FileType::Directory {
+            let cluster = self.allocate_cluster(None)?;
+            let parent_cluster = match dir {
+                Dir::FixedRoot => 0,
+                Dir::Chain(start) if start == self.geometry.root_cluster => 0,
+                Dir::Chain(start) => start
+            };
+            let mut dots = [0u8; ENTRY_SIZE * 2];
+            dots[..ENTRY_SIZE].copy_from_slice(&make_short_entry(b".          ", 0, ATTR_DIRECTORY, cluster));
+            dots[ENTRY_SIZE..].copy_from_slice(&make_short_entry(b"..         ", 0, ATTR_DIRECTORY, parent_cluster));
+            self.device.write_bytes(self.geometry.cluster_offset(cluster), &dots)?;
+            (ATTR_DIRECTORY, cluster)
+        } else if file_type == FileType::Regular {
+            (ATTR_ARCHIVE, 0)
+        } else {
+            return Err(FsError::Unsupported);
+        };
+
+        for (i, raw) in lfn.iter().enumerate() {
+            self.device.write_bytes(self.slot_offset(dir, first_slot + i)?, raw)?;
+        }
+
+        let slot = first_slot + lfn.len();
+        self.device.write_bytes(self.slot_offset(dir, slot)?, &make_short_entry(&short_name, case, attr, cluster))?;
+        Ok(Location { dir, slot })
+    }
+
+    fn remove_entry(&self, dir: Dir, name: &str) -> Result<(), FsError> {
+        let entry = self.find(dir, name)?;
+
+        if entry.is_directory() {
+            let contents = parse_directory(&self.read_directory(Dir::Chain(entry.cluster))?);
+
+            if contents.iter().any(|child| !child.is_dot()) {
+                return Err(FsError::NotEmpty);
+            }
+        }
+
+        for slot in entry.first_slot..=entry.slot {
+            self.device.write_bytes(self.slot_offset(dir, slot)?, &[ENTRY_DELETED])?;
+        }
+
+        // Handles still open see it's gone instead of the slot, which a new entry may take
+        let location = Location { dir, slot: entry.slot };
+        let mut inodes = self.inodes.lock();
+        inodes.numbers.remove(&location);
+        let removed = inodes.live.remove(&location);
+        drop(inodes);
+
+        if let Some(inode) = removed.and_then(|inode| inode.upgrade()) {
+            inode.removed.store(true, Ordering::Release);
+        }
+
+        let clusters = self.chain(entry.cluster)?;
+        self.free_chain(&clusters)
+    }
+
+    fn read_file(&self, location: Location, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let entry = self.read_slot(location)?;
+        let size = entry.size as u64;
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let length = buf.len().min((size - offset) as usize);
+        let cluster_size = self.geometry.cluster_size as u64;
+        let clusters = self.chain(entry.cluster)?;
+        let mut done = 0;
+
+        while done < length {
+            let position = offset + done as u64;
+            let cluster = *clusters.get((position / cluster_size) as usize).ok_or(FsError::Corrupted)?;
+            let in_cluster = position % cluster_size;
+            let count = ((cluster_size - in_cluster) as usize).min(length - done);
+            self.device.read_bytes(self.geometry.cluster_offset(cluster) + in_cluster, &mut buf[done..done + count])?;
+            done += count;
+        }
+
+        Ok(length)
+    }
+
+    /// Grows cluster chain of file to hold `size` bytes, returning the chain
+    fn grow_chain(&self, entry: &RawEntry, size: u64) -> Result<Vec<u32>, FsError> {
+        let mut clusters = self.chain(entry.cluster)?;
+        let needed = size.div_ceil(self.geometry.cluster_size as u64) as usize;
+
+        while clusters.len() < needed {
+            let cluster = self.allocate_cluster(clusters.last().copied())?;
+            clusters.push(cluster);
+        }
+
+        Ok(clusters)
+    }
+
+    fn write_file(&self, location: Location, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let entry = self.read_slot(location)?;
+        let end = offset.checked_add(buf.len() as u64).filter(|&end| end <= MAX_FILE_SIZE).ok_or(FsError::NoSpace)?;
+        let old_size = entry.size as u64;
+        let cluster_size = self.geometry.cluster_size as u64;
+        let old_allocated = self.chain(entry.cluster)?.len() as u64 * cluster_size;
+        let clusters = self.grow_chain(&entry, end)?;
+
+        // Bytes between old end of file and `offset` in already allocated clusters may hold stale data
+        if offset > old_size && old_size < old_allocated {
+            let zero_end = offset.min(old_allocated);
+            let zeroes = vec![0u8; (zero_end - old_size) as usize];
+            self.write_clusters(&clusters, old_size, &zeroes)?;
+        }
+
+        self.write_clusters(&clusters, offset, buf)?;
+        let first = clusters.first().copied().unwrap_or(0);
+        self.update_slot(location, first, end.max(old_size) as u32)?;
+        Ok(buf.len())
+    }
+
+    fn write_clusters(&self, clusters: &[u32], offset: u64, buf: &[u8]) -> Result<(), FsError> {
+        let cluster_size = self.geometry.cluster_size as u64;
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let cluster = *clusters.get((position / cluster_size) as usize).ok_or(FsError::Corrupted)?;
+            let in_cluster = position % cluster_size;
+            let count = ((cluster_size - in_cluster) as usize).min(buf.len() - done);
+            self.device.write_bytes(self.geometry.cluster_offset(cluster) + in_cluster, &buf[done..done + count])?;
+            done += count;
+        }
+
+        Ok(())
+    }
+
+    fn truncate_file(&self, location: Location, size: u64) -> Result<(), FsError> {
+        let entry = self.read_slot(location)?;
+        let old_size = entry.size as u64;
+
+        if size > MAX_FILE_SIZE {
+            return Err(FsError::NoSpace);
+        }
+
+        if size > old_size {
+            let old_allocated = self.chain(entry.cluster)?.len() as u64 * self.geometry.cluster_size as u64;
+            let clusters = self.grow_chain(&entry, size)?;
+
+            if old_size < old_allocated {
+                let zeroes = vec![0u8; (size.min(old_allocated) - old_size) as usize];
+                self.write_clusters(&clusters, old_size, &zeroes)?;
+            }
+
+            return self.update_slot(location, clusters.first().copied().unwrap_or(0), size as u32);
+        }
+
+        let clusters = self.chain(entry.cluster)?;
+        let keep = size.div_ceil(self.geometry.cluster_size as u64) as usize;
+
+        if keep < clusters.len() {
+            if keep > 0 {
+                self.write_fat(clusters[keep - 1], self.geometry.end_of_chain())?;
+            }
+
+            self.free_chain(&clusters[keep..])?;
+        }
+
+        self.update_slot(location, if keep == 0 { 0 } else { entry.cluster }, size as u32)
+    }
+
+    /// Inode of the entry at `location`, the one handed out before if it's still in use
+    fn inode(&self, location: Option<Location>, entry: Option<&RawEntry>) -> Result<Arc<dyn Inode>, FsError> {
+        let fs = self.this.upgrade().ok_or(FsError::Io)?;
+
+        let (Some(location), Some(entry)) = (location, entry) else {
+            return Ok(Arc::new(FatInode { fs, location: None, dir: Some(self.root_dir()), number: 1, removed: AtomicBool::new(false) }));
+        };
+
+        let mut inodes = self.inodes.lock();
+
+        if let Some(inode) = inodes.live.get(&location).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+
+        let dir = if entry.is_directory() { Some(Dir::Chain(entry.cluster)) } else { None };
+        let inode = Arc::new(FatInode { fs, location: Some(location), dir, number: inodes.number(location), removed: AtomicBool::new(false) });
+        inodes.live.insert(location, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    fn root_dir(&self) -> Dir {
+        match self.geometry.fat_type {
+            FatType::Fat32 => Dir::Chain(self.geometry.root_cluster),
+            _ => Dir::FixedRoot
+        }
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        "fat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.inode(None, None).expect("FAT filesystem dropped")
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        let _guard = self.lock.lock();
+        self.write_fsinfo()?;
+        self.device.flush()?;
+        Ok(())
+    }
+}
+
+pub struct FatInode {
+    fs: Arc<FatFs>,
+    /// `None` for root directory
+    location: Option<Location>,
+    /// `Some` for directories
+    dir: Option<Dir>,
+    number: u64,
+    /// Its entry was removed, the slot and clusters may belong to another file now
+    removed: AtomicBool
+}
+
+impl FatInode {
+    /// Fails once the entry is removed, checked with `FatFs::lock` held so it stays that way
+    fn check_removed(&self) -> Result<(), FsError> {
+        if self.removed.load(Ordering::Acquire) { Err(FsError::NotFound) } else { Ok(()) }
+    }
+
+    fn dir(&self) -> Result<Dir, FsError> {
+        self.check_removed()?;
+        self.dir.ok_or(FsError::NotDirectory)
+    }
+
+    fn file_location(&self) -> Result<Location, FsError> {
+        self.check_removed()?;
+
+        match (self.dir, self.location) {
+            (None, Some(location)) => Ok(location),
+            _ => Err(FsError::IsDirectory)
+        }
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        if let Some(location) = self.location {
+            let mut inodes = self.fs.inodes.lock();
+
+            if inodes.live.get(&location).is_some_and(|inode| inode.strong_count() == 0) {
+                inodes.live.remove(&location);
+            }
+        }
+    }
+}
+
+impl Inode for FatInode {
+    fn stat(&self) -> Result<Stat, FsError> {
+        let _guard = self.fs.lock.lock();
+        self.check_removed()?;
+
+        let Some(location) = self.location else {
+            let size = match self.fs.root_dir() {
+                Dir::FixedRoot => (self.fs.geometry.root_entries * ENTRY_SIZE) as u64,
+                Dir::Chain(start) => (self.fs.chain(start)?.len() * self.fs.geometry.cluster_size) as u64
+            };
+            return Ok(Stat::new(self.number, FileType::Directory, size));
+        };
+
+        let entry = self.fs.read_slot(location)?;
+        let mut stat = if entry.is_directory() {
+            Stat::new(self.number, FileType::Directory, (self.fs.chain(entry.cluster)?.len() * self.fs.geometry.cluster_size) as u64)
+        } else {
+            Stat::new(self.number, FileType::Regular, entry.size as u64)
+        };
+
+        if entry.attr & ATTR_READ_ONLY != 0 {
+            stat.mode &= 0o555;
+        }
+
+        stat.mtime = fat_time_to_unix(entry.write_date, entry.write_time);
+        stat.ctime = stat.mtime;
+        stat.atime = stat.mtime;
+        Ok(stat)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let _guard = self.fs.lock.lock();
+        let dir = self.dir()?;
+        let entry = self.fs.find(dir, name)?;
+        self.fs.inode(Some(Location { dir, slot: entry.slot }), Some(&entry))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let _guard = self.fs.lock.lock();
+        let location = self.file_location()?;
+        self.fs.read_file(location, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let _guard = self.fs.lock.lock();
+        let location = self.file_location()?;
+        self.fs.write_file(location, offset, buf)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        let _guard = self.fs.lock.lock();
+        let location = self.file_location()?;
+        self.fs.truncate_file(location, size)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let _guard = self.fs.lock.lock();
+        let dir = self.dir()?;
+        let mut entries = Vec::new();
+
+        for entry in parse_directory(&self.fs.read_directory(dir)?) {
+            if entry.is_dot() {
+                continue;
+            }
+
+            entries.push(DirEntry {
+                inode: self.fs.inodes.lock().number(Location { dir, slot: entry.slot }),
+                file_type: if entry.is_directory() { FileType::Directory } else { FileType::Regular },
+                name: entry.name
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        let _guard = self.fs.lock.lock();
+        let dir = self.dir()?;
+        let location = self.fs.create_entry(dir, name, file_type)?;
+        let entry = self.fs.read_slot(location)?;
+        self.fs.inode(Some(location), Some(&entry))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let _guard = self.fs.lock.lock();
+        let dir = self.dir()?;
+        self.fs.remove_entry(dir, name)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.fs.sync()
+    }
+}
@@ -0,0 +1,223 @@
+use core::ops::BitOr;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use crate::tty::Mode;
+
+use super::{page_cache::{self, FileId}, vfs::Dentry, DirEntry, FileType, FsError, Inode, Stat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags(1 << 0);
+    pub const WRITE: OpenFlags = OpenFlags(1 << 1);
+    pub const READ_WRITE: OpenFlags = OpenFlags(Self::READ.0 | Self::WRITE.0);
+    pub const CREATE: OpenFlags = OpenFlags(1 << 2);
+    pub const EXCLUSIVE: OpenFlags = OpenFlags(1 << 3);
+    pub const TRUNCATE: OpenFlags = OpenFlags(1 << 4);
+    pub const APPEND: OpenFlags = OpenFlags(1 << 5);
+    pub const DIRECTORY: OpenFlags = OpenFlags(1 << 6);
+    /// Reads and writes of pipes and devices fail with `WouldBlock` instead of waiting
+    pub const NONBLOCK: OpenFlags = OpenFlags(1 << 7);
+
+    pub fn contains(self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn readable(self) -> bool {
+        self.contains(OpenFlags::READ)
+    }
+
+    pub fn writable(self) -> bool {
+        self.contains(OpenFlags::WRITE) || self.contains(OpenFlags::APPEND)
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, rhs: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64)
+}
+
+/// Open file handle: inode plus position, shared by everyone holding the same handle. Reads and
+/// writes of regular files go through the page cache, other inodes are used directly.
+pub struct File {
+    path: String,
+    id: FileId,
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    /// Regular file, its contents are in the page cache
+    cached: bool,
+    offset: Mutex<u64>
+}
+
+impl File {
+    pub fn new(dentry: Dentry, id: FileId, flags: OpenFlags) -> File {
+        let cached = dentry.inode.stat().is_ok_and(|stat| stat.file_type == FileType::Regular);
+        File { path: dentry.path, id, inode: dentry.inode, flags, cached, offset: Mutex::new(0) }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.readable() {
+            return Err(FsError::PermissionDenied);
+        }
+
+        if !self.cached {
+            return self.read_uncached(buf);
+        }
+
+        let mut offset = self.offset.lock();
+        let read = page_cache::read(self.id, &self.inode, *offset, buf)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.writable() {
+            return Err(FsError::PermissionDenied);
+        }
+
+        if !self.cached {
+            return self.write_uncached(buf);
+        }
+
+        let mut offset = self.offset.lock();
+
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.inode.stat()?.size;
+        }
+
+        let written = page_cache::write(self.id, &self.inode, *offset, buf)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    /// Reads pipes and devices, which may block, so the position isn't locked meanwhile
+    fn read_uncached(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let offset = *self.offset.lock();
+
+        let read = if self.flags.contains(OpenFlags::NONBLOCK) {
+            self.inode.try_read_at(offset, buf)?
+        } else {
+            self.inode.read_at(offset, buf)?
+        };
+
+        *self.offset.lock() += read as u64;
+        Ok(read)
+    }
+
+    fn write_uncached(&self, buf: &[u8]) -> Result<usize, FsError> {
+        let offset = *self.offset.lock();
+
+        let written = if self.flags.contains(OpenFlags::NONBLOCK) {
+            self.inode.try_write_at(offset, buf)?
+        } else {
+            self.inode.write_at(offset, buf)?
+        };
+
+        *self.offset.lock() += written as u64;
+        Ok(written)
+    }
+
+    pub fn read_to_end(&self, data: &mut Vec<u8>) -> Result<usize, FsError> {
+        let mut chunk = [0u8; 512];
+        let mut total = 0;
+
+        loop {
+            let read = self.read(&mut chunk)?;
+
+            if read == 0 {
+                return Ok(total);
+            }
+
+            data.extend_from_slice(&chunk[..read]);
+            total += read;
+        }
+    }
+
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), FsError> {
+        while !data.is_empty() {
+            let written = self.write(data)?;
+
+            if written == 0 {
+                return Err(FsError::NoSpace);
+            }
+
+            data = &data[written..];
+        }
+
+        Ok(())
+    }
+
+    pub fn seek(&self, position: SeekFrom) -> Result<u64, FsError> {
+        let mut offset = self.offset.lock();
+        let new_offset = match position {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inode.stat()?.size.checked_add_signed(delta)
+        };
+
+        *offset = new_offset.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    pub fn stat(&self) -> Result<Stat, FsError> {
+        self.inode.stat()
+    }
+
+    pub fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.inode.readdir()
+    }
+
+    pub fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if !self.flags.writable() {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.inode.truncate(size)?;
+        page_cache::truncate(self.id, size);
+        Ok(())
+    }
+
+    pub fn terminal_mode(&self) -> Result<Mode, FsError> {
+        Ok(self.inode.terminal().ok_or(FsError::NotTerminal)?.mode())
+    }
+
+    /// Switches the terminal to `mode`, returns the previous one
+    pub fn set_terminal_mode(&self, mode: Mode) -> Result<Mode, FsError> {
+        Ok(self.inode.terminal().ok_or(FsError::NotTerminal)?.set_mode(mode))
+    }
+
+    /// Stores pages written through shared mappings, then syncs the inode
+    pub fn sync(&self) -> Result<(), FsError> {
+        page_cache::sync(self.id)?;
+        self.inode.sync()
+    }
+}
@@ -0,0 +1,212 @@
+use alloc::string::{String, ToString};
+
+use super::{path, vfs, FsError};
+
+/// Archive from `initramfs/` directory, packed as ustar by build script
+pub static EMBEDDED: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs.tar"));
+
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const TAR_BLOCK_SIZE: usize = 512;
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_REGULAR: u32 = 0o100000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+enum Entry<'a> {
+    Directory,
+    File(&'a [u8]),
+    Symlink(&'a str)
+}
+
+/// Unpacks cpio (newc) or ustar `archive` into existing directory `target`, creating missing
+/// parent directories. Device nodes and hard links are skipped. Returns number of created entries.
+pub fn unpack(archive: &[u8], target: &str) -> Result<usize, FsError> {
+    let mut created = 0;
+
+    walk(archive, |name, entry| {
+        if create(target, name, entry)? {
+            created += 1;
+        }
+
+        Ok(None::<()>)
+    })?;
+
+    Ok(created)
+}
+
+/// Contents of regular file `target` in `archive`, without unpacking it
+pub fn find<'a>(archive: &'a [u8], target: &str) -> Result<&'a [u8], FsError> {
+    let target = path::normalize(target);
+
+    walk(archive, |name, entry| match entry {
+        Entry::File(data) if path::normalize(name) == target => Ok(Some(data)),
+        _ => Ok(None)
+    })?.ok_or(FsError::NotFound)
+}
+
+/// Calls `visit` for entries of cpio or ustar `archive` until it returns `Some`
+fn walk<'a, R>(archive: &'a [u8], visit: impl FnMut(&str, Entry<'a>) -> Result<Option<R>, FsError>) -> Result<Option<R>, FsError> {
+    if archive.starts_with(b"070701") || archive.starts_with(b"070702") {
+        walk_cpio(archive, visit)
+    } else if archive.len() >= TAR_BLOCK_SIZE && (&archive[257..262] == b"ustar" || archive[..TAR_BLOCK_SIZE].iter().all(|&byte| byte == 0)) {
+        walk_tar(archive, visit)
+    } else {
+        Err(FsError::InvalidArgument)
+    }
+}
+
+fn parse_number(field: &[u8], radix: u32) -> Result<usize, FsError> {
+    let text = core::str::from_utf8(field).map_err(|_| FsError::Corrupted)?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+
+    if text.is_empty() {
+        return Ok(0);
+    }
+
+    usize::from_str_radix(text, radix).map_err(|_| FsError::Corrupted)
+}
+
+fn field(archive: &[u8], start: usize, length: usize) -> Result<&[u8], FsError> {
+    archive.get(start..start + length).ok_or(FsError::Corrupted)
+}
+
+fn walk_cpio<'a, R>(archive: &'a [u8], mut visit: impl FnMut(&str, Entry<'a>) -> Result<Option<R>, FsError>) -> Result<Option<R>, FsError> {
+    let mut offset = 0;
+
+    loop {
+        let header = field(archive, offset, CPIO_HEADER_SIZE)?;
+
+        if &header[..5] != b"07070" {
+            return Err(FsError::Corrupted);
+        }
+
+        let number = |index: usize| parse_number(&header[6 + index * 8..14 + index * 8], 16);
+        let mode = number(1)? as u32;
+        let file_size = number(6)?;
+        let name_size = number(11)?;
+
+        let name = field(archive, offset + CPIO_HEADER_SIZE, name_size)?;
+        let name = core::str::from_utf8(name).map_err(|_| FsError::Corrupted)?.trim_end_matches('\0');
+        let data_start = (offset + CPIO_HEADER_SIZE + name_size).next_multiple_of(4);
+        let data = field(archive, data_start, file_size)?;
+        offset = (data_start + file_size).next_multiple_of(4);
+
+        if name == CPIO_TRAILER {
+            return Ok(None);
+        }
+
+        let entry = match mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => Entry::Directory,
+            MODE_REGULAR => Entry::File(data),
+            MODE_SYMLINK => Entry::Symlink(core::str::from_utf8(data).map_err(|_| FsError::Corrupted)?),
+            _ => continue
+        };
+
+        if let Some(result) = visit(name, entry)? {
+            return Ok(Some(result));
+        }
+    }
+}
+
+fn walk_tar<'a, R>(archive: &'a [u8], mut visit: impl FnMut(&str, Entry<'a>) -> Result<Option<R>, FsError>) -> Result<Option<R>, FsError> {
+    let mut offset = 0;
+    let mut long_name: Option<String> = None;
+
+    while offset + TAR_BLOCK_SIZE <= archive.len() {
+        let header = &archive[offset..offset + TAR_BLOCK_SIZE];
+
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+
+        let checksum = parse_number(&header[148..156], 8)?;
+        let sum: usize = header.iter().enumerate().map(|(i, &byte)| if (148..156).contains(&i) { b' ' as usize } else { byte as usize }).sum();
+
+        if checksum != sum {
+            return Err(FsError::Corrupted);
+        }
+
+        let text = |start: usize, length: usize| {
+            let bytes = &header[start..start + length];
+            let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(length);
+            core::str::from_utf8(&bytes[..end]).map_err(|_| FsError::Corrupted)
+        };
+
+        let size = parse_number(&header[124..136], 8)?;
+        let data = field(archive, offset + TAR_BLOCK_SIZE, size)?;
+        offset += TAR_BLOCK_SIZE + size.next_multiple_of(TAR_BLOCK_SIZE);
+
+        let name = match long_name.take() {
+            Some(name) => name,
+            None if text(345, 155)?.is_empty() => text(0, 100)?.to_string(),
+            None => path::join(text(345, 155)?, text(0, 100)?)
+        };
+
+        let entry = match header[156] {
+            b'0' | b'\0' | b'7' => Entry::File(data),
+            b'5' => Entry::Directory,
+            b'2' => Entry::Symlink(text(157, 100)?),
+            // GNU long name: data is the name of the next entry
+            b'L' => {
+                long_name = Some(core::str::from_utf8(data).map_err(|_| FsError::Corrupted)?.trim_end_matches('\0').to_string());
+                continue;
+            },
+            _ => continue
+        };
+
+        if let Some(result) = visit(&name, entry)? {
+            return Ok(Some(result));
+        }
+    }
+
+    Ok(None)
+}
+
+fn create_directories(directory: &str) -> Result<(), FsError> {
+    let mut current = String::from("/");
+
+    for component in path::components(directory) {
+        current = path::join(&current, component);
+
+        match vfs::mkdir(&current) {
+            Ok(()) | Err(FsError::AlreadyExists) => {},
+            Err(error) => return Err(error)
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates single archive entry, returns false if entry was skipped
+fn create(target: &str, name: &str, entry: Entry) -> Result<bool, FsError> {
+    let name = path::normalize(name);
+
+    if name == "/" {
+        return Ok(false);
+    }
+
+    let full_path = path::join(target, &name[1..]);
+    let (parent, _) = path::split_last(&full_path).ok_or(FsError::InvalidPath)?;
+
+    match entry {
+        Entry::Directory => create_directories(&full_path)?,
+        Entry::File(data) => {
+            create_directories(parent)?;
+            vfs::write_all(&full_path, data)?;
+        },
+        Entry::Symlink(link_target) => {
+            create_directories(parent)?;
+
+            match vfs::unlink(&full_path) {
+                Ok(()) | Err(FsError::NotFound) => {},
+                Err(error) => return Err(error)
+            }
+
+            vfs::symlink(link_target, &full_path)?;
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,167 @@
+pub mod path;
+pub mod file;
+pub mod vfs;
+pub mod fat;
+pub mod ext2;
+pub mod tmpfs;
+pub mod initramfs;
+pub mod page_cache;
+pub mod pipe;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use crate::{block::BlockError, tty::Tty};
+
+pub use file::{File, OpenFlags, SeekFrom};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotDirectory,
+    IsDirectory,
+    AlreadyExists,
+    NotEmpty,
+    InvalidPath,
+    NameTooLong,
+    TooManyLinks,
+    ReadOnly,
+    NoSpace,
+    Busy,
+    PermissionDenied,
+    InvalidArgument,
+    Unsupported,
+    Corrupted,
+    Io,
+    /// Non-blocking read or write would have to wait
+    WouldBlock,
+    /// Write to a pipe nobody reads from
+    BrokenPipe,
+    /// Blocking read or write gave up because a signal arrived
+    Interrupted,
+    /// Terminal operation on a file that isn't a terminal
+    NotTerminal
+}
+
+impl From<BlockError> for FsError {
+    fn from(_: BlockError) -> Self {
+        FsError::Io
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub inode: u64,
+    pub file_type: FileType,
+    /// Permission bits (`0o755`), without file type bits
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub blocks: u64,
+    /// Seconds since Unix epoch, 0 if filesystem doesn't track it
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64
+}
+
+impl Stat {
+    pub fn new(inode: u64, file_type: FileType, size: u64) -> Stat {
+        let mode = if file_type == FileType::Directory { 0o755 } else { 0o644 };
+        Stat { inode, file_type, mode, nlink: 1, uid: 0, gid: 0, size, blocks: size.div_ceil(512), atime: 0, mtime: 0, ctime: 0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType
+}
+
+/// Single file, directory or symlink of a filesystem. Directory operations
+/// return `NotDirectory` on other inode types and vice versa.
+pub trait Inode: Send + Sync {
+    fn stat(&self) -> Result<Stat, FsError>;
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    /// Like `read_at`, but devices and pipes return `WouldBlock` instead of waiting for data
+    fn try_read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.read_at(offset, buf)
+    }
+
+    /// Like `write_at`, but devices and pipes return `WouldBlock` instead of waiting for room
+    fn try_write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.write_at(offset, buf)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Creates regular file or directory `name` in this directory
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.create(name, FileType::Directory)
+    }
+
+    /// Removes file, symlink or empty directory `name` from this directory
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    /// Line discipline of terminal devices
+    fn terminal(&self) -> Option<&Tty> {
+        None
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Page cache: contents of regular files in frames, keyed by file and page index. `File` reads go
+//! through it and writes go to the inode and to cached pages, so memory mapped files, which map
+//! the cached frames directly, see the same data as `read`. Pages written through shared
+//! mappings are dirty and get stored by `sync` or before they're evicted.
+//!
+//! Pages that nothing maps are evicted least recently used first when free memory runs low.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::slice;
+
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+use super::{FileType, FsError, Inode};
+use crate::memory::{self, Backing, MapError};
+
+const PAGE_SIZE: u64 = 4096;
+/// Pages are evicted before loading another one while fewer frames than this are free
+const LOW_WATERMARK: usize = 256;
+
+/// File across all mounted filesystems
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId {
+    /// Address of the filesystem
+    pub fs: usize,
+    pub inode: u64
+}
+
+struct Page {
+    frame: PhysFrame,
+    /// Written through a shared mapping since it was last stored
+    dirty: bool,
+    /// Value of `PageCache::clock` when it was last used
+    used: u64
+}
+
+struct CachedFile {
+    inode: Arc<dyn Inode>,
+    pages: BTreeMap<u64, Page>
+}
+
+struct PageCache {
+    files: BTreeMap<FileId, CachedFile>,
+    clock: u64
+}
+
+impl PageCache {
+    /// Frame of page `index` of `inode`, read from it unless it's cached
+    fn page(&mut self, id: FileId, inode: &Arc<dyn Inode>, index: u64) -> Result<&mut Page, FsError> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if !self.files.get(&id).is_some_and(|file| file.pages.contains_key(&index)) {
+            while memory::frames_free() < LOW_WATERMARK && self.evict() {}
+            let frame = match memory::allocate_frame() {
+                Some(frame) => frame,
+                None if self.evict() => memory::allocate_frame().ok_or(FsError::NoSpace)?,
+                None => return Err(FsError::NoSpace)
+            };
+
+            let bytes = unsafe { frame_bytes(frame) };
+
+            let read = match inode.read_at(index * PAGE_SIZE, bytes) {
+                Ok(read) => read,
+                Err(error) => {
+                    unsafe { memory::release_frame(frame) };
+                    return Err(error);
+                }
+            };
+
+            bytes[read..].fill(0);
+            let file = self.files.entry(id).or_insert_with(|| CachedFile { inode: inode.clone(), pages: BTreeMap::new() });
+            file.pages.insert(index, Page { frame, dirty: false, used: clock });
+        }
+
+        let page = self.files.get_mut(&id).and_then(|file| file.pages.get_mut(&index)).expect("Page was just cached");
+        page.used = clock;
+        Ok(page)
+    }
+
+    /// Frees the least recently used page that nothing maps, storing it first if it's dirty.
+    /// False if there's none.
+    fn evict(&mut self) -> bool {
+        let unmapped = self.files.iter().flat_map(|(&id, file)| file.pages.iter().map(move |(&index, page)| (id, index, page)));
+        let oldest = unmapped.filter(|(_, _, page)| memory::frame_references(page.frame) == 1).min_by_key(|(_, _, page)| page.used);
+
+        let Some((id, index, _)) = oldest else {
+            return false;
+        };
+
+        let file = self.files.get_mut(&id).expect("Evicted file is cached");
+        let page = file.pages.remove(&index).expect("Evicted page is cached");
+
+        // Write errors lose the data, like on a failing disk
+        if page.dirty {
+            let _ = write_back(&file.inode, index, page.frame);
+        }
+
+        if file.pages.is_empty() {
+            self.files.remove(&id);
+        }
+
+        unsafe { memory::release_frame(page.frame) };
+        true
+    }
+
+    /// Stores dirty pages of `id`. Pages stay dirty while something maps them, they may be
+    /// written again.
+    fn sync(&mut self, id: FileId) -> Result<(), FsError> {
+        let Some(file) = self.files.get_mut(&id) else {
+            return Ok(());
+        };
+
+        for (&index, page) in file.pages.iter_mut().filter(|(_, page)| page.dirty) {
+            write_back(&file.inode, index, page.frame)?;
+            page.dirty = memory::frame_references(page.frame) > 1;
+        }
+
+        Ok(())
+    }
+
+    /// Drops cached pages of `id`, mappings keep their frames
+    fn forget(&mut self, id: FileId) {
+        for page in self.files.remove(&id).into_iter().flat_map(|file| file.pages.into_values()) {
+            unsafe { memory::release_frame(page.frame) };
+        }
+    }
+}
+
+static CACHE: Mutex<PageCache> = Mutex::new(PageCache { files: BTreeMap::new(), clock: 0 });
+
+/// Contents of `frame` through the physical memory mapping
+unsafe fn frame_bytes(frame: PhysFrame) -> &'static mut [u8] {
+    slice::from_raw_parts_mut(memory::physical_to_virtual(frame.start_address()).as_mut_ptr(), PAGE_SIZE as usize)
+}
+
+/// Stores page `index` to `inode`, without the part past the end of the file
+fn write_back(inode: &Arc<dyn Inode>, index: u64, frame: PhysFrame) -> Result<(), FsError> {
+    let size = inode.stat()?.size;
+    let start = index * PAGE_SIZE;
+
+    if start >= size {
+        return Ok(());
+    }
+
+    let length = (size - start).min(PAGE_SIZE) as usize;
+    let bytes = unsafe { &frame_bytes(frame)[..length] };
+    inode.write_at(start, bytes)?;
+    Ok(())
+}
+
+/// Reads from file `id` at `offset` through the cache. Other inode types are read directly.
+pub fn read(id: FileId, inode: &Arc<dyn Inode>, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+    let stat = inode.stat()?;
+
+    if stat.file_type != FileType::Regular {
+        return inode.read_at(offset, buf);
+    }
+
+    let length = stat.size.saturating_sub(offset).min(buf.len() as u64) as usize;
+    let mut cache = CACHE.lock();
+    let mut done = 0;
+
+    while done < length {
+        let position = offset + done as u64;
+        let start = (position % PAGE_SIZE) as usize;
+        let chunk = (PAGE_SIZE as usize - start).min(length - done);
+        let page = cache.page(id, inode, position / PAGE_SIZE)?;
+        buf[done..done + chunk].copy_from_slice(unsafe { &frame_bytes(page.frame)[start..start + chunk] });
+        done += chunk;
+    }
+
+    Ok(length)
+}
+
+/// Writes to the inode of file `id` at `offset` and updates pages of it that are cached
+pub fn write(id: FileId, inode: &Arc<dyn Inode>, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+    let mut cache = CACHE.lock();
+    let written = inode.write_at(offset, buf)?;
+
+    let Some(file) = cache.files.get_mut(&id) else {
+        return Ok(written);
+    };
+
+    let (first, end) = (offset / PAGE_SIZE, (offset + written as u64).div_ceil(PAGE_SIZE));
+
+    for (&index, page) in file.pages.range_mut(first..end) {
+        let start = (index * PAGE_SIZE).max(offset);
+        let stop = ((index + 1) * PAGE_SIZE).min(offset + written as u64);
+        let bytes = unsafe { frame_bytes(page.frame) };
+        bytes[(start % PAGE_SIZE) as usize..(start % PAGE_SIZE + stop - start) as usize].copy_from_slice(&buf[(start - offset) as usize..(stop - offset) as usize]);
+    }
+
+    Ok(written)
+}
+
+/// Drops cached pages of file `id` past `size` and zeroes the end of the last one, after the
+/// inode was truncated
+pub fn truncate(id: FileId, size: u64) {
+    let mut cache = CACHE.lock();
+
+    let Some(file) = cache.files.get_mut(&id) else {
+        return;
+    };
+
+    for page in file.pages.split_off(&size.div_ceil(PAGE_SIZE)).into_values() {
+        unsafe { memory::release_frame(page.frame) };
+    }
+
+    if let Some(page) = file.pages.get(&(size / PAGE_SIZE)) {
+        unsafe { frame_bytes(page.frame)[(size % PAGE_SIZE) as usize..].fill(0) };
+    }
+}
+
+/// Stores pages of file `id` written through shared mappings
+pub fn sync(id: FileId) -> Result<(), FsError> {
+    CACHE.lock().sync(id)
+}
+
+/// Stores dirty pages of every file
+pub fn sync_all() -> Result<(), FsError> {
+    let mut cache = CACHE.lock();
+    let files: Vec<FileId> = cache.files.keys().copied().collect();
+
+    for id in files {
+        cache.sync(id)?;
+    }
+
+    Ok(())
+}
+
+/// Drops cached pages of file `id` without storing them, after it was removed
+pub fn forget(id: FileId) {
+    CACHE.lock().forget(id);
+}
+
+/// Stores and drops cached pages of every file on filesystem `fs`, before it's unmounted
+pub fn forget_fs(fs: usize) -> Result<(), FsError> {
+    let mut cache = CACHE.lock();
+    let files: Vec<FileId> = cache.files.keys().filter(|id| id.fs == fs).copied().collect();
+
+    for id in files {
+        cache.sync(id)?;
+        cache.forget(id);
+    }
+
+    Ok(())
+}
+
+/// Evicts up to `count` pages that nothing maps, returns how many were evicted
+pub fn shrink(count: usize) -> usize {
+    let mut cache = CACHE.lock();
+    (0..count).take_while(|_| cache.evict()).count()
+}
+
+/// Number of cached pages
+pub fn cached_pages() -> usize {
+    CACHE.lock().files.values().map(|file| file.pages.len()).sum()
+}
+
+/// File as a `Backing` for `AddressSpace::map_backed`, mappings get the cached frames
+pub struct MappedFile {
+    id: FileId,
+    inode: Arc<dyn Inode>
+}
+
+impl MappedFile {
+    pub fn new(id: FileId, inode: Arc<dyn Inode>) -> Arc<MappedFile> {
+        Arc::new(MappedFile { id, inode })
+    }
+}
+
+impl Backing for MappedFile {
+    fn frame(&self, index: u64, writable: bool) -> Result<PhysFrame, MapError> {
+        if index * PAGE_SIZE >= self.inode.stat().map_err(|_| MapError::Io)?.size {
+            return Err(MapError::NotMapped);
+        }
+
+        let mut cache = CACHE.lock();
+
+        let page = cache.page(self.id, &self.inode, index).map_err(|error| match error {
+            FsError::NoSpace => MapError::OutOfMemory,
+            _ => MapError::Io
+        })?;
+
+        page.dirty |= writable;
+        memory::share_frame(page.frame);
+        Ok(page.frame)
+    }
+
+    fn write_back(&self, index: u64) -> Result<(), MapError> {
+        let cache = CACHE.lock();
+
+        match cache.files.get(&self.id).and_then(|file| file.pages.get(&index)) {
+            Some(page) => write_back(&self.inode, index, page.frame).map_err(|_| MapError::Io),
+            None => Ok(())
+        }
+    }
+}
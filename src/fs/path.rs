@@ -0,0 +1,78 @@
+use alloc::{string::String, vec::Vec};
+
+pub const MAX_NAME_LENGTH: usize = 255;
+
+pub fn is_absolute(path: &str) -> bool {
+    path.starts_with('/')
+}
+
+/// Splits path into non-empty components, `.` and `..` are kept
+pub fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|component| !component.is_empty())
+}
+
+/// Lexically normalizes absolute path: removes `.`, empty components and resolves `..`
+/// without following symlinks. Relative paths are treated as relative to `/`.
+pub fn normalize(path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+
+    for component in components(path) {
+        match component {
+            "." => {},
+            ".." => { stack.pop(); },
+            name => stack.push(name)
+        }
+    }
+
+    join_components(stack.iter().copied())
+}
+
+pub fn join_components<'a>(components: impl Iterator<Item = &'a str>) -> String {
+    let mut result = String::new();
+
+    for component in components {
+        result.push('/');
+        result.push_str(component);
+    }
+
+    if result.is_empty() {
+        result.push('/');
+    }
+
+    result
+}
+
+/// Joins `path` to `base` unless `path` is absolute, result is not normalized
+pub fn join(base: &str, path: &str) -> String {
+    if is_absolute(path) {
+        return String::from(path);
+    }
+
+    let mut result = String::from(base);
+
+    if !result.ends_with('/') {
+        result.push('/');
+    }
+
+    result.push_str(path);
+    result
+}
+
+/// Splits path into parent and last component: "/a/b" -> ("/a", "b"), "b" -> (".", "b")
+pub fn split_last(path: &str) -> Option<(&str, &str)> {
+    let trimmed = path.trim_end_matches('/');
+    let name_start = trimmed.rfind('/').map_or(0, |index| index + 1);
+    let name = &trimmed[name_start..];
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let parent = match trimmed[..name_start].trim_end_matches('/') {
+        "" if is_absolute(path) => "/",
+        "" => ".",
+        parent => parent
+    };
+
+    Some((parent, name))
+}
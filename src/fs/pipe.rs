@@ -0,0 +1,198 @@
+//! Pipes: a buffer with a read end and a write end, each an inode of its own so the ends are
+//! closed separately. Reads wait for data and return 0 once every write end is gone, writes wait
+//! for room and fail with `BrokenPipe` once every read end is gone. `OpenFlags::NONBLOCK` files
+//! get `WouldBlock` instead of waiting, a signal for the process makes waiting ones give up with
+//! `Interrupted`.
+
+use alloc::{collections::VecDeque, format, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use super::{file::{File, OpenFlags}, page_cache::FileId, vfs::Dentry, FileType, FsError, Inode, Stat};
+use crate::{process::signal, thread::{self, ThreadId}};
+
+/// Bytes a pipe holds before writers wait
+pub const PIPE_SIZE: usize = 4096;
+
+/// Pipes have no filesystem, their ids only tell them apart
+static NEXT_PIPE: AtomicU64 = AtomicU64::new(1);
+
+struct Buffer {
+    bytes: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// Threads waiting for data or room, all are woken on any change
+    waiting: Vec<ThreadId>
+}
+
+impl Buffer {
+    fn wake(&mut self) {
+        for thread in self.waiting.drain(..) {
+            thread::unpark(thread);
+        }
+    }
+}
+
+struct Pipe {
+    number: u64,
+    buffer: Mutex<Buffer>
+}
+
+impl Pipe {
+    fn read(&self, buf: &mut [u8], block: bool) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            {
+                let mut buffer = self.buffer.lock();
+
+                if !buffer.bytes.is_empty() {
+                    let count = buf.len().min(buffer.bytes.len());
+
+                    for (byte, value) in buf.iter_mut().zip(buffer.bytes.drain(..count)) {
+                        *byte = value;
+                    }
+
+                    buffer.wake();
+                    return Ok(count);
+                }
+
+                if buffer.writers == 0 {
+                    return Ok(0);
+                }
+
+                if !block {
+                    return Err(FsError::WouldBlock);
+                }
+
+                buffer.waiting.push(thread::current().expect("Threads are not initialized"));
+            }
+
+            // Writes in between leave an unpark token, so this doesn't miss them
+            thread::park();
+
+            if signal::interrupted() {
+                return Err(FsError::Interrupted);
+            }
+        }
+    }
+
+    /// Writes as much of `buf` as fits, waiting only while the pipe is full
+    fn write(&self, buf: &[u8], block: bool) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            {
+                let mut buffer = self.buffer.lock();
+
+                if buffer.readers == 0 {
+                    return Err(FsError::BrokenPipe);
+                }
+
+                let room = PIPE_SIZE - buffer.bytes.len();
+
+                if room > 0 {
+                    let count = buf.len().min(room);
+                    buffer.bytes.extend(&buf[..count]);
+                    buffer.wake();
+                    return Ok(count);
+                }
+
+                if !block {
+                    return Err(FsError::WouldBlock);
+                }
+
+                buffer.waiting.push(thread::current().expect("Threads are not initialized"));
+            }
+
+            thread::park();
+
+            if signal::interrupted() {
+                return Err(FsError::Interrupted);
+            }
+        }
+    }
+}
+
+/// One end of a pipe, dropping the last inode of an end closes it
+struct End {
+    pipe: Arc<Pipe>,
+    writer: bool
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        let mut buffer = self.pipe.buffer.lock();
+
+        if self.writer {
+            buffer.writers -= 1;
+        } else {
+            buffer.readers -= 1;
+        }
+
+        buffer.wake();
+    }
+}
+
+impl Inode for End {
+    fn stat(&self) -> Result<Stat, FsError> {
+        let size = self.pipe.buffer.lock().bytes.len() as u64;
+        Ok(Stat::new(self.pipe.number, FileType::Fifo, size))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.writer {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.pipe.read(buf, true)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.writer {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.pipe.write(buf, true)
+    }
+
+    fn try_read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.writer {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.pipe.read(buf, false)
+    }
+
+    fn try_write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.writer {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.pipe.write(buf, false)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::InvalidArgument)
+    }
+}
+
+/// New pipe as its read end and write end. Only `OpenFlags::NONBLOCK` of `flags` is used.
+pub fn pipe(flags: OpenFlags) -> (File, File) {
+    let number = NEXT_PIPE.fetch_add(1, Ordering::Relaxed);
+    let buffer = Buffer { bytes: VecDeque::new(), readers: 1, writers: 1, waiting: Vec::new() };
+    let pipe = Arc::new(Pipe { number, buffer: Mutex::new(buffer) });
+    let nonblock = OpenFlags(flags.0 & OpenFlags::NONBLOCK.0);
+
+    let end = |writer: bool, access: OpenFlags| {
+        let dentry = Dentry { path: format!("pipe:[{number}]"), inode: Arc::new(End { pipe: pipe.clone(), writer }) };
+        File::new(dentry, FileId { fs: 0, inode: number }, access | nonblock)
+    };
+
+    (end(false, OpenFlags::READ), end(true, OpenFlags::WRITE))
+}
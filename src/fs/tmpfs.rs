@@ -0,0 +1,224 @@
+use alloc::{collections::BTreeMap, string::{String, ToString}, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::{path, DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+
+enum Content {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String)
+}
+
+struct TmpData {
+    content: Content,
+    nlink: u32
+}
+
+/// Filesystem living entirely in the kernel heap, contents are lost on reboot
+pub struct TmpFs {
+    root: Arc<TmpInode>
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<TmpFs> {
+        let next_inode = Arc::new(AtomicU64::new(2));
+        let root = TmpInode::new(&next_inode, Content::Directory(BTreeMap::new()));
+        Arc::new(TmpFs { root })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+pub struct TmpInode {
+    number: u64,
+    next_inode: Arc<AtomicU64>,
+    data: Mutex<TmpData>
+}
+
+impl TmpInode {
+    fn new(next_inode: &Arc<AtomicU64>, content: Content) -> Arc<TmpInode> {
+        let nlink = if let Content::Directory(_) = content { 2 } else { 1 };
+
+        Arc::new(TmpInode {
+            number: next_inode.fetch_add(1, Ordering::Relaxed),
+            next_inode: next_inode.clone(),
+            data: Mutex::new(TmpData { content, nlink })
+        })
+    }
+
+    fn insert(&self, name: &str, content: Content) -> Result<Arc<TmpInode>, FsError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+
+        if name.len() > path::MAX_NAME_LENGTH {
+            return Err(FsError::NameTooLong);
+        }
+
+        let mut data = self.data.lock();
+        let directory = matches!(content, Content::Directory(_));
+
+        let Content::Directory(entries) = &mut data.content else {
+            return Err(FsError::NotDirectory);
+        };
+
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let inode = TmpInode::new(&self.next_inode, content);
+        entries.insert(name.to_string(), inode.clone());
+
+        if directory {
+            data.nlink += 1;
+        }
+
+        Ok(inode)
+    }
+}
+
+impl Inode for TmpInode {
+    fn stat(&self) -> Result<Stat, FsError> {
+        let data = self.data.lock();
+        let (file_type, size) = match &data.content {
+            Content::File(bytes) => (FileType::Regular, bytes.len() as u64),
+            Content::Directory(entries) => (FileType::Directory, entries.len() as u64),
+            Content::Symlink(target) => (FileType::Symlink, target.len() as u64)
+        };
+
+        let mut stat = Stat::new(self.number, file_type, size);
+        stat.nlink = data.nlink;
+
+        if file_type == FileType::Symlink {
+            stat.mode = 0o777;
+        }
+
+        Ok(stat)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match &self.data.lock().content {
+            Content::Directory(entries) => entries.get(name).map(|inode| inode.clone() as Arc<dyn Inode>).ok_or(FsError::NotFound),
+            _ => Err(FsError::NotDirectory)
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match &self.data.lock().content {
+            Content::File(bytes) => {
+                if offset >= bytes.len() as u64 {
+                    return Ok(0);
+                }
+
+                let count = buf.len().min(bytes.len() - offset as usize);
+                buf[..count].copy_from_slice(&bytes[offset as usize..offset as usize + count]);
+                Ok(count)
+            },
+            Content::Directory(_) => Err(FsError::IsDirectory),
+            Content::Symlink(_) => Err(FsError::InvalidArgument)
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        match &mut self.data.lock().content {
+            Content::File(bytes) => {
+                let end = usize::try_from(offset).ok().and_then(|offset| offset.checked_add(buf.len())).ok_or(FsError::NoSpace)?;
+
+                if end > bytes.len() {
+                    bytes.try_reserve(end - bytes.len()).map_err(|_| FsError::NoSpace)?;
+                    bytes.resize(end, 0);
+                }
+
+                bytes[offset as usize..end].copy_from_slice(buf);
+                Ok(buf.len())
+            },
+            Content::Directory(_) => Err(FsError::IsDirectory),
+            Content::Symlink(_) => Err(FsError::InvalidArgument)
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        match &mut self.data.lock().content {
+            Content::File(bytes) => {
+                let size = usize::try_from(size).map_err(|_| FsError::NoSpace)?;
+
+                if size > bytes.len() {
+                    bytes.try_reserve(size - bytes.len()).map_err(|_| FsError::NoSpace)?;
+                }
+
+                bytes.resize(size, 0);
+                Ok(())
+            },
+            Content::Directory(_) => Err(FsError::IsDirectory),
+            Content::Symlink(_) => Err(FsError::InvalidArgument)
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let Content::Directory(entries) = &self.data.lock().content else {
+            return Err(FsError::NotDirectory);
+        };
+
+        // Locks are always taken parent first, so locking children here can't deadlock
+        entries.iter().map(|(name, inode)| {
+            let file_type = inode.stat()?.file_type;
+            Ok(DirEntry { name: name.clone(), inode: inode.number, file_type })
+        }).collect()
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        let content = match file_type {
+            FileType::Regular => Content::File(Vec::new()),
+            FileType::Directory => Content::Directory(BTreeMap::new()),
+            _ => return Err(FsError::Unsupported)
+        };
+
+        Ok(self.insert(name, content)?)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let mut data = self.data.lock();
+
+        let Content::Directory(entries) = &mut data.content else {
+            return Err(FsError::NotDirectory);
+        };
+
+        let inode = entries.get(name).ok_or(FsError::NotFound)?.clone();
+        let mut child = inode.data.lock();
+
+        let directory = match &child.content {
+            Content::Directory(children) if !children.is_empty() => return Err(FsError::NotEmpty),
+            Content::Directory(_) => true,
+            _ => false
+        };
+
+        entries.remove(name);
+        child.nlink = 0;
+
+        if directory {
+            data.nlink -= 1;
+        }
+
+        Ok(())
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Ok(self.insert(name, Content::Symlink(target.to_string()))?)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        match &self.data.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument)
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use alloc::{collections::VecDeque, string::{String, ToString}, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use super::{page_cache::{self, FileId}, path, DirEntry, File, FileSystem, FileType, FsError, Inode, OpenFlags, Stat};
+
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>
+}
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+static CWD: Mutex<String> = Mutex::new(String::new());
+
+/// Resolved path: canonical absolute path (no `.`, `..` or symlinks) and inode it points to
+#[derive(Clone)]
+pub struct Dentry {
+    pub path: String,
+    pub inode: Arc<dyn Inode>
+}
+
+fn mounted_at(path: &str) -> Option<Arc<dyn FileSystem>> {
+    MOUNTS.lock().iter().rev().find(|mount| mount.path == path).map(|mount| mount.fs.clone())
+}
+
+/// Identifies a filesystem in `FileId`
+fn fs_id(fs: &Arc<dyn FileSystem>) -> usize {
+    Arc::as_ptr(fs) as *const () as usize
+}
+
+/// Page cache key of canonical `dentry`, its filesystem is the innermost mount containing it
+fn file_id(dentry: &Dentry) -> Result<FileId, FsError> {
+    let mounts = MOUNTS.lock();
+    let contains = |mount: &&Mount| mount.path == "/" || dentry.path == mount.path || dentry.path.strip_prefix(mount.path.as_str()).is_some_and(|rest| rest.starts_with('/'));
+    let fs = mounts.iter().filter(contains).max_by_key(|mount| mount.path.len()).map(|mount| fs_id(&mount.fs)).ok_or(FsError::NotFound)?;
+    drop(mounts);
+    Ok(FileId { fs, inode: dentry.inode.stat()?.inode })
+}
+
+/// Mounts `fs` at absolute `mount_path`. Everything except `/` must be mounted over an existing directory.
+pub fn mount(mount_path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    if !path::is_absolute(mount_path) {
+        return Err(FsError::InvalidPath);
+    }
+
+    let mount_path = if mount_path == "/" {
+        String::from("/")
+    } else {
+        let dentry = lookup(mount_path)?;
+
+        if dentry.inode.stat()?.file_type != FileType::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        dentry.path
+    };
+
+    let mut mounts = MOUNTS.lock();
+
+    if mounts.iter().any(|mount| mount.path == mount_path) {
+        return Err(FsError::Busy);
+    }
+
+    mounts.push(Mount { path: mount_path, fs });
+    Ok(())
+}
+
+pub fn unmount(mount_path: &str) -> Result<Arc<dyn FileSystem>, FsError> {
+    let mount_path = path::normalize(mount_path);
+    let mut mounts = MOUNTS.lock();
+    let index = mounts.iter().position(|mount| mount.path == mount_path).ok_or(FsError::InvalidArgument)?;
+    let nested = mounts.iter().any(|mount| mount.path != mount_path && mount.path.starts_with(&mount_path) && (mount_path == "/" || mount.path.as_bytes()[mount_path.len()] == b'/'));
+
+    if nested {
+        return Err(FsError::Busy);
+    }
+
+    let fs = mounts.remove(index).fs;
+    page_cache::forget_fs(fs_id(&fs))?;
+    fs.sync()?;
+    Ok(fs)
+}
+
+/// Returns (mount path, filesystem name) pairs
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS.lock().iter().map(|mount| (mount.path.clone(), mount.fs.name())).collect()
+}
+
+pub fn cwd() -> String {
+    let cwd = CWD.lock();
+
+    if cwd.is_empty() {
+        String::from("/")
+    } else {
+        cwd.clone()
+    }
+}
+
+pub fn chdir(new_path: &str) -> Result<(), FsError> {
+    let dentry = lookup(new_path)?;
+
+    if dentry.inode.stat()?.file_type != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+
+    *CWD.lock() = dentry.path;
+    Ok(())
+}
+
+fn root() -> Result<Arc<dyn Inode>, FsError> {
+    mounted_at("/").map(|fs| fs.root()).ok_or(FsError::NotFound)
+}
+
+/// Walks `target` starting from directory `base` (absolute, canonical), following symlinks in
+/// the middle of the path and, if `follow_last` is set, in the last component too
+pub fn resolve_at(base: &str, target: &str, follow_last: bool) -> Result<Dentry, FsError> {
+    if target.is_empty() {
+        return Err(FsError::InvalidPath);
+    }
+
+    let root = root()?;
+    let mut stack: Vec<(String, Arc<dyn Inode>)> = Vec::new();
+    let mut pending: VecDeque<String> = VecDeque::new();
+    let mut symlinks = 0;
+
+    if !path::is_absolute(target) {
+        pending.extend(path::components(base).map(|component| component.to_string()));
+    }
+
+    pending.extend(path::components(target).map(|component| component.to_string()));
+    let trailing_slash = target.ends_with('/');
+
+    while let Some(component) = pending.pop_front() {
+        let current = stack.last().map_or(&root, |(_, inode)| inode).clone();
+
+        match component.as_str() {
+            "." => continue,
+            ".." => {
+                stack.pop();
+                continue;
+            },
+            _ => {}
+        }
+
+        if component.len() > path::MAX_NAME_LENGTH {
+            return Err(FsError::NameTooLong);
+        }
+
+        let mut inode = current.lookup(&component)?;
+        let full_path = path::join_components(stack.iter().map(|(name, _)| name.as_str()).chain([component.as_str()]));
+
+        if let Some(fs) = mounted_at(&full_path) {
+            inode = fs.root();
+        }
+
+        let is_last = pending.is_empty();
+
+        if (!is_last || follow_last || trailing_slash) && inode.stat()?.file_type == FileType::Symlink {
+            symlinks += 1;
+
+            if symlinks > MAX_SYMLINK_DEPTH {
+                return Err(FsError::TooManyLinks);
+            }
+
+            let link = inode.readlink()?;
+
+            if path::is_absolute(&link) {
+                stack.clear();
+            }
+
+            for component in path::components(&link).rev() {
+                pending.push_front(component.to_string());
+            }
+
+            continue;
+        }
+
+        stack.push((component, inode));
+    }
+
+    let inode = stack.last().map_or(&root, |(_, inode)| inode).clone();
+
+    if trailing_slash && inode.stat()?.file_type != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+
+    Ok(Dentry { path: path::join_components(stack.iter().map(|(name, _)| name.as_str())), inode })
+}
+
+pub fn lookup(target: &str) -> Result<Dentry, FsError> {
+    resolve_at(&cwd(), target, true)
+}
+
+/// Resolves parent directory of `target`, returning it with the last component name
+fn lookup_parent(target: &str) -> Result<(Dentry, String), FsError> {
+    let (parent, name) = path::split_last(target).ok_or(FsError::InvalidPath)?;
+
+    if name == "." || name == ".." {
+        return Err(FsError::InvalidPath);
+    }
+
+    if name.len() > path::MAX_NAME_LENGTH {
+        return Err(FsError::NameTooLong);
+    }
+
+    let dentry = lookup(parent)?;
+
+    if dentry.inode.stat()?.file_type != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+
+    Ok((dentry, name.to_string()))
+}
+
+pub fn open(target: &str, flags: OpenFlags) -> Result<File, FsError> {
+    let dentry = match lookup(target) {
+        Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => return Err(FsError::AlreadyExists),
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = lookup_parent(target)?;
+            let inode = parent.inode.create(&name, FileType::Regular)?;
+            Dentry { path: path::join(&parent.path, &name), inode }
+        },
+        Err(error) => return Err(error)
+    };
+
+    let file_type = dentry.inode.stat()?.file_type;
+
+    if file_type == FileType::Directory && (flags.writable() || flags.contains(OpenFlags::TRUNCATE)) {
+        return Err(FsError::IsDirectory);
+    }
+
+    if flags.contains(OpenFlags::DIRECTORY) && file_type != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+
+    let id = file_id(&dentry)?;
+
+    if flags.contains(OpenFlags::TRUNCATE) && flags.writable() {
+        dentry.inode.truncate(0)?;
+        page_cache::truncate(id, 0);
+    }
+
+    Ok(File::new(dentry, id, flags))
+}
+
+pub fn stat(target: &str) -> Result<Stat, FsError> {
+    lookup(target)?.inode.stat()
+}
+
+/// Like `stat`, but doesn't follow symlink in the last component
+pub fn lstat(target: &str) -> Result<Stat, FsError> {
+    resolve_at(&cwd(), target, false)?.inode.stat()
+}
+
+pub fn readdir(target: &str) -> Result<Vec<DirEntry>, FsError> {
+    lookup(target)?.inode.readdir()
+}
+
+pub fn mkdir(target: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(target)?;
+    parent.inode.mkdir(&name).map(|_| ())
+}
+
+pub fn unlink(target: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(target)?;
+    let full_path = path::join(&parent.path, &name);
+
+    if MOUNTS.lock().iter().any(|mount| mount.path == full_path) {
+        return Err(FsError::Busy);
+    }
+
+    // Inode number may be reused for a new file, its cached pages must not show up there
+    let id = file_id(&resolve_at(&parent.path, &name, false)?)?;
+    parent.inode.unlink(&name)?;
+    page_cache::forget(id);
+    Ok(())
+}
+
+pub fn symlink(target: &str, link_path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(link_path)?;
+    parent.inode.symlink(&name, target).map(|_| ())
+}
+
+pub fn readlink(target: &str) -> Result<String, FsError> {
+    resolve_at(&cwd(), target, false)?.inode.readlink()
+}
+
+/// Reads whole file into memory
+pub fn read_to_end(target: &str) -> Result<Vec<u8>, FsError> {
+    let file = open(target, OpenFlags::READ)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+pub fn write_all(target: &str, data: &[u8]) -> Result<(), FsError> {
+    let file = open(target, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)?;
+    file.write_all(data)
+}
+
+pub fn sync() -> Result<(), FsError> {
+    page_cache::sync_all()?;
+    let filesystems: Vec<Arc<dyn FileSystem>> = MOUNTS.lock().iter().map(|mount| mount.fs.clone()).collect();
+
+    for fs in filesystems {
+        fs.sync()?;
+    }
+
+    Ok(())
+}
@@ -1,52 +1,99 @@
+use alloc::{boxed::Box, vec};
+use core::ptr::{self, addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicPtr, Ordering};
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 
 use lazy_static::lazy_static;
 use x86_64::instructions::tables::load_tss;
-use x86_64::instructions::segmentation::{CS, DS, Segment};
+use x86_64::instructions::segmentation::{CS, DS, SS, Segment};
 use x86_64::structures::gdt::GlobalDescriptorTable;
 use x86_64::structures::gdt::Descriptor;
 use x86_64::structures::gdt::SegmentSelector;
 
+use crate::smp::{self, MAX_CPUS};
+
 pub const IST_INDEX: u16 = 0; // [0; 7]
+const IST_STACK_SIZE: usize = 4096 * 5;
 
-lazy_static! {
-    static ref STATIC_TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK }); // TODO: Make stack allocation
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
-}
+static mut BSP_TSS: TaskStateSegment = TaskStateSegment::new();
+static mut BSP_IST_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+
+/// TSS of each processor by `smp::cpu_index`, its kernel stack changes with the running thread
+static TSS: [AtomicPtr<TaskStateSegment>; MAX_CPUS] = [const { AtomicPtr::new(ptr::null_mut()) }; MAX_CPUS];
 
-struct GdtSelectors {
-    kernel_code_selector: SegmentSelector,
-    kernel_data_selector: SegmentSelector,
+/// User selectors that follow from the order in `create_gdt`, for code that can't call `selectors`
+pub const USER_DATA_SELECTOR: u16 = 0x1B;
+pub const USER_CODE_SELECTOR: u16 = 0x23;
+
+/// Segments are in the order `sysret` expects: user data right before user code
+pub struct GdtSelectors {
+    pub kernel_code_selector: SegmentSelector,
+    pub kernel_data_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
     tss_selector: SegmentSelector
 }
 
+fn create_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, GdtSelectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let kernel_code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let kernel_data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+    debug_assert_eq!((user_data_selector.0, user_code_selector.0), (USER_DATA_SELECTOR, USER_CODE_SELECTOR));
+    (gdt, GdtSelectors { kernel_code_selector, kernel_data_selector, user_data_selector, user_code_selector, tss_selector })
+}
+
 lazy_static! {
     static ref STATIC_GDT: (GlobalDescriptorTable, GdtSelectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let kernel_code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let kernel_data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&STATIC_TSS));
-        (gdt, GdtSelectors { kernel_code_selector, kernel_data_selector, tss_selector })
+        let (tss, stack) = unsafe { (&mut *addr_of_mut!(BSP_TSS), &*addr_of!(BSP_IST_STACK)) };
+        tss.interrupt_stack_table[IST_INDEX as usize] = VirtAddr::from_ptr(stack) + IST_STACK_SIZE;
+        create_gdt(tss)
     };
 }
 
-pub fn init() {
-    STATIC_GDT.0.load();
+fn load(gdt: &'static (GlobalDescriptorTable, GdtSelectors), tss: *mut TaskStateSegment) {
+    gdt.0.load();
 
     unsafe {
-        CS::set_reg(STATIC_GDT.1.kernel_code_selector);
-        DS::set_reg(STATIC_GDT.1.kernel_data_selector);
-        load_tss(STATIC_GDT.1.tss_selector);
+        CS::set_reg(gdt.1.kernel_code_selector);
+        DS::set_reg(gdt.1.kernel_data_selector);
+        SS::set_reg(gdt.1.kernel_data_selector);
+        load_tss(gdt.1.tss_selector);
     }
+
+    TSS[smp::cpu_index()].store(tss, Ordering::Release);
+}
+
+/// Loads GDT and TSS of the bootstrap processor
+pub fn init() {
+    load(&STATIC_GDT, unsafe { &mut *addr_of_mut!(BSP_TSS) });
+}
+
+/// Loads GDT for an application processor. TSS can't be shared, because it is marked busy when
+/// loaded, so every processor gets its own along with a double fault stack. Needs heap.
+pub fn init_ap() {
+    let stack = Box::leak(vec![0u8; IST_STACK_SIZE].into_boxed_slice());
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[IST_INDEX as usize] = VirtAddr::from_ptr(stack.as_ptr()) + IST_STACK_SIZE;
+    let tss = Box::leak(Box::new(tss));
+    let tss_ptr = tss as *mut TaskStateSegment;
+    load(Box::leak(Box::new(create_gdt(tss))), tss_ptr);
+}
+
+/// Selectors are the same on every processor
+pub fn selectors() -> &'static GdtSelectors {
+    &STATIC_GDT.1
+}
+
+/// Sets stack the calling processor switches to on interrupts and system calls from ring 3
+pub fn set_kernel_stack(top: VirtAddr) {
+    let tss = TSS[smp::cpu_index()].load(Ordering::Acquire);
+    assert!(!tss.is_null(), "TSS of this processor is not loaded");
+
+    // TSS is packed, so the field may be unaligned
+    unsafe { addr_of_mut!((*tss).privilege_stack_table[0]).write_unaligned(top) };
+    smp::current().set_kernel_stack(top);
 }
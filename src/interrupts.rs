@@ -1,21 +1,27 @@
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
+use x86_64::PrivilegeLevel;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use x86_64::instructions::port::Port;
 use x86_64::registers::control::Cr2;
 use crate::task::keyboard::add_scancode;
-use crate::{gdt, println};
-use spin::Mutex;
+use crate::{console, gdt, memory, println, serial, smp::apic, sync::SpinLock, syscall, thread, timer, usermode};
+
+mod user;
 
 pub const PIC1_OFFSET: u8 = 32;
 pub const PIC2_OFFSET: u8 = 32 + 8;
-pub static PICS_MUTEX: Mutex<ChainedPics> = Mutex::new(unsafe {ChainedPics::new(PIC1_OFFSET, PIC2_OFFSET)});
+pub static PICS_MUTEX: SpinLock<ChainedPics> = SpinLock::new(unsafe {ChainedPics::new(PIC1_OFFSET, PIC2_OFFSET)});
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum HardwareInterrupt {
     Timer = PIC1_OFFSET,
-    Keyboard
+    Keyboard,
+    /// `COM2` and `COM4`
+    Com2 = PIC1_OFFSET + 3,
+    /// `COM1` and `COM3`
+    Com1
 }
 
 impl HardwareInterrupt {
@@ -32,10 +38,26 @@ lazy_static! {
     static ref STATIC_IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(on_breakpoint);
+        idt.divide_error.set_handler_fn(on_divide_error);
+        idt.invalid_opcode.set_handler_fn(on_invalid_opcode);
+        idt.stack_segment_fault.set_handler_fn(on_stack_segment_fault);
+        idt.general_protection_fault.set_handler_fn(on_general_protection_fault);
         idt.page_fault.set_handler_fn(on_page_fault);
         unsafe { idt.double_fault.set_handler_fn(on_double_fault).set_stack_index(gdt::IST_INDEX); }
         idt[HardwareInterrupt::Timer.to_usize()].set_handler_fn(on_hardware_timer);
         idt[HardwareInterrupt::Keyboard.to_usize()].set_handler_fn(on_hardware_keyboard);
+        idt[HardwareInterrupt::Com1.to_usize()].set_handler_fn(on_hardware_com1);
+        idt[HardwareInterrupt::Com2.to_usize()].set_handler_fn(on_hardware_com2);
+        idt[apic::TIMER_VECTOR as usize].set_handler_fn(on_apic_timer);
+        idt[apic::RESCHEDULE_VECTOR as usize].set_handler_fn(on_reschedule);
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(on_spurious);
+        // Vectors user code may raise or be interrupted by save its registers first
+        user::install(&mut idt);
+
+        unsafe {
+            // Ring 3 may raise it with `int`
+            idt[syscall::INTERRUPT_VECTOR as usize].set_handler_addr(syscall::interrupt_entry()).set_privilege_level(PrivilegeLevel::Ring3);
+        }
 
         idt
     };
@@ -45,26 +67,91 @@ extern "x86-interrupt" fn on_breakpoint(stack_frame: InterruptStackFrame) {
     println!("Breakpoint: {:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn on_divide_error(stack_frame: InterruptStackFrame) {
+    panic!("Divide error: {:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn on_invalid_opcode(stack_frame: InterruptStackFrame) {
+    panic!("Invalid opcode: {:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn on_stack_segment_fault(stack_frame: InterruptStackFrame, code: u64) {
+    panic!("Stack segment fault ({}): {:#?}", code, stack_frame);
+}
+
+extern "x86-interrupt" fn on_general_protection_fault(stack_frame: InterruptStackFrame, code: u64) {
+    panic!("General protection fault ({}): {:#?}", code, stack_frame);
+}
+
+/// Only for ring 0, see `user` for faults of user code
 extern "x86-interrupt" fn on_page_fault(stack_frame: InterruptStackFrame, code: PageFaultErrorCode) {
+    // Writes to copy-on-write pages fault in ring 0 too, kernel writes user memory for system calls
+    if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::PROTECTION_VIOLATION) && memory::copy_on_write(Cr2::read()) {
+        return;
+    }
+
     println!("Page fault {:?}: {:#?}\nAccessed address: {:?}", code, stack_frame, Cr2::read());
 }
 
 extern "x86-interrupt" fn on_double_fault(stack_frame: InterruptStackFrame, code: u64) -> ! {
+    let _gs = usermode::KernelGs::enter(&stack_frame);
     panic!("Double fault ({}): {:#?}", code, stack_frame);
 }
 
 extern "x86-interrupt" fn on_hardware_timer(_stack_frame: InterruptStackFrame) {
+    hardware_timer();
+}
+
+fn hardware_timer() {
+    let now = timer::tick();
     unsafe { PICS_MUTEX.lock().notify_end_of_interrupt(HardwareInterrupt::Timer.to_u8()); }
+    // May switch to another thread, this handler then finishes when the current thread is resumed
+    thread::on_timer_tick(now);
 }
 
-extern "x86-interrupt" fn on_hardware_keyboard(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn on_hardware_keyboard(stack_frame: InterruptStackFrame) {
+    let _gs = usermode::KernelGs::enter(&stack_frame);
     let mut port: Port<u8> = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     add_scancode(scancode);
+    console::add_scancode(scancode);
     // keyboard::handle_key_press(&mut port);
     unsafe { PICS_MUTEX.lock().notify_end_of_interrupt(HardwareInterrupt::Keyboard.to_u8()); }
 }
 
+extern "x86-interrupt" fn on_hardware_com1(stack_frame: InterruptStackFrame) {
+    serial_interrupt(&stack_frame, HardwareInterrupt::Com1);
+}
+
+extern "x86-interrupt" fn on_hardware_com2(stack_frame: InterruptStackFrame) {
+    serial_interrupt(&stack_frame, HardwareInterrupt::Com2);
+}
+
+fn serial_interrupt(stack_frame: &InterruptStackFrame, interrupt: HardwareInterrupt) {
+    let _gs = usermode::KernelGs::enter(stack_frame);
+    serial::on_interrupt(interrupt.to_u8() - PIC1_OFFSET);
+    unsafe { PICS_MUTEX.lock().notify_end_of_interrupt(interrupt.to_u8()); }
+}
+
+/// Timer of application processors, bootstrap processor counts ticks with PIT
+extern "x86-interrupt" fn on_apic_timer(_stack_frame: InterruptStackFrame) {
+    apic_timer();
+}
+
+fn apic_timer() {
+    apic::end_of_interrupt();
+    thread::on_timer_tick(timer::ticks());
+}
+
+/// Wakes idle processor from `hlt`, its idle loop then picks up ready threads
+extern "x86-interrupt" fn on_reschedule(stack_frame: InterruptStackFrame) {
+    let _gs = usermode::KernelGs::enter(&stack_frame);
+    apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn on_spurious(_stack_frame: InterruptStackFrame) {}
+
+/// Loads interrupt table, it is the same for all processors
 pub fn init_idt() {
     STATIC_IDT.load();
 }
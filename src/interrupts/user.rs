@@ -0,0 +1,187 @@
+//! Entries of exceptions and timer interrupts that save every user register as `Registers`,
+//! like the system call entries, so signals can be acted on before ring 3 continues. Faults of
+//! user code raise signals here. From ring 0 the entries go on to the `x86-interrupt` handlers
+//! as if the CPU had called those.
+
+use core::{arch::global_asm, fmt};
+
+use x86_64::{
+    instructions::interrupts,
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, PageFaultErrorCode},
+    VirtAddr
+};
+
+use super::HardwareInterrupt;
+use crate::{memory, println, process::{self, signal::{self, Signal}}, smp::apic, syscall::Registers, usermode};
+
+const DIVIDE_ERROR: u64 = 0;
+const INVALID_OPCODE: u64 = 6;
+const STACK_SEGMENT_FAULT: u64 = 12;
+const GENERAL_PROTECTION_FAULT: u64 = 13;
+const PAGE_FAULT: u64 = 14;
+const TIMER: u64 = HardwareInterrupt::Timer as u64;
+const APIC_TIMER: u64 = apic::TIMER_VECTOR as u64;
+
+// The stubs leave the vector and an error code, 0 if the CPU pushes none, below the interrupt
+// frame. The common part swaps them for `r14` and `r15` and saves the other registers below, so
+// the stack holds `Registers` like after `int 0x80`, 16 byte aligned for the call.
+global_asm!(
+    "ruin_user_interrupt_common:",
+    "swapgs",
+    "xchg [rsp + 8], r15",
+    "xchg [rsp], r14",
+    "push r13",
+    "push r12",
+    "push rbp",
+    "push rbx",
+    "push r11",
+    "push rcx",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "cld",
+    "mov rdi, rsp",
+    "mov rsi, r14",
+    "mov rdx, r15",
+    "call ruin_user_interrupt",
+    "cli",
+    "pop rax",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop rcx",
+    "pop r11",
+    "pop rbx",
+    "pop rbp",
+    "pop r12",
+    "pop r13",
+    "pop r14",
+    "pop r15",
+    "swapgs",
+    "iretq"
+);
+
+/// Entry `$name` of interrupt `$vector`, ring 0 continues in `$kernel`
+macro_rules! entry {
+    ($name:literal, $vector:expr, $kernel:path) => {
+        global_asm!(
+            concat!($name, ":"),
+            "test qword ptr [rsp + 8], 3",
+            "jz {kernel}",
+            "push 0",
+            "push {vector}",
+            "jmp ruin_user_interrupt_common",
+            vector = const $vector,
+            kernel = sym $kernel
+        );
+    };
+    ($name:literal, $vector:expr, $kernel:path, error_code) => {
+        global_asm!(
+            concat!($name, ":"),
+            "test qword ptr [rsp + 16], 3",
+            "jz {kernel}",
+            "push {vector}",
+            "jmp ruin_user_interrupt_common",
+            vector = const $vector,
+            kernel = sym $kernel
+        );
+    };
+}
+
+entry!("ruin_divide_error_entry", DIVIDE_ERROR, super::on_divide_error);
+entry!("ruin_invalid_opcode_entry", INVALID_OPCODE, super::on_invalid_opcode);
+entry!("ruin_stack_segment_fault_entry", STACK_SEGMENT_FAULT, super::on_stack_segment_fault, error_code);
+entry!("ruin_general_protection_fault_entry", GENERAL_PROTECTION_FAULT, super::on_general_protection_fault, error_code);
+entry!("ruin_page_fault_entry", PAGE_FAULT, super::on_page_fault, error_code);
+entry!("ruin_timer_entry", TIMER, super::on_hardware_timer);
+entry!("ruin_apic_timer_entry", APIC_TIMER, super::on_apic_timer);
+
+extern "C" {
+    fn ruin_divide_error_entry();
+    fn ruin_invalid_opcode_entry();
+    fn ruin_stack_segment_fault_entry();
+    fn ruin_general_protection_fault_entry();
+    fn ruin_page_fault_entry();
+    fn ruin_timer_entry();
+    fn ruin_apic_timer_entry();
+}
+
+fn address(entry: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(entry as *const () as u64)
+}
+
+/// Points the vectors that have entries here to them
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.divide_error.set_handler_addr(address(ruin_divide_error_entry));
+        idt.invalid_opcode.set_handler_addr(address(ruin_invalid_opcode_entry));
+        idt.stack_segment_fault.set_handler_addr(address(ruin_stack_segment_fault_entry));
+        idt.general_protection_fault.set_handler_addr(address(ruin_general_protection_fault_entry));
+        idt.page_fault.set_handler_addr(address(ruin_page_fault_entry));
+        idt[TIMER as usize].set_handler_addr(address(ruin_timer_entry));
+        idt[APIC_TIMER as usize].set_handler_addr(address(ruin_apic_timer_entry));
+    }
+}
+
+/// Interrupt `vector` of ring 3 with error code `code`, called with interrupts disabled. Pending
+/// signals are acted on afterwards, which may change `registers`.
+#[no_mangle]
+extern "C" fn ruin_user_interrupt(registers: &mut Registers, vector: u64, code: u64) {
+    match vector {
+        TIMER => super::hardware_timer(),
+        APIC_TIMER => super::apic_timer(),
+        PAGE_FAULT => page_fault(registers, PageFaultErrorCode::from_bits_truncate(code)),
+        DIVIDE_ERROR => fault(registers, Signal::FPE, format_args!("Divide error")),
+        INVALID_OPCODE => fault(registers, Signal::ILL, format_args!("Invalid opcode")),
+        STACK_SEGMENT_FAULT => fault(registers, Signal::BUS, format_args!("Stack segment fault ({})", code)),
+        _ => fault(registers, Signal::SEGV, format_args!("General protection fault ({})", code))
+    }
+
+    interrupts::enable();
+    signal::deliver(registers);
+    interrupts::disable();
+}
+
+fn page_fault(registers: &Registers, code: PageFaultErrorCode) {
+    let address = Cr2::read();
+
+    if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::PROTECTION_VIOLATION) && memory::copy_on_write(address) {
+        return;
+    }
+
+    // Pages of mapped files are loaded on first touch, which may wait for the disk like a system
+    // call does
+    if !code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        if let Some(address_space) = process::address_space() {
+            interrupts::enable();
+            let loaded = address_space.fault(address, code.contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+            interrupts::disable();
+
+            if loaded {
+                return;
+            }
+        }
+    }
+
+    fault(registers, Signal::SEGV, format_args!("Page fault {:?} accessing {:?}", code, address));
+}
+
+/// Raises `signal` in the process of the faulting code, threads that are not processes end
+fn fault(registers: &Registers, signal: Signal, fault: fmt::Arguments) {
+    let instruction_pointer = VirtAddr::new(registers.rip);
+
+    let Some(pid) = process::current() else {
+        usermode::kill(fault, instruction_pointer);
+    };
+
+    println!("User fault: {} at {:?}, process {:?} gets {:?}", fault, instruction_pointer, pid, signal);
+    signal::fault(signal);
+}
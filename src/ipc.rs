@@ -0,0 +1,361 @@
+//! Channels for message passing. A channel has two endpoints, a message sent on one is received
+//! on the other in order. Messages carry up to `MESSAGE_DATA` bytes, up to `MESSAGE_HANDLES`
+//! endpoints that move to the receiver, and a buffer of whole pages the receiver maps instead of
+//! copying. Processes hold endpoints by handle, see `process::handles`, so an endpoint is a
+//! capability: only processes that were given it can use it.
+//!
+//! Sends wait while the peer has `QUEUE_SIZE` messages queued, receives wait for a message. `call`
+//! attaches a fresh reply endpoint to the message and waits for the answer on its peer. Waiting
+//! threads give up when their process gets a signal, kernel tasks use the `async` versions.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{future::Future, mem, pin::Pin, task::{Context, Poll, Waker}};
+
+use spin::Mutex;
+
+use crate::{memory::shared::SharedMemory, process::signal, thread::{self, ThreadId}};
+
+/// Most bytes a message carries inline
+pub const MESSAGE_DATA: usize = 256;
+/// Most endpoints a message carries, besides the reply endpoint of `call`
+pub const MESSAGE_HANDLES: usize = 4;
+/// Largest page buffer of a message in bytes
+pub const MESSAGE_BUFFER: u64 = 1 << 20;
+/// Messages queued for an endpoint before senders wait
+pub const QUEUE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// Other endpoint is gone. Receives get it once nothing is queued anymore.
+    PeerClosed,
+    /// Call would have to wait and waiting wasn't asked for
+    WouldBlock,
+    /// Call waited and gave up because a signal arrived
+    Interrupted,
+    /// Message is over the limits, or over what the receiver has room for
+    TooLarge,
+    /// An endpoint can't be sent over its own channel
+    InvalidHandle
+}
+
+#[derive(Default)]
+pub struct Message {
+    pub data: Vec<u8>,
+    /// Endpoints that move to the receiver
+    pub handles: Vec<Arc<Endpoint>>,
+    pub buffer: Option<Arc<SharedMemory>>,
+    /// Endpoint to answer a `call` on
+    pub reply: Option<Arc<Endpoint>>
+}
+
+impl Message {
+    /// Message with just `data`
+    pub fn new(data: &[u8]) -> Message {
+        Message { data: Vec::from(data), ..Message::default() }
+    }
+
+    fn check(&self, endpoint: &Endpoint) -> Result<(), IpcError> {
+        if self.data.len() > MESSAGE_DATA || self.handles.len() > MESSAGE_HANDLES || self.buffer.as_ref().is_some_and(|buffer| buffer.size() > MESSAGE_BUFFER) {
+            return Err(IpcError::TooLarge);
+        }
+
+        // The channel would keep itself alive
+        if self.handles.iter().any(|handle| Arc::ptr_eq(&handle.channel, &endpoint.channel)) {
+            return Err(IpcError::InvalidHandle);
+        }
+
+        Ok(())
+    }
+}
+
+/// What to wake when a queue changes
+enum Waiter<'a> {
+    Thread(ThreadId),
+    Task(&'a Waker)
+}
+
+/// Messages for one endpoint
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<Message>,
+    /// Endpoint that sends here is gone
+    sender_closed: bool,
+    /// Endpoint that receives from here is gone
+    receiver_closed: bool,
+    threads: Vec<ThreadId>,
+    tasks: Vec<Waker>
+}
+
+impl Queue {
+    fn wait(&mut self, waiter: Waiter) {
+        match waiter {
+            Waiter::Thread(thread) if !self.threads.contains(&thread) => self.threads.push(thread),
+            Waiter::Task(waker) if !self.tasks.iter().any(|task| task.will_wake(waker)) => self.tasks.push(waker.clone()),
+            _ => {}
+        }
+    }
+
+    /// Wakes everything waiting for the queue to change
+    fn wake(&mut self) {
+        for thread in self.threads.drain(..) {
+            thread::unpark(thread);
+        }
+
+        for task in self.tasks.drain(..) {
+            task.wake();
+        }
+    }
+
+    /// Whether a receive wouldn't wait
+    fn readable(&self) -> bool {
+        !self.messages.is_empty() || self.sender_closed
+    }
+}
+
+struct Channel {
+    /// Messages for endpoint 0 and for endpoint 1
+    queues: [Mutex<Queue>; 2]
+}
+
+/// One end of a channel, dropping it closes the end
+pub struct Endpoint {
+    channel: Arc<Channel>,
+    side: usize
+}
+
+/// New channel as its two endpoints
+pub fn channel() -> (Endpoint, Endpoint) {
+    let channel = Arc::new(Channel { queues: [Mutex::new(Queue::default()), Mutex::new(Queue::default())] });
+    (Endpoint { channel: channel.clone(), side: 0 }, Endpoint { channel, side: 1 })
+}
+
+impl Endpoint {
+    /// Queue of messages received here
+    fn inbox(&self) -> &Mutex<Queue> {
+        &self.channel.queues[self.side]
+    }
+
+    /// Queue of messages sent from here
+    fn outbox(&self) -> &Mutex<Queue> {
+        &self.channel.queues[1 - self.side]
+    }
+
+    /// Queues `message` for the peer. When the peer's queue is full `waiter` is woken once it
+    /// may have room, the message comes back with `WouldBlock` then.
+    fn start_send(&self, message: Message, waiter: Option<Waiter>) -> Result<(), (IpcError, Message)> {
+        if let Err(error) = message.check(self) {
+            return Err((error, message));
+        }
+
+        let mut outbox = self.outbox().lock();
+
+        if outbox.receiver_closed {
+            return Err((IpcError::PeerClosed, message));
+        }
+
+        if outbox.messages.len() >= QUEUE_SIZE {
+            if let Some(waiter) = waiter {
+                outbox.wait(waiter);
+            }
+
+            return Err((IpcError::WouldBlock, message));
+        }
+
+        outbox.messages.push_back(message);
+        outbox.wake();
+        Ok(())
+    }
+
+    /// Takes the next message if `fits` accepts it, which leaves it queued otherwise. When there's
+    /// none `waiter` is woken once one may have arrived.
+    fn start_receive(&self, waiter: Option<Waiter>, fits: &dyn Fn(&Message) -> bool) -> Result<Message, IpcError> {
+        let mut inbox = self.inbox().lock();
+
+        if inbox.messages.front().is_some_and(|message| !fits(message)) {
+            return Err(IpcError::TooLarge);
+        }
+
+        if let Some(message) = inbox.messages.pop_front() {
+            // Senders may wait for room
+            inbox.wake();
+            return Ok(message);
+        }
+
+        if inbox.sender_closed {
+            return Err(IpcError::PeerClosed);
+        }
+
+        if let Some(waiter) = waiter {
+            inbox.wait(waiter);
+        }
+
+        Err(IpcError::WouldBlock)
+    }
+
+    /// Sends `message`, waiting while the peer's queue is full unless `block` is false. Gives
+    /// the message back if it's not sent, so handles in it aren't lost.
+    pub fn send_or_return(&self, mut message: Message, block: bool) -> Result<(), (IpcError, Message)> {
+        let waiter = || block.then(|| Waiter::Thread(thread::current().expect("Threads are not initialized")));
+
+        loop {
+            match self.start_send(message, waiter()) {
+                Err((IpcError::WouldBlock, returned)) if block => message = returned,
+                result => return result
+            }
+
+            // Receives in between leave an unpark token, so this doesn't miss them
+            thread::park();
+
+            if signal::interrupted() {
+                return Err((IpcError::Interrupted, message));
+            }
+        }
+    }
+
+    /// Sends `message`, waiting while the peer's queue is full
+    pub fn send(&self, message: Message) -> Result<(), IpcError> {
+        self.send_or_return(message, true).map_err(|(error, _)| error)
+    }
+
+    /// Sends `message` if the peer's queue has room, `WouldBlock` otherwise
+    pub fn try_send(&self, message: Message) -> Result<(), IpcError> {
+        self.send_or_return(message, false).map_err(|(error, _)| error)
+    }
+
+    /// Receives the next message if `fits` accepts it, waiting for one unless `block` is false
+    pub fn receive_if(&self, block: bool, fits: &dyn Fn(&Message) -> bool) -> Result<Message, IpcError> {
+        loop {
+            let waiter = block.then(|| Waiter::Thread(thread::current().expect("Threads are not initialized")));
+
+            match self.start_receive(waiter, fits) {
+                Err(IpcError::WouldBlock) if block => {}
+                result => return result
+            }
+
+            thread::park();
+
+            if signal::interrupted() {
+                return Err(IpcError::Interrupted);
+            }
+        }
+    }
+
+    /// Receives the next message, waiting for one
+    pub fn receive(&self) -> Result<Message, IpcError> {
+        self.receive_if(true, &|_| true)
+    }
+
+    /// Receives the next message if there is one, `WouldBlock` otherwise
+    pub fn try_receive(&self) -> Result<Message, IpcError> {
+        self.receive_if(false, &|_| true)
+    }
+
+    /// Sends `message` with a reply endpoint and waits for the answer to it
+    pub fn call(&self, message: Message) -> Result<Message, IpcError> {
+        let (reply, answer) = channel();
+        self.send(Message { reply: Some(Arc::new(reply)), ..message })?;
+        answer.receive()
+    }
+
+    /// Whether a receive wouldn't wait, a message is queued or the peer is gone
+    pub fn readable(&self) -> bool {
+        self.inbox().lock().readable()
+    }
+
+    /// Future that sends `message` once the peer's queue has room
+    pub fn send_async(&self, message: Message) -> SendFuture<'_> {
+        SendFuture { endpoint: self, message: Some(message) }
+    }
+
+    /// Future of the next message
+    pub fn receive_async(&self) -> ReceiveFuture<'_> {
+        ReceiveFuture { endpoint: self }
+    }
+
+    /// Like `call` without blocking the thread
+    pub async fn call_async(&self, message: Message) -> Result<Message, IpcError> {
+        let (reply, answer) = channel();
+        self.send_async(Message { reply: Some(Arc::new(reply)), ..message }).await?;
+        answer.receive_async().await
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let unread = {
+            let mut inbox = self.inbox().lock();
+            inbox.receiver_closed = true;
+            inbox.wake();
+            mem::take(&mut inbox.messages)
+        };
+
+        let mut outbox = self.outbox().lock();
+        outbox.sender_closed = true;
+        outbox.wake();
+        drop(outbox);
+
+        // Endpoints in unread messages lock their channels when they're dropped
+        drop(unread);
+    }
+}
+
+pub struct SendFuture<'a> {
+    endpoint: &'a Endpoint,
+    message: Option<Message>
+}
+
+impl Future for SendFuture<'_> {
+    type Output = Result<(), IpcError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let message = self.message.take().expect("Send polled after it finished");
+
+        match self.endpoint.start_send(message, Some(Waiter::Task(context.waker()))) {
+            Err((IpcError::WouldBlock, message)) => {
+                self.message = Some(message);
+                Poll::Pending
+            }
+            result => Poll::Ready(result.map_err(|(error, _)| error))
+        }
+    }
+}
+
+pub struct ReceiveFuture<'a> {
+    endpoint: &'a Endpoint
+}
+
+impl Future for ReceiveFuture<'_> {
+    type Output = Result<Message, IpcError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        match self.endpoint.start_receive(Some(Waiter::Task(context.waker())), &|_| true) {
+            Err(IpcError::WouldBlock) => Poll::Pending,
+            result => Poll::Ready(result)
+        }
+    }
+}
+
+/// Waits until one of `endpoints` is readable and returns its index, the lowest if several are.
+/// Never returns for no endpoints unless a signal arrives.
+pub fn wait_any(endpoints: &[&Endpoint]) -> Result<usize, IpcError> {
+    let current = thread::current().expect("Threads are not initialized");
+
+    loop {
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            let mut inbox = endpoint.inbox().lock();
+
+            if inbox.readable() {
+                return Ok(index);
+            }
+
+            inbox.wait(Waiter::Thread(current));
+        }
+
+        // Endpoints that stay quiet keep the thread in their lists, it's only woken for nothing
+        thread::park();
+
+        if signal::interrupted() {
+            return Err(IpcError::Interrupted);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use lazy_static::lazy_static;
 use crate::{println, task::keyboard::ScancodeStream};
-use spin::{Mutex, MutexGuard};
+use crate::sync::{SpinLock, SpinLockGuard};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct KeyboardState {
@@ -116,7 +116,7 @@ pub struct EnglishQwertySet {
 }
 
 lazy_static! {
-    pub static ref KEYBOARD: Mutex<KeyboardState> = Mutex::new(KeyboardState {
+    pub static ref KEYBOARD: SpinLock<KeyboardState> = SpinLock::new(KeyboardState {
         lctrl_pressed: false,
         lshift_pressed: false,
         lalt_pressed: false,
@@ -198,7 +198,7 @@ lazy_static! {
         numpad_enter_pressed: false
     });
 
-    pub static ref ENG_QWERTY: Mutex<EnglishQwertySet> = Mutex::new(EnglishQwertySet {
+    pub static ref ENG_QWERTY: SpinLock<EnglishQwertySet> = SpinLock::new(EnglishQwertySet {
         q_pressed: false,
         w_pressed: false,
         e_pressed: false,
@@ -232,7 +232,7 @@ fn print_keyboard() {
     use crate::vga;
 
     fn print_sc(pressed: bool, text: &str) {
-        let mut writer: MutexGuard<vga::VgaWriter> = vga::WRITER.lock();
+        let mut writer: SpinLockGuard<vga::VgaWriter> = vga::WRITER.lock();
 
         if pressed {
             writer.set_color(vga::VgaColor::Black, vga::VgaColor::White);
@@ -243,8 +243,8 @@ fn print_keyboard() {
         writer.write_string(text);
     }
 
-    let kbd: MutexGuard<KeyboardState> = KEYBOARD.lock();
-    let eng: MutexGuard<EnglishQwertySet> = ENG_QWERTY.lock();
+    let kbd: SpinLockGuard<KeyboardState> = KEYBOARD.lock();
+    let eng: SpinLockGuard<EnglishQwertySet> = ENG_QWERTY.lock();
     vga::WRITER.lock().clear();
     print_sc(kbd.esc_pressed, "esc");
     print_sc(kbd.f1_pressed, "f1");
@@ -467,7 +467,7 @@ pub async fn handle_key_press(stream: &mut ScancodeStream) {
         0x38 => KEYBOARD.lock().lalt_pressed = true,
         0x39 => KEYBOARD.lock().space_pressed = true,
         0x3A => {
-            let mut kbd: MutexGuard<KeyboardState> = KEYBOARD.lock();
+            let mut kbd: SpinLockGuard<KeyboardState> = KEYBOARD.lock();
             kbd.caps_lock_pressed = true;
             kbd.caps_lock_active = !kbd.caps_lock_active;
         },
@@ -482,12 +482,12 @@ pub async fn handle_key_press(stream: &mut ScancodeStream) {
         0x43 => KEYBOARD.lock().f9_pressed = true,
         0x44 => KEYBOARD.lock().f10_pressed = true,
         0x45 => {
-            let mut kbd: MutexGuard<KeyboardState> = KEYBOARD.lock();
+            let mut kbd: SpinLockGuard<KeyboardState> = KEYBOARD.lock();
             kbd.num_lock_pressed = true;
             kbd.num_lock_active = !kbd.num_lock_active;
         },
         0x46 => {
-            let mut kbd: MutexGuard<KeyboardState> = KEYBOARD.lock();
+            let mut kbd: SpinLockGuard<KeyboardState> = KEYBOARD.lock();
             kbd.scroll_lock_pressed = true;
             kbd.scroll_lock_active = !kbd.scroll_lock_active;
         },
@@ -15,12 +15,32 @@ pub mod gdt;
 pub mod keyboard;
 pub mod pci;
 pub mod memory;
+#[path = "./storage/block.rs"]
+pub mod block;
 #[path = "./storage/ata_pio.rs"]
 pub mod ata_pio;
+#[path = "./storage/partition.rs"]
+pub mod partition;
 #[path = "./acpi/acpi.rs"]
 pub mod acpi;
 pub mod allocator;
 pub mod task;
+pub mod fs;
+pub mod timer;
+pub mod thread;
+pub mod smp;
+pub mod sync;
+pub mod usermode;
+pub mod syscall;
+pub mod elf;
+pub mod loader;
+pub mod process;
+pub mod tty;
+pub mod console;
+pub mod ipc;
+pub mod rtc;
+pub mod power;
+pub mod shell;
 
 use core::panic::PanicInfo;
 
@@ -35,7 +55,19 @@ pub fn halt_loop() -> ! {
     }
 }
 
+/// Gets the console ready for a panic message. Interrupts are disabled and console locks are
+/// released, the panicking code may be holding them.
+pub fn prepare_panic() {
+    x86_64::instructions::interrupts::disable();
+
+    unsafe {
+        serial::force_unlock();
+        vga::force_unlock();
+    }
+}
+
 pub fn panic_test(info: &PanicInfo) -> ! {
+    prepare_panic();
     serial_println!("Fail: {}", info);
     exit_qemu(QemuExitCode::Fail);
     loop {}
@@ -44,6 +76,7 @@ pub fn panic_test(info: &PanicInfo) -> ! {
 #[cfg(test)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    prepare_panic();
     serial_println!("{}", info);
     exit_qemu(QemuExitCode::Fail);
     loop {}
@@ -90,8 +123,13 @@ pub fn exit_qemu(code: QemuExitCode) {
 
 pub fn init() {
     gdt::init();
+    smp::init_bsp();
     interrupts::init_idt();
+    syscall::init();
     unsafe { interrupts::PICS_MUTEX.lock().initialize(); }
+    // Kernel messages go out on COM1 even if nothing answers the loopback test
+    let _ = serial::init(serial::ComPort::Com1, serial::DEFAULT_BAUD_RATE);
+    timer::init();
     x86_64::instructions::interrupts::enable();
 }
 
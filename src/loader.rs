@@ -0,0 +1,232 @@
+//! Loads static ELF executables into a fresh address space. Programs have to be linked away from
+//! the level 4 entries the kernel uses, like at `0x2000_0000_0000`.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+use crate::{
+    elf::{self, Elf, ElfError, ProgramHeader},
+    fs::{initramfs, vfs, FsError},
+    memory::{self, AddressSpace, MapError, RegionKind},
+    thread::{self, JoinHandle},
+    usermode
+};
+
+const PAGE_SIZE: u64 = 4096;
+/// User stack ends where user memory does
+pub const STACK_TOP: u64 = usermode::USER_END;
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    Fs(FsError),
+    Map(MapError),
+    /// Arguments and environment take too much of the stack
+    ArgumentsTooLong
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> Self {
+        LoadError::Elf(error)
+    }
+}
+
+impl From<FsError> for LoadError {
+    fn from(error: FsError) -> Self {
+        LoadError::Fs(error)
+    }
+}
+
+impl From<MapError> for LoadError {
+    fn from(error: MapError) -> Self {
+        LoadError::Map(error)
+    }
+}
+
+/// Loaded program ready to run
+pub struct Program {
+    address_space: Arc<AddressSpace>,
+    entry: VirtAddr,
+    stack_pointer: VirtAddr
+}
+
+impl Program {
+    pub fn address_space(&self) -> &Arc<AddressSpace> {
+        &self.address_space
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        self.entry
+    }
+
+    /// Points to `argc`, followed by `argv`, `envp` and auxiliary vector
+    pub fn stack_pointer(&self) -> VirtAddr {
+        self.stack_pointer
+    }
+
+    /// Switches the current thread to the program's address space and continues in ring 3,
+    /// see `usermode::enter`
+    pub fn enter(self) -> ! {
+        thread::set_page_table(Some(self.address_space.page_table()));
+        usermode::enter(self.entry, self.stack_pointer)
+    }
+
+    /// Runs the program on a new thread named `name`. It never returns a result, so wait for it
+    /// with `JoinHandle::wait`.
+    pub fn spawn(self, name: &str) -> JoinHandle<()> {
+        thread::Builder::new().name(name).spawn(move || self.enter())
+    }
+}
+
+/// Maps segments of executable `image` and a stack with `argv` and `envp` on it
+pub fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, LoadError> {
+    let elf = Elf::parse(image)?;
+    let address_space = AddressSpace::new()?;
+    map_segments(&address_space, &elf)?;
+    address_space.map(VirtAddr::new(STACK_TOP - STACK_SIZE), STACK_SIZE, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE, RegionKind::Stack)?;
+    let stack_pointer = build_stack(&address_space, &elf, argv, envp)?;
+
+    Ok(Program { address_space: Arc::new(address_space), entry: VirtAddr::new(elf.entry()), stack_pointer })
+}
+
+/// Loads executable at `path` in the VFS
+pub fn load_file(path: &str, argv: &[&str], envp: &[&str]) -> Result<Program, LoadError> {
+    let image = vfs::read_to_end(path)?;
+    load(&image, argv, envp)
+}
+
+/// Loads executable at `path` in the embedded initramfs, works before anything is mounted
+pub fn load_initramfs(path: &str, argv: &[&str], envp: &[&str]) -> Result<Program, LoadError> {
+    load(initramfs::find(initramfs::EMBEDDED, path)?, argv, envp)
+}
+
+/// Page flags for segment flags, memory is always readable
+fn page_flags(segment: &ProgramHeader) -> PageTableFlags {
+    let mut flags = PageTableFlags::empty();
+
+    if segment.flags & elf::PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+
+    if segment.flags & elf::PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    flags
+}
+
+/// Flags of a page that segments with `first` and `second` share, it gets permissions of both
+fn combine(first: PageTableFlags, second: PageTableFlags) -> PageTableFlags {
+    let executable = !first.contains(PageTableFlags::NO_EXECUTE) || !second.contains(PageTableFlags::NO_EXECUTE);
+    let mut flags = first | second;
+    flags.set(PageTableFlags::NO_EXECUTE, !executable);
+    flags
+}
+
+/// Maps pages of all segments as `Image` regions and copies the file data in
+fn map_segments(address_space: &AddressSpace, elf: &Elf) -> Result<(), LoadError> {
+    let mut ranges = Vec::new();
+
+    for segment in elf.segments().filter(|segment| segment.memory_size > 0) {
+        if segment.end() > usermode::USER_END {
+            return Err(MapError::KernelRange.into());
+        }
+
+        let start = segment.virtual_address & !(PAGE_SIZE - 1);
+        ranges.push((start, segment.end().next_multiple_of(PAGE_SIZE), page_flags(&segment)));
+    }
+
+    // Checked before mapping anything, so a huge segment doesn't take all memory first
+    let size: u64 = ranges.iter().map(|(start, end, _)| end - start).sum();
+
+    if size / PAGE_SIZE > memory::frames_free() as u64 {
+        return Err(MapError::OutOfMemory.into());
+    }
+
+    // Pieces between the range bounds get the flags of all ranges covering them, consecutive
+    // pieces with the same flags are mapped together
+    let mut bounds: Vec<u64> = ranges.iter().flat_map(|&(start, end, _)| [start, end]).collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut pieces: Vec<(u64, u64, PageTableFlags)> = Vec::new();
+
+    for bound in bounds.windows(2) {
+        let (start, end) = (bound[0], bound[1]);
+        let covering = ranges.iter().filter(|&&(first, last, _)| first < end && start < last);
+
+        let Some(flags) = covering.map(|&(_, _, flags)| flags).reduce(combine) else {
+            continue;
+        };
+
+        match pieces.last_mut() {
+            Some(piece) if piece.1 == start && piece.2 == flags => piece.1 = end,
+            _ => pieces.push((start, end, flags))
+        }
+    }
+
+    for (start, end, flags) in pieces {
+        address_space.map(VirtAddr::new(start), end - start, flags, RegionKind::Image)?;
+    }
+
+    // Pages are zeroed, so only the part stored in the file is copied
+    for segment in elf.segments().filter(|segment| segment.file_size > 0) {
+        address_space.write(VirtAddr::new(segment.virtual_address), elf.segment_data(&segment))?;
+    }
+
+    Ok(())
+}
+
+/// Lays out the stack the way System V ABI describes process start: `argc`, `argv` pointers,
+/// null, `envp` pointers, null and auxiliary vector pairs, strings above them. Returns the
+/// stack pointer, which is 16 byte aligned and points to `argc`.
+fn build_stack(address_space: &AddressSpace, elf: &Elf, argv: &[&str], envp: &[&str]) -> Result<VirtAddr, LoadError> {
+    let strings_size: usize = argv.iter().chain(envp).map(|string| string.len() + 1).sum();
+
+    if strings_size as u64 > STACK_SIZE {
+        return Err(LoadError::ArgumentsTooLong);
+    }
+
+    let strings_start = STACK_TOP - strings_size as u64;
+    let mut strings = Vec::with_capacity(strings_size);
+    let mut pointers = Vec::with_capacity(argv.len() + envp.len());
+
+    for string in argv.iter().chain(envp) {
+        pointers.push(strings_start + strings.len() as u64);
+        strings.extend_from_slice(string.as_bytes());
+        strings.push(0);
+    }
+
+    let (argv_pointers, envp_pointers) = pointers.split_at(argv.len());
+    let mut words = vec![argv.len() as u64];
+    words.extend_from_slice(argv_pointers);
+    words.push(0);
+    words.extend_from_slice(envp_pointers);
+    words.push(0);
+
+    if let Some(address) = elf.program_headers_address() {
+        words.extend_from_slice(&[AT_PHDR, address, AT_PHENT, elf::PROGRAM_HEADER_SIZE as u64, AT_PHNUM, elf.program_header_count() as u64]);
+    }
+
+    words.extend_from_slice(&[AT_PAGESZ, PAGE_SIZE, AT_ENTRY, elf.entry(), AT_NULL, 0]);
+
+    let stack_pointer = (strings_start - words.len() as u64 * 8) & !0xF;
+
+    // Program gets at least a page of the stack for itself
+    if STACK_TOP - stack_pointer > STACK_SIZE - PAGE_SIZE {
+        return Err(LoadError::ArgumentsTooLong);
+    }
+
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    address_space.write(VirtAddr::new(strings_start), &strings)?;
+    address_space.write(VirtAddr::new(stack_pointer), &bytes)?;
+    Ok(VirtAddr::new(stack_pointer))
+}
@@ -6,7 +6,7 @@
 
 extern crate alloc;
 use core::panic::PanicInfo;
-use ruin::{serial_println, println, memory, allocator, task::{executor::Executor, Task}};
+use ruin::{serial_println, println, memory, allocator, smp, thread, task::executor, fs::OpenFlags, console, serial::{self, ComPort}};
 use bootloader::{BootInfo, entry_point};
 use x86_64::VirtAddr;
 
@@ -15,6 +15,7 @@ use x86_64::VirtAddr;
 fn panic(info: &PanicInfo) -> ! {
     use ruin::halt_loop;
 
+    ruin::prepare_panic();
     serial_println!("{}", info);
     println!("{}", info);
     halt_loop();
@@ -48,6 +49,7 @@ pub fn kernel_start(boot_info: &'static BootInfo) -> ! {
     test_main();
 
     allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
 
     if ruin::acpi::find_xsdp_bios().is_some() {
         println!("Found XSDP")
@@ -55,13 +57,47 @@ pub fn kernel_start(boot_info: &'static BootInfo) -> ! {
         println!("Not found XSDP");
     };
 
-    // ata_pio::initialize();
+    ruin::fs::vfs::mount("/", ruin::fs::tmpfs::TmpFs::new()).unwrap();
+
+    match ruin::fs::initramfs::unpack(ruin::fs::initramfs::EMBEDDED, "/") {
+        Ok(count) => println!("initramfs: {} entries", count),
+        Err(error) => println!("initramfs: {:?}", error)
+    }
+
+    for disk in 0..ruin::ata_pio::probe() {
+        let name = alloc::format!("disk{}", disk);
+
+        match ruin::partition::scan(&name) {
+            Ok(partitions) => println!("{}: {} partitions", name, partitions.len()),
+            Err(error) => println!("{}: {:?}", name, error)
+        }
+    }
+
     println!("Vendor: {}", ruin::pci::check_vendor(0, 0));
 
-    let mut executor = Executor::new();
-    executor.spawn(Task::new(async_print_number()));
-    executor.spawn(Task::new(ruin::task::keyboard::print_keypress()));
-    executor.run();
+    thread::init();
+
+    match smp::init() {
+        Ok(count) => println!("SMP: {} CPUs online", count),
+        Err(error) => println!("SMP: {:?}", error)
+    }
+
+    ruin::shell::spawn(console::open(OpenFlags::READ_WRITE));
+
+    for index in 1..console::CONSOLES {
+        ruin::shell::spawn(console::open_virtual(index, OpenFlags::READ_WRITE));
+    }
+
+    // COM1 input reaches the console shell, other ports get shells of their own
+    for port in serial::probe().into_iter().filter(|&port| port != ComPort::Com1) {
+        ruin::shell::spawn(serial::open(port, OpenFlags::READ_WRITE));
+    }
+
+    let executor = executor::global();
+    executor.spawn(async_print_number());
+    executor.start(smp::cpu_count(), thread::Priority::High);
+
+    thread::exit();
 }
 
 entry_point!(kernel_start);
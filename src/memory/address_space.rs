@@ -0,0 +1,552 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{mapper::{MapToError, TranslateResult}, page_table::PageTableEntry, FrameAllocator, FrameDeallocator, Mapper, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate},
+    PhysAddr,
+    VirtAddr
+};
+
+use super::{allocate_frame, kernel_page_table, physical_to_virtual, shared::SharedMemory, with_memory, with_page_table, MemoryMapFrameAllocator, COPY_ON_WRITE};
+use crate::usermode;
+
+const PAGE_SIZE: u64 = 4096;
+/// Tables above user pages allow everything, the pages themselves decide
+const PARENT_FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE).union(PageTableFlags::USER_ACCESSIBLE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    OutOfMemory,
+    AlreadyMapped,
+    NotMapped,
+    /// Range is past `usermode::USER_END` or under a level 4 entry the kernel uses
+    KernelRange,
+    /// `Backing` failed to read or write the page
+    Io
+}
+
+impl From<MapToError<Size4KiB>> for MapError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        match error {
+            MapToError::FrameAllocationFailed => MapError::OutOfMemory,
+            MapToError::PageAlreadyMapped(_) | MapToError::ParentEntryHugePage => MapError::AlreadyMapped
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Segments of the loaded program
+    Image,
+    Stack,
+    /// Memory from `mmap`
+    Anonymous,
+    /// Frames shared with other address spaces, a copy of the address space shares them too
+    /// instead of copying on write
+    Shared,
+    /// Pages of a `Backing` mapped when they're first touched, writes go to a private copy
+    File,
+    /// Like `File`, but writes go to the backing frames and `AddressSpace::sync` stores them
+    SharedFile
+}
+
+impl RegionKind {
+    /// Whether copies of the address space share the frames instead of copying on write
+    fn is_shared(self) -> bool {
+        matches!(self, RegionKind::Shared | RegionKind::SharedFile)
+    }
+
+    fn is_backed(self) -> bool {
+        matches!(self, RegionKind::File | RegionKind::SharedFile)
+    }
+}
+
+/// Object whose pages `AddressSpace::map_backed` maps on demand, like a file in the page cache
+pub trait Backing: Send + Sync {
+    /// Frame holding page `index`, with a reference for the caller. `writable` if writes through
+    /// the mapping reach the frame, so it has to be stored at some point. `NotMapped` past the end.
+    fn frame(&self, index: u64, writable: bool) -> Result<PhysFrame, MapError>;
+
+    /// Stores page `index` after it was written through a shared mapping
+    fn write_back(&self, index: u64) -> Result<(), MapError>;
+}
+
+/// Backing of a region, from its first page
+#[derive(Clone)]
+struct Backed {
+    backing: Arc<dyn Backing>,
+    /// Page index of the region start in `backing`
+    first: u64
+}
+
+/// Pages mapped with the same flags for the same purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// Page aligned
+    pub start: VirtAddr,
+    /// Page aligned, not included
+    pub end: VirtAddr,
+    /// Flags given to `AddressSpace::map`
+    pub flags: PageTableFlags,
+    pub kind: RegionKind
+}
+
+impl Region {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(Page::containing_address(self.start), Page::containing_address(self.end))
+    }
+}
+
+/// Page table of user programs. Level 4 entries the kernel uses are copied from the kernel page
+/// table, so kernel memory looks the same everywhere, the remaining entries are its own. Entries
+/// the kernel page table has for user memory of threads without a process aren't copied. Frames
+/// and tables under them are released when it's dropped.
+pub struct AddressSpace {
+    page_table: PhysFrame,
+    /// Sorted by address, neighbours with the same flags and kind are merged unless they're backed
+    regions: Mutex<Vec<Region>>,
+    /// Backings of `File` and `SharedFile` regions by region start
+    backings: Mutex<BTreeMap<VirtAddr, Backed>>
+}
+
+impl AddressSpace {
+    /// Address space with nothing mapped for ring 3. Needs `memory::init_global`.
+    pub fn new() -> Result<AddressSpace, MapError> {
+        let kernel = kernel_page_table().expect("Memory is not initialized");
+        let frame = allocate_frame().ok_or(MapError::OutOfMemory)?;
+
+        unsafe {
+            let kernel = &*physical_to_virtual(kernel.start_address()).as_ptr::<PageTable>();
+            let table = &mut *physical_to_virtual(frame.start_address()).as_mut_ptr::<PageTable>();
+            table.zero();
+
+            for (entry, kernel_entry) in table.iter_mut().zip(kernel.iter()) {
+                if is_kernel_owned(kernel_entry) {
+                    entry.set_addr(kernel_entry.addr(), kernel_entry.flags());
+                }
+            }
+        }
+
+        Ok(AddressSpace { page_table: frame, regions: Mutex::new(Vec::new()), backings: Mutex::new(BTreeMap::new()) })
+    }
+
+    /// Level 4 table, for `Cr3`
+    pub fn page_table(&self) -> PhysFrame {
+        self.page_table
+    }
+
+    /// Maps `size` bytes from `address` to new zeroed frames that ring 3 can access and records
+    /// them as a region of `kind`. `flags` may add `WRITABLE` or `NO_EXECUTE`.
+    pub fn map(&self, address: VirtAddr, size: u64, flags: PageTableFlags, kind: RegionKind) -> Result<(), MapError> {
+        self.add_region(address, size, flags, kind, |start, end| map_user(self.page_table, start, end - start, flags))
+    }
+
+    /// Maps all of `memory` from `address` as a `Shared` region, writes are seen by every address
+    /// space that maps it
+    pub fn map_shared(&self, address: VirtAddr, memory: &SharedMemory, flags: PageTableFlags) -> Result<(), MapError> {
+        self.add_region(address, memory.size(), flags, RegionKind::Shared, |start, _| map_frames(self.page_table, start, memory.frames(), flags))
+    }
+
+    /// Records `size` bytes from page aligned `address` as a region backed by pages of `backing`
+    /// from `first`, which are mapped when they're first touched. Writes go to `backing` if
+    /// `shared`, otherwise to private copies.
+    pub fn map_backed(&self, address: VirtAddr, size: u64, flags: PageTableFlags, backing: Arc<dyn Backing>, first: u64, shared: bool) -> Result<(), MapError> {
+        if !address.is_aligned(PAGE_SIZE) {
+            return Err(MapError::KernelRange);
+        }
+
+        let kind = if shared { RegionKind::SharedFile } else { RegionKind::File };
+        self.add_region(address, size, flags, kind, |_, _| Ok(()))?;
+        self.backings.lock().insert(address, Backed { backing, first });
+        Ok(())
+    }
+
+    /// Maps pages covering `size` bytes from `address` with `map` and records them as a region,
+    /// unless they overlap another region
+    fn add_region(&self, address: VirtAddr, size: u64, flags: PageTableFlags, kind: RegionKind, map: impl FnOnce(VirtAddr, VirtAddr) -> Result<(), MapError>) -> Result<(), MapError> {
+        check_range(address, size)?;
+        let start = address.align_down(PAGE_SIZE);
+        let end = (address + size).align_up(PAGE_SIZE);
+        let mut regions = self.regions.lock();
+
+        if regions.iter().any(|region| region.start < end && start < region.end) {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        map(start, end)?;
+        let index = regions.partition_point(|region| region.start < start);
+        regions.insert(index, Region { start, end, flags, kind });
+
+        // Merges with the next region first, so `index` stays valid
+        for index in [index, index.saturating_sub(1)] {
+            if let [first, second, ..] = &regions[index..] {
+                if first.end == second.start && first.flags == second.flags && first.kind == second.kind && !first.kind.is_backed() {
+                    regions[index].end = regions[index + 1].end;
+                    regions.remove(index + 1);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn regions(&self) -> Vec<Region> {
+        self.regions.lock().clone()
+    }
+
+    /// Lowest page aligned address from `start` where `size` bytes don't overlap any region
+    pub fn find_free(&self, start: VirtAddr, size: u64) -> Option<VirtAddr> {
+        let mut candidate = start.align_up(PAGE_SIZE).as_u64();
+
+        for region in self.regions.lock().iter() {
+            let end = candidate.checked_add(size)?;
+
+            if region.start.as_u64() >= end {
+                break;
+            }
+
+            candidate = candidate.max(region.end.as_u64());
+        }
+
+        candidate.checked_add(size).filter(|&end| end <= usermode::USER_END).map(|_| VirtAddr::new(candidate))
+    }
+
+    /// Flags of the page containing `address`, `None` if it's not mapped
+    pub fn flags(&self, address: VirtAddr) -> Option<PageTableFlags> {
+        entry(self.page_table, Page::containing_address(address)).map(|(_, flags)| flags)
+    }
+
+    /// Physical address `address` is mapped to
+    pub fn translate(&self, address: VirtAddr) -> Option<PhysAddr> {
+        with_page_table(self.page_table, |mapper, _| mapper.translate_addr(address))
+    }
+
+    /// Maps the page containing `address` if it's in a backed region and not mapped yet, then
+    /// gives it a private copy if it's for `write`. False if the access isn't allowed or the
+    /// backing can't provide the page.
+    pub fn fault(&self, address: VirtAddr, write: bool) -> bool {
+        let regions = self.regions.lock();
+
+        let Some(region) = regions.iter().find(|region| region.contains(address)) else {
+            return false;
+        };
+
+        if !region.kind.is_backed() || (write && !region.flags.contains(PageTableFlags::WRITABLE)) {
+            return false;
+        }
+
+        let page = Page::containing_address(address);
+
+        if entry(self.page_table, page).is_none() {
+            let backed = self.backings.lock()[&region.start].clone();
+            let index = backed.first + (page.start_address() - region.start) / PAGE_SIZE;
+
+            let mut flags = region.flags;
+            let writable = flags.contains(PageTableFlags::WRITABLE);
+
+            if region.kind == RegionKind::File && writable {
+                flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            }
+
+            let Ok(frame) = backed.backing.frame(index, writable && region.kind == RegionKind::SharedFile) else {
+                return false;
+            };
+
+            if map_frame(self.page_table, page, frame, flags).is_err() {
+                unsafe { super::release_frame(frame) };
+                return false;
+            }
+        }
+
+        !write || region.kind == RegionKind::SharedFile || copy_on_write(self.page_table, address)
+    }
+
+    /// Maps pages of backed regions in `size` bytes from `address` that aren't mapped yet, so the
+    /// kernel can access them without faulting
+    pub fn populate(&self, address: VirtAddr, size: u64) {
+        if size == 0 {
+            return;
+        }
+
+        let pages = Page::<Size4KiB>::range_inclusive(Page::containing_address(address), Page::containing_address(address + (size - 1)));
+
+        for page in pages {
+            if entry(self.page_table, page).is_none() && !self.fault(page.start_address(), false) {
+                return;
+            }
+        }
+    }
+
+    /// Stores pages of `SharedFile` regions in `size` bytes from `address` to their backings
+    pub fn sync(&self, address: VirtAddr, size: u64) -> Result<(), MapError> {
+        let end = address + size;
+        let regions: Vec<Region> = self.regions.lock().iter().filter(|region| region.kind == RegionKind::SharedFile && region.start < end && address < region.end).copied().collect();
+
+        for region in regions {
+            let backed = self.backings.lock()[&region.start].clone();
+
+            for page in region.pages().filter(|page| page.start_address() + PAGE_SIZE > address && page.start_address() < end) {
+                if entry(self.page_table, page).is_some() {
+                    backed.backing.write_back(backed.first + (page.start_address() - region.start) / PAGE_SIZE)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// New address space with the same regions. Frames are shared, writable ones that aren't in a
+    /// `Shared` region become read-only in both and are copied by whichever writes first.
+    pub fn duplicate(&self) -> Result<AddressSpace, MapError> {
+        let copy = AddressSpace::new()?;
+        let regions = self.regions.lock();
+
+        for region in regions.iter() {
+            for page in region.pages() {
+                // Backed regions only have the pages that were touched
+                let Some((frame, mut flags)) = entry(self.page_table, page) else {
+                    if region.kind.is_backed() {
+                        continue;
+                    }
+
+                    return Err(MapError::NotMapped);
+                };
+
+                if !region.kind.is_shared() && flags.intersects(PageTableFlags::WRITABLE | COPY_ON_WRITE) {
+                    flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+                    set_flags(self.page_table, page, flags)?;
+                }
+
+                map_frames(copy.page_table, page.start_address(), &[frame], flags)?;
+            }
+        }
+
+        *copy.regions.lock() = regions.clone();
+        *copy.backings.lock() = self.backings.lock().clone();
+        Ok(copy)
+    }
+
+    /// Copies `bytes` to mapped memory at `address`, the address space doesn't have to be active.
+    /// Pages of backed regions are loaded and copy-on-write pages are copied first.
+    pub fn write(&self, address: VirtAddr, bytes: &[u8]) -> Result<(), MapError> {
+        self.copy(address, bytes.len(), true, |offset, memory, length| unsafe {
+            core::ptr::copy_nonoverlapping(bytes[offset..].as_ptr(), memory, length);
+        })
+    }
+
+    /// Fills `buffer` from mapped memory at `address`, the address space doesn't have to be active
+    pub fn read(&self, address: VirtAddr, buffer: &mut [u8]) -> Result<(), MapError> {
+        self.copy(address, buffer.len(), false, |offset, memory, length| unsafe {
+            core::ptr::copy_nonoverlapping(memory, buffer[offset..].as_mut_ptr(), length);
+        })
+    }
+
+    /// Calls `f` with offset, kernel pointer and length of each page sized piece of `length`
+    /// bytes at `address`
+    fn copy(&self, address: VirtAddr, length: usize, writing: bool, mut f: impl FnMut(usize, *mut u8, usize)) -> Result<(), MapError> {
+        if length > 0 {
+            check_range(address, length as u64)?;
+        }
+
+        let mut done = 0;
+
+        while done < length {
+            let current = address + done as u64;
+            let chunk = (PAGE_SIZE - current.as_u64() % PAGE_SIZE).min((length - done) as u64) as usize;
+
+            if self.translate(current).is_none() {
+                self.fault(current, false);
+            }
+
+            if writing {
+                copy_on_write(self.page_table, current);
+            }
+
+            let physical = self.translate(current).ok_or(MapError::NotMapped)?;
+            f(done, physical_to_virtual(physical).as_mut_ptr(), chunk);
+            done += chunk;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees the tables under level 4 entries that aren't the kernel's and releases their frames.
+    /// The address space must not be loaded on any processor anymore.
+    fn drop(&mut self) {
+        let kernel_table = kernel_page_table().expect("Memory is not initialized");
+
+        with_memory(|_, frame_allocator| unsafe {
+            let kernel = &*physical_to_virtual(kernel_table.start_address()).as_ptr::<PageTable>();
+            let table = &*physical_to_virtual(self.page_table.start_address()).as_ptr::<PageTable>();
+
+            for (entry, kernel_entry) in table.iter().zip(kernel.iter()) {
+                if !entry.is_unused() && !is_kernel_owned(kernel_entry) {
+                    free_table(PhysFrame::containing_address(entry.addr()), 3, frame_allocator);
+                }
+            }
+
+            frame_allocator.deallocate_frame(self.page_table);
+        });
+    }
+}
+
+/// Frees table `frame` of `level` and releases the frames it maps. User memory has no huge pages.
+unsafe fn free_table(frame: PhysFrame, level: u8, frame_allocator: &mut MemoryMapFrameAllocator) {
+    let table = &*physical_to_virtual(frame.start_address()).as_ptr::<PageTable>();
+
+    for entry in table.iter().filter(|entry| entry.flags().contains(PageTableFlags::PRESENT)) {
+        let child = PhysFrame::containing_address(entry.addr());
+
+        if level > 1 {
+            free_table(child, level - 1, frame_allocator);
+        } else {
+            frame_allocator.release(child);
+        }
+    }
+
+    frame_allocator.deallocate_frame(frame);
+}
+
+/// Frame and flags of `page` in `table`
+fn entry(table: PhysFrame, page: Page) -> Option<(PhysFrame, PageTableFlags)> {
+    with_page_table(table, |mapper, _| match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { frame, flags, .. } => Some((PhysFrame::containing_address(frame.start_address()), flags)),
+        _ => None
+    })
+}
+
+fn set_flags(table: PhysFrame, page: Page, flags: PageTableFlags) -> Result<(), MapError> {
+    with_page_table(table, |mapper, _| {
+        unsafe { mapper.update_flags(page, flags) }.map_err(|_| MapError::NotMapped)?.flush();
+        Ok(())
+    })
+}
+
+/// Maps `frames` from `address` in `table` for ring 3, each of them gets another reference
+fn map_frames(table: PhysFrame, address: VirtAddr, frames: &[PhysFrame], flags: PageTableFlags) -> Result<(), MapError> {
+    for (index, &frame) in frames.iter().enumerate() {
+        map_frame(table, Page::containing_address(address + index as u64 * PAGE_SIZE), frame, flags)?;
+        super::share_frame(frame);
+    }
+
+    Ok(())
+}
+
+/// Maps `page` to `frame` in `table` for ring 3, the caller's reference to the frame goes to the
+/// mapping
+fn map_frame(table: PhysFrame, page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), MapError> {
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    with_page_table(table, |mapper, frame_allocator| {
+        if mapper.translate_page(page).is_ok() {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        unsafe { mapper.map_to_with_table_flags(page, frame, flags, PARENT_FLAGS, frame_allocator)?.ignore() };
+        Ok(())
+    })
+}
+
+/// Gives the copy-on-write page containing `address` in `table` its own frame, or just makes it
+/// writable if no one else uses the frame anymore. Returns false if it's not such a page, or if
+/// there's no memory for the copy.
+pub(super) fn copy_on_write(table: PhysFrame, address: VirtAddr) -> bool {
+    if address.as_u64() >= usermode::USER_END || kernel_page_table().is_none() {
+        return false;
+    }
+
+    let page = Page::<Size4KiB>::containing_address(address);
+
+    with_page_table(table, |mapper, frame_allocator| {
+        let TranslateResult::Mapped { frame, flags, .. } = mapper.translate(page.start_address()) else {
+            return false;
+        };
+
+        if !flags.contains(COPY_ON_WRITE) {
+            return false;
+        }
+
+        let frame = PhysFrame::containing_address(frame.start_address());
+        let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+
+        if frame_allocator.references(frame) == 1 {
+            unsafe { mapper.update_flags(page, flags).expect("Mapped page has no entry").flush() };
+            return true;
+        }
+
+        let Some(copy) = frame_allocator.allocate_frame() else {
+            return false;
+        };
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(physical_to_virtual(frame.start_address()).as_ptr::<u8>(), physical_to_virtual(copy.start_address()).as_mut_ptr::<u8>(), PAGE_SIZE as usize);
+            mapper.unmap(page).expect("Mapped page has no entry").1.flush();
+            mapper.map_to_with_table_flags(page, copy, flags, PARENT_FLAGS, frame_allocator).expect("Page table disappeared").ignore();
+            frame_allocator.release(frame);
+        }
+
+        true
+    })
+}
+
+/// Whether level 4 entry `entry` of the kernel page table maps kernel memory, which every
+/// address space shares. Entries made for user memory of threads without a process are the only
+/// ones ring 3 can access.
+fn is_kernel_owned(entry: &PageTableEntry) -> bool {
+    !entry.is_unused() && !entry.flags().contains(PageTableFlags::USER_ACCESSIBLE)
+}
+
+/// Checks that `size` bytes from `address` may hold user memory. It must not be under level 4
+/// entries the kernel owns, every table shares those entries.
+fn check_range(address: VirtAddr, size: u64) -> Result<(), MapError> {
+    let end = address.as_u64().checked_add(size).ok_or(MapError::KernelRange)?;
+
+    if size == 0 || end > usermode::USER_END {
+        return Err(MapError::KernelRange);
+    }
+
+    let kernel_table = kernel_page_table().expect("Memory is not initialized");
+    let kernel = unsafe { &*physical_to_virtual(kernel_table.start_address()).as_ptr::<PageTable>() };
+    let last = VirtAddr::new(end - 1);
+
+    if (u16::from(address.p4_index())..=u16::from(last.p4_index())).any(|index| is_kernel_owned(&kernel[index as usize])) {
+        return Err(MapError::KernelRange);
+    }
+
+    Ok(())
+}
+
+/// Maps new zeroed user pages in level 4 table `table`
+pub(super) fn map_user(table: PhysFrame, address: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapError> {
+    check_range(address, size)?;
+    let pages = Page::<Size4KiB>::range_inclusive(Page::containing_address(address), Page::containing_address(address + size - 1u64));
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    with_page_table(table, |mapper, frame_allocator| {
+        for page in pages {
+            if mapper.translate_page(page).is_ok() {
+                return Err(MapError::AlreadyMapped);
+            }
+
+            let frame = frame_allocator.allocate_frame().ok_or(MapError::OutOfMemory)?;
+
+            unsafe {
+                physical_to_virtual(frame.start_address()).as_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE as usize);
+                // Missing pages are never cached in TLB, so there's nothing to flush
+                mapper.map_to_with_table_flags(page, frame, flags, PARENT_FLAGS, frame_allocator)?.ignore();
+            }
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,349 @@
+pub mod address_space;
+pub mod shared;
+
+use alloc::collections::BTreeMap;
+use core::{ops::Range, sync::atomic::{AtomicU64, Ordering}};
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Mutex;
+use x86_64::{
+    PhysAddr,
+    VirtAddr,
+    instructions::interrupts,
+    registers::control::{Cr0, Cr0Flags, Cr3},
+    structures::paging::{Page, Mapper, Size4KiB, PhysFrame, FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, page_table::FrameError, PageTableFlags, Translate, mapper::{MapToError, TranslateResult}}
+};
+
+pub use address_space::{AddressSpace, Backing, MapError, Region, RegionKind};
+pub use shared::SharedMemory;
+
+/// Frames below are left for real mode code (like AP trampoline) and BIOS data
+const LOW_MEMORY_END: u64 = 0x100000;
+/// Available bit of user page entries that are read-only only until written, their frame is
+/// shared after `AddressSpace::duplicate` and gets copied by the page fault handler
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+unsafe fn get_current_page_table(physical_offset: VirtAddr) -> &'static mut PageTable {
+    let (current_table, _) = Cr3::read();
+    let physical_mem = current_table.start_address();
+    let virtual_mem = physical_offset + physical_mem.as_u64();
+    let page_table_ptr: *mut PageTable = virtual_mem.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+fn _get_physical_address(virtual_address: VirtAddr, physical_offset: VirtAddr) -> Option<PhysAddr> {
+    let (current_table, _) = Cr3::read();
+    let indexes = [virtual_address.p4_index(), virtual_address.p3_index(), virtual_address.p2_index(), virtual_address.p1_index()];
+    let mut table_frame = current_table;
+
+    for &index in &indexes {
+        let virtual_mem = physical_offset + table_frame.start_address().as_u64();
+        let table_ptr: *const PageTable = virtual_mem.as_ptr();
+        let table = unsafe {&*table_ptr};
+        let entry = &table[index];
+        table_frame = match entry.frame() {
+            Ok(frame) => frame,
+            Err(FrameError::FrameNotPresent) => return None,
+            Err(FrameError::HugeFrame) => panic!("Huge pages are not supported")
+        };
+    }
+
+    Some(table_frame.start_address() + u64::from(virtual_address.page_offset()))
+}
+
+pub unsafe fn get_physical_address(virtual_address: VirtAddr, physical_offset: VirtAddr) -> Option<PhysAddr> {
+    _get_physical_address(virtual_address, physical_offset)
+}
+
+pub fn map_page(page: Page, mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xB8000)); // FIXME: 0xB8000?
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe {mapper.map_to(page, frame, flags, frame_allocator)}.unwrap().flush();
+}
+
+pub unsafe fn init(physical_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let l4_table = get_current_page_table(physical_offset);
+    OffsetPageTable::new(l4_table, physical_offset)
+}
+
+pub struct EmptyFrameAllocator {
+}
+
+impl EmptyFrameAllocator {
+    pub fn new() -> Self {
+        EmptyFrameAllocator {}
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        None
+    }
+}
+
+pub struct MemoryMapFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+    next_low: usize,
+    /// Freed frames, each one keeps the address of the next in its first 8 bytes
+    free: Option<PhysFrame>,
+    /// Frames handed out and not freed yet, low frames are not counted
+    in_use: usize,
+    /// Number of users of frames that have more than one, the others have one
+    references: BTreeMap<PhysFrame, usize>
+}
+
+impl MemoryMapFrameAllocator {
+    fn get_usable_frames_in(&self, range: Range<u64>) -> impl Iterator<Item = PhysFrame> {
+        let regions = self.memory_map.iter();
+        let usable_regions = regions.filter(|reg| reg.region_type == MemoryRegionType::Usable);
+        let usable_addresses = usable_regions.map(|reg| reg.range.start_addr()..reg.range.end_addr());
+        let frame_addresses = usable_addresses.flat_map(|addr| addr.step_by(4096)).filter(move |addr| range.contains(addr));
+        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    fn get_usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.get_usable_frames_in(LOW_MEMORY_END..u64::MAX)
+    }
+
+    pub unsafe fn new(memory_map: &'static MemoryMap) -> Self {
+        MemoryMapFrameAllocator { memory_map, next: 0, next_low: 0, free: None, in_use: 0, references: BTreeMap::new() }
+    }
+
+    /// Frames `allocate_frame` can still hand out
+    pub fn free_frames(&self) -> usize {
+        let usable = self.memory_map.iter().filter(|region| region.region_type == MemoryRegionType::Usable);
+        let total: u64 = usable.map(|region| region.range.end_addr().saturating_sub(region.range.start_addr().max(LOW_MEMORY_END)) / 4096).sum();
+        total as usize - self.in_use
+    }
+
+    /// Frame below 1 MiB, these are never returned by `allocate_frame`
+    pub fn allocate_low_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.get_usable_frames_in(0x1000..LOW_MEMORY_END).nth(self.next_low);
+        self.next_low += 1;
+        frame
+    }
+
+    /// Records another user of allocated `frame`, for example another page table mapping it
+    pub fn add_reference(&mut self, frame: PhysFrame) {
+        *self.references.entry(frame).or_insert(1) += 1;
+    }
+
+    /// Number of users of allocated `frame`
+    pub fn references(&self, frame: PhysFrame) -> usize {
+        self.references.get(&frame).copied().unwrap_or(1)
+    }
+
+    /// Drops a user of `frame` and frees it if that was the last one
+    ///
+    /// # Safety
+    /// The frame must have come from the frame allocator and the caller must not use it anymore.
+    pub unsafe fn release(&mut self, frame: PhysFrame) {
+        match self.references.get_mut(&frame) {
+            Some(2) => {
+                self.references.remove(&frame);
+            }
+            Some(references) => *references -= 1,
+            None => self.deallocate_frame(frame)
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for MemoryMapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = match self.free {
+            Some(frame) => {
+                let next = unsafe { physical_to_virtual(frame.start_address()).as_ptr::<u64>().read() };
+                self.free = (next != 0).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+                Some(frame)
+            }
+            None => {
+                let frame = self.get_usable_frames().nth(self.next);
+                self.next += 1;
+                frame
+            }
+        };
+
+        self.in_use += frame.is_some() as usize;
+        frame
+    }
+}
+
+/// Freed frames are reused before the rest of the memory map. Needs the physical memory mapping,
+/// so only frames allocated after `init_global` should be freed.
+impl FrameDeallocator<Size4KiB> for MemoryMapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let next = self.free.map_or(0, |next| next.start_address().as_u64());
+        physical_to_virtual(frame.start_address()).as_mut_ptr::<u64>().write(next);
+        self.free = Some(frame);
+        self.in_use -= 1;
+    }
+}
+
+
+static PHYSICAL_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Page table the kernel booted with, address spaces share its kernel part
+static KERNEL_PAGE_TABLE: AtomicU64 = AtomicU64::new(0);
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR: Mutex<Option<MemoryMapFrameAllocator>> = Mutex::new(None);
+
+/// Keeps page table and frame allocator, so memory can be mapped after boot
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: MemoryMapFrameAllocator) {
+    PHYSICAL_OFFSET.store(mapper.phys_offset().as_u64(), Ordering::Relaxed);
+    KERNEL_PAGE_TABLE.store(Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
+
+    // Kernel writes to user memory have to fault on copy-on-write pages too, application
+    // processors set it in the trampoline
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+
+    interrupts::without_interrupts(|| {
+        *MAPPER.lock() = Some(mapper);
+        *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    });
+}
+
+/// Runs `f` with global page table and frame allocator. Panics if `init_global` wasn't called.
+fn with_memory<R>(f: impl FnOnce(&mut OffsetPageTable<'static>, &mut MemoryMapFrameAllocator) -> R) -> R {
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        f(mapper.as_mut().expect("Memory is not initialized"), frame_allocator.as_mut().expect("Memory is not initialized"))
+    })
+}
+
+/// Runs `f` with page table whose level 4 table is in `table`, the global one if it's the kernel's
+fn with_page_table<R>(table: PhysFrame, f: impl FnOnce(&mut OffsetPageTable<'static>, &mut MemoryMapFrameAllocator) -> R) -> R {
+    with_memory(|mapper, frame_allocator| {
+        if Some(table) == kernel_page_table() {
+            return f(mapper, frame_allocator);
+        }
+
+        // Memory lock is held, so no one else changes the table meanwhile
+        let level_4 = unsafe { &mut *physical_to_virtual(table.start_address()).as_mut_ptr::<PageTable>() };
+        let mut other = unsafe { OffsetPageTable::new(level_4, mapper.phys_offset()) };
+        f(&mut other, frame_allocator)
+    })
+}
+
+/// Level 4 table the kernel booted with, `None` before `init_global`
+pub fn kernel_page_table() -> Option<PhysFrame> {
+    match KERNEL_PAGE_TABLE.load(Ordering::Relaxed) {
+        0 => None,
+        address => Some(PhysFrame::containing_address(PhysAddr::new(address)))
+    }
+}
+
+/// Address where physical memory `address` is mapped by bootloader
+pub fn physical_to_virtual(address: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_OFFSET.load(Ordering::Relaxed) + address.as_u64())
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    with_memory(|_, frame_allocator| frame_allocator.allocate_frame())
+}
+
+/// Returns `frame` to the frame allocator
+///
+/// # Safety
+/// The frame must have come from the frame allocator and must not be mapped or used anymore.
+pub unsafe fn deallocate_frame(frame: PhysFrame) {
+    with_memory(|_, frame_allocator| frame_allocator.deallocate_frame(frame));
+}
+
+/// Records another user of `frame`, it's freed by `release_frame` of the last one
+pub fn share_frame(frame: PhysFrame) {
+    with_memory(|_, frame_allocator| frame_allocator.add_reference(frame));
+}
+
+/// Drops a user of `frame`, freeing it if that was the last one
+///
+/// # Safety
+/// The frame must have come from the frame allocator and the caller must not use it anymore.
+pub unsafe fn release_frame(frame: PhysFrame) {
+    with_memory(|_, frame_allocator| frame_allocator.release(frame));
+}
+
+/// Number of users of allocated `frame`
+pub fn frame_references(frame: PhysFrame) -> usize {
+    with_memory(|_, frame_allocator| frame_allocator.references(frame))
+}
+
+/// Frames allocated and not freed, frames below 1 MiB are not counted
+pub fn frames_in_use() -> usize {
+    with_memory(|_, frame_allocator| frame_allocator.in_use)
+}
+
+/// Frames that can still be allocated
+pub fn frames_free() -> usize {
+    with_memory(|_, frame_allocator| frame_allocator.free_frames())
+}
+
+pub fn allocate_low_frame() -> Option<PhysFrame> {
+    with_memory(|_, frame_allocator| frame_allocator.allocate_low_frame())
+}
+
+/// Maps device registers at physical `address` uncached into physical memory mapping, which
+/// only covers RAM. Returns their virtual address.
+pub fn map_mmio(address: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let frames = PhysFrame::range_inclusive(PhysFrame::containing_address(address), PhysFrame::containing_address(address + size - 1u64));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+
+    with_memory(|mapper, frame_allocator| {
+        for frame in frames {
+            let page = Page::containing_address(physical_to_virtual(frame.start_address()));
+
+            if mapper.translate_addr(page.start_address()).is_none() {
+                unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+            }
+        }
+
+        Ok(physical_to_virtual(address))
+    })
+}
+
+/// Maps `frame` at the same virtual address, does nothing if it's already mapped there
+pub fn identity_map(frame: PhysFrame, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+
+    with_memory(|mapper, frame_allocator| {
+        match mapper.translate_addr(page.start_address()) {
+            Some(address) if address == frame.start_address() => Ok(()),
+            Some(_) => Err(MapToError::PageAlreadyMapped(frame)),
+            None => {
+                unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Maps `size` bytes from `address` to new zeroed frames that ring 3 can access, in the active
+/// page table. `flags` may add `WRITABLE` or `NO_EXECUTE`.
+pub fn map_user(address: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapError> {
+    address_space::map_user(Cr3::read().0, address, size, flags)
+}
+
+/// Whether ring 3 can access every page of `size` bytes from `address` in the active page
+/// table, and write to them if `writable`. Copy-on-write pages count as writable.
+pub fn is_user_accessible(address: VirtAddr, size: u64, writable: bool) -> bool {
+    if size == 0 {
+        return true;
+    }
+
+    let pages = Page::<Size4KiB>::range_inclusive(Page::containing_address(address), Page::containing_address(address + size - 1u64));
+    let required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    with_page_table(Cr3::read().0, |mapper, _| {
+        pages.into_iter().all(|page| match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags.contains(required) && (!writable || flags.intersects(PageTableFlags::WRITABLE | COPY_ON_WRITE)),
+            _ => false
+        })
+    })
+}
+
+/// Handles a write fault at `address` in the active page table if it hit a copy-on-write page.
+/// Returns false if it didn't, or if there's no memory for the copy.
+pub fn copy_on_write(address: VirtAddr) -> bool {
+    address_space::copy_on_write(Cr3::read().0, address)
+}
@@ -0,0 +1,110 @@
+//! Memory that several address spaces map at once, see `AddressSpace::map_shared`. Each mapping
+//! holds a reference to the frames besides the one of `SharedMemory`, so they stay until the last
+//! address space is dropped. Named objects stay in a registry until they're unlinked.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+use x86_64::{instructions::interrupts, structures::paging::PhysFrame};
+
+use super::{allocate_frame, physical_to_virtual, release_frame, MapError};
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug)]
+pub struct SharedMemory {
+    frames: Vec<PhysFrame>
+}
+
+impl SharedMemory {
+    /// Zeroed memory of `size` bytes rounded up to pages
+    pub fn new(size: u64) -> Result<Arc<SharedMemory>, MapError> {
+        let mut memory = SharedMemory { frames: Vec::new() };
+
+        for _ in 0..size.div_ceil(PAGE_SIZE) {
+            // Frames allocated so far are released when `memory` is dropped
+            let frame = allocate_frame().ok_or(MapError::OutOfMemory)?;
+            unsafe { physical_to_virtual(frame.start_address()).as_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE as usize) };
+            memory.frames.push(frame);
+        }
+
+        Ok(Arc::new(memory))
+    }
+
+    /// Size in bytes, a multiple of the page size
+    pub fn size(&self) -> u64 {
+        self.frames.len() as u64 * PAGE_SIZE
+    }
+
+    pub fn frames(&self) -> &[PhysFrame] {
+        &self.frames
+    }
+
+    /// Copies `bytes` to the memory from `offset`, `NotMapped` if they go past its end
+    pub fn write(&self, offset: u64, bytes: &[u8]) -> Result<(), MapError> {
+        self.copy(offset, bytes.len(), |done, memory, length| unsafe {
+            core::ptr::copy_nonoverlapping(bytes[done..].as_ptr(), memory, length);
+        })
+    }
+
+    /// Fills `buffer` from the memory from `offset`, `NotMapped` if it goes past its end
+    pub fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), MapError> {
+        self.copy(offset, buffer.len(), |done, memory, length| unsafe {
+            core::ptr::copy_nonoverlapping(memory, buffer[done..].as_mut_ptr(), length);
+        })
+    }
+
+    /// Calls `f` with offset, kernel pointer and length of each page sized piece of `length`
+    /// bytes from `offset`
+    fn copy(&self, offset: u64, length: usize, mut f: impl FnMut(usize, *mut u8, usize)) -> Result<(), MapError> {
+        if offset.checked_add(length as u64).is_none_or(|end| end > self.size()) {
+            return Err(MapError::NotMapped);
+        }
+
+        let mut done = 0;
+
+        while done < length {
+            let current = offset + done as u64;
+            let chunk = (PAGE_SIZE - current % PAGE_SIZE).min((length - done) as u64) as usize;
+            let frame = self.frames[(current / PAGE_SIZE) as usize];
+            let memory = physical_to_virtual(frame.start_address() + current % PAGE_SIZE);
+            f(done, memory.as_mut_ptr(), chunk);
+            done += chunk;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for &frame in &self.frames {
+            unsafe { release_frame(frame) };
+        }
+    }
+}
+
+static NAMED: Mutex<BTreeMap<String, Arc<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+fn with_named<R>(f: impl FnOnce(&mut BTreeMap<String, Arc<SharedMemory>>) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut NAMED.lock()))
+}
+
+/// Shared memory called `name`, created with `size` bytes if there's none yet
+pub fn open(name: &str, size: u64) -> Result<Arc<SharedMemory>, MapError> {
+    if let Some(memory) = with_named(|named| named.get(name).cloned()) {
+        return Ok(memory);
+    }
+
+    let memory = SharedMemory::new(size)?;
+
+    // Someone else may have created it meanwhile, theirs wins
+    Ok(with_named(|named| named.entry(String::from(name)).or_insert(memory).clone()))
+}
+
+/// Removes `name` from the registry, its memory stays while it's mapped. False if there's no such
+/// object.
+pub fn unlink(name: &str) -> bool {
+    let memory = with_named(|named| named.remove(name));
+    memory.is_some()
+}
@@ -1,22 +1,22 @@
+use alloc::vec::Vec;
+
 use x86_64::instructions::port::Port;
 
-pub fn read_config_u16(bus: u8, slot: u8, func: u8, offset: u8) -> u16 {
-    let lbus: u32 = bus as u32;
-    let lslot: u32 = slot as u32;
-    let lfunc: u32 = func as u32;
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
 
-    let address1: u32 = (lbus << 16) | (lslot << 11);
-    let address: u32;
+/// Reads the configuration dword containing `offset` of function `func` of device `slot` on `bus`
+pub fn read_config_u32(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+    let address = 0x8000_0000 | (bus as u32) << 16 | (slot as u32) << 11 | (func as u32) << 8 | (offset & 0xFC) as u32;
 
-    if address1 > 0 {
-        address = address1;
-    } else {
-        address = (lfunc << 8) | (offset & 0xFC) as u32 | (0x80000000 as u32);
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address);
+        Port::<u32>::new(CONFIG_DATA).read()
     }
+}
 
-    unsafe { Port::<u32>::new(0xCF8).write(address); }
-    let result: u32;
-    unsafe { result = Port::<u32>::new(0xCFC).read(); }
+pub fn read_config_u16(bus: u8, slot: u8, func: u8, offset: u8) -> u16 {
+    let result = read_config_u32(bus, slot, func, offset);
     return ((result >> ((offset & 2) << 3)) & 0xFFFF) as u16; // << 3 == * 8
 }
 
@@ -30,3 +30,66 @@ pub fn check_vendor(bus: u8, slot: u8) -> u16 {
 
     return vendor;
 }
+
+/// Function of a device on the PCI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub bus: u8,
+    pub slot: u8,
+    pub function: u8,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8
+}
+
+impl Device {
+    fn read(bus: u8, slot: u8, function: u8) -> Option<Device> {
+        let id = read_config_u32(bus, slot, function, 0);
+
+        if id as u16 == 0xFFFF {
+            return None;
+        }
+
+        let class = read_config_u32(bus, slot, function, 8);
+        Some(Device { bus, slot, function, vendor: id as u16, device: (id >> 16) as u16, class: (class >> 24) as u8, subclass: (class >> 16) as u8 })
+    }
+
+    /// Name of the device class
+    pub fn class_name(&self) -> &'static str {
+        match self.class {
+            0x01 => "Mass storage controller",
+            0x02 => "Network controller",
+            0x03 => "Display controller",
+            0x04 => "Multimedia controller",
+            0x05 => "Memory controller",
+            0x06 => "Bridge",
+            0x07 => "Communication controller",
+            0x08 => "System peripheral",
+            0x0C => "Serial bus controller",
+            _ => "Other"
+        }
+    }
+}
+
+/// Every function of every device, found by checking all slots of all buses
+pub fn devices() -> Vec<Device> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255 {
+        for slot in 0..32 {
+            let Some(device) = Device::read(bus, slot, 0) else {
+                continue;
+            };
+
+            devices.push(device);
+
+            // Header type bit 7 marks devices with several functions
+            if read_config_u32(bus, slot, 0, 0x0C) >> 16 & 0x80 != 0 {
+                devices.extend((1..8).filter_map(|function| Device::read(bus, slot, function)));
+            }
+        }
+    }
+
+    devices
+}
@@ -0,0 +1,49 @@
+//! Restarting and turning off the machine
+
+use x86_64::{instructions::{interrupts, port::Port, tables::lidt}, structures::DescriptorTablePointer, VirtAddr};
+
+use crate::{acpi, halt_loop};
+
+const KEYBOARD_CONTROLLER: u16 = 0x64;
+/// Keyboard controller command that pulses the CPU reset line
+const PULSE_RESET: u8 = 0xFE;
+
+/// Restarts the machine with the ACPI reset register, the keyboard controller or a triple fault
+pub fn reboot() -> ! {
+    interrupts::disable();
+    acpi::reset();
+
+    unsafe {
+        let mut controller: Port<u8> = Port::new(KEYBOARD_CONTROLLER);
+
+        // Input buffer has to be empty before a command
+        for _ in 0..100_000 {
+            if controller.read() & 2 == 0 {
+                break;
+            }
+        }
+
+        controller.write(PULSE_RESET);
+
+        // Exception with no IDT to handle it
+        lidt(&DescriptorTablePointer { limit: 0, base: VirtAddr::zero() });
+        core::arch::asm!("int3");
+    }
+
+    halt_loop();
+}
+
+/// Turns the machine off with ACPI, or the way emulators without it do. Halts if neither works.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    acpi::enter_soft_off();
+
+    unsafe {
+        // QEMU, Bochs and older QEMU, VirtualBox
+        Port::<u16>::new(0x604).write(0x2000);
+        Port::<u16>::new(0xB004).write(0x2000);
+        Port::<u16>::new(0x4004).write(0x3400);
+    }
+
+    halt_loop();
+}
@@ -0,0 +1,64 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{console, fs::file::{File, OpenFlags}};
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+pub const MAX_FILES: usize = 256;
+
+/// Open files of a process by descriptor. Cloning shares the files, along with their positions.
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<Arc<File>>>
+}
+
+impl FileTable {
+    /// Table with the console as standard input, output and error
+    pub fn console() -> FileTable {
+        let output = Arc::new(console::open(OpenFlags::WRITE));
+        let mut files = FileTable::default();
+        files.insert_at(STDIN, Arc::new(console::open(OpenFlags::READ)));
+        files.insert_at(STDOUT, output.clone());
+        files.insert_at(STDERR, output);
+        files
+    }
+
+    /// Gives `file` the lowest free descriptor, `None` if there are `MAX_FILES` already
+    pub fn insert(&mut self, file: Arc<File>) -> Option<usize> {
+        let free = (0..MAX_FILES).find(|&descriptor| self.files.get(descriptor).is_none_or(Option::is_none))?;
+        self.insert_at(free, file);
+        Some(free)
+    }
+
+    /// Puts `file` at `descriptor`, returns the file it replaces. `None` without a change if
+    /// `descriptor` is `MAX_FILES` or above.
+    pub fn insert_at(&mut self, descriptor: usize, file: Arc<File>) -> Option<Option<Arc<File>>> {
+        if descriptor >= MAX_FILES {
+            return None;
+        }
+
+        if self.files.len() <= descriptor {
+            self.files.resize(descriptor + 1, None);
+        }
+
+        Some(self.files[descriptor].replace(file))
+    }
+
+    pub fn get(&self, descriptor: usize) -> Option<Arc<File>> {
+        self.files.get(descriptor).cloned().flatten()
+    }
+
+    pub fn remove(&mut self, descriptor: usize) -> Option<Arc<File>> {
+        self.files.get_mut(descriptor)?.take()
+    }
+
+    /// Number of open descriptors
+    pub fn len(&self) -> usize {
+        self.files.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::ipc::Endpoint;
+
+pub const MAX_HANDLES: usize = 256;
+
+/// Channel endpoints of a process by handle. Cloning shares the endpoints.
+#[derive(Clone, Default)]
+pub struct HandleTable {
+    handles: Vec<Option<Arc<Endpoint>>>
+}
+
+impl HandleTable {
+    /// Gives `endpoint` the lowest free handle, `None` if there are `MAX_HANDLES` already
+    pub fn insert(&mut self, endpoint: Arc<Endpoint>) -> Option<usize> {
+        let free = (0..MAX_HANDLES).find(|&handle| self.handles.get(handle).is_none_or(Option::is_none))?;
+        self.insert_at(free, endpoint);
+        Some(free)
+    }
+
+    /// Puts `endpoint` at `handle`, returns the endpoint it replaces. `None` without a change if
+    /// `handle` is `MAX_HANDLES` or above.
+    pub fn insert_at(&mut self, handle: usize, endpoint: Arc<Endpoint>) -> Option<Option<Arc<Endpoint>>> {
+        if handle >= MAX_HANDLES {
+            return None;
+        }
+
+        if self.handles.len() <= handle {
+            self.handles.resize(handle + 1, None);
+        }
+
+        Some(self.handles[handle].replace(endpoint))
+    }
+
+    pub fn get(&self, handle: usize) -> Option<Arc<Endpoint>> {
+        self.handles.get(handle).cloned().flatten()
+    }
+
+    pub fn remove(&mut self, handle: usize) -> Option<Arc<Endpoint>> {
+        self.handles.get_mut(handle)?.take()
+    }
+
+    /// Number of open handles
+    pub fn len(&self) -> usize {
+        self.handles.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Handles that may still be inserted
+    pub fn free(&self) -> usize {
+        MAX_HANDLES - self.len()
+    }
+}
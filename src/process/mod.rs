@@ -0,0 +1,388 @@
+//! Processes: a program running on one thread in its own address space, with open files, channel
+//! handles, a parent and children. Address spaces share the kernel's level 4 entries, see
+//! `memory::AddressSpace`. A process that exits frees its memory, files and handles right away and
+//! stays as a zombie with only its exit status until its parent waits for it.
+//!
+//! Processes started by kernel code with `spawn` have the kernel as their parent, any kernel
+//! thread may wait for them. Children of an exited process are orphans, nobody waits for them.
+
+pub mod files;
+pub mod handles;
+pub mod signal;
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{mem, sync::atomic::{AtomicU64, Ordering}};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    loader::Program,
+    memory::{AddressSpace, MapError},
+    thread::{self, ThreadId}
+};
+
+use files::FileTable;
+use handles::HandleTable;
+use signal::{DefaultAction, Signal, Signals};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u64);
+
+impl Pid {
+    fn new() -> Pid {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(pid: u64) -> Pid {
+        Pid(pid)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Process called `exit` with this code
+    Exited(i32),
+    /// Process was ended by a signal
+    Signaled(Signal)
+}
+
+impl ExitStatus {
+    /// Status the way `wait` reports it to user code: exit code in bits 8 to 15, or the signal in
+    /// the low bits with bit 7 set if its default action dumps core
+    pub fn wait_status(self) -> u64 {
+        match self {
+            ExitStatus::Exited(code) => (code as u64 & 0xFF) << 8,
+            ExitStatus::Signaled(signal) if signal.default_action() == DefaultAction::Core => signal.number() | 0x80,
+            ExitStatus::Signaled(signal) => signal.number()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    /// Calling thread is not a process
+    NotProcess,
+    /// No child to wait for, or the given one is not a child of the caller
+    NoChild,
+    /// No process has the given id
+    NoProcess,
+    /// Blocking call gave up because a signal arrived
+    Interrupted,
+    /// Signal can't be caught or blocked
+    InvalidSignal,
+    Map(MapError)
+}
+
+impl From<MapError> for ProcessError {
+    fn from(error: MapError) -> Self {
+        ProcessError::Map(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parent {
+    /// Started by kernel code, kernel threads may wait for it
+    Kernel,
+    Process(Pid),
+    /// Parent has exited, nobody waits for it
+    Orphan
+}
+
+struct Process {
+    name: String,
+    parent: Parent,
+    children: Vec<Pid>,
+    thread: ThreadId,
+    /// `None` once the process has exited
+    address_space: Option<Arc<AddressSpace>>,
+    files: FileTable,
+    handles: HandleTable,
+    signals: Signals,
+    exit_status: Option<ExitStatus>
+}
+
+impl Process {
+    /// Running process without children, with default signal actions
+    fn new(name: &str, parent: Parent, thread: ThreadId, address_space: Arc<AddressSpace>, files: FileTable, handles: HandleTable) -> Process {
+        Process { name: String::from(name), parent, children: Vec::new(), thread, address_space: Some(address_space), files, handles, signals: Signals::default(), exit_status: None }
+    }
+}
+
+struct Processes {
+    processes: BTreeMap<Pid, Process>,
+    /// Process of each running thread
+    threads: BTreeMap<ThreadId, Pid>,
+    /// Threads blocked in `wait`, every exit wakes them to check again
+    waiting: Vec<ThreadId>
+}
+
+impl Processes {
+    fn current(&self) -> Option<Pid> {
+        self.threads.get(&thread::current()?).copied()
+    }
+
+    /// Parent of processes started by the calling thread
+    fn caller(&self) -> Parent {
+        self.current().map_or(Parent::Kernel, Parent::Process)
+    }
+
+    fn insert(&mut self, process: Process) -> Pid {
+        let pid = Pid::new();
+
+        if let Parent::Process(parent) = process.parent {
+            self.processes.get_mut(&parent).expect("Parent process is missing").children.push(pid);
+        }
+
+        self.threads.insert(process.thread, pid);
+        self.processes.insert(pid, process);
+        pid
+    }
+
+    /// Takes what `pid` holds, so it's freed outside of the lock
+    fn release(&mut self, pid: Pid) -> (Option<Arc<AddressSpace>>, FileTable, HandleTable) {
+        let process = self.processes.get_mut(&pid).expect("Exiting process is missing");
+        (process.address_space.take(), mem::take(&mut process.files), mem::take(&mut process.handles))
+    }
+
+    /// Turns `pid` into a zombie
+    fn exit(&mut self, pid: Pid, status: ExitStatus) {
+        let process = self.processes.get_mut(&pid).expect("Exiting process is missing");
+        process.exit_status = Some(status);
+        let (thread, parent, children) = (process.thread, process.parent, mem::take(&mut process.children));
+        self.threads.remove(&thread);
+
+        for child in children {
+            match self.processes.get_mut(&child) {
+                Some(child_process) if child_process.exit_status.is_some() => {
+                    self.processes.remove(&child);
+                }
+                Some(child_process) => child_process.parent = Parent::Orphan,
+                None => {}
+            }
+        }
+
+        if parent == Parent::Orphan {
+            self.processes.remove(&pid);
+        }
+
+        for waiter in self.waiting.drain(..) {
+            thread::unpark(waiter);
+        }
+    }
+
+    /// Removes an exited child of `parent`, `pid` if given. `None` if there are children but
+    /// none has exited yet.
+    fn reap(&mut self, parent: Parent, pid: Option<Pid>) -> Result<Option<(Pid, ExitStatus)>, ProcessError> {
+        let mut children = self.processes.iter().filter(|&(&child, process)| process.parent == parent && pid.is_none_or(|pid| pid == child)).peekable();
+
+        if children.peek().is_none() {
+            return Err(ProcessError::NoChild);
+        }
+
+        let Some((child, status)) = children.find_map(|(&child, process)| Some((child, process.exit_status?))) else {
+            return Ok(None);
+        };
+
+        self.processes.remove(&child);
+
+        if let Parent::Process(parent) = parent {
+            if let Some(parent) = self.processes.get_mut(&parent) {
+                parent.children.retain(|&other| other != child);
+            }
+        }
+
+        Ok(Some((child, status)))
+    }
+}
+
+static PROCESSES: Mutex<Processes> = Mutex::new(Processes { processes: BTreeMap::new(), threads: BTreeMap::new(), waiting: Vec::new() });
+
+/// Runs `f` on process table with interrupts disabled, exits take the lock from fault handlers
+fn with_processes<R>(f: impl FnOnce(&mut Processes) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut PROCESSES.lock()))
+}
+
+/// Starts `program` as a new process named `name`, a child of the calling process or of the
+/// kernel, with the console as standard input and output. Needs threads.
+pub fn spawn(program: Program, name: &str) -> Pid {
+    spawn_with_files(program, name, FileTable::console())
+}
+
+/// Starts `program` like `spawn` with open files `files`, e.g. ends of pipes as standard input
+/// and output
+pub fn spawn_with_files(program: Program, name: &str, files: FileTable) -> Pid {
+    spawn_with(program, name, files, HandleTable::default())
+}
+
+/// Starts `program` like `spawn` with open files `files` and channel endpoints `handles`, e.g.
+/// one to talk to the process that started it
+pub fn spawn_with(program: Program, name: &str, files: FileTable, handles: HandleTable) -> Pid {
+    let address_space = program.address_space().clone();
+
+    // Lock is held until the process is in the table, so its first system call finds it
+    with_processes(|processes| {
+        let parent = processes.caller();
+        let handle = program.spawn(name);
+        processes.insert(Process::new(name, parent, handle.id(), address_space, files, handles))
+    })
+}
+
+/// Process of the calling thread
+pub fn current() -> Option<Pid> {
+    with_processes(|processes| processes.current())
+}
+
+/// Address space of the calling process
+pub fn address_space() -> Option<Arc<AddressSpace>> {
+    with_processes(|processes| {
+        let pid = processes.current()?;
+        processes.processes[&pid].address_space.clone()
+    })
+}
+
+/// Runs `f` with open files of the calling process, `None` if the caller is not a process
+pub fn with_files<R>(f: impl FnOnce(&mut FileTable) -> R) -> Option<R> {
+    with_processes(|processes| {
+        let pid = processes.current()?;
+        processes.processes.get_mut(&pid).map(|process| f(&mut process.files))
+    })
+}
+
+/// Runs `f` with channel handles of the calling process, `None` if the caller is not a process
+pub fn with_handles<R>(f: impl FnOnce(&mut HandleTable) -> R) -> Option<R> {
+    with_processes(|processes| {
+        let pid = processes.current()?;
+        processes.processes.get_mut(&pid).map(|process| f(&mut process.handles))
+    })
+}
+
+/// Starts a child of the calling process with a copy of its memory, its open files and its
+/// handles. The child's thread switches to the copy and calls `entry`, which should continue in
+/// ring 3 and never return.
+pub fn fork(entry: impl FnOnce() + Send + 'static) -> Result<Pid, ProcessError> {
+    let (name, address_space, files, handles, signals) = with_processes(|processes| {
+        let process = &processes.processes[&processes.current()?];
+        Some((process.name.clone(), process.address_space.clone()?, process.files.clone(), process.handles.clone(), process.signals.fork()))
+    }).ok_or(ProcessError::NotProcess)?;
+
+    // Copying takes a while, the caller's memory doesn't change meanwhile because its only
+    // thread is here
+    let copy = Arc::new(address_space.duplicate()?);
+    let page_table = copy.page_table();
+
+    with_processes(|processes| {
+        let parent = processes.current().ok_or(ProcessError::NotProcess)?;
+
+        let handle = thread::Builder::new().name(&name).spawn(move || {
+            thread::set_page_table(Some(page_table));
+            entry();
+            exit(ExitStatus::Signaled(Signal::KILL))
+        });
+
+        let process = Process::new(&name, Parent::Process(parent), handle.id(), copy, files, handles);
+        Ok(processes.insert(Process { signals, ..process }))
+    })
+}
+
+/// Replaces memory of the calling process with `program`, which is renamed to `name`. The caller
+/// has to continue at the program's entry point, nothing of the old program is left to return to.
+pub fn exec(program: &Program, name: &str) -> Result<(), ProcessError> {
+    let old = with_processes(|processes| {
+        let process = processes.processes.get_mut(&processes.current()?)?;
+        process.name = String::from(name);
+        process.signals.exec();
+        process.address_space.replace(program.address_space().clone())
+    }).ok_or(ProcessError::NotProcess)?;
+
+    thread::set_page_table(Some(program.address_space().page_table()));
+    // Old address space isn't loaded anymore, so it can be freed
+    drop(old);
+    Ok(())
+}
+
+/// Ends the calling process with `status`, waking its parent if it waits. Threads that are not
+/// processes just end.
+pub fn exit(status: ExitStatus) -> ! {
+    // Address space can only be freed once it's not loaded
+    thread::set_page_table(None);
+
+    let released = with_processes(|processes| {
+        let pid = processes.current()?;
+        Some(processes.release(pid))
+    });
+
+    // Memory is freed before the parent can see the exit
+    drop(released);
+
+    with_processes(|processes| {
+        if let Some(pid) = processes.current() {
+            processes.exit(pid, status);
+        }
+    });
+
+    thread::exit()
+}
+
+/// Waits until child `pid`, or any child if `None`, exits and returns its id and status. Kernel
+/// threads wait for processes started with `spawn`.
+pub fn wait(pid: Option<Pid>) -> Result<(Pid, ExitStatus), ProcessError> {
+    loop {
+        let reaped = with_processes(|processes| {
+            let reaped = processes.reap(processes.caller(), pid)?;
+
+            if reaped.is_none() {
+                processes.waiting.push(thread::current().expect("Threads are not initialized"));
+            }
+
+            Ok::<_, ProcessError>(reaped)
+        })?;
+
+        match reaped {
+            Some(result) => return Ok(result),
+            // Exit in between leaves an unpark token, so this doesn't miss it
+            None => thread::park()
+        }
+
+        if signal::interrupted() {
+            return Err(ProcessError::Interrupted);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub name: String,
+    pub parent: Parent,
+    pub thread: ThreadId,
+    /// `Some` for zombies
+    pub exit_status: Option<ExitStatus>,
+    /// Stopped by a signal until `SIGCONT`
+    pub stopped: bool,
+    pub open_files: usize
+}
+
+/// Snapshot of all processes, including zombies
+pub fn list() -> Vec<ProcessInfo> {
+    with_processes(|processes| {
+        processes.processes.iter().map(|(&pid, process)| ProcessInfo {
+            pid,
+            name: process.name.clone(),
+            parent: process.parent,
+            thread: process.thread,
+            exit_status: process.exit_status,
+            stopped: process.signals.stopped(),
+            open_files: process.files.len()
+        }).collect()
+    })
+}
+
+pub fn info(pid: Pid) -> Option<ProcessInfo> {
+    list().into_iter().find(|info| info.pid == pid)
+}
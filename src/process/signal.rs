@@ -0,0 +1,431 @@
+//! Signals: every process has pending and blocked signals and an action for each signal. `kill`
+//! makes a signal pending, it's acted on when the process next returns to ring 3, after a system
+//! call, a fault or a timer tick. Default actions end, stop or continue the process or do
+//! nothing. A handler runs on the user stack with a `Frame` below the interrupted state, its
+//! return address is a restorer of the program's choice that calls `sigreturn`.
+//!
+//! `SIGKILL` and `SIGSTOP` can't be caught, blocked or ignored. Faults raise their signal even if
+//! it's blocked or ignored, the default action ends the process then.
+
+use core::mem::size_of;
+
+use super::{exit, with_processes, ExitStatus, Pid, ProcessError};
+use crate::{
+    fs::page_cache::FileId,
+    gdt,
+    process::files::STDIN,
+    syscall::{user, Error, Registers},
+    thread,
+    usermode
+};
+
+/// Signals are numbered from 1 up to below this
+pub const COUNT: usize = 32;
+
+/// Handler doesn't block its own signal while it runs
+pub const NO_DEFER: u64 = 0x4000_0000;
+/// Action goes back to the default once the handler is called
+pub const RESET_HANDLER: u64 = 0x8000_0000;
+
+/// Handlers start below the red zone of the interrupted code
+const RED_ZONE: u64 = 128;
+/// Flags of the interrupted code that `sigreturn` takes from the frame, others are fixed
+const USER_FLAGS: u64 = 0xDD5;
+const TRAP_FLAG: u64 = 1 << 8;
+const DIRECTION_FLAG: u64 = 1 << 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Signal(u8);
+
+impl Signal {
+    pub const HUP: Signal = Signal(1);
+    pub const INT: Signal = Signal(2);
+    pub const QUIT: Signal = Signal(3);
+    pub const ILL: Signal = Signal(4);
+    pub const TRAP: Signal = Signal(5);
+    pub const ABRT: Signal = Signal(6);
+    pub const BUS: Signal = Signal(7);
+    pub const FPE: Signal = Signal(8);
+    pub const KILL: Signal = Signal(9);
+    pub const USR1: Signal = Signal(10);
+    pub const SEGV: Signal = Signal(11);
+    pub const USR2: Signal = Signal(12);
+    pub const PIPE: Signal = Signal(13);
+    pub const ALRM: Signal = Signal(14);
+    pub const TERM: Signal = Signal(15);
+    pub const CHLD: Signal = Signal(17);
+    pub const CONT: Signal = Signal(18);
+    pub const STOP: Signal = Signal(19);
+    pub const TSTP: Signal = Signal(20);
+    pub const TTIN: Signal = Signal(21);
+    pub const TTOU: Signal = Signal(22);
+    pub const URG: Signal = Signal(23);
+    pub const WINCH: Signal = Signal(28);
+
+    /// Signal with Linux number `number`, `None` unless it's from 1 to `COUNT - 1`
+    pub fn new(number: u64) -> Option<Signal> {
+        (1..COUNT as u64).contains(&number).then_some(Signal(number as u8))
+    }
+
+    pub fn number(self) -> u64 {
+        u64::from(self.0)
+    }
+
+    /// Whether the action may be changed and the signal blocked
+    pub fn catchable(self) -> bool {
+        self != Signal::KILL && self != Signal::STOP
+    }
+
+    pub fn default_action(self) -> DefaultAction {
+        match self {
+            Signal::QUIT | Signal::ILL | Signal::TRAP | Signal::ABRT | Signal::BUS | Signal::FPE | Signal::SEGV => DefaultAction::Core,
+            Signal::STOP | Signal::TSTP | Signal::TTIN | Signal::TTOU => DefaultAction::Stop,
+            Signal::CONT => DefaultAction::Continue,
+            Signal::CHLD | Signal::URG | Signal::WINCH => DefaultAction::Ignore,
+            _ => DefaultAction::Terminate
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate, `wait` reports a core dump though none is written
+    Core,
+    Stop,
+    Continue,
+    Ignore
+}
+
+/// Set of signals as a bit mask, bit `n` is signal `n`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    pub const EMPTY: SignalSet = SignalSet(0);
+
+    pub fn contains(self, signal: Signal) -> bool {
+        self.0 & 1 << signal.0 != 0
+    }
+
+    pub fn insert(&mut self, signal: Signal) {
+        self.0 |= 1 << signal.0;
+    }
+
+    pub fn remove(&mut self, signal: Signal) {
+        self.0 &= !(1 << signal.0);
+    }
+
+    /// Without `SIGKILL`, `SIGSTOP` and bits that are no signal
+    fn blockable(self) -> SignalSet {
+        SignalSet(self.0 & !(1 | 1 << Signal::KILL.0 | 1 << Signal::STOP.0) & ((1 << COUNT) - 1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Default,
+    Ignore,
+    /// User function called with the signal number in `rdi`
+    Handler(Handler)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handler {
+    pub address: u64,
+    /// Blocked while the handler runs, besides the signal itself
+    pub mask: SignalSet,
+    /// `NO_DEFER` and `RESET_HANDLER`
+    pub flags: u64,
+    /// Return address of the handler, code there should call `sigreturn`
+    pub restorer: u64
+}
+
+impl Action {
+    /// Whether the signal is dropped without doing anything
+    fn ignores(self, signal: Signal) -> bool {
+        match self {
+            Action::Default => matches!(signal.default_action(), DefaultAction::Ignore | DefaultAction::Continue),
+            Action::Ignore => true,
+            Action::Handler(_) => false
+        }
+    }
+}
+
+/// Signal state of a process
+#[derive(Debug, Clone)]
+pub(super) struct Signals {
+    pending: SignalSet,
+    blocked: SignalSet,
+    actions: [Action; COUNT],
+    /// Stopped by a signal until `SIGCONT`
+    stopped: bool
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Signals { pending: SignalSet::EMPTY, blocked: SignalSet::EMPTY, actions: [Action::Default; COUNT], stopped: false }
+    }
+}
+
+impl Signals {
+    fn action(&self, signal: Signal) -> Action {
+        self.actions[signal.0 as usize]
+    }
+
+    fn send(&mut self, signal: Signal) {
+        if signal == Signal::CONT {
+            self.stopped = false;
+
+            for stop in [Signal::STOP, Signal::TSTP, Signal::TTIN, Signal::TTOU] {
+                self.pending.remove(stop);
+            }
+        } else if signal.default_action() == DefaultAction::Stop {
+            self.pending.remove(Signal::CONT);
+        }
+
+        // Blocked ones stay, the action may change before they're unblocked
+        if self.blocked.contains(signal) || !self.action(signal).ignores(signal) {
+            self.pending.insert(signal);
+        }
+    }
+
+    /// Sends `signal` so that it can't be blocked or ignored, for faults
+    fn force(&mut self, signal: Signal) {
+        if self.blocked.contains(signal) || self.action(signal) == Action::Ignore {
+            self.blocked.remove(signal);
+            self.actions[signal.0 as usize] = Action::Default;
+        }
+
+        self.send(signal);
+    }
+
+    /// Takes the lowest pending signal that isn't blocked. For handlers it blocks what they ask
+    /// for, the signals blocked before are returned too.
+    fn take(&mut self) -> Option<(Signal, Action, SignalSet)> {
+        let deliverable = self.pending.0 & !self.blocked.0;
+
+        if deliverable == 0 {
+            return None;
+        }
+
+        let signal = Signal(deliverable.trailing_zeros() as u8);
+        let action = self.action(signal);
+        let blocked = self.blocked;
+        self.pending.remove(signal);
+
+        if let Action::Handler(handler) = action {
+            self.blocked = SignalSet(self.blocked.0 | handler.mask.0).blockable();
+
+            if handler.flags & NO_DEFER == 0 {
+                self.blocked.insert(signal);
+            }
+
+            if handler.flags & RESET_HANDLER != 0 {
+                self.actions[signal.0 as usize] = Action::Default;
+            }
+        }
+
+        Some((signal, action, blocked))
+    }
+
+    /// Whether a pending signal needs the process back in ring 3, blocking calls give up then
+    fn interrupting(&self) -> bool {
+        let deliverable = SignalSet(self.pending.0 & !self.blocked.0);
+        (1..COUNT as u8).map(Signal).any(|signal| deliverable.contains(signal) && !self.action(signal).ignores(signal))
+    }
+
+    /// State of a forked child: same actions and mask, nothing pending
+    pub(super) fn fork(&self) -> Signals {
+        Signals { pending: SignalSet::EMPTY, stopped: false, ..self.clone() }
+    }
+
+    /// Handlers are gone with the old program, ignored signals stay ignored
+    pub(super) fn exec(&mut self) {
+        for action in &mut self.actions {
+            if let Action::Handler(_) = action {
+                *action = Action::Default;
+            }
+        }
+    }
+
+    pub(super) fn stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+/// Runs `f` with signal state of the calling process, `None` if the caller is not a process
+fn with_signals<R>(f: impl FnOnce(&mut Signals) -> R) -> Option<R> {
+    with_processes(|processes| {
+        let pid = processes.current()?;
+        processes.processes.get_mut(&pid).map(|process| f(&mut process.signals))
+    })
+}
+
+/// Sends `signal` to process `pid`, waking it if it's blocked. Zombies ignore it.
+pub fn kill(pid: Pid, signal: Signal) -> Result<(), ProcessError> {
+    let thread = with_processes(|processes| {
+        let process = processes.processes.get_mut(&pid)?;
+
+        if process.exit_status.is_some() {
+            return Some(None);
+        }
+
+        process.signals.send(signal);
+        Some(Some(process.thread))
+    }).ok_or(ProcessError::NoProcess)?;
+
+    if let Some(thread) = thread {
+        thread::unpark(thread);
+    }
+
+    Ok(())
+}
+
+/// Sends `signal` to every process with `terminal` as standard input, what Ctrl+C does. Safe to
+/// call from interrupt handlers.
+pub fn kill_terminal_readers(terminal: FileId, signal: Signal) {
+    with_processes(|processes| {
+        let readers = processes.processes.values_mut().filter(|process| process.exit_status.is_none());
+
+        for process in readers.filter(|process| process.files.get(STDIN).is_some_and(|file| file.id() == terminal)) {
+            process.signals.send(signal);
+            thread::unpark(process.thread);
+        }
+    });
+}
+
+/// What the calling process does on `signal`
+pub fn action(signal: Signal) -> Result<Action, ProcessError> {
+    with_signals(|signals| signals.action(signal)).ok_or(ProcessError::NotProcess)
+}
+
+/// Sets what the calling process does on `signal`, returns the previous action
+pub fn set_action(signal: Signal, action: Action) -> Result<Action, ProcessError> {
+    if !signal.catchable() {
+        return Err(ProcessError::InvalidSignal);
+    }
+
+    with_signals(|signals| {
+        let old = core::mem::replace(&mut signals.actions[signal.0 as usize], action);
+
+        // Pending signals that are ignored now are dropped, like ones sent later
+        if action.ignores(signal) {
+            signals.pending.remove(signal);
+        }
+
+        old
+    }).ok_or(ProcessError::NotProcess)
+}
+
+/// Changes the blocked signals of the calling process to what `f` returns for them, `SIGKILL`
+/// and `SIGSTOP` are never blocked. Returns the previously blocked signals.
+pub fn update_blocked(f: impl FnOnce(SignalSet) -> SignalSet) -> Result<SignalSet, ProcessError> {
+    with_signals(|signals| {
+        let old = signals.blocked;
+        signals.blocked = f(old).blockable();
+        old
+    }).ok_or(ProcessError::NotProcess)
+}
+
+/// Signals of the calling process that wait because they're blocked
+pub fn pending() -> Result<SignalSet, ProcessError> {
+    with_signals(|signals| SignalSet(signals.pending.0 & signals.blocked.0)).ok_or(ProcessError::NotProcess)
+}
+
+/// Whether the calling process has a signal to act on, blocking calls give up and return then
+pub fn interrupted() -> bool {
+    with_signals(|signals| signals.interrupting()).unwrap_or(false)
+}
+
+/// Raises `signal` in the calling process for a fault of its code
+pub(crate) fn fault(signal: Signal) {
+    with_signals(|signals| signals.force(signal));
+}
+
+/// Handler of `signal` as interrupted code sees it when it runs, `rsp` points to `restorer`
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Frame {
+    restorer: u64,
+    signal: u64,
+    registers: Registers,
+    /// Blocked signals before the handler ran
+    blocked: u64
+}
+
+/// Acts on pending signals of the calling process before it returns to ring 3 with `registers`.
+/// Ends or stops the process for default actions, or changes `registers` to call a handler.
+/// Needs interrupts enabled.
+pub(crate) fn deliver(registers: &mut Registers) {
+    while let Some((signal, action, blocked)) = with_signals(Signals::take).flatten() {
+        match action {
+            Action::Ignore => {}
+            Action::Default => match signal.default_action() {
+                DefaultAction::Terminate | DefaultAction::Core => exit(ExitStatus::Signaled(signal)),
+                DefaultAction::Stop => stop(),
+                DefaultAction::Continue | DefaultAction::Ignore => {}
+            },
+            Action::Handler(handler) => {
+                if enter_handler(registers, signal, handler, blocked).is_err() {
+                    // No room for the frame, the stack is broken
+                    exit(ExitStatus::Signaled(Signal::SEGV));
+                }
+
+                return;
+            }
+        }
+    }
+}
+
+/// Blocks the calling process until `SIGCONT`, or until it has `SIGKILL` to act on
+fn stop() {
+    with_signals(|signals| signals.stopped = true);
+
+    // `kill` in between leaves an unpark token, so this doesn't miss it
+    while with_signals(|signals| signals.stopped && !signals.pending.contains(Signal::KILL)).unwrap_or(false) {
+        thread::park();
+    }
+}
+
+fn enter_handler(registers: &mut Registers, signal: Signal, handler: Handler, blocked: SignalSet) -> Result<(), Error> {
+    // Returning to an address outside of user memory would fault in the kernel
+    if handler.address >= usermode::USER_END {
+        return Err(Error::BadAddress);
+    }
+
+    let frame = Frame { restorer: handler.restorer, signal: signal.number(), registers: *registers, blocked: blocked.0 };
+    let below = registers.rsp.checked_sub(RED_ZONE + size_of::<Frame>() as u64).ok_or(Error::BadAddress)?;
+    // Aligned like right after a call
+    let address = (below & !15).checked_sub(8).ok_or(Error::BadAddress)?;
+    user::write(address, frame)?;
+
+    registers.rip = handler.address;
+    registers.rsp = address;
+    registers.rdi = signal.number();
+    registers.rsi = 0;
+    registers.rdx = 0;
+    registers.rflags &= !(TRAP_FLAG | DIRECTION_FLAG);
+    Ok(())
+}
+
+/// Takes back the state a handler interrupted from its frame, for `sigreturn` right after the
+/// handler returned to the restorer. Only flags user code may change are taken from the frame.
+pub(crate) fn restore(registers: &mut Registers) -> Result<(), Error> {
+    let frame: Frame = user::read(registers.rsp.wrapping_sub(8))?;
+
+    if frame.registers.rip >= usermode::USER_END {
+        return Err(Error::BadAddress);
+    }
+
+    with_signals(|signals| signals.blocked = SignalSet(frame.blocked).blockable()).ok_or(Error::InvalidSyscall)?;
+
+    *registers = Registers {
+        cs: u64::from(gdt::USER_CODE_SELECTOR),
+        ss: u64::from(gdt::USER_DATA_SELECTOR),
+        rflags: frame.registers.rflags & USER_FLAGS | usermode::USER_RFLAGS,
+        ..frame.registers
+    };
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! Date and time of the CMOS real time clock
+
+use core::fmt;
+
+use x86_64::instructions::{interrupts, port::Port};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+/// Status A bit set while the clock updates its registers
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Status B bits of binary values instead of BCD and of 24 hour time
+const BINARY: u8 = 0x04;
+const HOURS_24: u8 = 0x02;
+/// Hours bit of PM in 12 hour time
+const PM: u8 = 0x80;
+
+/// Time of the clock, usually UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+fn read(register: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(register);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn read_raw() -> [u8; 6] {
+    while read(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    [SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR].map(read)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Current date and time. The clock has no century register everywhere, years are 2000 to 2099.
+pub fn now() -> DateTime {
+    let (raw, status) = interrupts::without_interrupts(|| {
+        // Registers may change while they're read, until two reads agree
+        let mut raw = read_raw();
+
+        loop {
+            let again = read_raw();
+
+            if again == raw {
+                break;
+            }
+
+            raw = again;
+        }
+
+        (raw, read(STATUS_B))
+    });
+
+    let [second, minute, hour, day, month, year] = raw;
+    let pm = hour & PM != 0;
+    let decode = |value: u8| if status & BINARY != 0 { value } else { from_bcd(value) };
+    let mut hour = decode(hour & !PM);
+
+    if status & HOURS_24 == 0 {
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+
+    DateTime { year: 2000 + decode(year) as u16, month: decode(month), day: decode(day), hour, minute: decode(minute), second: decode(second) }
+}
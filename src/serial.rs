@@ -1,24 +1,425 @@
+//! Serial ports `COM1` to `COM4`. Output is sent directly, input arrives with IRQ 4 (`COM1` and
+//! `COM3`) and IRQ 3 (`COM2` and `COM4`) and is kept per port until threads `read` it or tasks
+//! take it from a `task::serial::SerialStream`.
+//!
+//! `COM1` carries kernel messages and is set up on first use, `init` sets up the others once
+//! they're found. Bytes go out as they are, terminals on the other end see `\n` without `\r`.
+//! Ports opened with `open` are terminals instead, see `tty`.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{fmt::{self, Write}, sync::atomic::{AtomicBool, Ordering}};
+
+use futures_util::task::AtomicWaker;
 use lazy_static::lazy_static;
-use spin::Mutex;
-use uart_16550::SerialPort;
-use core::fmt::Write;
-use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::{
+    fs::{file::{File, OpenFlags}, page_cache::FileId, vfs::Dentry, FileType, FsError, Inode, Stat},
+    interrupts::PICS_MUTEX,
+    process::signal,
+    console,
+    sync::SpinLock,
+    thread::{self, ThreadId},
+    tty::{InputBuffer, Line, Received, Tty}
+};
+
+pub const DEFAULT_BAUD_RATE: u32 = 38400;
+/// Rate of divisor 1, others divide it
+const MAX_BAUD_RATE: u32 = 115200;
+/// Inode numbers of the ports are this plus the port index, far above those of pipes
+const SERIAL_INODE: u64 = 1 << 63;
+
+/// Register offsets from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+/// Line control with 8 data bits, no parity and one stop bit, and with the divisor latch
+const EIGHT_N_ONE: u8 = 0x03;
+const DIVISOR_LATCH: u8 = 0x80;
+/// FIFOs on and cleared, interrupt at 14 bytes
+const FIFO_ENABLE: u8 = 0xC7;
+/// Modem control of DTR, RTS and OUT2 which connects the interrupt line, and of loopback
+const MODEM_READY: u8 = 0x0B;
+const LOOPBACK: u8 = 0x1E;
+const RECEIVED_DATA_INTERRUPT: u8 = 0x01;
+/// Line status bits
+const DATA_READY: u8 = 0x01;
+const TRANSMIT_EMPTY: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// Nothing answers at the port
+    NotPresent,
+    /// Rate that doesn't divide 115200
+    InvalidBaudRate
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn base(self) -> u16 {
+        [0x3F8, 0x2F8, 0x3E8, 0x2E8][self.index()]
+    }
+
+    /// Legacy IRQ of the port, `COM3` and `COM4` share those of `COM1` and `COM2`
+    pub fn irq(self) -> u8 {
+        [4, 3, 4, 3][self.index()]
+    }
+
+    /// Name of the port, `ttyS0` to `ttyS3`
+    pub fn name(self) -> &'static str {
+        ["ttyS0", "ttyS1", "ttyS2", "ttyS3"][self.index()]
+    }
+}
+
+/// 16550 compatible UART at a base port
+#[derive(Debug)]
+pub struct Uart {
+    base: u16
+}
+
+impl Uart {
+    /// # Safety
+    /// `base` has to be the base port of a UART, or of nothing
+    pub const unsafe fn new(base: u16) -> Uart {
+        Uart { base }
+    }
+
+    fn register(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+
+    /// Sets up 8N1 at `baud_rate` with FIFOs and receive interrupts. Checks with a loopback test
+    /// that the UART is there, it's left off if not.
+    pub fn init(&mut self, baud_rate: u32) -> Result<(), SerialError> {
+        unsafe {
+            self.register(INTERRUPT_ENABLE).write(0);
+            self.set_baud_rate(baud_rate)?;
+            self.register(FIFO_CONTROL).write(FIFO_ENABLE);
+
+            self.register(MODEM_CONTROL).write(LOOPBACK);
+            self.register(DATA).write(0xAE);
+
+            if self.register(DATA).read() != 0xAE {
+                return Err(SerialError::NotPresent);
+            }
+
+            self.register(MODEM_CONTROL).write(MODEM_READY);
+            self.register(INTERRUPT_ENABLE).write(RECEIVED_DATA_INTERRUPT);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), SerialError> {
+        if baud_rate == 0 || MAX_BAUD_RATE % baud_rate != 0 {
+            return Err(SerialError::InvalidBaudRate);
+        }
+
+        let divisor = (MAX_BAUD_RATE / baud_rate) as u16;
+
+        unsafe {
+            self.register(LINE_CONTROL).write(EIGHT_N_ONE | DIVISOR_LATCH);
+            self.register(DATA).write(divisor as u8);
+            self.register(INTERRUPT_ENABLE).write((divisor >> 8) as u8);
+            self.register(LINE_CONTROL).write(EIGHT_N_ONE);
+        }
+
+        Ok(())
+    }
+
+    /// Sends `byte` once the transmitter has room
+    pub fn send(&mut self, byte: u8) {
+        unsafe {
+            while self.register(LINE_STATUS).read() & TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+
+            self.register(DATA).write(byte);
+        }
+    }
+
+    /// Byte that has arrived, if there is one
+    pub fn try_receive(&mut self) -> Option<u8> {
+        unsafe { (self.register(LINE_STATUS).read() & DATA_READY != 0).then(|| self.register(DATA).read()) }
+    }
+}
+
+impl Write for Uart {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for byte in text.bytes() {
+            self.send(byte);
+        }
+
+        Ok(())
+    }
+}
 
 lazy_static! {
-    pub static ref COM1: Mutex<SerialPort> = {
-        let mut serial_port: SerialPort = unsafe {
-            SerialPort::new(0x3F8)
-        };
-        serial_port.init();
-        Mutex::new(serial_port)
+    pub static ref COM1: SpinLock<Uart> = {
+        let mut uart = unsafe { Uart::new(ComPort::Com1.base()) };
+        // Output without anyone listening goes nowhere
+        let _ = uart.init(DEFAULT_BAUD_RATE);
+        SpinLock::new(uart)
     };
 }
 
+/// `COM2` to `COM4`, set up by `init`
+static OTHER_UARTS: [SpinLock<Uart>; 3] = unsafe { [SpinLock::new(Uart::new(0x2F8)), SpinLock::new(Uart::new(0x3E8)), SpinLock::new(Uart::new(0x2E8))] };
+
+struct Input {
+    buffer: InputBuffer,
+    /// Threads waiting for input
+    waiting: Vec<ThreadId>
+}
+
+static INPUTS: [SpinLock<Input>; 4] = [const { SpinLock::new(Input { buffer: InputBuffer::new(), waiting: Vec::new() }) }; 4];
+pub(crate) static WAKERS: [AtomicWaker; 4] = [const { AtomicWaker::new() }; 4];
+/// Ports whose input the interrupt handler takes
+static ACTIVE: [AtomicBool; 4] = [const { AtomicBool::new(false) }; 4];
+static TTYS: [Tty; 4] = [Tty::new(file_id(0)), Tty::new(file_id(1)), Tty::new(file_id(2)), Tty::new(file_id(3))];
+
+fn uart(port: ComPort) -> &'static SpinLock<Uart> {
+    match port {
+        ComPort::Com1 => &COM1,
+        port => &OTHER_UARTS[port.index() - 1]
+    }
+}
+
+/// Sets up `port` at `baud_rate` and starts taking its input, `COM1` too if it's set up already
+pub fn init(port: ComPort, baud_rate: u32) -> Result<(), SerialError> {
+    uart(port).lock().init(baud_rate)?;
+    ACTIVE[port.index()].store(true, Ordering::Release);
+
+    // Unmask the IRQ and the cascade to reach it
+    let mut pics = PICS_MUTEX.lock();
+    let [primary, secondary] = unsafe { pics.read_masks() };
+    unsafe { pics.write_masks(primary & !(1 << port.irq()) & !(1 << 2), secondary) };
+    Ok(())
+}
+
+/// Ports that answer, `COM1` first
+pub fn probe() -> Vec<ComPort> {
+    ComPort::ALL.into_iter().filter(|&port| ACTIVE[port.index()].load(Ordering::Acquire) || init(port, DEFAULT_BAUD_RATE).is_ok()).collect()
+}
+
+pub fn set_baud_rate(port: ComPort, baud_rate: u32) -> Result<(), SerialError> {
+    uart(port).lock().set_baud_rate(baud_rate)
+}
+
+/// Takes input of ports on `irq`, called by the interrupt handler
+pub(crate) fn on_interrupt(irq: u8) {
+    for port in ComPort::ALL.into_iter().filter(|port| port.irq() == irq && ACTIVE[port.index()].load(Ordering::Acquire)) {
+        let mut uart = uart(port).lock();
+        let mut input = INPUTS[port.index()].lock();
+        let mut received = false;
+
+        while let Some(byte) = uart.try_receive() {
+            input.buffer.push(arrive(port, byte));
+            received = true;
+        }
+
+        if received {
+            for thread in input.waiting.drain(..) {
+                thread::unpark(thread);
+            }
+
+            WAKERS[port.index()].wake();
+        }
+    }
+}
+
+/// Sends the signal of `byte` that arrived on `port`, if it's one for its terminal
+fn arrive(port: ComPort, byte: u8) -> Received {
+    let received = TTYS[port.index()].arrive(byte);
+
+    // `COM1` input is console input too, the byte is a signal if it's one for either
+    match port {
+        ComPort::Com1 => match console::tty(0).arrive(byte) {
+            Received::Byte(_) => received,
+            signal => signal
+        },
+        _ => received
+    }
+}
+
+/// Input received on `port`, if some is waiting
+pub(crate) fn receive(port: ComPort) -> Option<Received> {
+    let mut input = INPUTS[port.index()].lock();
+
+    // Before `init` nothing fills the buffer, the port is polled then
+    match input.buffer.pop() {
+        Some(received) => Some(received),
+        None if !ACTIVE[port.index()].load(Ordering::Acquire) => {
+            drop(input);
+            let byte = uart(port).lock().try_receive()?;
+            Some(arrive(port, byte))
+        }
+        None => None
+    }
+}
+
+/// Byte received on `port`, if one is waiting
+pub fn try_receive(port: ComPort) -> Option<u8> {
+    receive(port).map(Received::byte)
+}
+
+/// Reads input of `port` that is already there, 0 if there's none
+pub fn try_read(port: ComPort, buf: &mut [u8]) -> usize {
+    let mut count = 0;
+
+    while count < buf.len() {
+        match try_receive(port) {
+            Some(byte) => {
+                buf[count] = byte;
+                count += 1;
+            }
+            None => break
+        }
+    }
+
+    count
+}
+
+/// Reads input of `port`, waiting until there's at least one byte or a signal for the calling
+/// process
+pub fn read(port: ComPort, buf: &mut [u8]) -> Result<usize, FsError> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    loop {
+        let count = try_read(port, buf);
+
+        if count > 0 {
+            return Ok(count);
+        }
+
+        if signal::interrupted() {
+            return Err(FsError::Interrupted);
+        }
+
+        wait(port);
+    }
+}
+
+/// Waits until input arrives on `port` or the thread is unparked otherwise, like for a signal
+pub fn wait(port: ComPort) {
+    {
+        let mut input = INPUTS[port.index()].lock();
+
+        if !input.buffer.is_empty() {
+            return;
+        }
+
+        let current = thread::current().expect("Threads are not initialized");
+
+        if !input.waiting.contains(&current) {
+            input.waiting.push(current);
+        }
+    }
+
+    // Input in between leaves an unpark token, so this doesn't miss it
+    thread::park();
+}
+
+/// Sends `bytes` on `port`
+pub fn write(port: ComPort, bytes: &[u8]) {
+    let mut uart = uart(port).lock();
+
+    for &byte in bytes {
+        uart.send(byte);
+    }
+}
+
+struct Device {
+    port: ComPort
+}
+
+impl Line for Device {
+    fn receive(&self) -> Option<Received> {
+        receive(self.port)
+    }
+
+    fn wait(&self) {
+        wait(self.port);
+    }
+
+    fn send(&self, bytes: &[u8]) {
+        write(self.port, bytes);
+    }
+}
+
+impl Inode for Device {
+    fn stat(&self) -> Result<Stat, FsError> {
+        Ok(Stat::new(file_id(self.port.index()).inode, FileType::CharDevice, 0))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        TTYS[self.port.index()].read(self, buf)
+    }
+
+    fn try_read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match TTYS[self.port.index()].try_read(self, buf) {
+            None if !buf.is_empty() => Err(FsError::WouldBlock),
+            count => Ok(count.unwrap_or(0))
+        }
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        TTYS[self.port.index()].write(self, buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    fn terminal(&self) -> Option<&Tty> {
+        Some(&TTYS[self.port.index()])
+    }
+}
+
+/// Terminal of `port` as an open file with `flags`
+pub fn open(port: ComPort, flags: OpenFlags) -> File {
+    let mut path = String::from("/dev/");
+    path.push_str(port.name());
+    let dentry = Dentry { path, inode: Arc::new(Device { port }) };
+    File::new(dentry, file_id(port.index()), flags)
+}
+
+/// Id of files of the port with `index`
+const fn file_id(index: usize) -> FileId {
+    FileId { fs: 0, inode: SERIAL_INODE + index as u64 }
+}
+
 #[doc(hidden)]
-pub fn _print(args: core::fmt::Arguments) {
-    interrupts::without_interrupts(|| {
-        COM1.lock().write_fmt(args).unwrap();
-    });
+pub fn _print(args: fmt::Arguments) {
+    COM1.lock().write_fmt(args).unwrap();
+}
+
+/// Releases `COM1` so a panic can print
+///
+/// # Safety
+/// The holder may be in the middle of writing, it's for the panic path only
+pub unsafe fn force_unlock() {
+    COM1.force_unlock();
 }
 
 #[macro_export]
@@ -0,0 +1,235 @@
+//! Builtin commands of the shell
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+use crate::{
+    acpi,
+    allocator,
+    fs::{vfs, FileType},
+    memory,
+    pci,
+    power,
+    process::{self, Parent},
+    rtc,
+    serial::{self, ComPort, SerialError},
+    smp,
+    task::executor,
+    thread,
+    timer
+};
+
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// Runs the command with the words after its name
+    pub run: fn(&[&str], &mut dyn Write) -> fmt::Result
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command { name: "help", help: "List commands", run: help },
+    Command { name: "clear", help: "Clear the screen", run: clear },
+    Command { name: "echo", help: "Print the arguments", run: echo },
+    Command { name: "mem", help: "Show heap and physical memory use", run: mem },
+    Command { name: "lspci", help: "List PCI devices", run: lspci },
+    Command { name: "acpi", help: "List ACPI tables and processors", run: acpi },
+    Command { name: "tasks", help: "List threads and processes", run: tasks },
+    Command { name: "uptime", help: "Show time since boot", run: uptime },
+    Command { name: "date", help: "Show date and time of the real time clock", run: date },
+    Command { name: "serial", help: "List serial ports, or set the baud rate of one", run: serial },
+    Command { name: "pwd", help: "Show the working directory", run: pwd },
+    Command { name: "cd", help: "Change the working directory", run: cd },
+    Command { name: "ls", help: "List directories", run: ls },
+    Command { name: "cat", help: "Print files", run: cat },
+    Command { name: "reboot", help: "Restart the machine", run: reboot },
+    Command { name: "shutdown", help: "Turn the machine off", run: shutdown }
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+fn help(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    for command in COMMANDS {
+        writeln!(output, "{:<10}{}", command.name, command.help)?;
+    }
+
+    Ok(())
+}
+
+fn clear(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    output.write_str("\x1B[2J\x1B[H")
+}
+
+fn echo(arguments: &[&str], output: &mut dyn Write) -> fmt::Result {
+    writeln!(output, "{}", arguments.join(" "))
+}
+
+fn mem(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    let (used, free) = allocator::heap_usage();
+    writeln!(output, "Heap: {} KiB used, {} KiB free", used / 1024, free / 1024)?;
+    writeln!(output, "Physical memory: {} KiB used, {} KiB free", memory::frames_in_use() * 4, memory::frames_free() * 4)
+}
+
+fn lspci(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    for device in pci::devices() {
+        writeln!(
+            output,
+            "{:02x}:{:02x}.{} {:04x}:{:04x} {} ({:02x}:{:02x})",
+            device.bus, device.slot, device.function, device.vendor, device.device, device.class_name(), device.class, device.subclass
+        )?;
+    }
+
+    Ok(())
+}
+
+fn acpi(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    let Some(xsdp) = acpi::find_xsdp_bios() else {
+        return writeln!(output, "No ACPI");
+    };
+
+    let (revision, oem) = unsafe { ((*xsdp).revision, (*xsdp).oemiud) };
+    writeln!(output, "XSDP revision {}, OEM {}", revision, String::from_utf8_lossy(&oem).trim_end())?;
+
+    for table in acpi::tables() {
+        writeln!(output, "{} {:>6} bytes, OEM {}", String::from_utf8_lossy(&table[..4]), table.len(), String::from_utf8_lossy(&table[10..16]).trim_end())?;
+    }
+
+    if let Some(madt) = acpi::find_madt() {
+        writeln!(output, "Processors: {}, local APIC at {:#x}", madt.processors.len(), madt.local_apic_address)?;
+    }
+
+    Ok(())
+}
+
+fn tasks(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    writeln!(output, "{:>4} {:<16} {:<9} {:<7} {:>3} {:>9}", "TID", "NAME", "STATE", "PRIO", "CPU", "TIME")?;
+
+    for thread in thread::list() {
+        let cpu = thread.cpu.map_or(String::from("-"), |cpu| format!("{}", cpu));
+        let state = format!("{:?}", thread.state);
+        let priority = format!("{:?}", thread.priority);
+        writeln!(output, "{:>4} {:<16} {:<9} {:<7} {:>3} {:>7}ms", thread.id.as_u64(), thread.name, state, priority, cpu, thread.cpu_time)?;
+    }
+
+    let processes = process::list();
+
+    if !processes.is_empty() {
+        writeln!(output, "{:>4} {:>6} {:>4} {:<8} {:>5} NAME", "PID", "PARENT", "TID", "STATE", "FILES")?;
+    }
+
+    for process in processes {
+        let parent = match process.parent {
+            Parent::Process(pid) => format!("{}", pid.as_u64()),
+            Parent::Kernel => String::from("kernel"),
+            Parent::Orphan => String::from("-")
+        };
+
+        let state = match (process.exit_status, process.stopped) {
+            (Some(_), _) => "zombie",
+            (None, true) => "stopped",
+            (None, false) => "running"
+        };
+
+        writeln!(output, "{:>4} {:>6} {:>4} {:<8} {:>5} {}", process.pid.as_u64(), parent, process.thread.as_u64(), state, process.open_files, process.name)?;
+    }
+
+    writeln!(output, "Executor workers: {}", executor::global().workers())
+}
+
+fn uptime(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    let ms = timer::uptime_ms();
+    let seconds = ms / 1000;
+    writeln!(output, "Up {}:{:02}:{:02}.{:03}, {} CPUs", seconds / 3600, seconds / 60 % 60, seconds % 60, ms % 1000, smp::cpu_count())
+}
+
+fn date(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    writeln!(output, "{} UTC", rtc::now())
+}
+
+fn serial(arguments: &[&str], output: &mut dyn Write) -> fmt::Result {
+    let [name, rate] = arguments else {
+        for port in serial::probe() {
+            writeln!(output, "{} at {:#x}, IRQ {}", port.name(), port.base(), port.irq())?;
+        }
+
+        return Ok(());
+    };
+
+    let Some(port) = ComPort::ALL.into_iter().find(|port| port.name() == *name) else {
+        return writeln!(output, "serial: no port {}", name);
+    };
+
+    match rate.parse().map_err(|_| SerialError::InvalidBaudRate).and_then(|rate| serial::set_baud_rate(port, rate)) {
+        Ok(()) => Ok(()),
+        Err(error) => writeln!(output, "serial: {}: {:?}", rate, error)
+    }
+}
+
+fn pwd(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    writeln!(output, "{}", vfs::cwd())
+}
+
+fn cd(arguments: &[&str], output: &mut dyn Write) -> fmt::Result {
+    let path = arguments.first().copied().unwrap_or("/");
+
+    match vfs::chdir(path) {
+        Ok(()) => Ok(()),
+        Err(error) => writeln!(output, "cd: {}: {:?}", path, error)
+    }
+}
+
+fn ls(arguments: &[&str], output: &mut dyn Write) -> fmt::Result {
+    let cwd = [vfs::cwd()];
+    let paths: Vec<&str> = if arguments.is_empty() { cwd.iter().map(String::as_str).collect() } else { arguments.to_vec() };
+
+    for path in &paths {
+        if paths.len() > 1 {
+            writeln!(output, "{}:", path)?;
+        }
+
+        let mut entries = match vfs::readdir(path) {
+            Ok(entries) => entries,
+            Err(error) => {
+                writeln!(output, "ls: {}: {:?}", path, error)?;
+                continue;
+            }
+        };
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for entry in entries.iter().filter(|entry| entry.name != "." && entry.name != "..") {
+            let suffix = match entry.file_type {
+                FileType::Directory => "/",
+                FileType::Symlink => "@",
+                FileType::Fifo => "|",
+                _ => ""
+            };
+
+            writeln!(output, "{}{}", entry.name, suffix)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cat(arguments: &[&str], output: &mut dyn Write) -> fmt::Result {
+    for path in arguments {
+        match vfs::read_to_end(path) {
+            Ok(data) => output.write_str(&String::from_utf8_lossy(&data))?,
+            Err(error) => writeln!(output, "cat: {}: {:?}", path, error)?
+        }
+    }
+
+    Ok(())
+}
+
+fn reboot(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    writeln!(output, "Rebooting")?;
+    power::reboot();
+}
+
+fn shutdown(_: &[&str], output: &mut dyn Write) -> fmt::Result {
+    writeln!(output, "Shutting down")?;
+    power::shutdown();
+}
@@ -0,0 +1,312 @@
+//! Line editor of the shell. Input comes a byte at a time the way the console gives it: printable
+//! characters, backspace, tab, enter, a few control keys and the ANSI escape sequences of arrow,
+//! Home, End and Delete keys. Echo only moves the cursor back with backspace, which the VGA
+//! writer and serial terminals both understand.
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::mem;
+
+/// Lines kept for the up and down arrows
+pub const HISTORY_SIZE: usize = 64;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+const ESCAPE: u8 = 0x1B;
+/// Ctrl+A, Ctrl+E and Ctrl+U
+const LINE_START: u8 = 0x01;
+const LINE_END: u8 = 0x05;
+const KILL_LINE: u8 = 0x15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`
+    Started,
+    /// After `ESC [`
+    Bracket,
+    /// After `ESC [` and a parameter that starts with this digit
+    Parameter(u8)
+}
+
+/// Words `word` may be completed to, `first` if it's the first word of the line
+pub type Completer<'a> = &'a dyn Fn(&str, bool) -> Vec<String>;
+/// Where echo goes
+pub type Output<'a> = &'a mut dyn FnMut(&[u8]);
+
+pub struct Editor {
+    prompt: String,
+    /// Printable ASCII only
+    line: Vec<u8>,
+    cursor: usize,
+    history: VecDeque<String>,
+    /// Entry of `history` shown, counting back from the newest
+    browsing: Option<usize>,
+    /// Line that was being typed before browsing started
+    draft: Vec<u8>,
+    escape: Escape,
+    /// Previous byte was `\r`, a `\n` right after it is the same enter
+    carriage_return: bool
+}
+
+impl Editor {
+    pub fn new(prompt: &str) -> Editor {
+        Editor {
+            prompt: String::from(prompt),
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            browsing: None,
+            draft: Vec::new(),
+            escape: Escape::None,
+            carriage_return: false
+        }
+    }
+
+    /// Line typed so far
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.line).unwrap_or_default()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Entered lines, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    /// Shows the prompt, for the start of each line
+    pub fn prompt(&self, output: Output) {
+        output(self.prompt.as_bytes());
+    }
+
+    /// Handles input `byte`, echoing to `output`. Returns the line once enter is pressed.
+    pub fn feed(&mut self, byte: u8, complete: Completer, output: Output) -> Option<String> {
+        let carriage_return = mem::replace(&mut self.carriage_return, byte == b'\r');
+
+        match self.escape {
+            Escape::None => {}
+            Escape::Started => {
+                self.escape = if byte == b'[' { Escape::Bracket } else { Escape::None };
+                return None;
+            }
+            Escape::Bracket => {
+                self.escape = Escape::None;
+
+                match byte {
+                    b'A' => self.history_back(output),
+                    b'B' => self.history_forward(output),
+                    b'C' => self.right(output),
+                    b'D' => self.left(output),
+                    b'H' => self.home(output),
+                    b'F' => self.end(output),
+                    b'0'..=b'9' => self.escape = Escape::Parameter(byte),
+                    _ => {}
+                }
+
+                return None;
+            }
+            Escape::Parameter(parameter) => {
+                match byte {
+                    b'0'..=b'9' | b';' => return None,
+                    b'~' => match parameter {
+                        b'1' | b'7' => self.home(output),
+                        b'4' | b'8' => self.end(output),
+                        b'3' => self.delete(output),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+
+                self.escape = Escape::None;
+                return None;
+            }
+        }
+
+        match byte {
+            ESCAPE => self.escape = Escape::Started,
+            b'\n' if carriage_return => {}
+            b'\r' | b'\n' => return Some(self.finish(output)),
+            BACKSPACE | DELETE => self.backspace(output),
+            b'\t' => self.complete(complete, output),
+            LINE_START => self.home(output),
+            LINE_END => self.end(output),
+            KILL_LINE => self.replace_line(Vec::new(), output),
+            0x20..=0x7E => self.insert(byte, output),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Moves the cursor `count` characters left
+    fn back(count: usize, output: Output) {
+        for _ in 0..count {
+            output(&[BACKSPACE]);
+        }
+    }
+
+    fn insert(&mut self, byte: u8, output: Output) {
+        self.line.insert(self.cursor, byte);
+        output(&self.line[self.cursor..]);
+        self.cursor += 1;
+        Editor::back(self.line.len() - self.cursor, output);
+    }
+
+    /// Removes the character at the cursor and shows the rest of the line again
+    fn remove(&mut self, output: Output) {
+        self.line.remove(self.cursor);
+        output(&self.line[self.cursor..]);
+        output(b" ");
+        Editor::back(self.line.len() - self.cursor + 1, output);
+    }
+
+    fn backspace(&mut self, output: Output) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            output(&[BACKSPACE]);
+            self.remove(output);
+        }
+    }
+
+    fn delete(&mut self, output: Output) {
+        if self.cursor < self.line.len() {
+            self.remove(output);
+        }
+    }
+
+    fn left(&mut self, output: Output) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            output(&[BACKSPACE]);
+        }
+    }
+
+    fn right(&mut self, output: Output) {
+        if self.cursor < self.line.len() {
+            output(&self.line[self.cursor..self.cursor + 1]);
+            self.cursor += 1;
+        }
+    }
+
+    fn home(&mut self, output: Output) {
+        Editor::back(self.cursor, output);
+        self.cursor = 0;
+    }
+
+    fn end(&mut self, output: Output) {
+        output(&self.line[self.cursor..]);
+        self.cursor = self.line.len();
+    }
+
+    /// Shows `line` instead of the current one, with the cursor at its end
+    fn replace_line(&mut self, line: Vec<u8>, output: Output) {
+        self.home(output);
+        output(&line);
+        let leftover = self.line.len().saturating_sub(line.len());
+
+        for _ in 0..leftover {
+            output(b" ");
+        }
+
+        Editor::back(leftover, output);
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    fn history_back(&mut self, output: Output) {
+        let index = self.browsing.map_or(0, |index| index + 1);
+
+        if index >= self.history.len() {
+            return;
+        }
+
+        if self.browsing.is_none() {
+            self.draft = self.line.clone();
+        }
+
+        self.browsing = Some(index);
+        let line = self.history[self.history.len() - 1 - index].clone().into_bytes();
+        self.replace_line(line, output);
+    }
+
+    fn history_forward(&mut self, output: Output) {
+        let line = match self.browsing {
+            None => return,
+            Some(0) => {
+                self.browsing = None;
+                mem::take(&mut self.draft)
+            }
+            Some(index) => {
+                self.browsing = Some(index - 1);
+                self.history[self.history.len() - index].clone().into_bytes()
+            }
+        };
+
+        self.replace_line(line, output);
+    }
+
+    /// Completes the word before the cursor as far as all its completions agree, lists them if
+    /// that adds nothing
+    fn complete(&mut self, complete: Completer, output: Output) {
+        let start = self.line[..self.cursor].iter().rposition(|&byte| byte == b' ').map_or(0, |space| space + 1);
+        let first = self.line[..start].iter().all(|&byte| byte == b' ');
+        let word = String::from_utf8_lossy(&self.line[start..self.cursor]).into_owned();
+        let candidates: Vec<String> = complete(&word, first).into_iter().filter(|candidate| candidate.starts_with(&word) && candidate.is_ascii()).collect();
+
+        let Some(shortest) = candidates.iter().map(String::len).min() else {
+            return;
+        };
+
+        let common = (word.len()..shortest).find(|&index| candidates.iter().any(|candidate| candidate.as_bytes()[index] != candidates[0].as_bytes()[index])).unwrap_or(shortest);
+
+        if common > word.len() || candidates.len() == 1 {
+            for &byte in &candidates[0].as_bytes()[word.len()..common] {
+                self.insert(byte, output);
+            }
+
+            // Finished words go on with the next one, directories with their entries
+            if candidates.len() == 1 && !candidates[0].ends_with('/') {
+                self.insert(b' ', output);
+            }
+
+            return;
+        }
+
+        // Paths are listed by their last component
+        let shown = word.rfind('/').map_or(0, |slash| slash + 1);
+        output(b"\n");
+
+        for candidate in &candidates {
+            output(&candidate.as_bytes()[shown..]);
+            output(b"  ");
+        }
+
+        output(b"\n");
+        self.prompt(output);
+        output(&self.line);
+        Editor::back(self.line.len() - self.cursor, output);
+    }
+
+    /// Ends the line and adds it to the history
+    fn finish(&mut self, output: Output) -> String {
+        self.end(output);
+        output(b"\n");
+        self.cursor = 0;
+        self.browsing = None;
+        self.draft.clear();
+
+        let line = String::from_utf8(mem::take(&mut self.line)).unwrap_or_default();
+
+        if !line.trim().is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+
+            self.history.push_back(line.clone());
+        }
+
+        line
+    }
+}
@@ -0,0 +1,104 @@
+//! Shell built into the kernel, for looking at a running system. It runs on a kernel thread
+//! attached to a terminal, a virtual console or a serial port, reads lines with `editor::Editor` and
+//! runs the builtin commands of `commands`. Tab completes command names in the first word of a
+//! line and paths after it.
+
+pub mod commands;
+pub mod editor;
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+use crate::{fs::{file::File, vfs, FileType, FsError}, thread::{self, JoinHandle}, tty::Mode};
+
+use editor::Editor;
+
+pub const PROMPT: &str = "ruin> ";
+
+/// `fmt::Write` to a terminal
+pub struct TerminalWriter<'a>(pub &'a File);
+
+impl Write for TerminalWriter<'_> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.0.write_all(text.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Starts the shell on a kernel thread, reading from and writing to `terminal`
+pub fn spawn(terminal: File) -> JoinHandle<()> {
+    let name = format!("shell {}", terminal.path());
+    thread::Builder::new().name(&name).spawn(move || run(&terminal))
+}
+
+/// Reads and runs commands from `terminal` until it can't be read
+pub fn run(terminal: &File) {
+    // The editor echoes and edits lines itself, files that aren't terminals are read as they are
+    let _ = terminal.set_terminal_mode(Mode::SIGNALS);
+    let mut editor = Editor::new(PROMPT);
+    let mut output = |bytes: &[u8]| {
+        // Echo that doesn't get through is lost, reading notices a terminal that's gone
+        let _ = terminal.write_all(bytes);
+    };
+
+    let mut buf = [0u8; 64];
+    editor.prompt(&mut output);
+
+    loop {
+        let count = match terminal.read(&mut buf) {
+            Ok(0) => return,
+            Ok(count) => count,
+            // Only processes get interrupted, a kernel thread just reads again
+            Err(FsError::Interrupted) => continue,
+            Err(_) => return
+        };
+
+        for &byte in &buf[..count] {
+            if let Some(line) = editor.feed(byte, &complete, &mut output) {
+                // Output is lost the same way echo is
+                let _ = execute(&line, &mut TerminalWriter(terminal));
+                editor.prompt(&mut output);
+            }
+        }
+    }
+}
+
+/// Runs command `line`, its output goes to `output`
+pub fn execute(line: &str, output: &mut dyn Write) -> fmt::Result {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let Some((&name, arguments)) = words.split_first() else {
+        return Ok(());
+    };
+
+    match commands::find(name) {
+        Some(command) => (command.run)(arguments, output),
+        None => writeln!(output, "{}: command not found, see help", name)
+    }
+}
+
+/// Completions of `word`: command names for the first word of a line, paths otherwise.
+/// Directories end with `/`.
+pub fn complete(word: &str, first: bool) -> Vec<String> {
+    if first {
+        return commands::COMMANDS.iter().map(|command| command.name).filter(|name| name.starts_with(word)).map(String::from).collect();
+    }
+
+    let (directory, prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word)
+    };
+
+    let listed = if directory.is_empty() { vfs::cwd() } else { String::from(directory) };
+
+    let Ok(entries) = vfs::readdir(&listed) else {
+        return Vec::new();
+    };
+
+    let mut completions: Vec<String> = entries.into_iter()
+        .filter(|entry| entry.name != "." && entry.name != ".." && entry.name.starts_with(prefix))
+        .map(|entry| format!("{}{}{}", directory, entry.name, if entry.file_type == FileType::Directory { "/" } else { "" }))
+        .collect();
+
+    completions.sort();
+    completions
+}
@@ -0,0 +1,119 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use x86_64::{structures::paging::{mapper::MapToError, Size4KiB}, PhysAddr};
+
+use crate::{memory, timer};
+
+pub const TIMER_VECTOR: u8 = 48;
+pub const RESCHEDULE_VECTOR: u8 = 49;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const ID: u64 = 0x20;
+const TASK_PRIORITY: u64 = 0x80;
+const END_OF_INTERRUPT: u64 = 0xB0;
+const SPURIOUS_INTERRUPT: u64 = 0xF0;
+const INTERRUPT_COMMAND_LOW: u64 = 0x300;
+const INTERRUPT_COMMAND_HIGH: u64 = 0x310;
+const LVT_TIMER: u64 = 0x320;
+const TIMER_INITIAL_COUNT: u64 = 0x380;
+const TIMER_CURRENT_COUNT: u64 = 0x390;
+const TIMER_DIVIDE: u64 = 0x3E0;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b011;
+/// PIT ticks the timer frequency is measured for
+const CALIBRATION_TICKS: u64 = 10;
+
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// Timer count per PIT tick
+static TIMER_COUNT: AtomicU32 = AtomicU32::new(0);
+
+fn read(register: u64) -> u32 {
+    unsafe { core::ptr::read_volatile((BASE.load(Ordering::Relaxed) + register) as *const u32) }
+}
+
+fn write(register: u64, value: u32) {
+    unsafe { core::ptr::write_volatile((BASE.load(Ordering::Relaxed) + register) as *mut u32, value) }
+}
+
+/// Maps local APIC registers, every processor sees its own APIC at the same address
+pub fn init(address: PhysAddr) -> Result<(), MapToError<Size4KiB>> {
+    let base = memory::map_mmio(address, 4096)?;
+    BASE.store(base.as_u64(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Enables local APIC of the calling processor
+pub fn enable() {
+    write(TASK_PRIORITY, 0);
+    write(SPURIOUS_INTERRUPT, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// APIC ID of the calling processor
+pub fn id() -> u32 {
+    read(ID) >> 24
+}
+
+pub fn end_of_interrupt() {
+    write(END_OF_INTERRUPT, 0);
+}
+
+fn send(apic_id: u32, command: u32) {
+    write(INTERRUPT_COMMAND_HIGH, apic_id << 24);
+    write(INTERRUPT_COMMAND_LOW, command);
+
+    while read(INTERRUPT_COMMAND_LOW) & DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Sends interrupt `vector` to processor `apic_id`
+pub fn send_ipi(apic_id: u32, vector: u8) {
+    send(apic_id, LEVEL_ASSERT | vector as u32);
+}
+
+/// Resets processor `apic_id`, it then waits for startup IPI
+pub fn send_init(apic_id: u32) {
+    send(apic_id, DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+/// Starts processor `apic_id` in real mode at address `vector * 4096`
+pub fn send_startup(apic_id: u32, vector: u8) {
+    send(apic_id, DELIVERY_STARTUP | LEVEL_ASSERT | vector as u32);
+}
+
+/// Measures timer frequency against PIT, interrupts must be enabled
+pub fn calibrate_timer() {
+    write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(LVT_TIMER, TIMER_MASKED);
+
+    let start = timer::ticks();
+
+    while timer::ticks() == start {
+        core::hint::spin_loop();
+    }
+
+    write(TIMER_INITIAL_COUNT, u32::MAX);
+    let start = timer::ticks();
+
+    while timer::ticks() < start + CALIBRATION_TICKS {
+        core::hint::spin_loop();
+    }
+
+    let elapsed = u32::MAX - read(TIMER_CURRENT_COUNT);
+    write(TIMER_INITIAL_COUNT, 0);
+    TIMER_COUNT.store((elapsed / CALIBRATION_TICKS as u32).max(1), Ordering::Relaxed);
+}
+
+/// Starts periodic timer interrupt of the calling processor at `timer::TICKS_PER_SECOND`
+pub fn start_timer() {
+    write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(LVT_TIMER, TIMER_PERIODIC | TIMER_VECTOR as u32);
+    write(TIMER_INITIAL_COUNT, TIMER_COUNT.load(Ordering::Relaxed));
+}
@@ -0,0 +1,218 @@
+pub mod apic;
+mod trampoline;
+
+use alloc::{boxed::Box, vec};
+use core::{arch::asm, sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering}};
+use x86_64::{registers::model_specific::GsBase, PhysAddr, VirtAddr};
+
+use crate::{acpi, gdt, interrupts, serial_println, syscall, thread, timer};
+
+use trampoline::Trampoline;
+
+pub const MAX_CPUS: usize = 16;
+const AP_STACK_SIZE: usize = 16 * 1024;
+
+/// Boot states of an application processor. It's abandoned when it doesn't come online in time,
+/// then it must not run kernel code with the `PerCpu` given to the next one.
+const BOOT_WAITING: u8 = 0;
+const BOOT_ENTERED: u8 = 1;
+const BOOT_ABANDONED: u8 = 2;
+
+/// Data of one processor, found through its GS base
+#[repr(C)]
+pub struct PerCpu {
+    /// Address of this structure, so it can be read with a single `gs` relative load
+    this: AtomicU64,
+    /// Top of the running thread's stack, `syscall` entry switches to it at offset 8
+    kernel_stack: AtomicU64,
+    /// Scratch for the user stack pointer during `syscall` entry, at offset 16
+    user_stack: AtomicU64,
+    index: usize,
+    apic_id: AtomicU32,
+    /// `BOOT_WAITING`, `BOOT_ENTERED` or `BOOT_ABANDONED`
+    boot: AtomicU8,
+    online: AtomicBool
+}
+
+impl PerCpu {
+    const fn new(index: usize) -> PerCpu {
+        PerCpu { this: AtomicU64::new(0), kernel_stack: AtomicU64::new(0), user_stack: AtomicU64::new(0), index, apic_id: AtomicU32::new(0), boot: AtomicU8::new(BOOT_WAITING), online: AtomicBool::new(false) }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_kernel_stack(&self, top: VirtAddr) {
+        self.kernel_stack.store(top.as_u64(), Ordering::Relaxed);
+    }
+}
+
+static CPUS: [PerCpu; MAX_CPUS] = {
+    let mut cpus = [const { PerCpu::new(0) }; MAX_CPUS];
+    let mut index = 0;
+
+    while index < MAX_CPUS {
+        cpus[index].index = index;
+        index += 1;
+    }
+
+    cpus
+};
+
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+/// GS base of the bootstrap processor points to its `PerCpu`
+static BSP_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpError {
+    /// ACPI has no MADT, so processors can't be found
+    NoMadt,
+    MappingFailed,
+    /// No free page below 1 MiB for the startup code
+    NoLowMemory
+}
+
+fn install(index: usize) {
+    let cpu = &CPUS[index];
+    let address = cpu as *const PerCpu as u64;
+    cpu.this.store(address, Ordering::Relaxed);
+    GsBase::write(VirtAddr::new(address));
+}
+
+/// Sets up per-CPU data of the bootstrap processor
+pub(crate) fn init_bsp() {
+    install(0);
+    BSP_INSTALLED.store(true, Ordering::Release);
+    CPUS[0].online.store(true, Ordering::Release);
+}
+
+/// Per-CPU data of the calling processor
+pub fn current() -> &'static PerCpu {
+    unsafe {
+        let this: *const PerCpu;
+        asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags));
+        &*this
+    }
+}
+
+/// Index of the calling processor, 0 is the bootstrap processor. Before per-CPU data is set
+/// up only the bootstrap processor runs, so it's 0 then too.
+pub fn cpu_index() -> usize {
+    if !BSP_INSTALLED.load(Ordering::Acquire) {
+        return 0;
+    }
+
+    current().index
+}
+
+/// Number of processors online
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Interrupts processor `index` so it looks for ready threads
+pub(crate) fn send_reschedule(index: usize) {
+    apic::send_ipi(CPUS[index].apic_id(), apic::RESCHEDULE_VECTOR);
+}
+
+/// Spins until `done` or `ms` milliseconds pass, returns `done()`
+fn wait(ms: u64, done: impl Fn() -> bool) -> bool {
+    let end = timer::ticks() + timer::ms_to_ticks(ms);
+
+    while !done() && timer::ticks() < end {
+        core::hint::spin_loop();
+    }
+
+    done()
+}
+
+fn start_ap(trampoline: &Trampoline, index: usize, apic_id: u32) -> bool {
+    let cpu = &CPUS[index];
+    cpu.apic_id.store(apic_id, Ordering::Relaxed);
+    let stack = Box::leak(vec![0u64; AP_STACK_SIZE / 8].into_boxed_slice());
+    trampoline.prepare(stack.as_ptr() as u64 + AP_STACK_SIZE as u64, index, ap_main);
+
+    // INIT-SIPI-SIPI, second startup IPI is only needed if the first one was lost
+    apic::send_init(apic_id);
+    wait(10, || false);
+    apic::send_startup(apic_id, trampoline.vector());
+
+    if !wait(1, || cpu.online.load(Ordering::Acquire)) {
+        apic::send_startup(apic_id, trampoline.vector());
+    }
+
+    if wait(100, || cpu.online.load(Ordering::Acquire)) {
+        return true;
+    }
+
+    // Once in `ap_main` it's only slow and gets there, setting up the threads of its index
+    if cpu.boot.compare_exchange(BOOT_WAITING, BOOT_ABANDONED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        while !cpu.online.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+
+        return true;
+    }
+
+    // Back in reset it leaves the trampoline and the slot to the next processor
+    apic::send_init(apic_id);
+    wait(10, || false);
+    cpu.boot.store(BOOT_WAITING, Ordering::Release);
+    false
+}
+
+/// Starts application processors listed in ACPI MADT one by one. Threads and global memory
+/// must be initialized and interrupts enabled. Returns number of processors online.
+pub fn init() -> Result<usize, SmpError> {
+    let madt = acpi::find_madt().ok_or(SmpError::NoMadt)?;
+    apic::init(PhysAddr::new(madt.local_apic_address)).map_err(|_| SmpError::MappingFailed)?;
+    apic::enable();
+    apic::calibrate_timer();
+    CPUS[0].apic_id.store(apic::id(), Ordering::Relaxed);
+
+    let trampoline = Trampoline::install().ok_or(SmpError::NoLowMemory)?;
+
+    for processor in madt.processors.iter().filter(|processor| processor.apic_id as u32 != CPUS[0].apic_id()) {
+        let index = cpu_count();
+
+        if index == MAX_CPUS {
+            break;
+        }
+
+        if start_ap(&trampoline, index, processor.apic_id as u32) {
+            CPU_COUNT.store(index + 1, Ordering::Release);
+        } else {
+            serial_println!("CPU with APIC ID {} didn't start", processor.apic_id);
+        }
+    }
+
+    Ok(cpu_count())
+}
+
+/// Entry point of application processors, called by trampoline on their boot stack,
+/// which becomes stack of their idle thread
+extern "C" fn ap_main(index: usize) -> ! {
+    if CPUS[index].boot.compare_exchange(BOOT_WAITING, BOOT_ENTERED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        // Given up on, the slot may belong to another processor by now
+        loop {
+            x86_64::instructions::interrupts::disable();
+            x86_64::instructions::hlt();
+        }
+    }
+
+    install(index);
+    gdt::init_ap();
+    interrupts::init_idt();
+    syscall::init();
+    apic::enable();
+    thread::init_cpu();
+    apic::start_timer();
+    CPUS[index].online.store(true, Ordering::Release);
+    x86_64::instructions::interrupts::enable();
+    thread::idle_loop()
+}
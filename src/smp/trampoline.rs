@@ -0,0 +1,168 @@
+use core::{arch::global_asm, ptr::{addr_of, write_unaligned}};
+
+use x86_64::{registers::control::Cr3, structures::paging::PageTableFlags};
+
+use crate::memory;
+
+// Application processor starts in real mode at `vector * 4096` after startup IPI.
+// The code switches to protected mode, enables paging with the kernel page table and jumps
+// to long mode, then calls `entry(argument)` on `stack`. It is copied to a page below 1 MiB,
+// so absolute addresses are computed from `ebx`, which holds the page address, and jump
+// targets and GDT address are patched when it is copied.
+global_asm!(
+    ".global ruin_ap_trampoline",
+    ".global ruin_ap_protected_target",
+    ".global ruin_ap_protected",
+    ".global ruin_ap_long_target",
+    ".global ruin_ap_long",
+    ".global ruin_ap_gdt",
+    ".global ruin_ap_gdt_pointer",
+    ".global ruin_ap_cr3",
+    ".global ruin_ap_stack",
+    ".global ruin_ap_argument",
+    ".global ruin_ap_entry",
+    ".global ruin_ap_trampoline_end",
+    // Intel syntax allows only one symbol in memory operand, so offsets get their own names
+    ".set AP_GDT_POINTER, ruin_ap_gdt_pointer - ruin_ap_trampoline",
+    ".set AP_CR3, ruin_ap_cr3 - ruin_ap_trampoline",
+    ".set AP_STACK, ruin_ap_stack - ruin_ap_trampoline",
+    ".set AP_ARGUMENT, ruin_ap_argument - ruin_ap_trampoline",
+    ".set AP_ENTRY, ruin_ap_entry - ruin_ap_trampoline",
+    ".code16",
+    "ruin_ap_trampoline:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "xor ebx, ebx",
+    "mov bx, ax",
+    "shl ebx, 4",
+    "lgdt [AP_GDT_POINTER]",
+    "mov eax, cr0",
+    "or eax, 1", // protection enable
+    "mov cr0, eax",
+    // jmp 0x08:ruin_ap_protected with 32 bit offset
+    ".byte 0x66, 0xEA",
+    "ruin_ap_protected_target:",
+    ".long 0",
+    ".word 0x08",
+    ".code32",
+    "ruin_ap_protected:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov eax, cr4",
+    "or eax, 1 << 5", // physical address extension
+    "mov cr4, eax",
+    "mov eax, [ebx + AP_CR3]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080", // EFER
+    "rdmsr",
+    "or eax, (1 << 8) | (1 << 11)", // long mode, no-execute
+    "wrmsr",
+    "mov eax, cr0",
+    "or eax, (1 << 31) | (1 << 16)", // paging, write protect
+    "mov cr0, eax",
+    // jmp 0x18:ruin_ap_long
+    ".byte 0xEA",
+    "ruin_ap_long_target:",
+    ".long 0",
+    ".word 0x18",
+    ".code64",
+    "ruin_ap_long:",
+    "mov ebx, ebx",
+    "mov rsp, [rbx + AP_STACK]",
+    "mov rdi, [rbx + AP_ARGUMENT]",
+    "mov rax, [rbx + AP_ENTRY]",
+    "call rax",
+    "ud2",
+    ".balign 8",
+    "ruin_ap_gdt:",
+    ".quad 0",
+    ".quad 0x00CF9A000000FFFF", // 32 bit code
+    ".quad 0x00CF92000000FFFF", // data
+    ".quad 0x00AF9A000000FFFF", // 64 bit code
+    "ruin_ap_gdt_pointer:",
+    ".word 31",
+    ".long 0",
+    ".balign 8",
+    "ruin_ap_cr3:",
+    ".quad 0",
+    "ruin_ap_stack:",
+    ".quad 0",
+    "ruin_ap_argument:",
+    ".quad 0",
+    "ruin_ap_entry:",
+    ".quad 0",
+    "ruin_ap_trampoline_end:"
+);
+
+extern "C" {
+    static ruin_ap_trampoline: u8;
+    static ruin_ap_protected_target: u8;
+    static ruin_ap_protected: u8;
+    static ruin_ap_long_target: u8;
+    static ruin_ap_long: u8;
+    static ruin_ap_gdt: u8;
+    static ruin_ap_gdt_pointer: u8;
+    static ruin_ap_cr3: u8;
+    static ruin_ap_stack: u8;
+    static ruin_ap_argument: u8;
+    static ruin_ap_entry: u8;
+    static ruin_ap_trampoline_end: u8;
+}
+
+/// Offset of trampoline `symbol` from its start
+fn offset(symbol: *const u8) -> u64 {
+    symbol as u64 - addr_of!(ruin_ap_trampoline) as u64
+}
+
+/// Trampoline copied to a low page, which is identity mapped so it keeps running when
+/// the processor enables paging
+pub struct Trampoline {
+    base: u64
+}
+
+impl Trampoline {
+    pub fn install() -> Option<Trampoline> {
+        let frame = memory::allocate_low_frame()?;
+        memory::identity_map(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE).ok()?;
+        let base = frame.start_address().as_u64();
+
+        unsafe {
+            let size = offset(addr_of!(ruin_ap_trampoline_end)) as usize;
+            assert!(size <= 4096, "AP trampoline doesn't fit in a page");
+            core::ptr::copy_nonoverlapping(addr_of!(ruin_ap_trampoline), base as *mut u8, size);
+
+            let trampoline = Trampoline { base };
+            trampoline.write_u32(addr_of!(ruin_ap_protected_target), base + offset(addr_of!(ruin_ap_protected)));
+            trampoline.write_u32(addr_of!(ruin_ap_long_target), base + offset(addr_of!(ruin_ap_long)));
+            trampoline.write_u32(addr_of!(ruin_ap_gdt_pointer).add(2), base + offset(addr_of!(ruin_ap_gdt)));
+            trampoline.write_u64(addr_of!(ruin_ap_cr3), Cr3::read().0.start_address().as_u64());
+            Some(trampoline)
+        }
+    }
+
+    unsafe fn write_u32(&self, symbol: *const u8, value: u64) {
+        write_unaligned((self.base + offset(symbol)) as *mut u32, value as u32);
+    }
+
+    unsafe fn write_u64(&self, symbol: *const u8, value: u64) {
+        write_unaligned((self.base + offset(symbol)) as *mut u64, value);
+    }
+
+    /// Startup IPI vector, the page number
+    pub fn vector(&self) -> u8 {
+        (self.base >> 12) as u8
+    }
+
+    /// Sets what the next processor started with this trampoline runs
+    pub fn prepare(&self, stack_top: u64, argument: usize, entry: extern "C" fn(usize) -> !) {
+        unsafe {
+            self.write_u64(addr_of!(ruin_ap_stack), stack_top & !0xF);
+            self.write_u64(addr_of!(ruin_ap_argument), argument as u64);
+            self.write_u64(addr_of!(ruin_ap_entry), entry as usize as u64);
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use alloc::{format, sync::Arc};
+use spin::Mutex;
 use x86_64::instructions::port::Port;
-use crate::println;
+use crate::{println, block::{self, BlockDevice, BlockError, SECTOR_SIZE}};
 
 pub fn initialize() -> bool {
     let mut _data_reg_port: Port<u16> = Port::new(0x1F0);
@@ -54,3 +56,268 @@ pub fn initialize() -> bool {
     return true;
 }
 
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+const COMMAND_READ: u8 = 0x20;
+const COMMAND_READ_EXT: u8 = 0x24;
+const COMMAND_WRITE: u8 = 0x30;
+const COMMAND_WRITE_EXT: u8 = 0x34;
+const COMMAND_FLUSH: u8 = 0xE7;
+const COMMAND_FLUSH_EXT: u8 = 0xEA;
+const COMMAND_IDENTIFY: u8 = 0xEC;
+
+const MAX_SECTORS_PER_COMMAND: u64 = 128;
+/// Status reads before giving up on a drive, each takes about a microsecond on real hardware
+const MAX_STATUS_POLLS: u32 = 10_000_000;
+
+static PRIMARY_LOCK: Mutex<()> = Mutex::new(());
+static SECONDARY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtaBus {
+    Primary,
+    Secondary
+}
+
+impl AtaBus {
+    fn io_base(self) -> u16 {
+        match self {
+            AtaBus::Primary => 0x1F0,
+            AtaBus::Secondary => 0x170
+        }
+    }
+
+    fn control_port(self) -> u16 {
+        match self {
+            AtaBus::Primary => 0x3F6,
+            AtaBus::Secondary => 0x376
+        }
+    }
+
+    fn lock(self) -> &'static Mutex<()> {
+        match self {
+            AtaBus::Primary => &PRIMARY_LOCK,
+            AtaBus::Secondary => &SECONDARY_LOCK
+        }
+    }
+}
+
+pub struct AtaDrive {
+    bus: AtaBus,
+    slave: bool,
+    lba48: bool,
+    sectors: u64
+}
+
+impl AtaDrive {
+    fn port8(&self, offset: u16) -> Port<u8> {
+        Port::new(self.bus.io_base() + offset)
+    }
+
+    fn data_port(&self) -> Port<u16> {
+        Port::new(self.bus.io_base())
+    }
+
+    /// Reading alternate status 4 times gives the drive 400ns to update status after drive select
+    fn delay_400ns(&self) {
+        let mut alt_status: Port<u8> = Port::new(self.bus.control_port());
+
+        for _ in 0..4 {
+            unsafe { alt_status.read(); }
+        }
+    }
+
+    /// Reads status until `done` accepts it, `None` if the drive doesn't get there in time
+    fn poll(&self, done: impl Fn(u8) -> bool) -> Option<u8> {
+        let mut status_port = self.port8(7);
+        (0..MAX_STATUS_POLLS).map(|_| unsafe { status_port.read() }).find(|&status| done(status))
+    }
+
+    fn wait_ready(&self) -> Result<(), BlockError> {
+        let status = self.poll(|status| status & STATUS_BSY == 0 && status & (STATUS_ERR | STATUS_DF | STATUS_DRQ) != 0).ok_or(BlockError::Io)?;
+
+        if status & (STATUS_ERR | STATUS_DF) != 0 { Err(BlockError::Io) } else { Ok(()) }
+    }
+
+    fn wait_idle(&self) -> Result<(), BlockError> {
+        let status = self.poll(|status| status & STATUS_BSY == 0).ok_or(BlockError::Io)?;
+
+        if status & (STATUS_ERR | STATUS_DF) != 0 { Err(BlockError::Io) } else { Ok(()) }
+    }
+
+    /// Sends IDENTIFY to drive, returns `None` if there is no ATA drive (ATAPI and SATA drives are ignored)
+    pub fn identify(bus: AtaBus, slave: bool) -> Option<AtaDrive> {
+        let _guard = bus.lock().lock();
+        let mut drive = AtaDrive { bus, slave, lba48: false, sectors: 0 };
+        let mut status_port = drive.port8(7);
+
+        unsafe {
+            if status_port.read() == 0xFF { // floating bus
+                return None;
+            }
+
+            drive.port8(6).write(if slave { 0xB0 } else { 0xA0 });
+            drive.delay_400ns();
+            drive.port8(2).write(0);
+            drive.port8(3).write(0);
+            drive.port8(4).write(0);
+            drive.port8(5).write(0);
+            status_port.write(COMMAND_IDENTIFY);
+
+            if status_port.read() == 0 {
+                return None;
+            }
+
+            drive.poll(|status| status & STATUS_BSY == 0)?;
+
+            if drive.port8(4).read() != 0 || drive.port8(5).read() != 0 {
+                return None;
+            }
+        }
+
+        drive.wait_ready().ok()?;
+        let mut identify = [0u16; 256];
+        let mut data_port = drive.data_port();
+
+        for word in identify.iter_mut() {
+            *word = unsafe { data_port.read() };
+        }
+
+        drive.lba48 = identify[83] & (1 << 10) != 0;
+        drive.sectors = if drive.lba48 {
+            (identify[100] as u64) | (identify[101] as u64) << 16 | (identify[102] as u64) << 32 | (identify[103] as u64) << 48
+        } else {
+            (identify[60] as u64) | (identify[61] as u64) << 16
+        };
+
+        Some(drive)
+    }
+
+    fn select(&self, lba: u64, count: u64) {
+        let slave_bit: u8 = if self.slave { 0x10 } else { 0 };
+
+        unsafe {
+            if self.lba48 {
+                self.port8(6).write(0x40 | slave_bit);
+                self.delay_400ns();
+                self.port8(2).write((count >> 8) as u8);
+                self.port8(3).write((lba >> 24) as u8);
+                self.port8(4).write((lba >> 32) as u8);
+                self.port8(5).write((lba >> 40) as u8);
+            } else {
+                self.port8(6).write(0xE0 | slave_bit | ((lba >> 24) & 0x0F) as u8);
+                self.delay_400ns();
+            }
+
+            self.port8(2).write(count as u8);
+            self.port8(3).write(lba as u8);
+            self.port8(4).write((lba >> 8) as u8);
+            self.port8(5).write((lba >> 16) as u8);
+        }
+    }
+
+    fn check_range(&self, lba: u64, len: usize) -> Result<u64, BlockError> {
+        if len % SECTOR_SIZE != 0 {
+            return Err(BlockError::BadBuffer);
+        }
+
+        let count = (len / SECTOR_SIZE) as u64;
+
+        if lba.checked_add(count).ok_or(BlockError::OutOfRange)? > self.sectors || (!self.lba48 && lba + count > 1 << 28) {
+            return Err(BlockError::OutOfRange);
+        }
+
+        Ok(count)
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let count = self.check_range(lba, buf.len())?;
+        let _guard = self.bus.lock().lock();
+        let mut data_port = self.data_port();
+        let mut done: u64 = 0;
+
+        while done < count {
+            let chunk = (count - done).min(MAX_SECTORS_PER_COMMAND);
+            self.select(lba + done, chunk);
+            unsafe { self.port8(7).write(if self.lba48 { COMMAND_READ_EXT } else { COMMAND_READ }); }
+
+            for sector in done..done + chunk {
+                self.wait_ready()?;
+                let start = sector as usize * SECTOR_SIZE;
+
+                for word in buf[start..start + SECTOR_SIZE].chunks_exact_mut(2) {
+                    word.copy_from_slice(&unsafe { data_port.read() }.to_le_bytes());
+                }
+
+                self.delay_400ns();
+            }
+
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let count = self.check_range(lba, buf.len())?;
+        let guard = self.bus.lock().lock();
+        let mut data_port = self.data_port();
+        let mut done: u64 = 0;
+
+        while done < count {
+            let chunk = (count - done).min(MAX_SECTORS_PER_COMMAND);
+            self.select(lba + done, chunk);
+            unsafe { self.port8(7).write(if self.lba48 { COMMAND_WRITE_EXT } else { COMMAND_WRITE }); }
+
+            for sector in done..done + chunk {
+                self.wait_ready()?;
+                let start = sector as usize * SECTOR_SIZE;
+
+                for word in buf[start..start + SECTOR_SIZE].chunks_exact(2) {
+                    unsafe { data_port.write(u16::from_le_bytes([word[0], word[1]])); }
+                }
+            }
+
+            done += chunk;
+        }
+
+        drop(guard);
+        self.flush()
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let _guard = self.bus.lock().lock();
+
+        unsafe {
+            self.port8(6).write(if self.slave { 0xB0 } else { 0xA0 });
+            self.delay_400ns();
+            self.port8(7).write(if self.lba48 { COMMAND_FLUSH_EXT } else { COMMAND_FLUSH });
+        }
+
+        self.wait_idle()
+    }
+}
+
+/// Identifies drives on both legacy buses and registers them as "disk0".."disk3", returns number of drives found
+pub fn probe() -> usize {
+    let mut found = 0;
+
+    for (bus, slave) in [(AtaBus::Primary, false), (AtaBus::Primary, true), (AtaBus::Secondary, false), (AtaBus::Secondary, true)] {
+        if let Some(drive) = AtaDrive::identify(bus, slave) {
+            block::register(&format!("disk{}", found), Arc::new(drive));
+            found += 1;
+        }
+    }
+
+    found
+}
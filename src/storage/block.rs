@@ -0,0 +1,207 @@
+use alloc::{string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use spin::Mutex;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    OutOfRange,
+    BadBuffer,
+    Io,
+    ReadOnly
+}
+
+pub trait BlockDevice: Send + Sync {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64;
+
+    /// `buf` length must be a multiple of `sector_size()`
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// `buf` length must be a multiple of `sector_size()`
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Reads bytes at any byte offset, doing read-modify cycles on partial sectors
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let sector_size = self.sector_size();
+        let mut sector = vec![0u8; sector_size];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let lba = position / sector_size as u64;
+            let in_sector = (position % sector_size as u64) as usize;
+            let count = (sector_size - in_sector).min(buf.len() - done);
+
+            if in_sector == 0 && count == sector_size {
+                let whole = (buf.len() - done) / sector_size * sector_size;
+                self.read_sectors(lba, &mut buf[done..done + whole])?;
+                done += whole;
+                continue;
+            }
+
+            self.read_sectors(lba, &mut sector)?;
+            buf[done..done + count].copy_from_slice(&sector[in_sector..in_sector + count]);
+            done += count;
+        }
+
+        Ok(())
+    }
+
+    fn write_bytes(&self, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let sector_size = self.sector_size();
+        let mut sector = vec![0u8; sector_size];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let lba = position / sector_size as u64;
+            let in_sector = (position % sector_size as u64) as usize;
+            let count = (sector_size - in_sector).min(buf.len() - done);
+
+            if in_sector == 0 && count == sector_size {
+                let whole = (buf.len() - done) / sector_size * sector_size;
+                self.write_sectors(lba, &buf[done..done + whole])?;
+                done += whole;
+                continue;
+            }
+
+            self.read_sectors(lba, &mut sector)?;
+            sector[in_sector..in_sector + count].copy_from_slice(&buf[done..done + count]);
+            self.write_sectors(lba, &sector)?;
+            done += count;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_request(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<(), BlockError> {
+    let sector_size = device.sector_size();
+
+    if len % sector_size != 0 {
+        return Err(BlockError::BadBuffer);
+    }
+
+    let end = lba.checked_add((len / sector_size) as u64).ok_or(BlockError::OutOfRange)?;
+
+    if end > device.sector_count() {
+        return Err(BlockError::OutOfRange);
+    }
+
+    Ok(())
+}
+
+/// RAM-backed disk, mostly for tests and images passed by the bootloader
+pub struct MemoryDisk {
+    data: Mutex<Vec<u8>>
+}
+
+impl MemoryDisk {
+    pub fn new(sectors: u64) -> MemoryDisk {
+        MemoryDisk { data: Mutex::new(vec![0; sectors as usize * SECTOR_SIZE]) }
+    }
+
+    pub fn from_vec(mut data: Vec<u8>) -> MemoryDisk {
+        let padded = data.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        data.resize(padded, 0);
+        MemoryDisk { data: Mutex::new(data) }
+    }
+}
+
+impl BlockDevice for MemoryDisk {
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Window of `count` sectors starting at `start` of another device (used for partitions)
+pub struct BlockSlice {
+    device: Arc<dyn BlockDevice>,
+    start: u64,
+    count: u64
+}
+
+impl BlockSlice {
+    pub fn new(device: Arc<dyn BlockDevice>, start: u64, count: u64) -> Result<BlockSlice, BlockError> {
+        let end = start.checked_add(count).ok_or(BlockError::OutOfRange)?;
+
+        if end > device.sector_count() {
+            return Err(BlockError::OutOfRange);
+        }
+
+        Ok(BlockSlice { device, start, count })
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl BlockDevice for BlockSlice {
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        self.device.read_sectors(self.start + lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        self.device.write_sectors(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.device.flush()
+    }
+}
+
+static DEVICES: Mutex<Vec<(String, Arc<dyn BlockDevice>)>> = Mutex::new(Vec::new());
+
+/// Registers device under `name` (e.g. "disk0", "disk0p2"), replacing previous device with same name
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) {
+    let mut devices = DEVICES.lock();
+    devices.retain(|(existing, _)| existing != name);
+    devices.push((name.to_string(), device));
+}
+
+pub fn unregister(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    let mut devices = DEVICES.lock();
+    let index = devices.iter().position(|(existing, _)| existing == name)?;
+    Some(devices.remove(index).1)
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().iter().find(|(existing, _)| existing == name).map(|(_, device)| device.clone())
+}
+
+pub fn names() -> Vec<String> {
+    DEVICES.lock().iter().map(|(name, _)| name.clone()).collect()
+}
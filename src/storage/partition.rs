@@ -0,0 +1,343 @@
+use core::fmt;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+
+use crate::block::{self, BlockDevice, BlockError, BlockSlice};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+const MAX_LOGICAL_PARTITIONS: usize = 128; // protects from EBR loops
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+const GPT_MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError {
+    Block(BlockError),
+    NoDevice,
+    NoPartitionTable,
+    InvalidGpt
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(error: BlockError) -> Self {
+        PartitionError::Block(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+    pub const EFI_SYSTEM: Guid = Guid::from_fields(0xC12A7328, 0xF81F, 0x11D2, [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+    pub const MICROSOFT_BASIC_DATA: Guid = Guid::from_fields(0xEBD0A0A2, 0xB9E5, 0x4433, [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+    pub const LINUX_FILESYSTEM: Guid = Guid::from_fields(0x0FC63DAF, 0x8483, 0x4772, [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4]);
+
+    /// GUIDs are stored mixed-endian: first three fields little-endian, the rest as bytes
+    pub const fn from_fields(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Guid {
+        let a = data1.to_le_bytes();
+        let b = data2.to_le_bytes();
+        let c = data3.to_le_bytes();
+        Guid([a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], data4[0], data4[1], data4[2], data4[3], data4[4], data4[5], data4[6], data4[7]])
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    Mbr(u8),
+    Gpt(Guid)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// 1-based: 1..=4 for MBR primary, 5.. for logical, 1.. for GPT entries
+    pub number: usize,
+    pub start_lba: u64,
+    pub sector_count: u64,
+    pub partition_type: PartitionType,
+    pub name: String,
+    pub bootable: bool
+}
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = make_crc32_table();
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFFFFFF;
+
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+struct MbrEntry {
+    bootable: bool,
+    partition_type: u8,
+    start_lba: u64,
+    sector_count: u64
+}
+
+fn parse_mbr_entries(sector: &[u8]) -> [MbrEntry; 4] {
+    core::array::from_fn(|i| {
+        let entry = &sector[MBR_TABLE_OFFSET + i * 16..MBR_TABLE_OFFSET + (i + 1) * 16];
+        MbrEntry {
+            bootable: entry[0] == 0x80,
+            partition_type: entry[4],
+            start_lba: read_u32(entry, 8) as u64,
+            sector_count: read_u32(entry, 12) as u64
+        }
+    })
+}
+
+fn read_sector(device: &dyn BlockDevice, lba: u64) -> Result<Vec<u8>, BlockError> {
+    let mut sector = vec![0u8; device.sector_size()];
+    device.read_sectors(lba, &mut sector)?;
+    Ok(sector)
+}
+
+fn read_logical_partitions(device: &dyn BlockDevice, extended_start: u64, partitions: &mut Vec<Partition>) -> Result<(), PartitionError> {
+    let mut ebr_lba = extended_start;
+
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        let sector = read_sector(device, ebr_lba)?;
+
+        if sector[510..512] != MBR_SIGNATURE {
+            break;
+        }
+
+        let entries = parse_mbr_entries(&sector);
+        let logical = &entries[0];
+
+        if logical.partition_type != MBR_TYPE_EMPTY && logical.sector_count != 0 {
+            partitions.push(Partition {
+                number,
+                start_lba: ebr_lba + logical.start_lba, // relative to this EBR
+                sector_count: logical.sector_count,
+                partition_type: PartitionType::Mbr(logical.partition_type),
+                name: String::new(),
+                bootable: logical.bootable
+            });
+        }
+
+        let link = &entries[1];
+
+        if !MBR_EXTENDED_TYPES.contains(&link.partition_type) || link.start_lba == 0 {
+            break;
+        }
+
+        ebr_lba = extended_start + link.start_lba; // relative to the extended partition
+    }
+
+    Ok(())
+}
+
+fn read_mbr(device: &dyn BlockDevice, sector: &[u8]) -> Result<Vec<Partition>, PartitionError> {
+    let mut partitions = Vec::new();
+    let mut extended = Vec::new();
+
+    for (i, entry) in parse_mbr_entries(sector).iter().enumerate() {
+        if entry.partition_type == MBR_TYPE_EMPTY || entry.sector_count == 0 {
+            continue;
+        }
+
+        if MBR_EXTENDED_TYPES.contains(&entry.partition_type) {
+            extended.push(entry.start_lba);
+            continue;
+        }
+
+        partitions.push(Partition {
+            number: i + 1,
+            start_lba: entry.start_lba,
+            sector_count: entry.sector_count,
+            partition_type: PartitionType::Mbr(entry.partition_type),
+            name: String::new(),
+            bootable: entry.bootable
+        });
+    }
+
+    for extended_start in extended {
+        read_logical_partitions(device, extended_start, &mut partitions)?;
+    }
+
+    Ok(partitions)
+}
+
+struct GptHeader {
+    alternate_lba: u64,
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc32: u32
+}
+
+fn read_gpt_header(device: &dyn BlockDevice, lba: u64) -> Result<GptHeader, PartitionError> {
+    let mut sector = read_sector(device, lba)?;
+
+    if &sector[0..8] != GPT_SIGNATURE {
+        return Err(PartitionError::InvalidGpt);
+    }
+
+    let header_size = read_u32(&sector, 12) as usize;
+
+    if header_size < GPT_MIN_HEADER_SIZE || header_size > sector.len() {
+        return Err(PartitionError::InvalidGpt);
+    }
+
+    let header_crc32 = read_u32(&sector, 16);
+    sector[16..20].fill(0);
+
+    if crc32(&sector[..header_size]) != header_crc32 || read_u64(&sector, 24) != lba {
+        return Err(PartitionError::InvalidGpt);
+    }
+
+    let header = GptHeader {
+        alternate_lba: read_u64(&sector, 32),
+        entries_lba: read_u64(&sector, 72),
+        entry_count: read_u32(&sector, 80) as usize,
+        entry_size: read_u32(&sector, 84) as usize,
+        entries_crc32: read_u32(&sector, 88)
+    };
+
+    if header.entry_size < GPT_MIN_ENTRY_SIZE || header.entry_size % 8 != 0 || header.entry_count.saturating_mul(header.entry_size) > GPT_MAX_ENTRIES_SIZE {
+        return Err(PartitionError::InvalidGpt);
+    }
+
+    Ok(header)
+}
+
+fn read_gpt_entries(device: &dyn BlockDevice, header: &GptHeader) -> Result<Vec<Partition>, PartitionError> {
+    let sector_size = device.sector_size();
+    let size = header.entry_count * header.entry_size;
+    let mut entries = vec![0u8; size.div_ceil(sector_size) * sector_size];
+    device.read_sectors(header.entries_lba, &mut entries)?;
+
+    if crc32(&entries[..size]) != header.entries_crc32 {
+        return Err(PartitionError::InvalidGpt);
+    }
+
+    let mut partitions = Vec::new();
+
+    for (i, entry) in entries[..size].chunks_exact(header.entry_size).enumerate() {
+        let type_guid = Guid(entry[0..16].try_into().unwrap());
+
+        if type_guid == Guid::ZERO {
+            continue;
+        }
+
+        let first_lba = read_u64(entry, 32);
+        let last_lba = read_u64(entry, 40);
+
+        if last_lba < first_lba {
+            return Err(PartitionError::InvalidGpt);
+        }
+
+        let name_units = entry[56..128].chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).take_while(|&unit| unit != 0);
+        let name = char::decode_utf16(name_units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+
+        partitions.push(Partition {
+            number: i + 1,
+            start_lba: first_lba,
+            sector_count: last_lba - first_lba + 1,
+            partition_type: PartitionType::Gpt(type_guid),
+            name,
+            bootable: read_u64(entry, 48) & (1 << 2) != 0 // legacy BIOS bootable attribute
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// Reads primary GPT, falling back to the backup header at the end of the disk if primary header or entries are corrupted
+fn read_gpt(device: &dyn BlockDevice) -> Result<Vec<Partition>, PartitionError> {
+    let primary = read_gpt_header(device, 1);
+
+    if let Ok(header) = &primary {
+        if let Ok(partitions) = read_gpt_entries(device, header) {
+            return Ok(partitions);
+        }
+    }
+
+    let backup_lba = match primary {
+        Ok(header) if header.alternate_lba < device.sector_count() => header.alternate_lba,
+        _ => device.sector_count() - 1
+    };
+    let backup = read_gpt_header(device, backup_lba)?;
+    read_gpt_entries(device, &backup)
+}
+
+pub fn read_partitions(device: &dyn BlockDevice) -> Result<Vec<Partition>, PartitionError> {
+    let sector = read_sector(device, 0)?;
+
+    if sector[510..512] != MBR_SIGNATURE {
+        // Disks without protective MBR can still carry a valid GPT
+        return read_gpt(device).map_err(|_| PartitionError::NoPartitionTable);
+    }
+
+    if parse_mbr_entries(&sector).iter().any(|entry| entry.partition_type == MBR_TYPE_GPT_PROTECTIVE) {
+        return read_gpt(device);
+    }
+
+    read_mbr(device, &sector)
+}
+
+/// Reads partition table of registered device `disk_name` and registers every partition as "<disk_name>p<number>"
+pub fn scan(disk_name: &str) -> Result<Vec<Partition>, PartitionError> {
+    let device = block::get(disk_name).ok_or(PartitionError::NoDevice)?;
+    let partitions = read_partitions(device.as_ref())?;
+
+    for partition in &partitions {
+        let slice = BlockSlice::new(device.clone(), partition.start_lba, partition.sector_count)?;
+        block::register(&format!("{}p{}", disk_name, partition.number), Arc::new(slice));
+    }
+
+    Ok(partitions)
+}
@@ -0,0 +1,214 @@
+//! Kernel spinlock. Interrupts stay disabled while it's held, so interrupt handlers can take
+//! the same locks as the code they interrupt. Debug builds remember where each lock was taken
+//! and panic on recursive locking or on spinning suspiciously long.
+
+use core::{cell::UnsafeCell, fmt, ops::{Deref, DerefMut}, panic::Location, sync::atomic::{AtomicBool, Ordering}};
+
+#[cfg(debug_assertions)]
+use core::{ptr, sync::atomic::{AtomicPtr, AtomicUsize}};
+
+use x86_64::instructions::interrupts;
+
+#[cfg(debug_assertions)]
+use crate::smp;
+
+/// Spins after which a held lock is reported as a deadlock
+#[cfg(debug_assertions)]
+const SPIN_LIMIT: usize = 100_000_000;
+#[cfg(debug_assertions)]
+const NO_CPU: usize = usize::MAX;
+
+/// Processor holding a lock and where it was locked
+#[derive(Debug, Clone, Copy)]
+pub struct Holder {
+    pub cpu: usize,
+    pub location: &'static Location<'static>
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CPU {} at {}", self.cpu, self.location)
+    }
+}
+
+/// Holder of a lock, only tracked in debug builds
+struct Owner {
+    #[cfg(debug_assertions)]
+    cpu: AtomicUsize,
+    #[cfg(debug_assertions)]
+    location: AtomicPtr<Location<'static>>
+}
+
+impl Owner {
+    const fn new() -> Owner {
+        Owner {
+            #[cfg(debug_assertions)]
+            cpu: AtomicUsize::new(NO_CPU),
+            #[cfg(debug_assertions)]
+            location: AtomicPtr::new(ptr::null_mut())
+        }
+    }
+
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn set(&self, location: &'static Location<'static>) {
+        #[cfg(debug_assertions)]
+        {
+            self.location.store(location as *const _ as *mut _, Ordering::Relaxed);
+            self.cpu.store(smp::cpu_index(), Ordering::Relaxed);
+        }
+    }
+
+    fn clear(&self) {
+        #[cfg(debug_assertions)]
+        self.cpu.store(NO_CPU, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<Holder> {
+        #[cfg(debug_assertions)]
+        {
+            let cpu = self.cpu.load(Ordering::Relaxed);
+            let location = self.location.load(Ordering::Relaxed);
+
+            if cpu != NO_CPU && !location.is_null() {
+                return Some(Holder { cpu, location: unsafe { &*location } });
+            }
+        }
+
+        None
+    }
+}
+
+pub struct SpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    owner: Owner,
+    value: UnsafeCell<T>
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+    /// Whether interrupts are enabled again when the guard is dropped
+    interrupts_enabled: bool
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for SpinLockGuard<'_, T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> SpinLock<T> {
+        SpinLock { locked: AtomicBool::new(false), owner: Owner::new(), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        #[cfg(debug_assertions)]
+        let mut spins = 0;
+
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // With interrupts disabled only the holder itself can run on its processor
+            #[cfg(debug_assertions)]
+            if let Some(holder) = self.owner.get().filter(|holder| holder.cpu == smp::cpu_index()) {
+                panic!("Recursive locking of lock held since {}", holder.location);
+            }
+
+            while self.locked.load(Ordering::Relaxed) {
+                #[cfg(debug_assertions)]
+                {
+                    spins += 1;
+
+                    if spins == SPIN_LIMIT {
+                        match self.owner.get() {
+                            Some(holder) => panic!("Possible deadlock, lock is held by {}", holder),
+                            None => panic!("Possible deadlock, lock is held too long")
+                        }
+                    }
+                }
+
+                core::hint::spin_loop();
+            }
+        }
+
+        self.owner.set(Location::caller());
+        SpinLockGuard { lock: self, interrupts_enabled }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        if self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            if interrupts_enabled {
+                interrupts::enable();
+            }
+
+            return None;
+        }
+
+        self.owner.set(Location::caller());
+        Some(SpinLockGuard { lock: self, interrupts_enabled })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Where the lock was taken, always `None` in release builds
+    pub fn holder(&self) -> Option<Holder> {
+        self.owner.get()
+    }
+
+    /// Releases the lock whoever holds it
+    ///
+    /// # Safety
+    /// The holder may still be using the value, it's meant for the panic path only
+    pub unsafe fn force_unlock(&self) {
+        self.owner.clear();
+        self.locked.store(false, Ordering::Release);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        SpinLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.owner.clear();
+        self.lock.locked.store(false, Ordering::Release);
+
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use core::arch::{asm, global_asm};
+
+use x86_64::{instructions::interrupts, VirtAddr};
+
+use super::Registers;
+use crate::{gdt, thread};
+
+// Both entries save every general purpose register and an interrupt frame as `Registers` on the
+// thread's kernel stack, call `ruin_syscall_dispatch` with interrupts enabled and return to
+// ring 3 with whatever `Registers` hold then, so the dispatcher can change where user code
+// continues. Nothing from the kernel is left in registers.
+//
+// `syscall` doesn't switch stacks, so the entry swaps in the kernel GS base first and finds the
+// kernel stack at `gs:[8]`, keeping the user stack pointer at `gs:[16]` until it's pushed. It
+// builds the interrupt frame itself, return address and flags come in `rcx` and `r11`.
+global_asm!(
+    ".global ruin_syscall_entry",
+    "ruin_syscall_entry:",
+    "swapgs",
+    "mov gs:[16], rsp",
+    "mov rsp, gs:[8]",
+    "push {user_data}",
+    "push qword ptr gs:[16]",
+    "push r11",
+    "push {user_code}",
+    "push rcx",
+    "push r15",
+    "push r14",
+    "push r13",
+    "push r12",
+    "push rbp",
+    "push rbx",
+    "push r11",
+    "push rcx",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "sti",
+    "mov rdi, rsp",
+    "call ruin_syscall_dispatch",
+    "cli",
+    "pop rax",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop rcx",
+    "pop r11",
+    "pop rbx",
+    "pop rbp",
+    "pop r12",
+    "pop r13",
+    "pop r14",
+    "pop r15",
+    // `sysret` takes return address and flags from `rcx` and `r11` and loads fixed selectors
+    "mov rcx, [rsp]",
+    "mov r11, [rsp + 16]",
+    "mov rsp, [rsp + 24]",
+    "swapgs",
+    "sysretq",
+    user_data = const gdt::USER_DATA_SELECTOR,
+    user_code = const gdt::USER_CODE_SELECTOR
+);
+
+// `int 0x80` arrives on the kernel stack already, GS is only swapped if it came from ring 3.
+// Stack is 16 byte aligned before the interrupt frame, so 20 words keep it aligned for the call.
+// `ruin_syscall_return` is also where `resume` enters with a prepared `Registers`.
+global_asm!(
+    ".global ruin_syscall_interrupt",
+    "ruin_syscall_interrupt:",
+    "test qword ptr [rsp + 8], 3",
+    "jz 1f",
+    "swapgs",
+    "1:",
+    "push r15",
+    "push r14",
+    "push r13",
+    "push r12",
+    "push rbp",
+    "push rbx",
+    "push r11",
+    "push rcx",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "cld",
+    "sti",
+    "mov rdi, rsp",
+    "call ruin_syscall_dispatch",
+    "cli",
+    ".global ruin_syscall_return",
+    "ruin_syscall_return:",
+    "pop rax",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop rcx",
+    "pop r11",
+    "pop rbx",
+    "pop rbp",
+    "pop r12",
+    "pop r13",
+    "pop r14",
+    "pop r15",
+    "test qword ptr [rsp + 8], 3",
+    "jz 2f",
+    "swapgs",
+    "2:",
+    "iretq"
+);
+
+extern "C" {
+    fn ruin_syscall_entry();
+    fn ruin_syscall_interrupt();
+}
+
+/// Target of `syscall`, for `LSTAR`
+pub fn syscall_entry() -> VirtAddr {
+    VirtAddr::new(ruin_syscall_entry as *const () as u64)
+}
+
+/// Handler of `int 0x80`, it's not an `x86-interrupt` function because it needs the registers
+pub fn interrupt_entry() -> VirtAddr {
+    VirtAddr::new(ruin_syscall_interrupt as *const () as u64)
+}
+
+/// Continues the current thread in ring 3 with `registers`, like returning from a system call.
+/// Its kernel stack is abandoned the same way as in `usermode::enter`.
+pub(super) fn resume(registers: &Registers) -> ! {
+    let kernel_stack = thread::kernel_stack_top().expect("Thread has no kernel stack");
+    interrupts::disable();
+    gdt::set_kernel_stack(kernel_stack);
+
+    unsafe {
+        asm!(
+            "mov rsp, {registers}",
+            "jmp ruin_syscall_return",
+            registers = in(reg) registers as *const Registers,
+            options(noreturn)
+        );
+    }
+}
@@ -0,0 +1,923 @@
+//! System calls. User code puts the number in `rax` and arguments in `rdi`, `rsi`, `rdx`, `r10`,
+//! `r8` and `r9`, then executes `syscall` or `int 0x80`. Result comes back in `rax`, errors as
+//! negated `Error` codes. `syscall` also overwrites `rcx` and `r11`, other registers are kept.
+//!
+//! Strings are passed as address and length, except where they come from C style arrays.
+//! Channel messages are passed as `UserMessage`, with handles as arrays of 32 bit integers.
+
+mod entry;
+pub mod user;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{mem::size_of, sync::atomic::{AtomicU64, Ordering}};
+
+use x86_64::{
+    registers::{model_specific::{Efer, EferFlags, LStar, SFMask, Star}, rflags::RFlags},
+    structures::paging::PageTableFlags,
+    VirtAddr
+};
+
+use crate::{
+    console,
+    fs::{self, file::{File, OpenFlags}, page_cache, vfs, FileType, FsError},
+    gdt,
+    ipc::{self, Endpoint, IpcError, Message, MESSAGE_BUFFER, MESSAGE_DATA, MESSAGE_HANDLES},
+    loader::{self, LoadError},
+    memory::{self, shared, MapError, RegionKind},
+    println,
+    process::{self, files, signal::{self, Action, Signal, SignalSet}, ExitStatus, Pid, ProcessError},
+    thread,
+    tty::Mode,
+    usermode
+};
+
+pub use entry::interrupt_entry;
+
+pub const INTERRUPT_VECTOR: u8 = 0x80;
+
+pub const WRITE: u64 = 0;
+pub const READ: u64 = 1;
+pub const EXIT: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const MMAP: u64 = 5;
+pub const GETPID: u64 = 6;
+pub const OPEN: u64 = 7;
+pub const CLOSE: u64 = 8;
+pub const FORK: u64 = 9;
+pub const EXEC: u64 = 10;
+pub const WAIT: u64 = 11;
+pub const GETPPID: u64 = 12;
+pub const SHM_MAP: u64 = 13;
+pub const SHM_UNLINK: u64 = 14;
+pub const MSYNC: u64 = 15;
+pub const PIPE: u64 = 16;
+pub const DUP: u64 = 17;
+pub const DUP2: u64 = 18;
+pub const KILL: u64 = 19;
+pub const SIGACTION: u64 = 20;
+pub const SIGPROCMASK: u64 = 21;
+pub const SIGRETURN: u64 = 22;
+pub const CHANNEL: u64 = 23;
+pub const CLOSE_HANDLE: u64 = 24;
+pub const SEND: u64 = 25;
+pub const RECEIVE: u64 = 26;
+pub const CALL: u64 = 27;
+pub const WAIT_HANDLES: u64 = 28;
+pub const TERMINAL_MODE: u64 = 29;
+pub const SET_TERMINAL_MODE: u64 = 30;
+
+/// `mmap` and `shm_map` flags, memory is always readable
+pub const MMAP_WRITE: u64 = 1;
+pub const MMAP_EXECUTE: u64 = 2;
+/// `sigaction` handler values that aren't addresses
+pub const SIG_DEFAULT: u64 = 0;
+pub const SIG_IGNORE: u64 = 1;
+
+/// `sigprocmask` ways to change the blocked signals
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/// `send` and `receive` fail with `WouldBlock` instead of waiting
+pub const IPC_NONBLOCK: u64 = 1;
+/// `UserMessage::reply` of received messages that don't come from `call`
+pub const NO_HANDLE: u64 = u64::MAX;
+
+/// Forked children share the memory instead of getting a copy, writes to a mapped file go to the
+/// file. Only for `mmap`.
+pub const MMAP_SHARED: u64 = 4;
+/// Maps an open file instead of zeroed memory, only for `mmap`
+pub const MMAP_FILE: u64 = 8;
+
+const PAGE_SIZE: u64 = 4096;
+/// `mmap` without an address takes memory from here up, threads without a process only there
+const MMAP_START: u64 = 0x6000_0000_0000;
+/// Most handles `wait_handles` waits for at once
+const MAX_WAIT: usize = 64;
+
+static MMAP_NEXT: AtomicU64 = AtomicU64::new(MMAP_START);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Error {
+    /// No system call has this number
+    InvalidSyscall = 1,
+    /// Pointer argument is not user memory that can be accessed the needed way
+    BadAddress = 2,
+    /// File descriptor is not open
+    BadDescriptor = 3,
+    InvalidArgument = 4,
+    OutOfMemory = 5,
+    NotFound = 6,
+    /// No child to wait for
+    NoChild = 7,
+    /// File is not an executable that can be loaded
+    BadExecutable = 8,
+    TooManyFiles = 9,
+    /// Other filesystem errors
+    Io = 10,
+    /// File wasn't opened for the access
+    AccessDenied = 11,
+    /// Descriptor is non-blocking and the call would have to wait
+    WouldBlock = 12,
+    /// Write to a pipe without readers
+    BrokenPipe = 13,
+    /// No process has the given id
+    NoProcess = 14,
+    /// Call waited and gave up because a signal arrived
+    Interrupted = 15,
+    /// Other endpoint of the channel is closed
+    PeerClosed = 16,
+    /// Terminal call on a descriptor that isn't a terminal
+    NotTerminal = 17
+}
+
+impl Error {
+    /// Value of `rax` when the call fails with this error
+    pub fn code(self) -> i64 {
+        -(self as i64)
+    }
+}
+
+impl From<FsError> for Error {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => Error::NotFound,
+            FsError::InvalidPath | FsError::NameTooLong | FsError::InvalidArgument => Error::InvalidArgument,
+            FsError::PermissionDenied => Error::AccessDenied,
+            FsError::WouldBlock => Error::WouldBlock,
+            FsError::BrokenPipe => Error::BrokenPipe,
+            FsError::Interrupted => Error::Interrupted,
+            FsError::NotTerminal => Error::NotTerminal,
+            _ => Error::Io
+        }
+    }
+}
+
+impl From<IpcError> for Error {
+    fn from(error: IpcError) -> Self {
+        match error {
+            IpcError::PeerClosed => Error::PeerClosed,
+            IpcError::WouldBlock => Error::WouldBlock,
+            IpcError::Interrupted => Error::Interrupted,
+            IpcError::TooLarge | IpcError::InvalidHandle => Error::InvalidArgument
+        }
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(error: LoadError) -> Self {
+        match error {
+            LoadError::Elf(_) | LoadError::Map(MapError::KernelRange) => Error::BadExecutable,
+            LoadError::Fs(error) => error.into(),
+            LoadError::Map(_) => Error::OutOfMemory,
+            LoadError::ArgumentsTooLong => Error::InvalidArgument
+        }
+    }
+}
+
+impl From<ProcessError> for Error {
+    fn from(error: ProcessError) -> Self {
+        match error {
+            ProcessError::NotProcess => Error::InvalidSyscall,
+            ProcessError::NoChild => Error::NoChild,
+            ProcessError::NoProcess => Error::NoProcess,
+            ProcessError::Interrupted => Error::Interrupted,
+            ProcessError::InvalidSignal => Error::InvalidArgument,
+            ProcessError::Map(_) => Error::OutOfMemory
+        }
+    }
+}
+
+/// User registers saved by the entry stubs, in the order they are pushed: general purpose
+/// registers, then an interrupt frame. Arguments are read from them, `rax` gets the result and
+/// the rest may be changed to continue elsewhere.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub rcx: u64,
+    pub r11: u64,
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64
+}
+
+impl Registers {
+    /// Fresh ring 3 state starting at `entry` with stack pointer `stack`
+    pub fn user(entry: VirtAddr, stack: VirtAddr) -> Registers {
+        let selectors = gdt::selectors();
+
+        Registers {
+            rip: entry.as_u64(),
+            cs: u64::from(selectors.user_code_selector.0),
+            rflags: usermode::USER_RFLAGS,
+            rsp: stack.as_u64(),
+            ss: u64::from(selectors.user_data_selector.0),
+            ..Registers::default()
+        }
+    }
+
+    pub fn arguments(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+type Handler = fn(&mut Registers) -> Result<u64, Error>;
+
+/// Handlers by system call number
+static TABLE: [Handler; 31] = [
+    write, read, exit, yield_now, sleep, mmap, getpid, open, close, fork, exec, wait, getppid, shm_map, shm_unlink, msync, pipe, dup, dup2, kill,
+    sigaction, sigprocmask, sigreturn, channel, close_handle, send, receive, call, wait_handles, terminal_mode, set_terminal_mode
+];
+
+/// Enables `syscall` on the calling processor, every processor has to call it
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(selectors.user_code_selector, selectors.user_data_selector, selectors.kernel_code_selector, selectors.kernel_data_selector)
+        .expect("GDT segments are not in the order sysret expects");
+    LStar::write(entry::syscall_entry());
+    // Interrupts stay disabled until the entry is on the kernel stack
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+#[no_mangle]
+extern "C" fn ruin_syscall_dispatch(registers: &mut Registers) {
+    let result = TABLE.get(registers.rax as usize).ok_or(Error::InvalidSyscall).and_then(|handler| handler(registers));
+
+    registers.rax = match result {
+        Ok(value) => value,
+        Err(error) => error.code() as u64
+    };
+
+    signal::deliver(registers);
+}
+
+/// Writes up to `length` bytes from `address` to open file `descriptor`, returns how many were
+/// written
+fn write(registers: &mut Registers) -> Result<u64, Error> {
+    let [descriptor, address, length, ..] = registers.arguments();
+    let bytes = user::slice(address, length)?;
+    Ok(file(descriptor)?.write(bytes)? as u64)
+}
+
+/// Reads up to `length` bytes from open file `descriptor` to `address`, returns how many were
+/// read. Pipes and the console wait until at least one byte arrives, 0 is the end of the file.
+fn read(registers: &mut Registers) -> Result<u64, Error> {
+    let [descriptor, address, length, ..] = registers.arguments();
+    let buffer = user::slice_mut(address, length)?;
+    Ok(file(descriptor)?.read(buffer)? as u64)
+}
+
+/// Open file `descriptor` of the calling process. Threads that are not processes have the
+/// console as descriptors 0 to 2.
+fn file(descriptor: u64) -> Result<Arc<File>, Error> {
+    match process::with_files(|files| files.get(descriptor as usize)) {
+        Some(file) => file.ok_or(Error::BadDescriptor),
+        None if descriptor == files::STDIN as u64 => Ok(Arc::new(console::open(OpenFlags::READ))),
+        None if descriptor <= files::STDERR as u64 => Ok(Arc::new(console::open(OpenFlags::WRITE))),
+        None => Err(Error::BadDescriptor)
+    }
+}
+
+/// Ends the calling process with the exit code in the low 32 bits
+fn exit(registers: &mut Registers) -> Result<u64, Error> {
+    process::exit(ExitStatus::Exited(registers.rdi as i32))
+}
+
+fn yield_now(_: &mut Registers) -> Result<u64, Error> {
+    thread::yield_now();
+    Ok(0)
+}
+
+fn sleep(registers: &mut Registers) -> Result<u64, Error> {
+    thread::sleep(registers.rdi);
+    Ok(0)
+}
+
+/// Maps `length` bytes of zeroed memory at page aligned `address` of the caller's address space,
+/// or wherever there is room if it's 0. Threads without a process can't choose the address.
+/// With `MMAP_FILE` it maps file `descriptor` from page aligned `offset` instead, its pages are
+/// read when they're first touched. Returns the address.
+fn mmap(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, length, flags, descriptor, offset, ..] = registers.arguments();
+
+    if length == 0 || flags & !(MMAP_WRITE | MMAP_EXECUTE | MMAP_SHARED | MMAP_FILE) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let length = length.checked_next_multiple_of(PAGE_SIZE).ok_or(Error::InvalidArgument)?;
+    let address_space = process::address_space();
+
+    let address = match (address, &address_space) {
+        (0, Some(address_space)) => address_space.find_free(VirtAddr::new(MMAP_START), length).ok_or(Error::OutOfMemory)?.as_u64(),
+        // Threads without a process share the kernel page table. They only get memory from
+        // `MMAP_START` up, which nothing else maps there.
+        (0, None) => {
+            let address = MMAP_NEXT.fetch_add(length, Ordering::Relaxed);
+
+            if address.checked_add(length).is_none_or(|end| end > usermode::USER_END) {
+                return Err(Error::OutOfMemory);
+            }
+
+            address
+        }
+        (_, None) => return Err(Error::InvalidArgument),
+        (address, _) => address
+    };
+
+    if address % PAGE_SIZE != 0 || address.checked_add(length).is_none_or(|end| end > usermode::USER_END) {
+        return Err(Error::InvalidArgument);
+    }
+
+    let shared = flags & MMAP_SHARED != 0;
+    let kind = if shared { RegionKind::Shared } else { RegionKind::Anonymous };
+
+    let result = match address_space {
+        Some(address_space) if flags & MMAP_FILE != 0 => {
+            let file = file(descriptor)?;
+
+            if offset % PAGE_SIZE != 0 || file.stat()?.file_type != FileType::Regular {
+                return Err(Error::InvalidArgument);
+            }
+
+            // Private copies may be written even if the file can't
+            if !file.flags().readable() || (shared && flags & MMAP_WRITE != 0 && !file.flags().writable()) {
+                return Err(Error::AccessDenied);
+            }
+
+            let backing = page_cache::MappedFile::new(file.id(), file.inode().clone());
+            address_space.map_backed(VirtAddr::new(address), length, page_flags(flags), backing, offset / PAGE_SIZE, shared)
+        }
+        Some(address_space) => address_space.map(VirtAddr::new(address), length, page_flags(flags), kind),
+        None if flags & MMAP_FILE != 0 => return Err(Error::InvalidSyscall),
+        None => memory::map_user(VirtAddr::new(address), length, page_flags(flags))
+    };
+
+    result.map_err(map_error)?;
+    Ok(address)
+}
+
+/// Page flags for `mmap` flags
+fn page_flags(flags: u64) -> PageTableFlags {
+    let mut page_flags = PageTableFlags::empty();
+
+    if flags & MMAP_WRITE != 0 {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+
+    if flags & MMAP_EXECUTE == 0 {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    page_flags
+}
+
+fn map_error(error: MapError) -> Error {
+    match error {
+        MapError::OutOfMemory => Error::OutOfMemory,
+        _ => Error::InvalidArgument
+    }
+}
+
+/// Maps shared memory named by `length` bytes at `address` into the calling process, creating
+/// it with `size` bytes if there's none. Returns the address it's mapped at.
+fn shm_map(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, length, size, flags, ..] = registers.arguments();
+
+    if size == 0 || flags & !(MMAP_WRITE | MMAP_EXECUTE) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let address_space = process::address_space().ok_or(Error::InvalidSyscall)?;
+    let memory = shared::open(user::string(address, length)?, size).map_err(map_error)?;
+    let start = address_space.find_free(VirtAddr::new(MMAP_START), memory.size()).ok_or(Error::OutOfMemory)?;
+    address_space.map_shared(start, &memory, page_flags(flags)).map_err(map_error)?;
+    Ok(start.as_u64())
+}
+
+/// Stores pages of files mapped with `MMAP_SHARED` in `length` bytes from `address` to the files
+fn msync(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, length, ..] = registers.arguments();
+
+    if address.checked_add(length).is_none_or(|end| end > usermode::USER_END) {
+        return Err(Error::BadAddress);
+    }
+
+    let address_space = process::address_space().ok_or(Error::InvalidSyscall)?;
+    address_space.sync(VirtAddr::new(address), length).map_err(|_| Error::Io)?;
+    Ok(0)
+}
+
+/// Removes the name of shared memory named by `length` bytes at `address`, mappings stay
+fn shm_unlink(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, length, ..] = registers.arguments();
+
+    if !shared::unlink(user::string(address, length)?) {
+        return Err(Error::NotFound);
+    }
+
+    Ok(0)
+}
+
+/// Id of the calling process, or of the thread for threads that are not processes
+fn getpid(_: &mut Registers) -> Result<u64, Error> {
+    match process::current() {
+        Some(pid) => Ok(pid.as_u64()),
+        None => Ok(thread::current().expect("System call outside of a thread").as_u64())
+    }
+}
+
+/// Id of the parent process, 0 if the parent is the kernel or has exited
+fn getppid(_: &mut Registers) -> Result<u64, Error> {
+    let pid = process::current().ok_or(Error::InvalidSyscall)?;
+
+    match process::info(pid).map(|info| info.parent) {
+        Some(process::Parent::Process(parent)) => Ok(parent.as_u64()),
+        _ => Ok(0)
+    }
+}
+
+/// Opens file at path of `length` bytes at `address` with `OpenFlags` bits, returns descriptor
+fn open(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, length, flags, ..] = registers.arguments();
+    let path = user::string(address, length)?;
+    let file = Arc::new(vfs::open(path, OpenFlags(flags as u32))?);
+    let descriptor = process::with_files(|files| files.insert(file)).ok_or(Error::InvalidSyscall)?;
+    Ok(descriptor.ok_or(Error::TooManyFiles)? as u64)
+}
+
+fn close(registers: &mut Registers) -> Result<u64, Error> {
+    process::with_files(|files| files.remove(registers.rdi as usize)).flatten().ok_or(Error::BadDescriptor)?;
+    Ok(0)
+}
+
+/// Creates a pipe and writes descriptors of its read end and write end to `descriptors`, an
+/// array of two 32 bit integers. `flags` may be `OpenFlags::NONBLOCK`.
+fn pipe(registers: &mut Registers) -> Result<u64, Error> {
+    let [descriptors, flags, ..] = registers.arguments();
+
+    if flags & !u64::from(OpenFlags::NONBLOCK.0) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    user::check(descriptors, 8, true)?;
+    let (reader, writer) = fs::pipe::pipe(OpenFlags(flags as u32));
+
+    let pair = process::with_files(|files| {
+        let reader = files.insert(Arc::new(reader))?;
+
+        match files.insert(Arc::new(writer)) {
+            Some(writer) => Some([reader as u32, writer as u32]),
+            None => {
+                files.remove(reader);
+                None
+            }
+        }
+    }).ok_or(Error::InvalidSyscall)?;
+
+    user::write(descriptors, pair.ok_or(Error::TooManyFiles)?)?;
+    Ok(0)
+}
+
+/// Gives open file `descriptor` the lowest free descriptor as well, returns it
+fn dup(registers: &mut Registers) -> Result<u64, Error> {
+    let file = file(registers.rdi)?;
+    let descriptor = process::with_files(|files| files.insert(file)).ok_or(Error::InvalidSyscall)?;
+    Ok(descriptor.ok_or(Error::TooManyFiles)? as u64)
+}
+
+/// Makes `new` another descriptor of open file `old`, closing what `new` had before. Returns
+/// `new`.
+fn dup2(registers: &mut Registers) -> Result<u64, Error> {
+    let [old, new, ..] = registers.arguments();
+    let file = file(old)?;
+
+    if new >= files::MAX_FILES as u64 {
+        return Err(Error::BadDescriptor);
+    }
+
+    // Replaced file is closed once the table is unlocked
+    let replaced = process::with_files(|files| files.insert_at(new as usize, file)).ok_or(Error::InvalidSyscall)?;
+    drop(replaced);
+    Ok(new)
+}
+
+/// Mode of terminal `descriptor`, bits of `tty::Mode`
+fn terminal_mode(registers: &mut Registers) -> Result<u64, Error> {
+    Ok(file(registers.rdi)?.terminal_mode()?.0 as u64)
+}
+
+/// Switches terminal `descriptor` to the mode with bits of `tty::Mode` in the low byte, returns
+/// the previous mode
+fn set_terminal_mode(registers: &mut Registers) -> Result<u64, Error> {
+    let [descriptor, mode, ..] = registers.arguments();
+    let mode = Mode(u8::try_from(mode).map_err(|_| Error::InvalidArgument)?);
+    Ok(file(descriptor)?.set_terminal_mode(mode)?.0 as u64)
+}
+
+/// Starts a copy of the calling process. Returns the child's id in the parent and 0 in the
+/// child, which continues from the same place.
+fn fork(registers: &mut Registers) -> Result<u64, Error> {
+    let mut child = *registers;
+    child.rax = 0;
+    let pid = process::fork(move || entry::resume(&child))?;
+    Ok(pid.as_u64())
+}
+
+/// Replaces the calling process with executable at path of `length` bytes at `address`.
+/// `argv` is a null terminated array of pointers to NUL terminated strings, or 0 for none. Only
+/// returns on failure, the old program is kept then.
+fn exec(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, length, argv, ..] = registers.arguments();
+    let path = user::string(address, length)?;
+    let arguments = if argv == 0 { Vec::new() } else { user::c_string_array(argv)? };
+    // Loading doesn't touch the caller's memory, so the strings stay valid until `exec`
+    let program = loader::load_file(path, &arguments, &[])?;
+    let name = String::from(arguments.first().copied().unwrap_or(path));
+    process::exec(&program, &name)?;
+    *registers = Registers::user(program.entry(), program.stack_pointer());
+    Ok(0)
+}
+
+/// Waits until child `pid`, or any child if it's 0, exits. Writes its status to `status` unless
+/// it's 0, see `ExitStatus::wait_status`. Returns the child's id.
+fn wait(registers: &mut Registers) -> Result<u64, Error> {
+    let [pid, status, ..] = registers.arguments();
+
+    if status != 0 {
+        user::check(status, 8, true)?;
+    }
+
+    let target = (pid != 0).then(|| Pid::from_u64(pid));
+    let (pid, exit_status) = process::wait(target)?;
+
+    if status != 0 {
+        user::write(status, exit_status.wait_status())?;
+    }
+
+    Ok(pid.as_u64())
+}
+
+/// Sends signal number `signal` to process `pid`. Signal 0 only checks that the process exists.
+fn kill(registers: &mut Registers) -> Result<u64, Error> {
+    let [pid, number, ..] = registers.arguments();
+    let pid = Pid::from_u64(pid);
+
+    if number == 0 {
+        return process::info(pid).map(|_| 0).ok_or(Error::NoProcess);
+    }
+
+    signal::kill(pid, Signal::new(number).ok_or(Error::InvalidArgument)?)?;
+    Ok(0)
+}
+
+/// `sigaction` argument, laid out like the one of Linux
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct SigAction {
+    /// `SIG_DEFAULT`, `SIG_IGNORE` or the address of a handler
+    handler: u64,
+    flags: u64,
+    /// Return address of the handler, it should call `sigreturn`
+    restorer: u64,
+    mask: u64
+}
+
+impl SigAction {
+    fn new(action: Action) -> SigAction {
+        match action {
+            Action::Default => SigAction { handler: SIG_DEFAULT, ..SigAction::default() },
+            Action::Ignore => SigAction { handler: SIG_IGNORE, ..SigAction::default() },
+            Action::Handler(handler) => SigAction { handler: handler.address, flags: handler.flags, restorer: handler.restorer, mask: handler.mask.0 }
+        }
+    }
+
+    /// Handlers outside of user memory are `BadAddress`, the kernel would fault returning to one
+    fn action(self) -> Result<Action, Error> {
+        match self.handler {
+            SIG_DEFAULT => Ok(Action::Default),
+            SIG_IGNORE => Ok(Action::Ignore),
+            address if address >= usermode::USER_END => Err(Error::BadAddress),
+            address => Ok(Action::Handler(signal::Handler { address, mask: SignalSet(self.mask), flags: self.flags, restorer: self.restorer }))
+        }
+    }
+}
+
+/// Sets the action for `signal` from the `SigAction` at `action` unless it's 0, writes the
+/// previous one to `old` unless that's 0
+fn sigaction(registers: &mut Registers) -> Result<u64, Error> {
+    let [number, action, old, ..] = registers.arguments();
+    let signal = Signal::new(number).ok_or(Error::InvalidArgument)?;
+
+    if old != 0 {
+        user::check(old, size_of::<SigAction>() as u64, true)?;
+    }
+
+    let previous = match action {
+        0 => signal::action(signal)?,
+        action => signal::set_action(signal, user::read::<SigAction>(action)?.action()?)?
+    };
+
+    if old != 0 {
+        user::write(old, SigAction::new(previous))?;
+    }
+
+    Ok(0)
+}
+
+/// Blocks the signals in the 64 bit mask at `set`, unblocks them or blocks just them, depending
+/// on `how`. `set` may be 0 to leave them. Writes the previously blocked ones to `old` unless
+/// it's 0.
+fn sigprocmask(registers: &mut Registers) -> Result<u64, Error> {
+    let [how, set, old, ..] = registers.arguments();
+
+    if how > SIG_SETMASK {
+        return Err(Error::InvalidArgument);
+    }
+
+    if old != 0 {
+        user::check(old, 8, true)?;
+    }
+
+    let set = if set != 0 { Some(user::read::<u64>(set)?) } else { None };
+
+    let previous = signal::update_blocked(|blocked| match (how, set) {
+        (_, None) => blocked,
+        (SIG_BLOCK, Some(set)) => SignalSet(blocked.0 | set),
+        (SIG_UNBLOCK, Some(set)) => SignalSet(blocked.0 & !set),
+        (_, Some(set)) => SignalSet(set)
+    })?;
+
+    if old != 0 {
+        user::write(old, previous.0)?;
+    }
+
+    Ok(0)
+}
+
+/// Returns from a signal handler to the code it interrupted, with every register as it was. The
+/// handler's `ret` has to lead here with the stack as it left it.
+fn sigreturn(registers: &mut Registers) -> Result<u64, Error> {
+    if process::current().is_none() {
+        return Err(Error::InvalidSyscall);
+    }
+
+    if let Err(error) = signal::restore(registers) {
+        println!("Bad signal frame at {:#x}: {:?}", registers.rsp, error);
+        signal::fault(Signal::SEGV);
+    }
+
+    signal::deliver(registers);
+    // `sysret` would lose `rcx` and `r11`
+    entry::resume(registers)
+}
+
+/// Creates a channel and writes handles of its two endpoints to `handles`, an array of two 32 bit
+/// integers
+fn channel(registers: &mut Registers) -> Result<u64, Error> {
+    let address = registers.rdi;
+    user::check(address, 8, true)?;
+    let (first, second) = ipc::channel();
+
+    let pair = process::with_handles(|handles| {
+        let first = handles.insert(Arc::new(first))?;
+
+        match handles.insert(Arc::new(second)) {
+            Some(second) => Some([first as u32, second as u32]),
+            None => {
+                handles.remove(first);
+                None
+            }
+        }
+    }).ok_or(Error::InvalidSyscall)?;
+
+    user::write(address, pair.ok_or(Error::TooManyFiles)?)?;
+    Ok(0)
+}
+
+/// Closes `handle`, the other endpoint sees its peer gone once no process holds this one
+fn close_handle(registers: &mut Registers) -> Result<u64, Error> {
+    let endpoint = process::with_handles(|handles| handles.remove(registers.rdi as usize)).ok_or(Error::InvalidSyscall)?;
+    // Unread messages are dropped with the endpoint, outside of the process table lock
+    drop(endpoint.ok_or(Error::BadDescriptor)?);
+    Ok(0)
+}
+
+/// Endpoint of `handle` of the calling process
+fn endpoint(handle: u64) -> Result<Arc<Endpoint>, Error> {
+    process::with_handles(|handles| handles.get(handle as usize)).ok_or(Error::InvalidSyscall)?.ok_or(Error::BadDescriptor)
+}
+
+/// Message of `send`, `receive` and `call` in user memory. Sending reads `data_length` bytes at
+/// `data`, `handle_count` handles at `handles` and page aligned `buffer_length` bytes at
+/// `buffer`, which are copied to pages the receiver maps. Receiving takes the lengths as room
+/// there is, writes what the message has and sets `buffer` to where its pages are mapped.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct UserMessage {
+    pub data: u64,
+    pub data_length: u64,
+    pub handles: u64,
+    pub handle_count: u64,
+    pub buffer: u64,
+    pub buffer_length: u64,
+    /// Handle to answer a received `call` on, `NO_HANDLE` for other messages
+    pub reply: u64
+}
+
+/// Message at `address` with its handles, which leave the caller's table. Returns their numbers
+/// too, `return_handles` puts them back if the message isn't sent.
+fn take_message(address: u64) -> Result<(Message, Vec<usize>), Error> {
+    let message: UserMessage = user::read(address)?;
+
+    if message.data_length > MESSAGE_DATA as u64 || message.handle_count > MESSAGE_HANDLES as u64 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let data = Vec::from(user::slice(message.data, message.data_length)?);
+    let mut numbers = Vec::new();
+
+    for index in 0..message.handle_count {
+        let number = user::read::<u32>(message.handles + index * 4)? as usize;
+
+        // The same endpoint can't move twice
+        if numbers.contains(&number) {
+            return Err(Error::InvalidArgument);
+        }
+
+        numbers.push(number);
+    }
+
+    let buffer = match message.buffer_length {
+        0 => None,
+        length => Some(copy_buffer(message.buffer, length)?)
+    };
+
+    let handles = process::with_handles(|handles| {
+        if numbers.iter().any(|&number| handles.get(number).is_none()) {
+            return None;
+        }
+
+        Some(numbers.iter().filter_map(|&number| handles.remove(number)).collect())
+    }).ok_or(Error::InvalidSyscall)?.ok_or(Error::BadDescriptor)?;
+
+    Ok((Message { data, handles, buffer, reply: None }, numbers))
+}
+
+/// Pages of `length` bytes at `address` copied to memory the receiver maps
+fn copy_buffer(address: u64, length: u64) -> Result<Arc<shared::SharedMemory>, Error> {
+    if address % PAGE_SIZE != 0 || length % PAGE_SIZE != 0 || length > MESSAGE_BUFFER {
+        return Err(Error::InvalidArgument);
+    }
+
+    let bytes = user::slice(address, length)?;
+    let memory = shared::SharedMemory::new(length).map_err(map_error)?;
+    memory.write(0, bytes).map_err(map_error)?;
+    Ok(memory)
+}
+
+/// Puts handles of a message that wasn't sent back where they were
+fn return_handles(numbers: &[usize], message: Message) {
+    process::with_handles(|handles| {
+        for (&number, endpoint) in numbers.iter().zip(message.handles) {
+            handles.insert_at(number, endpoint);
+        }
+    });
+}
+
+/// Checks that a message received to `request`, the `UserMessage` at `address`, can be written
+/// back
+fn check_request(address: u64, request: &UserMessage) -> Result<(), Error> {
+    user::check(address, size_of::<UserMessage>() as u64, true)?;
+    user::check(request.data, request.data_length, true)?;
+    user::check(request.handles, request.handle_count.saturating_mul(4), true)
+}
+
+/// Whether `message` fits the room of `request`, with `free` handles left in the table
+fn fits(message: &Message, request: &UserMessage, free: usize) -> bool {
+    let handles = message.handles.len() + usize::from(message.reply.is_some());
+    message.data.len() as u64 <= request.data_length && message.handles.len() as u64 <= request.handle_count && handles <= free
+}
+
+/// Gives the receiving process `message`, described by the `UserMessage` at `address`
+fn give_message(message: Message, address: u64, request: UserMessage) -> Result<(), Error> {
+    let mut received = UserMessage { data_length: message.data.len() as u64, handle_count: message.handles.len() as u64, reply: NO_HANDLE, ..request };
+    user::slice_mut(request.data, received.data_length)?.copy_from_slice(&message.data);
+
+    if let Some(buffer) = &message.buffer {
+        let address_space = process::address_space().ok_or(Error::InvalidSyscall)?;
+        let start = address_space.find_free(VirtAddr::new(MMAP_START), buffer.size()).ok_or(Error::OutOfMemory)?;
+        address_space.map_shared(start, buffer, page_flags(MMAP_WRITE)).map_err(map_error)?;
+        received.buffer = start.as_u64();
+        received.buffer_length = buffer.size();
+    } else {
+        received.buffer = 0;
+        received.buffer_length = 0;
+    }
+
+    // `fits` made sure there's room for all of them
+    let (numbers, reply) = process::with_handles(|handles| {
+        let numbers: Vec<usize> = message.handles.into_iter().filter_map(|endpoint| handles.insert(endpoint)).collect();
+        (numbers, message.reply.and_then(|reply| handles.insert(reply)))
+    }).ok_or(Error::InvalidSyscall)?;
+
+    for (index, &number) in numbers.iter().enumerate() {
+        user::write(request.handles + index as u64 * 4, number as u32)?;
+    }
+
+    received.reply = reply.map_or(NO_HANDLE, |reply| reply as u64);
+    user::write(address, received)
+}
+
+/// Receives a message on `endpoint` to the `UserMessage` at `address`, waiting if `block`
+fn receive_to(endpoint: &Endpoint, address: u64, block: bool) -> Result<(), Error> {
+    let request: UserMessage = user::read(address)?;
+    check_request(address, &request)?;
+    let free = process::with_handles(|handles| handles.free()).ok_or(Error::InvalidSyscall)?;
+    let message = endpoint.receive_if(block, &|message| fits(message, &request, free))?;
+    give_message(message, address, request)
+}
+
+/// Sends the `UserMessage` at `address` on channel `handle`, waiting while the other end has a
+/// full queue unless `flags` has `IPC_NONBLOCK`. Handles in the message move to the receiver.
+fn send(registers: &mut Registers) -> Result<u64, Error> {
+    let [handle, address, flags, ..] = registers.arguments();
+
+    if flags & !IPC_NONBLOCK != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let endpoint = endpoint(handle)?;
+    let (message, numbers) = take_message(address)?;
+
+    endpoint.send_or_return(message, flags & IPC_NONBLOCK == 0).map_err(|(error, message)| {
+        return_handles(&numbers, message);
+        error
+    })?;
+
+    Ok(0)
+}
+
+/// Receives the next message on channel `handle` to the `UserMessage` at `address`, waiting for
+/// one unless `flags` has `IPC_NONBLOCK`. A message that doesn't fit stays queued and the call
+/// fails with `InvalidArgument`.
+fn receive(registers: &mut Registers) -> Result<u64, Error> {
+    let [handle, address, flags, ..] = registers.arguments();
+
+    if flags & !IPC_NONBLOCK != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let endpoint = endpoint(handle)?;
+    receive_to(&endpoint, address, flags & IPC_NONBLOCK == 0)?;
+    Ok(0)
+}
+
+/// Sends the `UserMessage` at `message` on channel `handle` with a reply handle and waits for the
+/// answer, which is received to the `UserMessage` at `answer`. An answer that doesn't fit is lost.
+fn call(registers: &mut Registers) -> Result<u64, Error> {
+    let [handle, message, answer, ..] = registers.arguments();
+    let endpoint = endpoint(handle)?;
+    check_request(answer, &user::read(answer)?)?;
+    let (message, numbers) = take_message(message)?;
+    let (reply, answers) = ipc::channel();
+
+    endpoint.send_or_return(Message { reply: Some(Arc::new(reply)), ..message }, true).map_err(|(error, message)| {
+        return_handles(&numbers, message);
+        error
+    })?;
+
+    receive_to(&answers, answer, true)?;
+    Ok(0)
+}
+
+/// Waits until one of `count` channel handles at `address`, an array of 32 bit integers, has a
+/// message or a closed peer. Returns the index of the first such handle.
+fn wait_handles(registers: &mut Registers) -> Result<u64, Error> {
+    let [address, count, ..] = registers.arguments();
+
+    if count == 0 || count > MAX_WAIT as u64 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let endpoints = (0..count).map(|index| endpoint(u64::from(user::read::<u32>(address + index * 4)?))).collect::<Result<Vec<_>, _>>()?;
+    let endpoints: Vec<&Endpoint> = endpoints.iter().map(|endpoint| &**endpoint).collect();
+    Ok(ipc::wait_any(&endpoints)? as u64)
+}
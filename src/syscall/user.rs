@@ -0,0 +1,124 @@
+//! Access to memory given by user code. Every pointer is checked against the page table before
+//! the kernel touches it, so a bad pointer fails the call instead of faulting in ring 0. Only
+//! writes to copy-on-write pages still fault, the handler copies them.
+
+use alloc::vec::Vec;
+use core::{mem::size_of, slice, str};
+
+use x86_64::VirtAddr;
+
+use super::Error;
+use crate::{memory, process, usermode};
+
+/// Checks that `[address, address + length)` is below `usermode::USER_END` and mapped for
+/// ring 3, writable too if `writable`. Pages of mapped files are loaded first.
+pub fn check(address: u64, length: u64, writable: bool) -> Result<(), Error> {
+    let end = address.checked_add(length).ok_or(Error::BadAddress)?;
+
+    if end > usermode::USER_END {
+        return Err(Error::BadAddress);
+    }
+
+    if !memory::is_user_accessible(VirtAddr::new(address), length, writable) {
+        if let Some(address_space) = process::address_space() {
+            address_space.populate(VirtAddr::new(address), length);
+        }
+
+        if !memory::is_user_accessible(VirtAddr::new(address), length, writable) {
+            return Err(Error::BadAddress);
+        }
+    }
+
+    Ok(())
+}
+
+const PAGE_SIZE: u64 = 4096;
+/// Longest string `c_string` reads, without the terminating NUL
+pub const MAX_STRING: u64 = 4096;
+/// Most pointers `c_string_array` reads
+pub const MAX_ARRAY: usize = 256;
+
+/// User bytes at `address`. User memory is only unmapped when its process execs or exits, so they
+/// stay valid during the call.
+pub fn slice(address: u64, length: u64) -> Result<&'static [u8], Error> {
+    check(address, length, false)?;
+
+    if length == 0 {
+        return Ok(&[]);
+    }
+
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length as usize) })
+}
+
+/// Writable user bytes at `address`. Other user threads may change them at any time.
+pub fn slice_mut(address: u64, length: u64) -> Result<&'static mut [u8], Error> {
+    check(address, length, true)?;
+
+    if length == 0 {
+        return Ok(&mut []);
+    }
+
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, length as usize) })
+}
+
+/// Reads a `T` from user memory, it doesn't have to be aligned
+pub fn read<T: Copy>(address: u64) -> Result<T, Error> {
+    check(address, size_of::<T>() as u64, false)?;
+    Ok(unsafe { (address as *const T).read_unaligned() })
+}
+
+/// Writes a `T` to user memory, it doesn't have to be aligned
+pub fn write<T: Copy>(address: u64, value: T) -> Result<(), Error> {
+    check(address, size_of::<T>() as u64, true)?;
+    unsafe { (address as *mut T).write_unaligned(value) };
+    Ok(())
+}
+
+/// UTF-8 string of `length` bytes at `address`
+pub fn string(address: u64, length: u64) -> Result<&'static str, Error> {
+    str::from_utf8(slice(address, length)?).map_err(|_| Error::InvalidArgument)
+}
+
+/// NUL terminated UTF-8 string at `address`, checked page by page until the NUL
+pub fn c_string(address: u64) -> Result<&'static str, Error> {
+    let mut length = 0;
+
+    loop {
+        let current = address.checked_add(length).ok_or(Error::BadAddress)?;
+        let chunk = PAGE_SIZE - current % PAGE_SIZE;
+
+        if let Some(end) = slice(current, chunk)?.iter().position(|&byte| byte == 0) {
+            length += end as u64;
+            break;
+        }
+
+        length += chunk;
+
+        if length > MAX_STRING {
+            return Err(Error::InvalidArgument);
+        }
+    }
+
+    if length > MAX_STRING {
+        return Err(Error::InvalidArgument);
+    }
+
+    string(address, length)
+}
+
+/// Strings of a null terminated array of pointers to NUL terminated strings, like `argv`
+pub fn c_string_array(address: u64) -> Result<Vec<&'static str>, Error> {
+    let mut strings = Vec::new();
+
+    for index in 0..=MAX_ARRAY as u64 {
+        let pointer = read::<u64>(address.checked_add(index * 8).ok_or(Error::BadAddress)?)?;
+
+        if pointer == 0 {
+            return Ok(strings);
+        }
+
+        strings.push(c_string(pointer)?);
+    }
+
+    Err(Error::InvalidArgument)
+}
@@ -0,0 +1,126 @@
+use core::{future::Future, mem, pin::Pin, task::{Context, Poll}};
+
+use alloc::{boxed::Box, vec::Vec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B)
+}
+
+/// Future together with its output once it's ready
+enum MaybeDone<F: Future> {
+    Pending(F),
+    Done(F::Output),
+    Taken
+}
+
+impl<F: Future> MaybeDone<F> {
+    /// Polls the future unless it's done already, returns whether output is available
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> bool {
+        // Future is never moved out, only dropped in place when replaced by its output
+        let this = unsafe { self.get_unchecked_mut() };
+
+        if let MaybeDone::Pending(future) = this {
+            match unsafe { Pin::new_unchecked(future) }.poll(context) {
+                Poll::Ready(output) => *this = MaybeDone::Done(output),
+                Poll::Pending => return false
+            }
+        }
+
+        true
+    }
+
+    fn take(self: Pin<&mut Self>) -> F::Output {
+        match mem::replace(unsafe { self.get_unchecked_mut() }, MaybeDone::Taken) {
+            MaybeDone::Done(output) => output,
+            _ => panic!("Output is not ready")
+        }
+    }
+}
+
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let (mut a, mut b) = unsafe { (Pin::new_unchecked(&mut this.a), Pin::new_unchecked(&mut this.b)) };
+        let a_ready = a.as_mut().poll(context);
+        let b_ready = b.as_mut().poll(context);
+
+        if a_ready && b_ready {
+            Poll::Ready((a.take(), b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Runs both futures concurrently and waits for both outputs
+pub fn join<A: Future, B: Future>(a: A, b: B) -> Join<A, B> {
+    Join { a: MaybeDone::Pending(a), b: MaybeDone::Pending(b) }
+}
+
+pub struct JoinAll<F: Future> {
+    futures: Pin<Box<[MaybeDone<F>]>>
+}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        // Boxed slice never moves, so its elements stay pinned
+        let futures = unsafe { self.futures.as_mut().get_unchecked_mut() };
+        let mut ready = true;
+
+        for future in futures.iter_mut() {
+            ready &= unsafe { Pin::new_unchecked(future) }.poll(context);
+        }
+
+        if ready {
+            Poll::Ready(futures.iter_mut().map(|future| unsafe { Pin::new_unchecked(future) }.take()).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Runs all futures concurrently, outputs are in the same order as futures
+pub fn join_all<F: Future>(futures: impl IntoIterator<Item = F>) -> JoinAll<F> {
+    let futures: Box<[MaybeDone<F>]> = futures.into_iter().map(MaybeDone::Pending).collect();
+    JoinAll { futures: Box::into_pin(futures) }
+}
+
+pub struct Select<A, B> {
+    a: A,
+    b: B
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.a) }.poll(context) {
+            return Poll::Ready(Either::Left(output));
+        }
+
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.b) }.poll(context) {
+            return Poll::Ready(Either::Right(output));
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Waits for whichever future finishes first, the other one is dropped with the `Select`.
+/// Pass `&mut future` to keep using it afterwards.
+pub fn select<A: Future, B: Future>(a: A, b: B) -> Select<A, B> {
+    Select { a, b }
+}
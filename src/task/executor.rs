@@ -1,104 +1,251 @@
-use core::{task::{Waker, Context, Poll}, mem::size_of};
+use core::{future::Future, pin::pin, task::{Context, Poll, Waker}};
 
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use crossbeam_queue::ArrayQueue;
+use alloc::{collections::{BTreeMap, VecDeque}, sync::{Arc, Weak}, task::Wake, vec::Vec};
+use crossbeam_queue::SegQueue;
+use lazy_static::lazy_static;
+use spin::Mutex;
 use x86_64::instructions::interrupts;
 
-use super::{Task, TaskId};
+use crate::thread::{self, Priority, ThreadId};
 
-pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>
+use super::{join::JoinState, JoinHandle, Task};
+
+/// Tasks a worker takes from the shared queue at once, so others can steal some of them
+const BATCH_SIZE: usize = 16;
+
+/// Worker thread with its own run queue. Queues are locked with interrupts disabled, because
+/// interrupt handlers wake tasks too.
+struct Worker {
+    thread: Option<ThreadId>,
+    queue: Mutex<VecDeque<Arc<Task>>>
 }
 
-const MAX_PROCESSES: usize = 8192 / size_of::<Executor>();
+lazy_static! {
+    /// Executor each worker thread belongs to, lets tasks find the executor they run on
+    static ref EXECUTORS: Mutex<BTreeMap<ThreadId, Weak<Shared>>> = Mutex::new(BTreeMap::new());
+}
 
-struct TaskWaker {
-    task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>
+/// State shared by an executor, its spawners and its tasks
+struct Shared {
+    /// Tasks spawned or woken outside of workers
+    injector: SegQueue<Arc<Task>>,
+    workers: Mutex<Vec<Arc<Worker>>>,
+    /// Workers parked because there was nothing to run
+    sleeping: Mutex<Vec<ThreadId>>
 }
 
-impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue
-        }))
+impl Shared {
+    /// Worker running on the current thread
+    fn local_worker(&self) -> Option<Arc<Worker>> {
+        let current = thread::current()?;
+        interrupts::without_interrupts(|| self.workers.lock().iter().find(|worker| worker.thread == Some(current)).cloned())
     }
 
-    fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("Task queue is full");
+    fn schedule(&self, task: Arc<Task>) {
+        match self.local_worker() {
+            Some(worker) => interrupts::without_interrupts(|| worker.queue.lock().push_back(task)),
+            None => self.injector.push(task)
+        }
+
+        let sleeping = interrupts::without_interrupts(|| self.sleeping.lock().pop());
+
+        if let Some(thread) = sleeping {
+            thread::unpark(thread);
+        }
     }
-}
 
-impl Wake for TaskWaker {
-    fn wake(self: Arc<Self>) {
-        self.wake_task();
+    fn spawn<F>(self: &Arc<Self>, future: F) -> JoinHandle<F::Output> where F: Future + Send + 'static, F::Output: Send + 'static {
+        let state = JoinState::new();
+        let task_state = state.clone();
+        let shared = self.clone();
+        let task = Task::new(async move { task_state.finish(Ok(future.await)) }, move |task| shared.schedule(task));
+        JoinHandle::new(task, state)
     }
 
-    fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task();
+    /// Next task for `worker`: its own queue first, then the shared queue, then half of
+    /// another worker's queue
+    fn next_task(&self, worker: &Worker) -> Option<Arc<Task>> {
+        interrupts::without_interrupts(|| {
+            if let Some(task) = worker.queue.lock().pop_front() {
+                return Some(task);
+            }
+
+            if let Some(task) = self.injector.pop() {
+                let mut queue = worker.queue.lock();
+
+                while queue.len() < BATCH_SIZE {
+                    match self.injector.pop() {
+                        Some(task) => queue.push_back(task),
+                        None => break
+                    }
+                }
+
+                return Some(task);
+            }
+
+            let workers = self.workers.lock().clone();
+
+            for victim in workers.iter().filter(|victim| !core::ptr::eq(victim.as_ref(), worker)) {
+                let mut stolen = {
+                    let mut queue = victim.queue.lock();
+                    let keep = queue.len() / 2;
+                    queue.split_off(keep)
+                };
+
+                if let Some(task) = stolen.pop_front() {
+                    worker.queue.lock().append(&mut stolen);
+                    return Some(task);
+                }
+            }
+
+            None
+        })
+    }
+
+    fn has_work(&self) -> bool {
+        !self.injector.is_empty() || interrupts::without_interrupts(|| self.workers.lock().iter().any(|worker| !worker.queue.lock().is_empty()))
+    }
+
+    /// Parks `worker` until a task is scheduled. Before threads are initialized it halts instead.
+    fn sleep(&self, worker: &Worker) {
+        let Some(thread) = worker.thread else {
+            interrupts::disable();
+
+            if self.has_work() {
+                interrupts::enable();
+            } else {
+                interrupts::enable_and_hlt();
+            }
+
+            return;
+        };
+
+        interrupts::without_interrupts(|| self.sleeping.lock().push(thread));
+
+        // Tasks scheduled before this worker was marked as sleeping wouldn't unpark it
+        if !self.has_work() {
+            thread::park();
+        }
+
+        interrupts::without_interrupts(|| self.sleeping.lock().retain(|&sleeping| sleeping != thread));
+    }
+
+    fn run_worker(self: &Arc<Self>) -> ! {
+        let worker = Arc::new(Worker { thread: thread::current(), queue: Mutex::new(VecDeque::new()) });
+        interrupts::without_interrupts(|| self.workers.lock().push(worker.clone()));
+
+        if let Some(thread) = worker.thread {
+            interrupts::without_interrupts(|| EXECUTORS.lock().insert(thread, Arc::downgrade(self)));
+        }
+
+        loop {
+            match self.next_task(&worker) {
+                Some(task) => task.run(),
+                None => self.sleep(&worker)
+            }
+        }
     }
 }
 
+/// Runs tasks on any number of worker threads. Each worker has its own queue and steals
+/// from others when it runs out of tasks.
+pub struct Executor {
+    shared: Arc<Shared>
+}
+
 impl Executor {
     pub fn new() -> Executor {
         Executor {
-            tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(MAX_PROCESSES)), // fits in 8 KiB
-            waker_cache: BTreeMap::new()
+            shared: Arc::new(Shared { injector: SegQueue::new(), workers: Mutex::new(Vec::new()), sleeping: Mutex::new(Vec::new()) })
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
+    /// Starts running `future`, it can be awaited through returned handle
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output> where F: Future + Send + 'static, F::Output: Send + 'static {
+        self.shared.spawn(future)
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        Spawner { shared: self.shared.clone() }
+    }
 
-        if self.tasks.insert(task_id, task).is_some() {
-            panic!("Task already queued");
+    /// Starts `count` worker threads named "executor"
+    pub fn start(&self, count: usize, priority: Priority) {
+        for _ in 0..count {
+            let shared = self.shared.clone();
+            thread::Builder::new().name("executor").priority(priority).spawn(move || shared.run_worker());
         }
+    }
 
-        self.task_queue.push(task_id).expect("Task queue is fulled")
+    /// Turns the current thread into a worker
+    pub fn run(&self) -> ! {
+        self.shared.run_worker()
     }
 
-    fn idle_sleep(&self) {
-        interrupts::disable();
+    /// Number of worker threads
+    pub fn workers(&self) -> usize {
+        interrupts::without_interrupts(|| self.shared.workers.lock().len())
+    }
+}
 
-        if self.task_queue.is_empty() {
-            interrupts::enable_and_hlt();
-        } else {
-            interrupts::enable();
-        }
+impl Default for Executor {
+    fn default() -> Self {
+        Executor::new()
     }
+}
 
-    pub fn run(&mut self) -> ! {
-        loop {
-            self.run_ready_tasks();
-            self.idle_sleep();
-        }
+/// Handle for spawning tasks onto an executor, can be moved into tasks and other threads
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>
+}
+
+impl Spawner {
+    /// Spawner of the executor running on the current thread, `None` outside of worker threads
+    pub fn current() -> Option<Spawner> {
+        let current = thread::current()?;
+        let shared = interrupts::without_interrupts(|| EXECUTORS.lock().get(&current)?.upgrade())?;
+        Some(Spawner { shared })
     }
 
-    fn run_ready_tasks(&mut self) {
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache
-        } = self;
-
-        while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue
-            };
-            let waker = waker_cache.entry(task_id).or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
-            }
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output> where F: Future + Send + 'static, F::Output: Send + 'static {
+        self.shared.spawn(future)
+    }
+}
+
+lazy_static! {
+    static ref GLOBAL: Executor = Executor::new();
+}
+
+/// Executor kernel tasks run on
+pub fn global() -> &'static Executor {
+    &GLOBAL
+}
+
+/// Spawns `future` onto the global executor
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output> where F: Future + Send + 'static, F::Output: Send + 'static {
+    GLOBAL.spawn(future)
+}
+
+struct ThreadWaker(ThreadId);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        thread::unpark(self.0);
+    }
+}
+
+/// Runs `future` to completion on the current thread, which is parked while the future is pending
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current().expect("Threads are not initialized"))));
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
         }
+
+        thread::park();
     }
 }
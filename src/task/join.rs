@@ -0,0 +1,91 @@
+use core::{future::Future, pin::Pin, task::{Context, Poll}};
+
+use alloc::sync::Arc;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+use super::{Task, TaskId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// Task was cancelled before it finished
+    Cancelled
+}
+
+enum Slot<T> {
+    Pending,
+    Finished(Result<T, JoinError>),
+    /// Result was taken by awaiting the handle
+    Taken
+}
+
+/// Result slot shared by a spawned task and its `JoinHandle`. The first result stored wins.
+pub(crate) struct JoinState<T> {
+    slot: Mutex<Slot<T>>,
+    waker: AtomicWaker
+}
+
+impl<T> JoinState<T> {
+    pub(crate) fn new() -> Arc<JoinState<T>> {
+        Arc::new(JoinState { slot: Mutex::new(Slot::Pending), waker: AtomicWaker::new() })
+    }
+
+    pub(crate) fn finish(&self, result: Result<T, JoinError>) {
+        let mut slot = self.slot.lock();
+
+        if matches!(*slot, Slot::Pending) {
+            *slot = Slot::Finished(result);
+            drop(slot);
+            self.waker.wake();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        !matches!(*self.slot.lock(), Slot::Pending)
+    }
+}
+
+/// Resolves to output of a spawned task. Dropping the handle detaches the task, it keeps running.
+pub struct JoinHandle<T> {
+    task: Arc<Task>,
+    state: Arc<JoinState<T>>
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(task: Arc<Task>, state: Arc<JoinState<T>>) -> JoinHandle<T> {
+        JoinHandle { task, state }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.task.id()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+
+    /// Cancels the task, its future is dropped instead of being polled again. Awaiting the handle
+    /// then gives `JoinError::Cancelled`, unless the task has already finished.
+    pub fn abort(&self) {
+        self.state.finish(Err(JoinError::Cancelled));
+        self.task.abort();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        self.state.waker.register(context.waker());
+        let mut slot = self.state.slot.lock();
+
+        match core::mem::replace(&mut *slot, Slot::Taken) {
+            Slot::Finished(result) => Poll::Ready(result),
+            Slot::Pending => {
+                *slot = Slot::Pending;
+                Poll::Pending
+            }
+            Slot::Taken => panic!("JoinHandle polled after completion")
+        }
+    }
+}
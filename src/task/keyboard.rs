@@ -47,6 +47,8 @@ impl Stream for ScancodeStream {
     }
 }
 
+/// Queues `scancode` for the stream. Before a stream is created nobody reads them, the console
+/// decodes scancodes itself.
 pub(crate) fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
@@ -54,8 +56,6 @@ pub(crate) fn add_scancode(scancode: u8) {
         } else {
             WAKER.wake();
         }
-    } else {
-        println!("Warning: Scancode queue isn't initialized");
     }
 }
 
@@ -1,12 +1,21 @@
+pub mod combinators;
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+pub mod serial;
+pub mod sync;
 
-use core::{pin::Pin, future::Future, task::{Context, Poll}, sync::atomic::{AtomicU64, Ordering::Relaxed}};
+use core::{pin::Pin, future::Future, task::{Context, Poll, Waker}, sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering::{self, Relaxed}}};
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, sync::Arc, task::Wake};
+use spin::Mutex;
+
+pub use combinators::{join, join_all, select, Either};
+pub use executor::{block_on, spawn, Executor, Spawner};
+pub use join::{JoinError, JoinHandle};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
     fn new() -> Self {
@@ -15,20 +24,134 @@ impl TaskId {
     }
 }
 
-pub struct Task {
+/// Waiting for a wake up
+const IDLE: u8 = 0;
+/// In a run queue
+const SCHEDULED: u8 = 1;
+/// Being polled by a worker
+const RUNNING: u8 = 2;
+/// Woken while being polled, worker queues it again afterwards
+const NOTIFIED: u8 = 3;
+/// Finished or cancelled, its future is dropped
+const DONE: u8 = 4;
+
+/// Spawned future, it is its own waker. Waking puts it to a run queue through `schedule`.
+pub(crate) struct Task {
     id: TaskId,
-    future: Pin<Box<dyn Future<Output = ()>>>
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    state: AtomicU8,
+    cancelled: AtomicBool,
+    schedule: Box<dyn Fn(Arc<Task>) + Send + Sync>
 }
 
 impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
-        Task {
+    /// Creates task that is scheduled right away
+    pub(crate) fn new(future: impl Future<Output = ()> + Send + 'static, schedule: impl Fn(Arc<Task>) + Send + Sync + 'static) -> Arc<Task> {
+        let task = Arc::new(Task {
             id: TaskId::new(),
-            future: Box::pin(future)
+            future: Mutex::new(Some(Box::pin(future))),
+            state: AtomicU8::new(SCHEDULED),
+            cancelled: AtomicBool::new(false),
+            schedule: Box::new(schedule)
+        });
+
+        (task.schedule)(task.clone());
+        task
+    }
+
+    pub(crate) fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Polls the future once, queue entry is consumed by this
+    pub(crate) fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::Release);
+        let mut future = self.future.lock();
+
+        if self.cancelled.load(Ordering::Acquire) {
+            *future = None;
+        }
+
+        let finished = match future.as_mut() {
+            Some(pinned) => {
+                let waker = Waker::from(self.clone());
+                pinned.as_mut().poll(&mut Context::from_waker(&waker)).is_ready()
+            }
+            None => true
+        };
+
+        if finished {
+            *future = None;
+            self.state.store(DONE, Ordering::Release);
+            return;
+        }
+
+        drop(future);
+
+        if self.state.compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            self.state.store(SCHEDULED, Ordering::Release);
+            (self.schedule)(self.clone());
+        }
+    }
+
+    fn wake_task(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return
+            };
+
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => state = actual
+            }
+        }
+
+        if state == IDLE {
+            (self.schedule)(self.clone());
         }
     }
 
-    fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+    /// Drops the future next time a worker picks the task, instead of polling it
+    pub(crate) fn abort(self: &Arc<Self>) {
+        self.cancelled.store(true, Ordering::Release);
+        self.wake_task();
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+/// Future that is ready after being polled `count` times, lets other tasks run meanwhile
+pub struct YieldNow {
+    count: usize
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.count == 0 {
+            return Poll::Ready(());
+        }
+
+        self.count -= 1;
+        context.waker().wake_by_ref();
+        Poll::Pending
     }
 }
+
+/// Gives other ready tasks a chance to run
+pub fn yield_now() -> YieldNow {
+    YieldNow { count: 1 }
+}
@@ -0,0 +1,43 @@
+//! Serial input for tasks, the way `keyboard::ScancodeStream` gives scancodes
+
+use core::{pin::Pin, task::{Context, Poll}};
+
+use futures_util::{stream::Stream, StreamExt};
+
+use crate::serial::{self, ComPort, WAKERS};
+
+/// Bytes received on a port. Threads reading the port at the same time take bytes from it too.
+pub struct SerialStream {
+    port: ComPort
+}
+
+impl SerialStream {
+    pub fn new(port: ComPort) -> SerialStream {
+        SerialStream { port }
+    }
+
+    pub async fn get_next(&mut self) -> u8 {
+        self.next().await.unwrap()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        if let Some(byte) = serial::try_receive(self.port) {
+            return Poll::Ready(Some(byte));
+        }
+
+        let waker = &WAKERS[self.port.index()];
+        waker.register(context.waker());
+
+        match serial::try_receive(self.port) {
+            None => Poll::Pending,
+            Some(byte) => {
+                waker.take();
+                Poll::Ready(Some(byte))
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Synchronization between tasks. Waiting futures register their waker and return `Pending`
+//! instead of spinning, so the worker can run other tasks meanwhile.
+
+pub mod mpsc;
+pub mod oneshot;
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+
+use core::{mem, task::Waker};
+
+use alloc::collections::VecDeque;
+
+pub use mutex::{AsyncMutex, AsyncMutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Acquire, Semaphore, SemaphorePermit};
+pub use crate::sync::{SpinLock, SpinLockGuard};
+
+/// Wakers of futures waiting on a primitive. Each waiting future keeps the key of its entry,
+/// so it can update its waker or leave the queue when it's dropped.
+struct WaitQueue {
+    next_key: u64,
+    waiters: VecDeque<(u64, Waker)>
+}
+
+impl WaitQueue {
+    const fn new() -> WaitQueue {
+        WaitQueue { next_key: 0, waiters: VecDeque::new() }
+    }
+
+    /// Queues `waker`, or replaces the waker of `key` if it's still queued
+    fn register(&mut self, key: &mut Option<u64>, waker: &Waker) {
+        if let Some(entry) = key.and_then(|key| self.waiters.iter_mut().find(|(queued, _)| *queued == key)) {
+            entry.1.clone_from(waker);
+            return;
+        }
+
+        let new_key = self.next_key;
+        self.next_key += 1;
+        self.waiters.push_back((new_key, waker.clone()));
+        *key = Some(new_key);
+    }
+
+    fn is_queued(&self, key: u64) -> bool {
+        self.waiters.iter().any(|(queued, _)| *queued == key)
+    }
+
+    /// Removes entry of `key`, returns false if it was dequeued by a wake up already
+    fn remove(&mut self, key: u64) -> bool {
+        let len = self.waiters.len();
+        self.waiters.retain(|(queued, _)| *queued != key);
+        self.waiters.len() != len
+    }
+
+    /// Dequeues the longest waiting future. Its waker should be called after the lock of the
+    /// primitive is released.
+    fn pop(&mut self) -> Option<Waker> {
+        self.waiters.pop_front().map(|(_, waker)| waker)
+    }
+
+    /// Dequeues every waiting future
+    fn take(&mut self) -> impl Iterator<Item = Waker> {
+        mem::take(&mut self.waiters).into_iter().map(|(_, waker)| waker)
+    }
+}
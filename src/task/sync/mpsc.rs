@@ -0,0 +1,288 @@
+//! Multi-producer single-consumer channels. Bounded senders wait while the channel is full,
+//! unbounded ones never wait and can be used from interrupt handlers.
+
+use core::{future::{poll_fn, Future}, mem, pin::Pin, task::{Context, Poll, Waker}};
+
+use alloc::{collections::VecDeque, sync::Arc};
+use futures_util::stream::Stream;
+
+use super::{SpinLock, WaitQueue};
+
+/// Receiver was dropped, the value is given back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender was dropped and the channel is empty
+    Closed
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    /// `None` for unbounded channels
+    capacity: Option<usize>,
+    senders: usize,
+    receiver_alive: bool,
+    receiver: Option<Waker>,
+    /// Senders waiting for room in a full channel
+    waiting_senders: WaitQueue
+}
+
+impl<T> State<T> {
+    /// Queues `value`, returns waker of the receiver to call after the lock is released
+    fn push(&mut self, value: T) -> Result<Option<Waker>, TrySendError<T>> {
+        if !self.receiver_alive {
+            return Err(TrySendError::Closed(value));
+        }
+
+        if self.capacity.is_some_and(|capacity| self.queue.len() >= capacity) {
+            return Err(TrySendError::Full(value));
+        }
+
+        self.queue.push_back(value);
+        Ok(self.receiver.take())
+    }
+
+    /// Dequeues next value together with waker of a sender waiting for room
+    fn pop(&mut self) -> Option<(T, Option<Waker>)> {
+        let value = self.queue.pop_front()?;
+        Some((value, self.waiting_senders.pop()))
+    }
+}
+
+type Channel<T> = Arc<SpinLock<State<T>>>;
+
+fn new_channel<T>(capacity: Option<usize>) -> Channel<T> {
+    Arc::new(SpinLock::new(State {
+        queue: VecDeque::new(),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        receiver: None,
+        waiting_senders: WaitQueue::new()
+    }))
+}
+
+fn wake(waker: Option<Waker>) {
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+fn clone_sender<T>(channel: &Channel<T>) -> Channel<T> {
+    channel.lock().senders += 1;
+    channel.clone()
+}
+
+fn drop_sender<T>(channel: &Channel<T>) {
+    let mut state = channel.lock();
+    state.senders -= 1;
+
+    // Receiver learns that the channel is closed
+    let waker = if state.senders == 0 { state.receiver.take() } else { None };
+    drop(state);
+    wake(waker);
+}
+
+/// Creates channel that holds at most `capacity` values
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "Channel capacity must be positive");
+    let channel = new_channel(Some(capacity));
+    (Sender { channel: channel.clone() }, Receiver { channel })
+}
+
+pub fn unbounded<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let channel = new_channel(None);
+    (UnboundedSender { channel: channel.clone() }, Receiver { channel })
+}
+
+pub struct Sender<T> {
+    channel: Channel<T>
+}
+
+impl<T> Sender<T> {
+    /// Waits until there's room for `value`
+    pub fn send(&self, value: T) -> Send<'_, T> {
+        Send { sender: self, value: Some(value), key: None }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let waker = self.channel.lock().push(value)?;
+        wake(waker);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.channel.lock().receiver_alive
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender { channel: clone_sender(&self.channel) }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.channel);
+    }
+}
+
+/// Future returned by `Sender::send`
+pub struct Send<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    key: Option<u64>
+}
+
+// Value is never pinned, it's only moved into the channel
+impl<T> Unpin for Send<'_, T> {}
+
+impl<T> Future for Send<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut state = this.sender.channel.lock();
+        let value = this.value.take().expect("Send polled after completion");
+
+        let result = match state.push(value) {
+            Ok(waker) => Ok(waker),
+            Err(TrySendError::Closed(value)) => Err(SendError(value)),
+            Err(TrySendError::Full(value)) => {
+                this.value = Some(value);
+                state.waiting_senders.register(&mut this.key, context.waker());
+                return Poll::Pending;
+            }
+        };
+
+        if let Some(key) = this.key.take() {
+            state.waiting_senders.remove(key);
+        }
+
+        drop(state);
+        Poll::Ready(result.map(wake))
+    }
+}
+
+impl<T> Drop for Send<'_, T> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        let mut state = self.sender.channel.lock();
+
+        // Woken for room it won't use, next waiting sender gets it instead
+        let waker = if state.waiting_senders.remove(key) { None } else { state.waiting_senders.pop() };
+        drop(state);
+        wake(waker);
+    }
+}
+
+pub struct UnboundedSender<T> {
+    channel: Channel<T>
+}
+
+impl<T> UnboundedSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.channel.lock().push(value) {
+            Ok(waker) => {
+                wake(waker);
+                Ok(())
+            }
+            Err(TrySendError::Closed(value) | TrySendError::Full(value)) => Err(SendError(value))
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.channel.lock().receiver_alive
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        UnboundedSender { channel: clone_sender(&self.channel) }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.channel);
+    }
+}
+
+/// Receiving half of both bounded and unbounded channels
+pub struct Receiver<T> {
+    channel: Channel<T>
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value, `None` once every sender is dropped and the channel is empty
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|context| self.poll_recv(context)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.channel.lock();
+
+        match state.pop() {
+            Some((value, waker)) => {
+                drop(state);
+                wake(waker);
+                Ok(value)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty)
+        }
+    }
+
+    pub fn poll_recv(&mut self, context: &mut Context) -> Poll<Option<T>> {
+        let mut state = self.channel.lock();
+
+        if let Some((value, waker)) = state.pop() {
+            drop(state);
+            wake(waker);
+            return Poll::Ready(Some(value));
+        }
+
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+
+        state.receiver = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        self.poll_recv(context)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.lock();
+        state.receiver_alive = false;
+        let queue = mem::take(&mut state.queue);
+        let wakers = state.waiting_senders.take();
+        drop(state);
+        drop(queue);
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
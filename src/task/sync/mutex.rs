@@ -0,0 +1,64 @@
+use core::{cell::UnsafeCell, ops::{Deref, DerefMut}};
+
+use super::{Semaphore, SemaphorePermit};
+
+/// Mutex for tasks, a task waiting for the lock is parked until the holder releases it. Guard
+/// can be held across `.await`.
+pub struct AsyncMutex<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>
+}
+
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+
+pub struct AsyncMutexGuard<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+    _permit: SemaphorePermit<'a>
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for AsyncMutexGuard<'_, T> {}
+
+impl<T> AsyncMutex<T> {
+    pub const fn new(value: T) -> AsyncMutex<T> {
+        AsyncMutex { semaphore: Semaphore::new(1), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> AsyncMutex<T> {
+    pub async fn lock(&self) -> AsyncMutexGuard<'_, T> {
+        AsyncMutexGuard { mutex: self, _permit: self.semaphore.acquire().await }
+    }
+
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        self.semaphore.try_acquire().map(|permit| AsyncMutexGuard { mutex: self, _permit: permit })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        AsyncMutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
@@ -0,0 +1,127 @@
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+
+use super::{SpinLock, WaitQueue};
+
+struct State {
+    /// Set by `notify_one` when nobody was waiting, the next waiting task takes it
+    permit: bool,
+    /// Incremented by `notify_waiters`
+    generation: u64,
+    waiters: WaitQueue
+}
+
+/// Event tasks wait for. It can be notified from interrupt handlers too.
+pub struct Notify {
+    state: SpinLock<State>
+}
+
+impl Notify {
+    pub const fn new() -> Notify {
+        Notify { state: SpinLock::new(State { permit: false, generation: 0, waiters: WaitQueue::new() }) }
+    }
+
+    /// Waits for a notification. Notifications from `notify_waiters` after this call count
+    /// even if the future is polled only afterwards.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self, generation: self.state.lock().generation, key: None }
+    }
+
+    /// Wakes the longest waiting task, or lets the next task that waits pass right away
+    pub fn notify_one(&self) {
+        let waker = self.state.lock().notify_one();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wakes every task waiting at the moment
+    pub fn notify_waiters(&self) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        let wakers = state.waiters.take();
+        drop(state);
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+impl State {
+    fn notify_one(&mut self) -> Option<Waker> {
+        let waker = self.waiters.pop();
+
+        if waker.is_none() {
+            self.permit = true;
+        }
+
+        waker
+    }
+}
+
+/// Future returned by `Notify::notified`
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: u64,
+    key: Option<u64>
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let notify = self.notify;
+        let mut state = notify.state.lock();
+
+        // Dequeued waiters were notified, either by `notify_waiters` or `notify_one`
+        let dequeued = self.key.is_some_and(|key| !state.waiters.is_queued(key));
+
+        if dequeued || state.generation != self.generation {
+            self.key = None;
+            return Poll::Ready(());
+        }
+
+        if state.permit {
+            state.permit = false;
+
+            if let Some(key) = self.key.take() {
+                state.waiters.remove(key);
+            }
+
+            return Poll::Ready(());
+        }
+
+        state.waiters.register(&mut self.key, context.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        let mut state = self.notify.state.lock();
+
+        // Notification from `notify_one` that was never observed goes to the next waiter
+        let waker = if !state.waiters.remove(key) && state.generation == self.generation {
+            state.notify_one()
+        } else {
+            None
+        };
+
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
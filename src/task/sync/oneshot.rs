@@ -0,0 +1,110 @@
+//! Channel for sending a single value, for example a reply to a request
+
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+
+use alloc::sync::Arc;
+
+use super::SpinLock;
+
+/// Sender was dropped without sending a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed
+}
+
+struct State<T> {
+    value: Option<T>,
+    sender_alive: bool,
+    receiver_alive: bool,
+    receiver: Option<Waker>
+}
+
+pub struct Sender<T> {
+    state: Arc<SpinLock<State<T>>>
+}
+
+/// Resolves to the sent value
+pub struct Receiver<T> {
+    state: Arc<SpinLock<State<T>>>
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(SpinLock::new(State { value: None, sender_alive: true, receiver_alive: true, receiver: None }));
+    (Sender { state: state.clone() }, Receiver { state })
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, it's given back if the receiver was dropped
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut state = self.state.lock();
+
+        if !state.receiver_alive {
+            return Err(value);
+        }
+
+        state.value = Some(value);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.state.lock().receiver_alive
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.sender_alive = false;
+        let waker = state.receiver.take();
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock();
+
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.sender_alive => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Closed)
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+
+        if let Some(value) = state.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+
+        if !state.sender_alive {
+            return Poll::Ready(Err(RecvError));
+        }
+
+        state.receiver = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.receiver_alive = false;
+        let value = state.value.take();
+        drop(state);
+        drop(value);
+    }
+}
@@ -0,0 +1,170 @@
+use core::{cell::UnsafeCell, future::Future, ops::{Deref, DerefMut}, pin::Pin, task::{Context, Poll}};
+
+use super::{SpinLock, SpinLockGuard, WaitQueue};
+
+struct State {
+    readers: usize,
+    writer: bool,
+    waiters: WaitQueue
+}
+
+/// Lock for tasks with either many readers or a single writer. Every waiting task is woken
+/// when the lock becomes free, writers don't have priority over readers.
+pub struct RwLock<T: ?Sized> {
+    state: SpinLock<State>,
+    value: UnsafeCell<T>
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> RwLock<T> {
+        RwLock {
+            state: SpinLock::new(State { readers: 0, writer: false, waiters: WaitQueue::new() }),
+            value: UnsafeCell::new(value)
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        Acquire { lock: self, write: false, key: None }.await;
+        RwLockReadGuard { lock: self }
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        Acquire { lock: self, write: true, key: None }.await;
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let acquired = self.state.lock().try_acquire(false);
+        acquired.then(|| RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let acquired = self.state.lock().try_acquire(true);
+        acquired.then(|| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl State {
+    fn try_acquire(&mut self, write: bool) -> bool {
+        if self.writer || (write && self.readers > 0) {
+            return false;
+        }
+
+        if write {
+            self.writer = true;
+        } else {
+            self.readers += 1;
+        }
+
+        true
+    }
+}
+
+/// Wakes every waiting task after releasing `state`, the ones that can't lock it wait again
+fn wake_waiters(mut state: SpinLockGuard<'_, State>) {
+    let wakers = state.waiters.take();
+    drop(state);
+
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+struct Acquire<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    write: bool,
+    key: Option<u64>
+}
+
+impl<T: ?Sized> Future for Acquire<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let lock = self.lock;
+        let mut state = lock.state.lock();
+
+        if !state.try_acquire(self.write) {
+            state.waiters.register(&mut self.key, context.waker());
+            return Poll::Pending;
+        }
+
+        if let Some(key) = self.key.take() {
+            state.waiters.remove(key);
+        }
+
+        Poll::Ready(())
+    }
+}
+
+impl<T: ?Sized> Drop for Acquire<'_, T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.lock.state.lock().waiters.remove(key);
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.readers -= 1;
+
+        if state.readers == 0 {
+            wake_waiters(state);
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.writer = false;
+        wake_waiters(state);
+    }
+}
@@ -0,0 +1,119 @@
+use core::{future::Future, pin::Pin, task::{Context, Poll}};
+
+use super::{SpinLock, WaitQueue};
+
+struct State {
+    permits: usize,
+    waiters: WaitQueue
+}
+
+/// Counts permits, tasks acquiring one wait while there are none left
+pub struct Semaphore {
+    state: SpinLock<State>
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore { state: SpinLock::new(State { permits, waiters: WaitQueue::new() }) }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    /// Waits for a permit, it's given back when `SemaphorePermit` is dropped
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire { semaphore: self, key: None }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+
+        if state.permits == 0 {
+            return None;
+        }
+
+        state.permits -= 1;
+        Some(SemaphorePermit { semaphore: self })
+    }
+
+    pub fn add_permits(&self, count: usize) {
+        for _ in 0..count {
+            self.release();
+        }
+    }
+
+    /// Adds one permit and wakes the longest waiting task for it
+    fn release(&self) {
+        let mut state = self.state.lock();
+        state.permits += 1;
+        let waker = state.waiters.pop();
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by `Semaphore::acquire`
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    key: Option<u64>
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let semaphore = self.semaphore;
+        let mut state = semaphore.state.lock();
+
+        if state.permits == 0 {
+            state.waiters.register(&mut self.key, context.waker());
+            return Poll::Pending;
+        }
+
+        state.permits -= 1;
+
+        if let Some(key) = self.key.take() {
+            state.waiters.remove(key);
+        }
+
+        Poll::Ready(SemaphorePermit { semaphore })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        let mut state = self.semaphore.state.lock();
+
+        // Woken for a permit it won't take, next waiter gets the chance instead
+        let waker = if !state.waiters.remove(key) && state.permits > 0 {
+            state.waiters.pop()
+        } else {
+            None
+        };
+
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Permit of a `Semaphore`, released when dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
@@ -0,0 +1,71 @@
+use core::{arch::global_asm, sync::atomic::AtomicBool};
+
+// Saves callee-saved registers on current stack, stores stack pointer to `old.rsp` and clears
+// `old.on_cpu`, then loads `new_rsp` and restores registers saved there the same way.
+// Everything else is saved by the caller according to System V ABI (including
+// interrupt handlers, which save all registers they use).
+global_asm!(
+    ".global ruin_switch_context",
+    "ruin_switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov byte ptr [rdi + 8], 0",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret"
+);
+
+extern "C" {
+    fn ruin_switch_context(old: *mut Context, new_rsp: u64);
+}
+
+const SAVED_REGISTERS: usize = 6;
+
+/// Saved stack pointer of a thread that is not running
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+    pub rsp: u64,
+    /// Set while some processor runs on this context. Thread may be made ready before its
+    /// processor has switched away from it, then no one else may switch to it until this is cleared.
+    pub on_cpu: AtomicBool
+}
+
+impl Context {
+    /// Prepares stack `[bottom, top)` so switching to it starts executing `entry`
+    pub fn new(top: u64, entry: extern "C" fn() -> !) -> Context {
+        let top = top & !0xF;
+        // `entry` is reached by `ret`, so it sees the stack as if it was called
+        let return_address = top - 16;
+        let rsp = return_address - (SAVED_REGISTERS * 8) as u64;
+
+        unsafe {
+            *((top - 8) as *mut u64) = 0;
+            *(return_address as *mut u64) = entry as usize as u64;
+
+            for i in 0..SAVED_REGISTERS {
+                *((rsp + i as u64 * 8) as *mut u64) = 0;
+            }
+        }
+
+        Context { rsp, on_cpu: AtomicBool::new(false) }
+    }
+}
+
+/// Switches from thread whose context is `old` to `new`. Returns when someone switches back to `old`.
+///
+/// # Safety
+/// Interrupts must be disabled and `new` must be a context of a suspended thread
+pub unsafe fn switch(old: *mut Context, new: *const Context) {
+    ruin_switch_context(old, (*new).rsp);
+}
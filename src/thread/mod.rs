@@ -0,0 +1,575 @@
+pub mod context;
+pub mod scheduler;
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{instructions::interrupts, registers::control::Cr3, structures::paging::PhysFrame, VirtAddr};
+
+use crate::{gdt, memory, smp, timer};
+
+use context::Context;
+use scheduler::Scheduler;
+
+pub use scheduler::Priority;
+
+pub const DEFAULT_STACK_SIZE: usize = 32 * 1024;
+const IDLE_STACK_SIZE: usize = 4096;
+const STACK_CANARY: u64 = 0x5255_494E_5354_4B21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> ThreadId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Sleeping,
+    Blocked,
+    Dead
+}
+
+struct Thread {
+    id: ThreadId,
+    name: String,
+    state: ThreadState,
+    context: Context,
+    /// `None` for threads running on the stack processor booted with
+    stack: Option<Vec<u64>>,
+    entry: Option<Box<dyn FnOnce() + Send>>,
+    wake_at: u64,
+    unpark_token: bool,
+    waiting_for: Option<ThreadId>,
+    /// Level 4 table loaded while the thread runs, `None` for the kernel page table
+    page_table: Option<PhysFrame>,
+    priority: Priority,
+    vruntime: u64,
+    cpu_ticks: u64,
+    switches: u64
+}
+
+impl Thread {
+    fn new(name: String, stack_size: usize, entry: Option<Box<dyn FnOnce() + Send>>) -> Box<Thread> {
+        let mut thread = Box::new(Thread {
+            id: ThreadId::new(),
+            name,
+            state: ThreadState::Ready,
+            context: Context::default(),
+            stack: None,
+            entry,
+            wake_at: 0,
+            unpark_token: false,
+            waiting_for: None,
+            page_table: None,
+            priority: Priority::Normal,
+            vruntime: 0,
+            cpu_ticks: 0,
+            switches: 0
+        });
+
+        if thread.entry.is_some() {
+            let mut stack = vec![0u64; stack_size.div_ceil(8)];
+            stack[0] = STACK_CANARY;
+            let top = stack.as_ptr() as u64 + stack.len() as u64 * 8;
+            thread.context = Context::new(top, thread_start);
+            thread.stack = Some(stack);
+        }
+
+        thread
+    }
+
+    /// End of the thread's own stack, interrupts from ring 3 start there
+    fn stack_top(&self) -> Option<u64> {
+        self.stack.as_ref().map(|stack| (stack.as_ptr() as u64 + stack.len() as u64 * 8) & !0xF)
+    }
+
+    fn check_stack(&self) {
+        if let Some(stack) = &self.stack {
+            if stack[0] != STACK_CANARY {
+                panic!("Stack overflow in thread {} ({:?})", self.name, self.id);
+            }
+        }
+    }
+}
+
+/// Scheduling state of one processor
+#[derive(Clone, Copy)]
+struct Cpu {
+    current: ThreadId,
+    idle: ThreadId,
+    /// Ticks current thread has been running since it was switched to
+    slice_used: u64
+}
+
+/// Ready threads are shared by all processors, each of them takes the next one when its
+/// current thread stops or is preempted
+struct Threads {
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    scheduler: Scheduler,
+    /// Indexed by `smp::cpu_index`
+    cpus: Vec<Cpu>
+}
+
+impl Threads {
+    fn cpu(&mut self) -> &mut Cpu {
+        &mut self.cpus[smp::cpu_index()]
+    }
+
+    fn current(&mut self) -> &mut Thread {
+        let current = self.cpu().current;
+        self.threads.get_mut(&current).expect("Current thread is missing")
+    }
+
+    /// Frees stacks of finished threads, except those some processor is still switching away from
+    fn reap(&mut self) {
+        self.threads.retain(|_, thread| thread.state != ThreadState::Dead || thread.context.on_cpu.load(Ordering::Acquire));
+    }
+
+    /// Requeues current thread if it is still runnable and picks next one, preferring others
+    /// if `yielding`. Returns contexts to switch between, `None` if current thread keeps running.
+    fn prepare_switch(&mut self, yielding: bool) -> Option<(*mut Context, *const Context)> {
+        let cpu = smp::cpu_index();
+        let Cpu { current: current_id, idle, .. } = self.cpus[cpu];
+        let current = self.threads.get_mut(&current_id).expect("Current thread is missing");
+        current.check_stack();
+
+        if current.state == ThreadState::Running {
+            current.state = ThreadState::Ready;
+
+            if current_id != idle {
+                self.scheduler.push(current_id, current.vruntime);
+            }
+        }
+
+        // Thread woken before its processor switched away from it is left for later
+        let threads = &self.threads;
+        let runnable = |id| id == current_id || !threads[&id].context.on_cpu.load(Ordering::Acquire);
+        let next_id = self.scheduler.pop(yielding.then_some(current_id), runnable).unwrap_or(idle);
+        self.cpus[cpu].slice_used = 0;
+        let next = self.threads.get_mut(&next_id).expect("Scheduled thread is missing");
+        next.state = ThreadState::Running;
+
+        if next_id == current_id {
+            return None;
+        }
+
+        next.switches += 1;
+        next.context.on_cpu.store(true, Ordering::Relaxed);
+
+        if let Some(top) = next.stack_top() {
+            gdt::set_kernel_stack(VirtAddr::new(top));
+        }
+
+        load_page_table(next.page_table);
+
+        self.cpus[cpu].current = next_id;
+        let old = &mut self.threads.get_mut(&current_id)?.context as *mut Context;
+        let new = &self.threads.get(&next_id)?.context as *const Context;
+        Some((old, new))
+    }
+
+    /// Makes ready every thread matching `wake`, without allocating since the run queue has room
+    /// for every thread
+    fn wake_where(&mut self, wake: impl Fn(&Thread) -> bool) {
+        let mut woken = 0;
+
+        for thread in self.threads.values_mut() {
+            if matches!(thread.state, ThreadState::Sleeping | ThreadState::Blocked) && wake(thread) {
+                thread.state = ThreadState::Ready;
+                thread.waiting_for = None;
+                thread.vruntime = self.scheduler.placement(thread.vruntime);
+                self.scheduler.push(thread.id, thread.vruntime);
+                woken += 1;
+            }
+        }
+
+        self.kick_idle_cpus(woken);
+    }
+
+    /// Interrupts up to `count` other idle processors, so they pick up ready threads without
+    /// waiting for their next timer tick
+    fn kick_idle_cpus(&self, count: usize) {
+        let this = smp::cpu_index();
+        let idle = self.cpus.iter().enumerate().filter(|&(index, cpu)| index != this && cpu.current == cpu.idle);
+
+        for (index, _) in idle.take(count) {
+            smp::send_reschedule(index);
+        }
+    }
+}
+
+static THREADS: Mutex<Option<Threads>> = Mutex::new(None);
+
+/// Runs `f` on thread table with interrupts disabled, `None` if threads aren't initialized
+fn with_threads<R>(f: impl FnOnce(&mut Threads) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| THREADS.lock().as_mut().map(f))
+}
+
+/// Loads `table` into `Cr3` unless it's there already, `None` is the kernel page table
+fn load_page_table(table: Option<PhysFrame>) {
+    if let Some(table) = table.or_else(memory::kernel_page_table) {
+        let (current, flags) = Cr3::read();
+
+        if current != table {
+            unsafe { Cr3::write(table, flags) };
+        }
+    }
+}
+
+/// Switches to the next thread. Interrupts must be disabled, they stay disabled when this returns.
+fn reschedule(yielding: bool) {
+    let switch = THREADS.lock().as_mut().and_then(|threads| threads.prepare_switch(yielding));
+
+    if let Some((old, new)) = switch {
+        unsafe { context::switch(old, new) };
+    }
+}
+
+/// Turns the code running now into the "main" thread and starts scheduling
+pub fn init() {
+    interrupts::without_interrupts(|| {
+        let mut guard = THREADS.lock();
+        assert!(guard.is_none(), "Threads are already initialized");
+
+        let mut main = Thread::new(String::from("main"), 0, None);
+        main.state = ThreadState::Running;
+        main.context.on_cpu.store(true, Ordering::Relaxed);
+        let mut idle = Thread::new(String::from("idle"), IDLE_STACK_SIZE, Some(Box::new(|| idle_loop())));
+        idle.priority = Priority::Low;
+        let (main_id, idle_id) = (main.id, idle.id);
+
+        let cpu = Cpu { current: main_id, idle: idle_id, slice_used: 0 };
+        let mut threads = Threads { threads: BTreeMap::new(), scheduler: Scheduler::default(), cpus: vec![cpu] };
+        threads.threads.insert(main_id, main);
+        threads.threads.insert(idle_id, idle);
+        threads.scheduler.reserve(threads.threads.len());
+        *guard = Some(threads);
+    });
+}
+
+/// Turns the code running on a newly started processor into its idle thread
+pub(crate) fn init_cpu() {
+    with_threads(|threads| {
+        assert_eq!(smp::cpu_index(), threads.cpus.len(), "Processors must be added in order");
+
+        let mut idle = Thread::new(String::from("idle"), 0, None);
+        idle.state = ThreadState::Running;
+        idle.priority = Priority::Low;
+        idle.context.on_cpu.store(true, Ordering::Relaxed);
+        threads.cpus.push(Cpu { current: idle.id, idle: idle.id, slice_used: 0 });
+        threads.threads.insert(idle.id, idle);
+    }).expect("Threads are not initialized");
+}
+
+pub(crate) fn idle_loop() -> ! {
+    loop {
+        interrupts::disable();
+
+        if with_threads(|threads| threads.scheduler.is_empty()).unwrap_or(true) {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+            yield_now();
+        }
+    }
+}
+
+extern "C" fn thread_start() -> ! {
+    // Every switch happens with interrupts disabled, new thread enables them itself
+    let entry = THREADS.lock().as_mut().and_then(|threads| threads.current().entry.take()).expect("Thread has no entry point");
+    interrupts::enable();
+    entry();
+    exit();
+}
+
+/// Called by timer interrupt handler after end of interrupt is acknowledged.
+/// Charges the tick to the current thread, wakes sleepers and preempts if needed.
+pub(crate) fn on_timer_tick(now: u64) {
+    let preempt = THREADS.lock().as_mut().is_some_and(|threads| {
+        // Processor's timer may tick before it has joined scheduling
+        let Some(cpu) = threads.cpus.get_mut(smp::cpu_index()) else {
+            return false;
+        };
+
+        cpu.slice_used += 1;
+        let Cpu { current, idle, slice_used } = *cpu;
+        let Threads { threads: table, scheduler, .. } = threads;
+        let thread = table.get_mut(&current).expect("Current thread is missing");
+        thread.cpu_ticks += 1;
+
+        if current != idle {
+            thread.vruntime = scheduler.charge(thread.vruntime, 1, thread.priority);
+        }
+
+        let vruntime = thread.vruntime;
+        threads.wake_where(|thread| thread.state == ThreadState::Sleeping && thread.wake_at <= now);
+
+        if current == idle {
+            !threads.scheduler.is_empty()
+        } else {
+            threads.scheduler.should_preempt(vruntime, slice_used)
+        }
+    });
+
+    if preempt {
+        reschedule(false);
+    }
+}
+
+pub struct Builder {
+    name: Option<String>,
+    stack_size: usize,
+    priority: Priority
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder { name: None, stack_size: DEFAULT_STACK_SIZE, priority: Priority::Normal }
+    }
+
+    pub fn name(mut self, name: &str) -> Builder {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    pub fn stack_size(mut self, size: usize) -> Builder {
+        self.stack_size = size;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Builder {
+        self.priority = priority;
+        self
+    }
+
+    /// Starts new thread running `f`. Panics if threads aren't initialized.
+    pub fn spawn<F, T>(self, f: F) -> JoinHandle<T> where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+        let result = Arc::new(Mutex::new(None));
+        let thread_result = result.clone();
+        let entry: Box<dyn FnOnce() + Send> = Box::new(move || {
+            let value = f();
+            *thread_result.lock() = Some(value);
+        });
+
+        let mut thread = Thread::new(self.name.unwrap_or_else(|| String::from("thread")), self.stack_size.max(IDLE_STACK_SIZE), Some(entry));
+        thread.priority = self.priority;
+        let id = thread.id;
+
+        with_threads(|threads| {
+            threads.reap();
+            thread.vruntime = threads.scheduler.placement(0);
+            let vruntime = thread.vruntime;
+            threads.threads.insert(id, thread);
+            threads.scheduler.reserve(threads.threads.len());
+            threads.scheduler.push(id, vruntime);
+            threads.kick_idle_cpus(1);
+        }).expect("Threads are not initialized");
+
+        JoinHandle { id, result }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+pub fn spawn<F, T>(f: F) -> JoinHandle<T> where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+    Builder::new().spawn(f)
+}
+
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    result: Arc<Mutex<Option<T>>>
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        is_finished(self.id)
+    }
+
+    /// Blocks until thread finishes and returns its result
+    pub fn join(self) -> T {
+        self.wait();
+        self.result.lock().take().expect("Thread finished without result")
+    }
+
+    /// Blocks until thread finishes, also if it ended with `exit` and left no result
+    pub fn wait(&self) {
+        while !is_finished(self.id) {
+            interrupts::without_interrupts(|| {
+                let blocked = THREADS.lock().as_mut().is_some_and(|threads| {
+                    let target = threads.threads.get(&self.id).is_some_and(|thread| thread.state != ThreadState::Dead);
+
+                    if target {
+                        let current = threads.current();
+                        current.state = ThreadState::Blocked;
+                        current.waiting_for = Some(self.id);
+                    }
+
+                    target
+                });
+
+                if blocked {
+                    reschedule(false);
+                }
+            });
+        }
+    }
+}
+
+fn is_finished(id: ThreadId) -> bool {
+    with_threads(|threads| threads.threads.get(&id).is_none_or(|thread| thread.state == ThreadState::Dead)).unwrap_or(true)
+}
+
+pub fn current() -> Option<ThreadId> {
+    with_threads(|threads| threads.cpu().current)
+}
+
+/// Top of the current thread's stack, `None` for threads running on a boot stack
+pub(crate) fn kernel_stack_top() -> Option<VirtAddr> {
+    with_threads(|threads| threads.current().stack_top()).flatten().map(VirtAddr::new)
+}
+
+/// Makes the current thread run with level 4 table `table`, `None` switches back to the kernel
+/// page table. The table must keep the kernel part of the kernel page table.
+pub(crate) fn set_page_table(table: Option<PhysFrame>) {
+    interrupts::without_interrupts(|| {
+        with_threads(|threads| threads.current().page_table = table).expect("Threads are not initialized");
+        load_page_table(table);
+    });
+}
+
+/// Gives up rest of the time slice to other ready threads
+pub fn yield_now() {
+    interrupts::without_interrupts(|| reschedule(true));
+}
+
+/// Blocks current thread for at least `ms` milliseconds. Before threads are initialized it halts instead.
+pub fn sleep(ms: u64) {
+    let wake_at = timer::ticks() + timer::ms_to_ticks(ms);
+
+    interrupts::without_interrupts(|| {
+        let scheduled = with_threads(|threads| {
+            let current = threads.current();
+            current.state = ThreadState::Sleeping;
+            current.wake_at = wake_at;
+        });
+
+        if scheduled.is_some() {
+            reschedule(false);
+        }
+    });
+
+    while timer::ticks() < wake_at {
+        if current().is_some() {
+            yield_now();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+/// Blocks current thread until `unpark` is called for it. Returns immediately if it was
+/// unparked since the last call. May also return spuriously.
+pub fn park() {
+    interrupts::without_interrupts(|| {
+        let blocked = THREADS.lock().as_mut().is_some_and(|threads| {
+            let current = threads.current();
+
+            if current.unpark_token {
+                current.unpark_token = false;
+                return false;
+            }
+
+            current.state = ThreadState::Blocked;
+            true
+        });
+
+        if blocked {
+            reschedule(false);
+        }
+    });
+}
+
+/// Wakes parked thread `id`, safe to call from interrupt handlers
+pub fn unpark(id: ThreadId) {
+    with_threads(|threads| {
+        match threads.threads.get_mut(&id) {
+            Some(thread) if thread.state == ThreadState::Blocked && thread.waiting_for.is_none() => threads.wake_where(|thread| thread.id == id),
+            Some(thread) => thread.unpark_token = true,
+            None => {}
+        }
+    });
+}
+
+/// Finishes current thread, waking threads that join it
+pub fn exit() -> ! {
+    interrupts::disable();
+
+    with_threads(|threads| {
+        let id = threads.cpu().current;
+        // Whoever waits for the thread may free its page table once it's dead
+        threads.current().page_table = None;
+        load_page_table(None);
+        threads.current().state = ThreadState::Dead;
+        threads.wake_where(|thread| thread.waiting_for == Some(id));
+    }).expect("Threads are not initialized");
+
+    reschedule(false);
+    unreachable!("Dead thread was scheduled");
+}
+
+/// Changes priority of thread `id`, takes effect from its next tick
+pub fn set_priority(id: ThreadId, priority: Priority) -> bool {
+    with_threads(|threads| threads.threads.get_mut(&id).map(|thread| thread.priority = priority).is_some()).unwrap_or(false)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: ThreadState,
+    pub priority: Priority,
+    /// Time spent running, in milliseconds
+    pub cpu_time: u64,
+    /// How many times thread was switched to
+    pub context_switches: u64,
+    /// Processor running the thread now
+    pub cpu: Option<usize>
+}
+
+/// Snapshot of all live threads, including the idle thread whose CPU time is time the CPU was idle
+pub fn list() -> Vec<ThreadInfo> {
+    with_threads(|threads| {
+        threads.threads.values().filter(|thread| thread.state != ThreadState::Dead).map(|thread| ThreadInfo {
+            id: thread.id,
+            name: thread.name.clone(),
+            state: thread.state,
+            priority: thread.priority,
+            cpu_time: thread.cpu_ticks * 1000 / timer::TICKS_PER_SECOND,
+            context_switches: thread.switches,
+            cpu: threads.cpus.iter().position(|cpu| cpu.current == thread.id)
+        }).collect()
+    }).unwrap_or_default()
+}
+
+pub fn info(id: ThreadId) -> Option<ThreadInfo> {
+    list().into_iter().find(|info| info.id == id)
+}
@@ -0,0 +1,91 @@
+use alloc::vec::Vec;
+
+use super::ThreadId;
+
+/// Timer ticks a thread may run before it is preempted in favor of another ready thread
+pub const TIME_SLICE_TICKS: u64 = 10;
+/// Virtual runtime a thread of normal priority gains per tick
+const NORMAL_TICK: u64 = 1024;
+/// Woken thread gets at most this much virtual runtime credit for the time it slept,
+/// so interactive threads run soon after waking without starving everyone else
+const SLEEPER_CREDIT: u64 = NORMAL_TICK * TIME_SLICE_TICKS / 2;
+/// Running thread is preempted early if a ready thread is behind it by more than this
+const WAKEUP_GRANULARITY: u64 = NORMAL_TICK * 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High
+}
+
+impl Priority {
+    /// Share of CPU time relative to other priorities
+    pub fn weight(self) -> u64 {
+        match self {
+            Priority::Low => 256,
+            Priority::Normal => 1024,
+            Priority::High => 4096
+        }
+    }
+}
+
+/// Picks ready thread with the lowest virtual runtime. Virtual runtime grows slower for
+/// threads with higher priority, so they get proportionally more CPU time.
+#[derive(Default)]
+pub struct Scheduler {
+    /// Sorted by virtual runtime
+    ready: Vec<(u64, ThreadId)>,
+    min_vruntime: u64
+}
+
+impl Scheduler {
+    /// Makes sure queue can hold `threads` entries without allocating
+    pub fn reserve(&mut self, threads: usize) {
+        self.ready.reserve(threads.saturating_sub(self.ready.len()));
+    }
+
+    pub fn push(&mut self, thread: ThreadId, vruntime: u64) {
+        let index = self.ready.partition_point(|&entry| entry < (vruntime, thread));
+        self.ready.insert(index, (vruntime, thread));
+    }
+
+    /// Takes `runnable` thread with the lowest virtual runtime, skipping `skip` if anyone else is ready
+    pub fn pop(&mut self, skip: Option<ThreadId>, runnable: impl Fn(ThreadId) -> bool) -> Option<ThreadId> {
+        let mut candidates = self.ready.iter().enumerate().filter(|(_, (_, thread))| runnable(*thread));
+        let (index, _) = candidates.clone().find(|(_, (_, thread))| Some(*thread) != skip).or_else(|| candidates.next())?;
+        let entry = self.ready.remove(index);
+        self.min_vruntime = self.min_vruntime.max(entry.0);
+        Some(entry.1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ready.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Virtual runtime for a thread that becomes ready after being created, sleeping or blocked
+    pub fn placement(&self, vruntime: u64) -> u64 {
+        vruntime.max(self.min_vruntime.saturating_sub(SLEEPER_CREDIT))
+    }
+
+    /// Virtual runtime after running `ticks` with `priority`
+    pub fn charge(&mut self, vruntime: u64, ticks: u64, priority: Priority) -> u64 {
+        let vruntime = vruntime + ticks * NORMAL_TICK * Priority::Normal.weight() / priority.weight();
+        let leftmost = self.ready.first().map_or(vruntime, |&(ready, _)| ready);
+        self.min_vruntime = self.min_vruntime.max(vruntime.min(leftmost));
+        vruntime
+    }
+
+    /// Whether thread with `vruntime` that ran for `slice_used` ticks should give up the CPU
+    pub fn should_preempt(&self, vruntime: u64, slice_used: u64) -> bool {
+        match self.ready.first() {
+            Some(&(ready, _)) => slice_used >= TIME_SLICE_TICKS || ready + WAKEUP_GRANULARITY < vruntime,
+            None => false
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::port::Port;
+
+pub const TICKS_PER_SECOND: u64 = 1000;
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Programs PIT channel 0 to fire timer interrupt `TICKS_PER_SECOND` times a second
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICKS_PER_SECOND) as u16;
+    let mut command: Port<u8> = Port::new(PIT_COMMAND);
+    let mut channel: Port<u8> = Port::new(PIT_CHANNEL0);
+
+    unsafe {
+        command.write(0x36); // channel 0, low then high byte, square wave
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+    }
+}
+
+/// Called from timer interrupt handler
+pub fn tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICKS_PER_SECOND
+}
+
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TICKS_PER_SECOND).div_ceil(1000)
+}
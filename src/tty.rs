@@ -0,0 +1,382 @@
+//! Terminals: the line discipline between the raw input of a virtual console or serial port and
+//! the threads reading it.
+//!
+//! In canonical mode input is collected into lines that can be edited with erase (`DEL` or
+//! backspace) and kill (Ctrl+U), and reads return a line at a time. Ctrl+D ends the line without
+//! a newline, on an empty line reads return 0. In raw mode bytes are read as they arrive. Echo
+//! and signals can be turned on in either mode: Ctrl+C sends `SIGINT`, Ctrl+\ `SIGQUIT` and
+//! Ctrl+Z `SIGTSTP` to processes with the terminal as standard input.
+//!
+//! Signals are sent by interrupt handlers as input arrives, in the mode of that moment, and the
+//! byte is kept as `Received::Signal` so reading only drops the line. Everything else happens
+//! when input is read, so input is echoed once somebody reads. Output always has `\n` turned into
+//! `\r\n`.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{ops::BitOr, sync::atomic::{AtomicU8, Ordering}};
+
+use crate::{
+    fs::{page_cache::FileId, FsError},
+    process::signal::{self, Signal},
+    sync::SpinLock
+};
+
+/// Raw input kept until it's read, later input is dropped
+pub const INPUT_SIZE: usize = 1024;
+
+/// Control characters
+const INTERRUPT: u8 = 0x03;
+const END_OF_FILE: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const KILL: u8 = 0x15;
+const SUSPEND: u8 = 0x1A;
+const QUIT: u8 = 0x1C;
+const ERASE: u8 = 0x7F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode(pub u8);
+
+impl Mode {
+    /// Bytes are read as they arrive
+    pub const RAW: Mode = Mode(0);
+    /// Input is read in edited lines
+    pub const CANONICAL: Mode = Mode(1 << 0);
+    /// Input is written back to the terminal
+    pub const ECHO: Mode = Mode(1 << 1);
+    /// Ctrl+C, Ctrl+\ and Ctrl+Z send signals instead of being read
+    pub const SIGNALS: Mode = Mode(1 << 2);
+    pub const DEFAULT: Mode = Mode(Self::CANONICAL.0 | Self::ECHO.0 | Self::SIGNALS.0);
+
+    pub fn contains(self, other: Mode) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Mode {
+    type Output = Mode;
+
+    fn bitor(self, rhs: Mode) -> Mode {
+        Mode(self.0 | rhs.0)
+    }
+}
+
+/// Signal that `byte` sends in `mode`
+fn signal_of(mode: Mode, byte: u8) -> Option<Signal> {
+    if !mode.contains(Mode::SIGNALS) {
+        return None;
+    }
+
+    match byte {
+        INTERRUPT => Some(Signal::INT),
+        QUIT => Some(Signal::QUIT),
+        SUSPEND => Some(Signal::TSTP),
+        _ => None
+    }
+}
+
+/// Appends how `byte` is echoed to `echo`, control characters as `^` and a letter
+fn echo_byte(byte: u8, echo: &mut Vec<u8>) {
+    match byte {
+        b'\t' | b'\n' | 0x20..=0x7E => echo.push(byte),
+        0x00..=0x1F => echo.extend_from_slice(&[b'^', byte + 0x40]),
+        ERASE => echo.extend_from_slice(b"^?"),
+        byte => echo.push(byte)
+    }
+}
+
+/// Byte of input, with whether it sent a signal when it arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received {
+    Byte(u8),
+    Signal(u8)
+}
+
+impl Received {
+    pub fn byte(self) -> u8 {
+        match self {
+            Received::Byte(byte) | Received::Signal(byte) => byte
+        }
+    }
+}
+
+/// Fixed size queue of raw input, so interrupt handlers don't allocate
+pub struct InputBuffer {
+    entries: [Received; INPUT_SIZE],
+    start: usize,
+    len: usize
+}
+
+impl InputBuffer {
+    pub const fn new() -> InputBuffer {
+        InputBuffer { entries: [Received::Byte(0); INPUT_SIZE], start: 0, len: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, received: Received) {
+        if self.len < INPUT_SIZE {
+            self.entries[(self.start + self.len) % INPUT_SIZE] = received;
+            self.len += 1;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Received> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let received = self.entries[self.start];
+        self.start = (self.start + 1) % INPUT_SIZE;
+        self.len -= 1;
+        Some(received)
+    }
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        InputBuffer::new()
+    }
+}
+
+/// Turns input into what is read and what is echoed, following a terminal `Mode`
+#[derive(Debug, Default)]
+pub struct LineDiscipline {
+    /// Line being edited in canonical mode
+    line: Vec<u8>,
+    /// Input that can be read. In canonical mode each is one line, an empty one is the end of
+    /// the file.
+    ready: VecDeque<Vec<u8>>,
+    /// Previous byte was `\r`, a `\n` after it doesn't end another line
+    after_return: bool
+}
+
+impl LineDiscipline {
+    pub const fn new() -> LineDiscipline {
+        LineDiscipline { line: Vec::new(), ready: VecDeque::new(), after_return: false }
+    }
+
+    /// Takes `received` input in `mode`, adding what's echoed to `echo`
+    pub fn receive(&mut self, mode: Mode, received: Received, echo: &mut Vec<u8>) {
+        let byte = received.byte();
+        let after_return = core::mem::replace(&mut self.after_return, byte == b'\r');
+        let echoing = mode.contains(Mode::ECHO);
+
+        // The signal was sent as the byte arrived, what's typed so far is dropped
+        if let Received::Signal(_) = received {
+            self.line.clear();
+
+            if echoing {
+                echo_byte(byte, echo);
+                echo.extend_from_slice(if mode.contains(Mode::CANONICAL) { b"\n" } else { b"" });
+            }
+
+            return;
+        }
+
+        if !mode.contains(Mode::CANONICAL) {
+            match self.ready.back_mut() {
+                Some(bytes) if !bytes.is_empty() => bytes.push(byte),
+                _ => self.ready.push_back(Vec::from([byte]))
+            }
+
+            if echoing {
+                echo_byte(byte, echo);
+            }
+
+            return;
+        }
+
+        match byte {
+            b'\n' if after_return => {}
+            b'\r' | b'\n' => {
+                self.line.push(b'\n');
+                self.ready.push_back(core::mem::take(&mut self.line));
+
+                if echoing {
+                    echo.push(b'\n');
+                }
+            }
+            END_OF_FILE => self.ready.push_back(core::mem::take(&mut self.line)),
+            ERASE | BACKSPACE => {
+                if let Some(erased) = self.line.pop() {
+                    if echoing {
+                        erase(erased, echo);
+                    }
+                }
+            }
+            KILL => {
+                for erased in self.line.drain(..).rev() {
+                    if echoing {
+                        erase(erased, echo);
+                    }
+                }
+            }
+            byte => {
+                self.line.push(byte);
+
+                if echoing {
+                    echo_byte(byte, echo);
+                }
+            }
+        }
+    }
+
+    /// Reads what's ready in `mode`, a line at most in canonical mode. `None` if nothing is,
+    /// `Some(0)` at the end of the file.
+    pub fn read(&mut self, mode: Mode, buf: &mut [u8]) -> Option<usize> {
+        let mut count = 0;
+
+        while let Some(bytes) = self.ready.front_mut() {
+            if bytes.is_empty() {
+                // The end of the file is read on its own
+                if count == 0 {
+                    self.ready.pop_front();
+                }
+
+                return Some(count);
+            }
+
+            let taken = bytes.len().min(buf.len() - count);
+            buf[count..count + taken].copy_from_slice(&bytes[..taken]);
+            bytes.drain(..taken);
+            count += taken;
+
+            if bytes.is_empty() {
+                self.ready.pop_front();
+            }
+
+            if count == buf.len() || mode.contains(Mode::CANONICAL) {
+                break;
+            }
+        }
+
+        if count == 0 { None } else { Some(count) }
+    }
+
+    /// Makes the line being edited readable, when leaving canonical mode
+    pub fn flush_line(&mut self) {
+        if !self.line.is_empty() {
+            self.ready.push_back(core::mem::take(&mut self.line));
+        }
+    }
+}
+
+/// Appends rubbing out `erased` to `echo`
+fn erase(erased: u8, echo: &mut Vec<u8>) {
+    let mut shown = Vec::new();
+    echo_byte(erased, &mut shown);
+
+    for _ in 0..shown.len() {
+        echo.extend_from_slice(b"\x08 \x08");
+    }
+}
+
+/// Where a terminal's input comes from and its output goes
+pub trait Line: Sync {
+    /// Raw input that arrived, `None` if there's none
+    fn receive(&self) -> Option<Received>;
+
+    /// Waits until input may have arrived, may return early
+    fn wait(&self);
+
+    fn send(&self, bytes: &[u8]);
+}
+
+pub struct Tty {
+    /// File id of the terminal device, processes with it as standard input get its signals
+    id: FileId,
+    /// `Mode` bits, read by interrupt handlers
+    mode: AtomicU8,
+    discipline: SpinLock<LineDiscipline>
+}
+
+impl Tty {
+    pub const fn new(id: FileId) -> Tty {
+        Tty { id, mode: AtomicU8::new(Mode::DEFAULT.0), discipline: SpinLock::new(LineDiscipline::new()) }
+    }
+
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    pub fn mode(&self) -> Mode {
+        Mode(self.mode.load(Ordering::Acquire))
+    }
+
+    /// Switches to `mode`, returns the previous one
+    pub fn set_mode(&self, mode: Mode) -> Mode {
+        let mut discipline = self.discipline.lock();
+        let previous = Mode(self.mode.swap(mode.0, Ordering::AcqRel));
+
+        if !mode.contains(Mode::CANONICAL) {
+            discipline.flush_line();
+        }
+
+        previous
+    }
+
+    /// Sends the signal of `byte` if it's one in the current mode, called by interrupt handlers
+    /// as input arrives. The result is kept until the input is read.
+    pub fn arrive(&self, byte: u8) -> Received {
+        match signal_of(self.mode(), byte) {
+            Some(signal) => {
+                signal::kill_terminal_readers(self.id, signal);
+                Received::Signal(byte)
+            }
+            None => Received::Byte(byte)
+        }
+    }
+
+    /// Reads input that is already there, `None` if there's none
+    pub fn try_read(&self, line: &dyn Line, buf: &mut [u8]) -> Option<usize> {
+        let mut echo = Vec::new();
+
+        let read = {
+            let mut discipline = self.discipline.lock();
+            let mode = self.mode();
+
+            while let Some(received) = line.receive() {
+                discipline.receive(mode, received, &mut echo);
+            }
+
+            discipline.read(mode, buf)
+        };
+
+        self.write(line, &echo);
+        read
+    }
+
+    /// Reads input, waiting until there's some or a signal for the calling process
+    pub fn read(&self, line: &dyn Line, buf: &mut [u8]) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if let Some(count) = self.try_read(line, buf) {
+                return Ok(count);
+            }
+
+            if signal::interrupted() {
+                return Err(FsError::Interrupted);
+            }
+
+            line.wait();
+        }
+    }
+
+    /// Sends `bytes` with `\n` turned into `\r\n`
+    pub fn write(&self, line: &dyn Line, bytes: &[u8]) {
+        for (index, part) in bytes.split(|&byte| byte == b'\n').enumerate() {
+            if index > 0 {
+                line.send(b"\r\n");
+            }
+
+            if !part.is_empty() {
+                line.send(part);
+            }
+        }
+    }
+}
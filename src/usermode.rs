@@ -0,0 +1,106 @@
+//! Running code in ring 3. A thread enters user mode with `enter` and doesn't come back,
+//! interrupts from ring 3 run on the thread's kernel stack and return to user code. A fault in
+//! user mode raises a signal in the process, or ends the thread if it's not one, instead of
+//! bringing down the kernel.
+//!
+//! User code can change GS base by loading a segment, so the kernel's per-CPU GS base waits in
+//! `KernelGsBase` while ring 3 runs and `swapgs` exchanges them on every switch between rings.
+
+use core::{arch::asm, fmt};
+
+use x86_64::{instructions::interrupts, structures::idt::InterruptStackFrame, VirtAddr};
+
+use crate::{gdt, println, process::{self, signal::Signal, ExitStatus}, thread};
+
+/// Interrupt flag and the always set bit 1
+pub(crate) const USER_RFLAGS: u64 = 0x202;
+
+/// User memory ends here. The last page below the canonical hole stays unmapped, so returning
+/// from a system call never lands on a non-canonical address.
+pub const USER_END: u64 = 0x0000_7FFF_FFFF_F000;
+
+/// Whether the interrupted code ran in ring 3
+pub fn from_user(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+/// Swaps in the kernel's GS base for an interrupt from ring 3, swaps back when dropped. It has
+/// to be created before anything in the handler uses per-CPU data.
+pub(crate) struct KernelGs {
+    from_user: bool
+}
+
+impl KernelGs {
+    #[inline(always)]
+    pub(crate) fn enter(stack_frame: &InterruptStackFrame) -> KernelGs {
+        let from_user = from_user(stack_frame);
+
+        if from_user {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+
+        KernelGs { from_user }
+    }
+}
+
+impl Drop for KernelGs {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if self.from_user {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
+
+/// Ends the current thread after a fault in user mode when it's not a process, which would get a
+/// signal instead. Called from exception handlers.
+pub(crate) fn kill(fault: fmt::Arguments, instruction_pointer: VirtAddr) -> ! {
+    println!("User fault: {} at {:?}, thread {:?} killed", fault, instruction_pointer, thread::current());
+    process::exit(ExitStatus::Signaled(Signal::KILL))
+}
+
+/// Continues the current thread in ring 3 at `entry` with stack pointer `stack_top`. Both have
+/// to be mapped with `memory::map_user`. Only threads with their own stack can do this, it
+/// becomes their kernel stack and whatever is on it now is never dropped.
+pub fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let kernel_stack = thread::kernel_stack_top().expect("Thread has no kernel stack");
+    let selectors = gdt::selectors();
+
+    // Interrupt arriving before `iretq` would run on the stack that's being abandoned
+    interrupts::disable();
+    gdt::set_kernel_stack(kernel_stack);
+
+    unsafe {
+        asm!(
+            "push {data}",
+            "push {stack}",
+            "push {rflags}",
+            "push {code}",
+            "push {entry}",
+            // No kernel values are left in registers for user code
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "swapgs",
+            "iretq",
+            data = in(reg) u64::from(selectors.user_data_selector.0),
+            stack = in(reg) stack_top.as_u64(),
+            rflags = in(reg) USER_RFLAGS,
+            code = in(reg) u64::from(selectors.user_code_selector.0),
+            entry = in(reg) entry.as_u64(),
+            options(noreturn)
+        );
+    }
+}
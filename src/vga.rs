@@ -1,12 +1,14 @@
 use core::fmt;
 use core::fmt::Write;
-use core::ptr::NonNull;
+use core::ops::Range;
+use core::ptr::{addr_of_mut, NonNull};
 
-use spin::Mutex;
 use volatile::VolatileRef;
+use x86_64::instructions::port::Port;
 
 use lazy_static::lazy_static;
-use x86_64::instructions::interrupts;
+
+use crate::sync::SpinLock;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -50,85 +52,321 @@ pub struct VgaChar {
 pub const BUFFER_WIDTH: usize = 80;
 pub const BUFFER_HEIGHT: usize = 25;
 
+/// CRT controller registers of the cursor position
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+const CURSOR_HIGH: u8 = 0x0E;
+const CURSOR_LOW: u8 = 0x0F;
+
 #[repr(transparent)]
 pub struct VgaBuffer {
     pub chars: [[VgaChar; BUFFER_WIDTH]; BUFFER_HEIGHT]
 }
 
+/// Screens of the virtual consoles, `WRITER` writes the first
+pub const SCREENS: usize = 6;
+/// Parameters of an escape sequence that are kept, later ones are dropped
+const MAX_PARAMETERS: usize = 4;
+/// VGA colors of ANSI colors 0 to 7, the bright ones add 8
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+const BLANK: VgaChar = VgaChar { character: b' ', color: ColorCode(0x07) };
+
+/// Where the writer is in an ANSI escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`
+    Started,
+    /// After `ESC [`, with the number of parameters started
+    Csi(usize)
+}
+
+/// Writes text to a screen, interpreting `\n`, `\r`, backspace and ANSI escape sequences for the
+/// cursor, erasing and colors. Screens that aren't shown are kept in memory.
 pub struct VgaWriter {
+    row: usize,
     column: usize,
     color: ColorCode,
+    /// Color `ESC [ 0 m` goes back to
+    default_color: ColorCode,
+    /// Colors and attributes set by escape sequences
+    foreground: u8,
+    background: u8,
+    bold: bool,
+    reverse: bool,
+    escape: Escape,
+    parameters: [u16; MAX_PARAMETERS],
+    /// Buffer is the one of the VGA hardware
+    visible: bool,
     pub buffer: &'static mut VgaBuffer
 }
 
 impl VgaWriter {
+    /// Writer of the screen that is shown, writing at the bottom
     pub fn new(foreground: VgaColor, background: VgaColor) -> VgaWriter {
-        VgaWriter { column: 0, color: ColorCode::new(foreground, background), buffer: unsafe { &mut *(0xB8000 as *mut VgaBuffer) } }
+        VgaWriter::with_buffer(foreground, background, unsafe { &mut *(0xB8000 as *mut VgaBuffer) }, true)
+    }
+
+    fn with_buffer(foreground: VgaColor, background: VgaColor, buffer: &'static mut VgaBuffer, visible: bool) -> VgaWriter {
+        let color = ColorCode::new(foreground, background);
+
+        VgaWriter {
+            row: BUFFER_HEIGHT - 1,
+            column: 0,
+            color,
+            default_color: color,
+            foreground: foreground as u8,
+            background: background as u8,
+            bold: false,
+            reverse: false,
+            escape: Escape::None,
+            parameters: [0; MAX_PARAMETERS],
+            visible,
+            buffer
+        }
+    }
+
+    /// Row and column where the next character goes
+    pub fn position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    pub fn color(&self) -> ColorCode {
+        self.color
     }
 
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            // Backspace only moves the cursor, like on terminals
+            0x08 => self.column = self.column.saturating_sub(1),
             _ => {
                 if self.column >= BUFFER_WIDTH {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column;
-                let color = self.color;
-
-                unsafe {
-                    VolatileRef::new(NonNull::new(&mut self.buffer.chars[row][col]).unwrap()).as_mut_ptr().write(VgaChar {
-                        character: byte,
-                        color
-                    })
-                };
+                let character = VgaChar { character: byte, color: self.color };
+                self.put(self.row, self.column, character);
                 self.column += 1;
             }
         }
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
+        self.write_bytes(s.as_bytes());
+    }
+
+    /// Writes `bytes` as ASCII, other bytes show as a square
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.follow_escape(byte) {
+                continue;
+            }
+
             match byte {
-                0x20..=0x7E | b'\n' => self.write_byte(byte),
+                0x20..=0x7E | b'\n' | b'\r' | 0x08 => self.write_byte(byte),
                 _ => self.write_byte(0xFE)
             }
         }
+
+        self.move_cursor();
     }
 
-    fn clear_row(&mut self, row: usize) {
+    /// Follows ANSI escape sequences, returns whether `byte` is part of one. Sequences that
+    /// aren't supported are dropped.
+    fn follow_escape(&mut self, byte: u8) -> bool {
+        self.escape = match (self.escape, byte) {
+            (Escape::None, 0x1B) => Escape::Started,
+            (Escape::None, _) => return false,
+            (Escape::Started, b'[') => {
+                self.parameters = [0; MAX_PARAMETERS];
+                Escape::Csi(1)
+            }
+            (Escape::Started, _) => Escape::None,
+            (Escape::Csi(count), b'0'..=b'9') => {
+                if let Some(parameter) = self.parameters.get_mut(count - 1) {
+                    *parameter = parameter.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+
+                Escape::Csi(count)
+            }
+            (Escape::Csi(count), b';') => Escape::Csi(count + 1),
+            // Private sequences like showing the cursor look the same with a `?` in front
+            (Escape::Csi(count), b'?') => Escape::Csi(count),
+            (Escape::Csi(count), _) => {
+                self.control(byte, count.min(MAX_PARAMETERS));
+                Escape::None
+            }
+        };
+
+        true
+    }
+
+    /// Runs control sequence `ESC [ parameters command` with `count` parameters
+    fn control(&mut self, command: u8, count: usize) {
+        let [first, second, ..] = self.parameters;
+        // Moves by 0 move by 1
+        let distance = first.max(1) as usize;
+
+        match command {
+            b'A' => self.row = self.row.saturating_sub(distance),
+            b'B' => self.row = (self.row + distance).min(BUFFER_HEIGHT - 1),
+            b'C' => self.column = (self.column + distance).min(BUFFER_WIDTH - 1),
+            b'D' => self.column = self.column.min(BUFFER_WIDTH - 1).saturating_sub(distance),
+            // Positions count from 1
+            b'H' | b'f' => {
+                self.row = (first.max(1) as usize).min(BUFFER_HEIGHT) - 1;
+                self.column = (second.max(1) as usize).min(BUFFER_WIDTH) - 1;
+            }
+            b'J' => {
+                let (row, column) = (self.row, self.column.min(BUFFER_WIDTH));
+
+                match first {
+                    0 => {
+                        self.erase(row, column..BUFFER_WIDTH);
+                        (row + 1..BUFFER_HEIGHT).for_each(|row| self.erase(row, 0..BUFFER_WIDTH));
+                    }
+                    1 => {
+                        (0..row).for_each(|row| self.erase(row, 0..BUFFER_WIDTH));
+                        self.erase(row, 0..(column + 1).min(BUFFER_WIDTH));
+                    }
+                    _ => (0..BUFFER_HEIGHT).for_each(|row| self.erase(row, 0..BUFFER_WIDTH))
+                }
+            }
+            b'K' => {
+                let column = self.column.min(BUFFER_WIDTH);
+
+                match first {
+                    0 => self.erase(self.row, column..BUFFER_WIDTH),
+                    1 => self.erase(self.row, 0..(column + 1).min(BUFFER_WIDTH)),
+                    _ => self.erase(self.row, 0..BUFFER_WIDTH)
+                }
+            }
+            b'm' => {
+                for index in 0..count {
+                    self.select_graphic_rendition(self.parameters[index]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies color or attribute `parameter` of `ESC [ ... m`
+    fn select_graphic_rendition(&mut self, parameter: u16) {
+        let [default_foreground, default_background] = [self.default_color.0 & 0x0F, self.default_color.0 >> 4];
+
+        match parameter {
+            0 => {
+                (self.foreground, self.background) = (default_foreground, default_background);
+                (self.bold, self.reverse) = (false, false);
+            }
+            1 => self.bold = true,
+            7 => self.reverse = true,
+            22 => self.bold = false,
+            27 => self.reverse = false,
+            30..=37 => self.foreground = ANSI_COLORS[parameter as usize - 30],
+            39 => self.foreground = default_foreground,
+            40..=47 => self.background = ANSI_COLORS[parameter as usize - 40],
+            49 => self.background = default_background,
+            90..=97 => self.foreground = ANSI_COLORS[parameter as usize - 90] | 8,
+            100..=107 => self.background = ANSI_COLORS[parameter as usize - 100] | 8,
+            _ => {}
+        }
+
+        // Bold is shown bright
+        let foreground = if self.bold { self.foreground | 8 } else { self.foreground };
+        let (foreground, background) = if self.reverse { (self.background, foreground) } else { (foreground, self.background) };
+        self.color = ColorCode(background << 4 | foreground);
+    }
+
+    /// Moves the blinking cursor to where the next character goes, if this screen is shown
+    fn move_cursor(&self) {
+        if !self.visible {
+            return;
+        }
+
+        let position = self.row * BUFFER_WIDTH + self.column.min(BUFFER_WIDTH - 1);
+
+        unsafe {
+            let mut index: Port<u8> = Port::new(CRTC_INDEX);
+            let mut data: Port<u8> = Port::new(CRTC_DATA);
+            index.write(CURSOR_LOW);
+            data.write(position as u8);
+            index.write(CURSOR_HIGH);
+            data.write((position >> 8) as u8);
+        }
+    }
+
+    fn put(&mut self, row: usize, column: usize, character: VgaChar) {
+        unsafe { VolatileRef::new(NonNull::new(&mut self.buffer.chars[row][column]).unwrap()).as_mut_ptr().write(character) };
+    }
+
+    /// Blanks `columns` of `row` with the current background
+    fn erase(&mut self, row: usize, columns: Range<usize>) {
         let empty = VgaChar {
             character: b' ',
             color: self.color
         };
 
-        for col in 0..BUFFER_WIDTH {
-            unsafe { VolatileRef::new(NonNull::new(&mut self.buffer.chars[row][col]).unwrap()).as_mut_ptr().write(empty); }
+        for column in columns {
+            self.put(row, column, empty);
         }
     }
 
+    fn clear_row(&mut self, row: usize) {
+        self.erase(row, 0..BUFFER_WIDTH);
+    }
+
     fn new_line(&mut self) {
+        self.column = 0;
+
+        if self.row < BUFFER_HEIGHT - 1 {
+            self.row += 1;
+            return;
+        }
+
         for row in 1..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
                 let character = self.buffer.chars[row][col]; // non-volatile read because of volatile write line below
-                unsafe { VolatileRef::new(NonNull::new(&mut self.buffer.chars[row - 1][col]).unwrap()).as_mut_ptr().write(character); }
+                self.put(row - 1, col, character);
             }
         }
 
         self.clear_row(BUFFER_HEIGHT - 1);
-        self.column = 0;
     }
 
     pub fn set_color(&mut self, foreround: VgaColor, background: VgaColor) {
         self.color = ColorCode::new(foreround, background);
+        self.default_color = self.color;
+        (self.foreground, self.background) = (foreround as u8, background as u8);
+        (self.bold, self.reverse) = (false, false);
     }
 
+    /// Blanks the screen and moves to its top left
     pub fn clear(&mut self) {
         for row in 0..BUFFER_HEIGHT {
             self.clear_row(row)
         }
+
+        (self.row, self.column) = (0, 0);
+        self.move_cursor();
+    }
+
+    /// Exchanges the contents of the screens, and which of the two is shown
+    fn swap_screens(&mut self, other: &mut VgaWriter) {
+        for row in 0..BUFFER_HEIGHT {
+            for column in 0..BUFFER_WIDTH {
+                let (mine, theirs) = (self.buffer.chars[row][column], other.buffer.chars[row][column]);
+                self.put(row, column, theirs);
+                other.put(row, column, mine);
+            }
+        }
+
+        core::mem::swap(&mut self.buffer, &mut other.buffer);
+        core::mem::swap(&mut self.visible, &mut other.visible);
+        self.move_cursor();
+        other.move_cursor();
     }
 }
 
@@ -139,15 +377,65 @@ impl Write for VgaWriter {
     }
 }
 
+/// Memory of the screens that are not shown
+static mut HIDDEN: [VgaBuffer; SCREENS - 1] = [const { VgaBuffer { chars: [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT] } }; SCREENS - 1];
+
 lazy_static! {
-    pub static ref WRITER: Mutex<VgaWriter> = Mutex::new(VgaWriter::new(VgaColor::LightGray, VgaColor::Black));
+    pub static ref WRITER: SpinLock<VgaWriter> = SpinLock::new(VgaWriter::new(VgaColor::LightGray, VgaColor::Black));
+
+    /// Writers of the other screens, which start hidden
+    static ref OTHER_WRITERS: [SpinLock<VgaWriter>; SCREENS - 1] = core::array::from_fn(|index| {
+        // Each writer takes one of the buffers, once
+        let buffer = unsafe { &mut *addr_of_mut!(HIDDEN[index]) };
+        let mut writer = VgaWriter::with_buffer(VgaColor::LightGray, VgaColor::Black, buffer, false);
+        writer.row = 0;
+        SpinLock::new(writer)
+    });
+}
+
+/// Screen that is shown, locked while switching
+static VISIBLE: SpinLock<usize> = SpinLock::new(0);
+
+/// Writer of `screen`, from 0 to `SCREENS - 1`
+pub fn writer(screen: usize) -> &'static SpinLock<VgaWriter> {
+    match screen {
+        0 => &WRITER,
+        screen => &OTHER_WRITERS[screen - 1]
+    }
+}
+
+/// Screen that is shown
+pub fn visible() -> usize {
+    *VISIBLE.lock()
+}
+
+/// Shows `screen`, the one shown before keeps its contents in memory. Safe to call from interrupt
+/// handlers.
+pub fn show(screen: usize) {
+    let mut visible = VISIBLE.lock();
+
+    if screen == *visible || screen >= SCREENS {
+        return;
+    }
+
+    // Locked in order so two switches can't wait for each other
+    let (first, second) = (writer(screen.min(*visible)), writer(screen.max(*visible)));
+    let (mut first, mut second) = (first.lock(), second.lock());
+    first.swap_screens(&mut second);
+    *visible = screen;
 }
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    interrupts::without_interrupts(|| { // To prevent deadlock on interrupt
-        WRITER.lock().write_fmt(args).unwrap();
-    });
+    WRITER.lock().write_fmt(args).unwrap();
+}
+
+/// Releases `WRITER` so a panic can print
+///
+/// # Safety
+/// The holder may be in the middle of writing, it's for the panic path only
+pub unsafe fn force_unlock() {
+    WRITER.force_unlock();
 }
 
 #[macro_export]
@@ -0,0 +1,54 @@
+//! ELF images built in memory for loading and running
+
+use alloc::{vec, vec::Vec};
+use ruin::elf;
+
+pub const CODE: u64 = 0x2000_0000_0000;
+pub const DATA: u64 = CODE + 0x1000;
+
+pub struct Segment<'a> {
+    pub kind: u32,
+    pub flags: u32,
+    pub address: u64,
+    pub data: &'a [u8],
+    pub memory_size: u64
+}
+
+/// Executable with program headers after the file header and segment `i` in page `i + 1`
+pub fn build(entry: u64, segments: &[Segment]) -> Vec<u8> {
+    let mut image = vec![0u8; 0x1000 * (segments.len() + 1)];
+    image[..4].copy_from_slice(b"\x7FELF");
+    image[4..7].copy_from_slice(&[2, 1, 1]);
+    image[16..18].copy_from_slice(&2u16.to_le_bytes());
+    image[18..20].copy_from_slice(&0x3Eu16.to_le_bytes());
+    image[20..24].copy_from_slice(&1u32.to_le_bytes());
+    image[24..32].copy_from_slice(&entry.to_le_bytes());
+    image[32..40].copy_from_slice(&64u64.to_le_bytes());
+    image[52..54].copy_from_slice(&64u16.to_le_bytes());
+    image[54..56].copy_from_slice(&(elf::PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    image[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+    for (index, segment) in segments.iter().enumerate() {
+        let offset = 0x1000 * (index as u64 + 1) + segment.address % 0x1000;
+        let header = 64 + index * elf::PROGRAM_HEADER_SIZE;
+        let fields = [offset, segment.address, segment.address, segment.data.len() as u64, segment.memory_size, 0x1000];
+        image[header..header + 4].copy_from_slice(&segment.kind.to_le_bytes());
+        image[header + 4..header + 8].copy_from_slice(&segment.flags.to_le_bytes());
+
+        for (i, field) in fields.iter().enumerate() {
+            image[header + 8 + i * 8..header + 16 + i * 8].copy_from_slice(&field.to_le_bytes());
+        }
+
+        image[offset as usize..offset as usize + segment.data.len()].copy_from_slice(segment.data);
+    }
+
+    image
+}
+
+/// Executable with `code` in an executable page at `CODE` and `data` in a writable page at `DATA`
+pub fn executable(code: &[u8], data: &[u8]) -> Vec<u8> {
+    build(CODE, &[
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_X, address: CODE, data: code, memory_size: 0x1000 },
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_W, address: DATA, data, memory_size: 0x1000 }
+    ])
+}
@@ -0,0 +1,10 @@
+//! Reading what programs left in their address space
+
+use ruin::memory::AddressSpace;
+use x86_64::VirtAddr;
+
+pub fn read_u64(address_space: &AddressSpace, address: u64) -> u64 {
+    let mut bytes = [0u8; 8];
+    address_space.read(VirtAddr::new(address), &mut bytes).unwrap();
+    u64::from_le_bytes(bytes)
+}
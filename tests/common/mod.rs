@@ -0,0 +1,6 @@
+//! Helpers shared by the kernel tests, each test uses some of them
+
+#![allow(dead_code)]
+
+pub mod elf;
+pub mod memory;
@@ -0,0 +1,200 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{collections::BTreeSet, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, smp, thread, timer, task::{self, executor, Either, Executor, JoinError, Spawner}};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    executor::global().start(smp::cpu_count(), thread::Priority::Normal);
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_join_output() {
+    let handle = task::spawn(async { 6 * 7 });
+    assert_eq!(task::block_on(handle), Ok(42));
+}
+
+#[test_case]
+fn test_many_tasks() {
+    // Far more than the old fixed queue could hold
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..1000).map(|i| {
+        let counter = counter.clone();
+
+        task::spawn(async move {
+            counter.fetch_add(1, Ordering::Relaxed);
+            i
+        })
+    }).collect();
+
+    let sum = task::block_on(async {
+        let mut sum = 0;
+
+        for handle in handles {
+            sum += handle.await.unwrap();
+        }
+
+        sum
+    });
+
+    assert_eq!(counter.load(Ordering::Relaxed), 1000);
+    assert_eq!(sum, (0..1000).sum());
+}
+
+#[test_case]
+fn test_spawn_from_task() {
+    let result = task::block_on(task::spawn(async {
+        let children: Vec<_> = (1..=4).map(|i| task::spawn(async move { i * 10 })).collect();
+        let mut sum = 0;
+
+        for child in children {
+            sum += child.await.unwrap();
+        }
+
+        sum
+    }));
+
+    assert_eq!(result, Ok(100));
+}
+
+#[test_case]
+fn test_abort() {
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let handle = task::spawn(async {
+        let _guard = Guard;
+        core::future::pending::<()>().await;
+    });
+
+    thread::sleep(10);
+    assert!(!handle.is_finished());
+    handle.abort();
+    assert!(handle.is_finished());
+    thread::sleep(10);
+    assert!(DROPPED.load(Ordering::SeqCst));
+    assert_eq!(task::block_on(handle), Err(JoinError::Cancelled));
+
+    // Aborting finished task keeps its result
+    let finished = task::spawn(async { 1 });
+    thread::sleep(10);
+    finished.abort();
+    assert_eq!(task::block_on(finished), Ok(1));
+}
+
+#[test_case]
+fn test_work_stealing() {
+    // Tasks are spawned from one worker, so they land in its queue and others have to steal them
+    let cpus = Arc::new(Mutex::new(BTreeSet::new()));
+    let task_cpus = cpus.clone();
+
+    task::block_on(task::spawn(async move {
+        let handles: Vec<_> = (0..smp::cpu_count() * 4).map(|_| {
+            let cpus = task_cpus.clone();
+
+            task::spawn(async move {
+                let end = timer::uptime_ms() + 20;
+
+                while timer::uptime_ms() < end {
+                    core::hint::spin_loop();
+                }
+
+                cpus.lock().insert(smp::cpu_index());
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    })).unwrap();
+
+    assert!(cpus.lock().len() > 1, "all tasks ran on one processor");
+}
+
+#[test_case]
+fn test_separate_executor() {
+    let executor = Executor::new();
+    let spawner = executor.spawner();
+    thread::spawn(move || executor.run());
+
+    let handle = spawner.spawn(async { task::yield_now().await; "done" });
+    assert_eq!(task::block_on(handle), Ok("done"));
+}
+
+#[test_case]
+fn test_spawner_current() {
+    assert!(Spawner::current().is_none());
+
+    let executor = Executor::new();
+    let spawner = executor.spawner();
+    thread::spawn(move || executor.run());
+
+    // Child is spawned onto the same executor without passing a spawner in
+    let handle = spawner.spawn(async {
+        let spawner = Spawner::current().unwrap();
+        spawner.spawn(async { Spawner::current().is_some() }).await
+    });
+
+    assert_eq!(task::block_on(handle), Ok(Ok(true)));
+}
+
+#[test_case]
+fn test_join() {
+    let (a, b) = task::block_on(task::join(task::spawn(async { 1 }), async { task::yield_now().await; 2 }));
+    assert_eq!((a, b), (Ok(1), 2));
+
+    let outputs = task::block_on(task::join_all((0..10).map(|i| task::spawn(async move { i * 2 }))));
+    assert_eq!(outputs.into_iter().map(Result::unwrap).collect::<Vec<_>>(), (0..10).map(|i| i * 2).collect::<Vec<_>>());
+}
+
+#[test_case]
+fn test_select() {
+    let mut slow = task::spawn(core::future::pending::<()>());
+    let fast = task::spawn(async { 5 });
+
+    match task::block_on(task::select(&mut slow, fast)) {
+        Either::Right(output) => assert_eq!(output, Ok(5)),
+        Either::Left(_) => panic!("pending task finished")
+    }
+
+    // Handle passed by reference can still be used
+    slow.abort();
+    assert_eq!(task::block_on(slow), Err(JoinError::Cancelled));
+}
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, ata_pio, block, partition, fs::{ext2::Ext2Fs, FileSystem, FileType, FsError, Inode}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+
+    // target/test-disk.img built by tests/images/build.sh is attached as primary slave
+    assert!(ata_pio::probe() >= 2, "Test disk is not attached");
+    partition::scan("disk1").unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+fn mount() -> Arc<Ext2Fs> {
+    Ext2Fs::new(block::get("disk1p4").unwrap()).unwrap()
+}
+
+fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+    let mut data = vec![0u8; inode.stat().unwrap().size as usize];
+    assert_eq!(inode.read_at(0, &mut data).unwrap(), data.len());
+    data
+}
+
+fn assert_consistent(fs: &Ext2Fs) {
+    let problems = fs.check().unwrap();
+    assert!(problems.is_empty(), "{:?}", problems);
+}
+
+#[test_case]
+fn test_host_files() {
+    let fs = mount();
+    assert_eq!(fs.block_size(), 1024);
+    assert_consistent(&fs);
+
+    let root = fs.root();
+    assert_eq!(read_all(&root.lookup("hello.txt").unwrap()), b"Hello from host\n");
+    assert_eq!(root.lookup("HELLO.TXT").err(), Some(FsError::NotFound));
+    assert_eq!(root.lookup("link").unwrap().readlink().unwrap(), "hello.txt");
+
+    let dir = root.lookup("Some Directory").unwrap();
+    assert_eq!(dir.stat().unwrap().file_type, FileType::Directory);
+    let data = read_all(&dir.lookup("A rather long file name.data").unwrap());
+    assert_eq!(data.len(), 20000);
+    assert!(data.iter().enumerate().all(|(i, &byte)| byte == (i % 94 + 33) as u8));
+
+    let names: Vec<String> = root.readdir().unwrap().into_iter().map(|entry| entry.name).collect();
+    assert!(names.iter().any(|name| name == "lost+found"));
+}
+
+#[test_case]
+fn test_write_workload() {
+    let fs = mount();
+    let root = fs.root();
+    let links = root.stat().unwrap().nlink;
+
+    let dir = root.mkdir("written by ruin").unwrap();
+    assert_eq!(root.mkdir("written by ruin").err(), Some(FsError::AlreadyExists));
+    assert_eq!(root.stat().unwrap().nlink, links + 1);
+
+    for i in 0..100 {
+        let file = dir.create(&format!("file with a fairly long name {}", i), FileType::Regular).unwrap();
+        file.write_at(0, format!("contents {}", i).as_bytes()).unwrap();
+    }
+
+    // Past the single indirect block with 1 KiB blocks, leaving a hole in between
+    let big = dir.create("big.bin", FileType::Regular).unwrap();
+    let payload: Vec<u8> = (0..100_000u32).map(|i| (i * 7) as u8).collect();
+    big.write_at(0, &payload).unwrap();
+    big.write_at(400_000, b"tail").unwrap();
+    assert_eq!(big.stat().unwrap().size, 400_004);
+
+    let mut chunk = vec![0u8; 100_000];
+    big.read_at(0, &mut chunk).unwrap();
+    assert_eq!(chunk, payload);
+    big.read_at(200_000, &mut chunk).unwrap();
+    assert!(chunk.iter().all(|&byte| byte == 0));
+    assert_eq!(big.read_at(400_000, &mut chunk).unwrap(), 4);
+    assert_eq!(&chunk[..4], b"tail");
+
+    big.truncate(5000).unwrap();
+    assert_eq!(read_all(&big), &payload[..5000]);
+
+    let sub = dir.mkdir("sub").unwrap();
+    sub.symlink("short", "../file with a fairly long name 3").unwrap();
+    let target = "long/".repeat(20);
+    sub.symlink("long", &target).unwrap();
+    assert_eq!(sub.lookup("long").unwrap().readlink().unwrap(), target);
+    assert_eq!(read_all(&dir.lookup("file with a fairly long name 33").unwrap()), b"contents 33");
+    assert_consistent(&fs);
+
+    assert_eq!(root.unlink("written by ruin").err(), Some(FsError::NotEmpty));
+
+    for entry in sub.readdir().unwrap() {
+        sub.unlink(&entry.name).unwrap();
+    }
+
+    for entry in dir.readdir().unwrap() {
+        dir.unlink(&entry.name).unwrap();
+    }
+
+    root.unlink("written by ruin").unwrap();
+    assert_eq!(root.lookup("written by ruin").err(), Some(FsError::NotFound));
+    assert_eq!(root.stat().unwrap().nlink, links);
+    fs.sync().unwrap();
+    assert_consistent(&fs);
+}
+
+#[test_case]
+fn test_remount_sees_writes() {
+    let fs = mount();
+    let file = fs.root().create("persist.txt", FileType::Regular).unwrap();
+    file.write_at(0, b"persisted").unwrap();
+    fs.sync().unwrap();
+    drop(file);
+    drop(fs);
+
+    let fs = mount();
+    let file = fs.root().lookup("persist.txt").unwrap();
+    assert_eq!(read_all(&file), b"persisted");
+    fs.root().unlink("persist.txt").unwrap();
+    assert_consistent(&fs);
+}
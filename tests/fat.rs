@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, ata_pio, block, partition, fs::{fat::{FatFs, FatType}, FileSystem, FileType, FsError, Inode}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+
+    // target/test-disk.img built by tests/images/build.sh is attached as primary slave
+    assert!(ata_pio::probe() >= 2, "Test disk is not attached");
+    partition::scan("disk1").unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+fn mount(partition: &str) -> Arc<FatFs> {
+    FatFs::new(block::get(partition).unwrap()).unwrap()
+}
+
+fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+    let mut data = vec![0u8; inode.stat().unwrap().size as usize];
+    assert_eq!(inode.read_at(0, &mut data).unwrap(), data.len());
+    data
+}
+
+fn check_host_files(fs: &FatFs) {
+    let root = fs.root();
+    assert_eq!(read_all(&root.lookup("HELLO.TXT").unwrap()), b"Hello from host\n");
+
+    let dir = root.lookup("some directory").unwrap();
+    assert_eq!(dir.stat().unwrap().file_type, FileType::Directory);
+    let data = read_all(&dir.lookup("A rather long file name.data").unwrap());
+    assert_eq!(data.len(), 20000);
+    assert!(data.iter().enumerate().all(|(i, &byte)| byte == (i % 94 + 33) as u8));
+
+    let names: Vec<String> = root.readdir().unwrap().into_iter().map(|entry| entry.name).collect();
+    assert!(names.iter().any(|name| name == "Some Directory"));
+}
+
+fn write_workload(fs: &FatFs) {
+    let root = fs.root();
+    let free = fs.free_clusters().unwrap();
+
+    let dir = root.mkdir("Written by ruin").unwrap();
+    assert_eq!(root.mkdir("WRITTEN BY RUIN").err(), Some(FsError::AlreadyExists));
+
+    for i in 0..40 {
+        let file = dir.create(&format!("file with a long name {}", i), FileType::Regular).unwrap();
+        file.write_at(0, format!("contents {}", i).as_bytes()).unwrap();
+    }
+
+    let big = dir.create("big.bin", FileType::Regular).unwrap();
+    let payload: Vec<u8> = (0..30000u32).map(|i| (i * 7) as u8).collect();
+    big.write_at(0, &payload).unwrap();
+    big.write_at(40000, b"tail").unwrap();
+    assert_eq!(big.stat().unwrap().size, 40004);
+
+    let data = read_all(&dir.lookup("BIG.BIN").unwrap());
+    assert_eq!(&data[..30000], &payload[..]);
+    assert!(data[30000..40000].iter().all(|&byte| byte == 0));
+    assert_eq!(read_all(&dir.lookup("file with a long name 33").unwrap()), b"contents 33");
+
+    assert_eq!(root.unlink("Written by ruin").err(), Some(FsError::NotEmpty));
+
+    for entry in dir.readdir().unwrap() {
+        dir.unlink(&entry.name).unwrap();
+    }
+
+    root.unlink("Written by ruin").unwrap();
+    assert_eq!(root.lookup("Written by ruin").err(), Some(FsError::NotFound));
+    fs.sync().unwrap();
+    assert_eq!(fs.free_clusters().unwrap(), free);
+}
+
+#[test_case]
+fn test_fat12() {
+    let fs = mount("disk1p1");
+    assert_eq!(fs.fat_type(), FatType::Fat12);
+    check_host_files(&fs);
+    write_workload(&fs);
+}
+
+#[test_case]
+fn test_fat16() {
+    let fs = mount("disk1p2");
+    assert_eq!(fs.fat_type(), FatType::Fat16);
+    check_host_files(&fs);
+    write_workload(&fs);
+}
+
+#[test_case]
+fn test_fat32() {
+    let fs = mount("disk1p3");
+    assert_eq!(fs.fat_type(), FatType::Fat32);
+    check_host_files(&fs);
+    write_workload(&fs);
+}
+
+#[test_case]
+fn test_remount_sees_writes() {
+    let fs = mount("disk1p3");
+    let file = fs.root().create("persist.txt", FileType::Regular).unwrap();
+    file.write_at(0, b"persisted").unwrap();
+    fs.sync().unwrap();
+    drop(file);
+    drop(fs);
+
+    let fs = mount("disk1p3");
+    let file = fs.root().lookup("persist.txt").unwrap();
+    assert_eq!(read_all(&file), b"persisted");
+    fs.root().unlink("persist.txt").unwrap();
+}
+
+#[test_case]
+fn test_unlinked_handle() {
+    let fs = mount("disk1p2");
+    let root = fs.root();
+    let free = fs.free_clusters().unwrap();
+    let old = root.create("stale.txt", FileType::Regular).unwrap();
+    old.write_at(0, b"old").unwrap();
+    let number = old.stat().unwrap().inode;
+    root.unlink("stale.txt").unwrap();
+
+    // The new file may take the slot of the old one, the old handle doesn't reach it
+    let new = root.create("stale.txt", FileType::Regular).unwrap();
+    new.write_at(0, b"new").unwrap();
+    assert_ne!(new.stat().unwrap().inode, number);
+    assert_eq!(old.write_at(0, b"stale").err(), Some(FsError::NotFound));
+    assert_eq!(old.read_at(0, &mut [0u8; 4]).err(), Some(FsError::NotFound));
+    assert_eq!(old.stat().err(), Some(FsError::NotFound));
+    assert_eq!(read_all(&root.lookup("stale.txt").unwrap()), b"new");
+
+    root.unlink("stale.txt").unwrap();
+    fs.sync().unwrap();
+    assert_eq!(fs.free_clusters().unwrap(), free);
+}
@@ -0,0 +1,52 @@
+#!/bin/sh
+# Builds target/test-disk.img used by filesystem tests (attached as the ATA primary slave, "disk1").
+# Requires sfdisk, mkfs.fat, mtools and mke2fs, and the nightly toolchain with rust-src for the
+# example user program that goes on the ext2 partition.
+set -e
+
+cd "$(dirname "$0")/../.."
+IMAGE=target/test-disk.img
+SECTOR=512
+mkdir -p target
+rm -f "$IMAGE"
+truncate -s 64M "$IMAGE"
+
+# p1: FAT12, p2: FAT16, p3: FAT32, p4: ext2
+sfdisk --quiet "$IMAGE" <<PARTITIONS
+label: dos
+start=2048, size=2048, type=1
+start=4096, size=32768, type=6
+start=36864, size=81920, type=c
+start=118784, size=32768, type=83
+PARTITIONS
+
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+printf 'Hello from host\n' > "$WORK/hello.txt"
+awk 'BEGIN { for (i = 0; i < 20000; i++) printf "%c", i % 94 + 33 }' > "$WORK/long.data"
+
+# Example program, built for ruin with the runtime in user/rt
+cargo build --release -p hello --target user/x86_64-ruin.json
+
+format() {
+    start=$1
+    size=$2
+    shift 2
+    mkfs.fat -q --offset "$start" "$@" "$IMAGE" $((size * SECTOR / 1024))
+    part="$IMAGE@@$((start * SECTOR))"
+    mcopy -i "$part" "$WORK/hello.txt" ::hello.txt
+    mmd -i "$part" "::Some Directory"
+    mcopy -i "$part" "$WORK/long.data" "::Some Directory/A rather long file name.data"
+}
+
+format 2048 2048 -F 12
+format 4096 32768 -F 16
+format 36864 81920 -F 32
+
+EXT2="$WORK/ext2"
+mkdir -p "$EXT2/Some Directory"
+cp "$WORK/hello.txt" "$EXT2/hello.txt"
+cp "$WORK/long.data" "$EXT2/Some Directory/A rather long file name.data"
+ln -s hello.txt "$EXT2/link"
+cp target/x86_64-ruin/release/hello "$EXT2/hello"
+mke2fs -q -F -t ext2 -b 1024 -E offset=$((118784 * SECTOR)) -d "$EXT2" "$IMAGE" $((32768 * SECTOR / 1024))k
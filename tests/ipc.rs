@@ -0,0 +1,270 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{ipc::{self, IpcError, Message, QUEUE_SIZE}, memory::{self, shared::SharedMemory, MemoryMapFrameAllocator}, allocator, fs::{tmpfs::TmpFs, vfs}, loader, process::{self, files::FileTable, handles::HandleTable, ExitStatus}, smp, task, thread};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::elf::{executable, DATA};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// `UserMessage` fields at `offset` of `data`
+fn put_message(data: &mut [u8], offset: usize, fields: [u64; 7]) {
+    for (index, field) in fields.iter().enumerate() {
+        data[offset + index * 8..offset + index * 8 + 8].copy_from_slice(&field.to_le_bytes());
+    }
+}
+
+#[test_case]
+fn test_send_receive() {
+    let (a, b) = ipc::channel();
+    assert_eq!(b.try_receive().err(), Some(IpcError::WouldBlock));
+
+    a.send(Message::new(b"one")).unwrap();
+    a.send(Message::new(b"two")).unwrap();
+    assert!(b.readable() && !a.readable());
+    assert_eq!(b.receive().unwrap().data, b"one");
+    assert_eq!(b.try_receive().unwrap().data, b"two");
+
+    // Queue of the other end is full
+    for _ in 0..QUEUE_SIZE {
+        b.try_send(Message::new(b"")).unwrap();
+    }
+
+    assert_eq!(b.try_send(Message::new(b"")), Err(IpcError::WouldBlock));
+    assert_eq!(a.try_send(Message::new(&[0; ipc::MESSAGE_DATA + 1])), Err(IpcError::TooLarge));
+
+    // Queued messages are still received after the sender is gone
+    drop(b);
+    assert_eq!(a.send(Message::new(b"")), Err(IpcError::PeerClosed));
+
+    for _ in 0..QUEUE_SIZE {
+        a.receive().unwrap();
+    }
+
+    assert_eq!(a.receive().err(), Some(IpcError::PeerClosed));
+}
+
+#[test_case]
+fn test_handles_and_buffers() {
+    let before = memory::frames_in_use();
+    let (a, b) = ipc::channel();
+    let (c, d) = ipc::channel();
+    let a = Arc::new(a);
+
+    // An endpoint can't travel over its own channel
+    assert_eq!(a.send(Message { handles: vec![a.clone()], ..Message::default() }), Err(IpcError::InvalidHandle));
+
+    let buffer = SharedMemory::new(8192).unwrap();
+    buffer.write(4094, b"page").unwrap();
+    a.send(Message { handles: vec![Arc::new(c)], buffer: Some(buffer), ..Message::new(b"x") }).unwrap();
+
+    let received = b.receive().unwrap();
+    let mut bytes = [0u8; 4];
+    received.buffer.as_ref().unwrap().read(4094, &mut bytes).unwrap();
+    assert_eq!(&bytes, b"page");
+    assert!(received.buffer.as_ref().unwrap().read(8190, &mut bytes).is_err());
+
+    // Received endpoint works like the sent one
+    received.handles[0].send(Message::new(b"moved")).unwrap();
+    assert_eq!(d.receive().unwrap().data, b"moved");
+
+    drop((a, b, d, received));
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_call_and_wait() {
+    let (client, server) = ipc::channel();
+    let (other, quiet) = ipc::channel();
+
+    let handle = thread::spawn(move || {
+        // Only `server` gets a message
+        assert_eq!(ipc::wait_any(&[&quiet, &server]), Ok(1));
+        let request = server.receive().unwrap();
+        let mut answer = request.data.clone();
+        answer.reverse();
+        request.reply.unwrap().send(Message::new(&answer)).unwrap();
+        drop(quiet);
+    });
+
+    assert_eq!(client.call(Message::new(b"abc")).unwrap().data, b"cba");
+    handle.join();
+    // Closed peers count as readable
+    assert_eq!(ipc::wait_any(&[&other, &client]), Ok(0));
+    assert!(client.readable());
+}
+
+#[test_case]
+fn test_async() {
+    let (client, server) = ipc::channel();
+
+    let handle = thread::spawn(move || {
+        thread::sleep(10);
+        let request = server.receive().unwrap();
+        request.reply.unwrap().send(Message::new(&[request.data[0] + 1])).unwrap();
+    });
+
+    let answer = task::block_on(async {
+        client.call_async(Message::new(&[1])).await
+    });
+
+    assert_eq!(answer.unwrap().data, [2]);
+    handle.join();
+
+    let (a, b) = ipc::channel();
+
+    let received = task::block_on(async {
+        for _ in 0..QUEUE_SIZE {
+            a.send_async(Message::new(b"")).await.unwrap();
+        }
+
+        let (sent, received) = task::join(a.send_async(Message::new(b"last")), async {
+            let mut last = Vec::new();
+
+            for _ in 0..=QUEUE_SIZE {
+                last = b.receive_async().await.unwrap().data;
+            }
+
+            last
+        }).await;
+
+        sent.unwrap();
+        received
+    });
+
+    assert_eq!(received, b"last");
+}
+
+#[test_case]
+fn test_process_server() {
+    // Waits for handle 0, receives a message there and answers on its reply handle with the
+    // first data byte plus the first byte of the buffer. Exits with the result of the send.
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x48, 0x8D, 0xBB, 0x00, 0x01, 0x00, 0x00, // lea rdi, [rbx + 0x100]
+        0xBE, 0x01, 0x00, 0x00, 0x00,       // mov esi, 1
+        0xB8, 0x1C, 0x00, 0x00, 0x00,       // mov eax, 28 (wait_handles)
+        0x0F, 0x05,                         // syscall
+        0x31, 0xFF,                         // xor edi, edi
+        0x48, 0x89, 0xDE,                   // mov rsi, rbx
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x1A, 0x00, 0x00, 0x00,       // mov eax, 26 (receive)
+        0x0F, 0x05,                         // syscall
+        0x4C, 0x8B, 0x63, 0x20,             // mov r12, qword ptr [rbx + 32]
+        0x41, 0x0F, 0xB6, 0x04, 0x24,       // movzx eax, byte ptr [r12]
+        0x00, 0x83, 0x00, 0x02, 0x00, 0x00, // add byte ptr [rbx + 0x200], al
+        0x48, 0x8B, 0x7B, 0x30,             // mov rdi, qword ptr [rbx + 48]
+        0x48, 0x8D, 0xB3, 0x80, 0x00, 0x00, 0x00, // lea rsi, [rbx + 0x80]
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x19, 0x00, 0x00, 0x00,       // mov eax, 25 (send)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let mut data = vec![0u8; 0x210];
+    put_message(&mut data, 0, [DATA + 0x200, 16, DATA + 0x180, 4, 0, 0, 0]);
+    put_message(&mut data, 0x80, [DATA + 0x200, 1, 0, 0, 0, 0, 0]);
+
+    let before = memory::frames_in_use();
+    let (client, server) = ipc::channel();
+    let mut handles = HandleTable::default();
+    handles.insert(Arc::new(server));
+    let program = loader::load(&executable(&code, &data), &["server"], &[]).unwrap();
+    let pid = process::spawn_with(program, "server", FileTable::console(), handles);
+
+    let buffer = SharedMemory::new(4096).unwrap();
+    buffer.write(0, &[5]).unwrap();
+    let answer = client.call(Message { buffer: Some(buffer), ..Message::new(b"a") }).unwrap();
+    assert_eq!(answer.data, b"f");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(0))));
+
+    // Handles of the process are closed with it
+    assert_eq!(client.receive().err(), Some(IpcError::PeerClosed));
+    drop((answer, client));
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_moving_handles() {
+    // Makes two channels and sends the first end of the second one over the first one, then
+    // sends "z" on the endpoint it received. Exits with the byte that arrives at the second end
+    // plus the number of handles in the first message.
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x48, 0x89, 0xDF,                   // mov rdi, rbx
+        0xB8, 0x17, 0x00, 0x00, 0x00,       // mov eax, 23 (channel)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x8D, 0x7B, 0x08,             // lea rdi, [rbx + 8]
+        0xB8, 0x17, 0x00, 0x00, 0x00,       // mov eax, 23 (channel)
+        0x0F, 0x05,                         // syscall
+        0x8B, 0x3B,                         // mov edi, dword ptr [rbx]
+        0x48, 0x8D, 0x73, 0x40,             // lea rsi, [rbx + 0x40]
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x19, 0x00, 0x00, 0x00,       // mov eax, 25 (send)
+        0x0F, 0x05,                         // syscall
+        0x8B, 0x7B, 0x04,                   // mov edi, dword ptr [rbx + 4]
+        0x48, 0x8D, 0xB3, 0x80, 0x00, 0x00, 0x00, // lea rsi, [rbx + 0x80]
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x1A, 0x00, 0x00, 0x00,       // mov eax, 26 (receive)
+        0x0F, 0x05,                         // syscall
+        0x4C, 0x8B, 0xA3, 0x98, 0x00, 0x00, 0x00, // mov r12, qword ptr [rbx + 0x98]
+        0x8B, 0xBB, 0x80, 0x01, 0x00, 0x00, // mov edi, dword ptr [rbx + 0x180]
+        0x48, 0x8D, 0xB3, 0xC0, 0x00, 0x00, 0x00, // lea rsi, [rbx + 0xC0]
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x19, 0x00, 0x00, 0x00,       // mov eax, 25 (send)
+        0x0F, 0x05,                         // syscall
+        0x8B, 0x7B, 0x0C,                   // mov edi, dword ptr [rbx + 12]
+        0x48, 0x8D, 0xB3, 0x00, 0x01, 0x00, 0x00, // lea rsi, [rbx + 0x100]
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x1A, 0x00, 0x00, 0x00,       // mov eax, 26 (receive)
+        0x0F, 0x05,                         // syscall
+        0x0F, 0xB6, 0xBB, 0x00, 0x02, 0x00, 0x00, // movzx edi, byte ptr [rbx + 0x200]
+        0x4C, 0x01, 0xE7,                   // add rdi, r12
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let mut data = vec![0u8; 0x211];
+    put_message(&mut data, 0x40, [DATA + 0x200, 0, DATA + 8, 1, 0, 0, 0]);
+    put_message(&mut data, 0x80, [DATA + 0x200, 16, DATA + 0x180, 4, 0, 0, 0]);
+    put_message(&mut data, 0xC0, [DATA + 0x210, 1, 0, 0, 0, 0, 0]);
+    put_message(&mut data, 0x100, [DATA + 0x200, 16, DATA + 0x180, 4, 0, 0, 0]);
+    data[0x210] = b'z';
+
+    let pid = process::spawn(loader::load(&executable(&code, &data), &["moving"], &[]).unwrap(), "moving");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(i32::from(b'z') + 1))));
+}
@@ -0,0 +1,224 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, AddressSpace, MapError, MemoryMapFrameAllocator, RegionKind}, allocator, elf::{self, Elf, ElfError}, fs::{tmpfs::TmpFs, vfs}, loader::{self, LoadError, Program}, smp, thread};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::{elf::{build, Segment, CODE, DATA}, memory::read_u64};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Stores `argc`, `argv[0]` and stack pointer at `DATA`, increments the counter after them
+/// and exits
+const PROGRAM: [u8; 41] = [
+    0x48, 0x8B, 0x04, 0x24,             // mov rax, [rsp]
+    0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+    0x48, 0x89, 0x03,                   // mov [rbx], rax
+    0x48, 0x8B, 0x44, 0x24, 0x08,       // mov rax, [rsp + 8]
+    0x48, 0x89, 0x43, 0x08,             // mov [rbx + 8], rax
+    0x48, 0x89, 0x63, 0x10,             // mov [rbx + 16], rsp
+    0x48, 0xFF, 0x43, 0x18,             // inc qword ptr [rbx + 24]
+    0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+    0x0F, 0x05                          // syscall
+];
+
+/// Code segment with `PROGRAM` and data segment with a counter at 41 and a page of zeroes
+fn program() -> Vec<u8> {
+    let mut data = [0u8; 32];
+    data[24] = 41;
+
+    build(CODE, &[
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_X, address: CODE, data: &PROGRAM, memory_size: PROGRAM.len() as u64 },
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_W, address: DATA, data: &data, memory_size: 0x2000 }
+    ])
+}
+
+fn read_string(address_space: &AddressSpace, address: u64) -> Vec<u8> {
+    let mut bytes = vec![0u8; 16];
+    address_space.read(VirtAddr::new(address), &mut bytes).unwrap();
+    bytes.truncate(bytes.iter().position(|&byte| byte == 0).unwrap());
+    bytes
+}
+
+/// Runs `program` until it exits and returns its address space, which stays readable
+fn run(program: Program) -> Arc<AddressSpace> {
+    let address_space = program.address_space().clone();
+    program.spawn("loader test").wait();
+    address_space
+}
+
+#[test_case]
+fn test_parse_errors() {
+    let image = program();
+    assert!(Elf::parse(&image).is_ok());
+    assert_eq!(Elf::parse(&image[..40]).err(), Some(ElfError::Truncated));
+
+    let mut bad = image.clone();
+    bad[0] = 0;
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadMagic));
+
+    let mut bad = image.clone();
+    bad[18] = 0x28;
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::WrongMachine));
+
+    let mut bad = image.clone();
+    bad[16] = 3;
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::NotExecutable));
+
+    // Entry in the data segment
+    let mut bad = image.clone();
+    bad[24..32].copy_from_slice(&DATA.to_le_bytes());
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadEntry));
+
+    // Segment stored past the end of the file
+    assert_eq!(Elf::parse(&image[..0x2010]).err(), Some(ElfError::BadSegment));
+
+    let dynamic = build(CODE, &[
+        Segment { kind: elf::PT_INTERP, flags: elf::PF_R, address: DATA, data: b"/lib/ld.so\0", memory_size: 11 },
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_X, address: CODE, data: &PROGRAM, memory_size: PROGRAM.len() as u64 }
+    ]);
+    assert_eq!(Elf::parse(&dynamic).err(), Some(ElfError::Dynamic));
+}
+
+#[test_case]
+fn test_segment_permissions() {
+    let program = loader::load(&program(), &["prog"], &[]).unwrap();
+    let address_space = program.address_space();
+    let code = address_space.flags(VirtAddr::new(CODE)).unwrap();
+    let data = address_space.flags(VirtAddr::new(DATA)).unwrap();
+    assert!(code.contains(PageTableFlags::USER_ACCESSIBLE) && !code.contains(PageTableFlags::WRITABLE) && !code.contains(PageTableFlags::NO_EXECUTE));
+    assert!(data.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+    // Memory past the file part of the data segment is mapped and zeroed
+    assert!(address_space.flags(VirtAddr::new(DATA + 0x1800)).is_some());
+    assert_eq!(read_u64(address_space, DATA + 0x1800), 0);
+    assert_eq!(read_u64(address_space, DATA + 24), 41);
+
+    // A page segments share gets permissions of both
+    let image = build(CODE, &[
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_X, address: CODE, data: &PROGRAM, memory_size: 0x800 },
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_W, address: CODE + 0x800, data: &[], memory_size: 0x1000 }
+    ]);
+    let program = loader::load(&image, &[], &[]).unwrap();
+    let shared = program.address_space().flags(VirtAddr::new(CODE)).unwrap();
+    let data = program.address_space().flags(VirtAddr::new(CODE + 0x1000)).unwrap();
+    assert!(shared.contains(PageTableFlags::WRITABLE) && !shared.contains(PageTableFlags::NO_EXECUTE));
+    assert!(data.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+
+    // Segments larger than free memory fail before anything is mapped
+    let image = build(CODE, &[
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_X, address: CODE, data: &PROGRAM, memory_size: 0x5000_0000_0000 }
+    ]);
+    assert_eq!(loader::load(&image, &[], &[]).err(), Some(LoadError::Map(MapError::OutOfMemory)));
+}
+
+#[test_case]
+fn test_stack_layout() {
+    let program = loader::load(&program(), &["prog", "-v"], &["HOME=/"]).unwrap();
+    let stack = program.stack_pointer().as_u64();
+    let address_space = program.address_space();
+    assert_eq!(stack % 16, 0);
+    assert_eq!(read_u64(address_space, stack), 2);
+    assert_eq!(read_string(address_space, read_u64(address_space, stack + 8)), b"prog");
+    assert_eq!(read_string(address_space, read_u64(address_space, stack + 16)), b"-v");
+    assert_eq!(read_u64(address_space, stack + 24), 0);
+    assert_eq!(read_string(address_space, read_u64(address_space, stack + 32)), b"HOME=/");
+    assert_eq!(read_u64(address_space, stack + 40), 0);
+
+    // Auxiliary vector ends with AT_NULL and has the page size and entry point
+    let mut auxv = Vec::new();
+    let mut address = stack + 48;
+
+    while read_u64(address_space, address) != 0 {
+        auxv.push((read_u64(address_space, address), read_u64(address_space, address + 8)));
+        address += 16;
+    }
+
+    assert!(auxv.contains(&(6, 4096)));
+    assert!(auxv.contains(&(9, CODE)));
+}
+
+#[test_case]
+fn test_run_program() {
+    let address_space = run(loader::load(&program(), &["prog", "one", "two"], &[]).unwrap());
+    assert_eq!(read_u64(&address_space, DATA), 3);
+    assert_eq!(read_string(&address_space, read_u64(&address_space, DATA + 8)), b"prog");
+    assert_eq!(read_u64(&address_space, DATA + 16) % 16, 0);
+    assert_eq!(read_u64(&address_space, DATA + 24), 42);
+}
+
+#[test_case]
+fn test_separate_address_spaces() {
+    let image = program();
+    let first = loader::load(&image, &["first"], &[]).unwrap();
+    let second = loader::load(&image, &["second", "x"], &[]).unwrap();
+    let (first, second) = (run(first), run(second));
+
+    // Same addresses, different memory
+    assert_eq!(read_u64(&first, DATA), 1);
+    assert_eq!(read_u64(&second, DATA), 2);
+    assert_eq!(read_u64(&first, DATA + 24), 42);
+    assert_eq!(read_u64(&second, DATA + 24), 42);
+    // Kernel page table doesn't see either of them
+    assert!(!memory::is_user_accessible(VirtAddr::new(CODE), 1, false));
+}
+
+#[test_case]
+fn test_kernel_range() {
+    let image = build(0x40_0000, &[
+        Segment { kind: elf::PT_LOAD, flags: elf::PF_R | elf::PF_X, address: 0x40_0000, data: &PROGRAM, memory_size: PROGRAM.len() as u64 }
+    ]);
+    assert_eq!(loader::load(&image, &[], &[]).err(), Some(LoadError::Map(MapError::KernelRange)));
+
+    // User memory in the kernel page table stays out of kernel entries too, but may share the
+    // entries made for it
+    let heap = VirtAddr::new(allocator::HEAP_START as u64 + 0x4000_0000);
+    assert_eq!(memory::map_user(heap, 0x1000, PageTableFlags::WRITABLE), Err(MapError::KernelRange));
+    assert_eq!(memory::map_user(VirtAddr::new(0x40_0000), 0x1000, PageTableFlags::WRITABLE), Err(MapError::KernelRange));
+    memory::map_user(VirtAddr::new(0x5800_0000_0000), 0x1000, PageTableFlags::WRITABLE).unwrap();
+    memory::map_user(VirtAddr::new(0x5800_0000_2000), 0x1000, PageTableFlags::WRITABLE).unwrap();
+
+    // Address spaces don't get the entries made for it, they map there on their own
+    let address_space = AddressSpace::new().unwrap();
+    assert_eq!(address_space.translate(VirtAddr::new(0x5800_0000_0000)), None);
+    address_space.map(VirtAddr::new(0x5800_0000_0000), 0x1000, PageTableFlags::WRITABLE, RegionKind::Anonymous).unwrap();
+}
+
+#[test_case]
+fn test_load_file() {
+    vfs::mkdir("/bin").unwrap();
+    vfs::write_all("/bin/prog", &program()).unwrap();
+    let address_space = run(loader::load_file("/bin/prog", &["prog"], &[]).unwrap());
+    assert_eq!(read_u64(&address_space, DATA + 24), 42);
+    assert_eq!(loader::load_file("/bin/missing", &[], &[]).err(), Some(LoadError::Fs(ruin::fs::FsError::NotFound)));
+}
@@ -52,3 +52,17 @@ fn test_println_text() {
         }
     });
 }
+
+#[test_case]
+fn test_backspace() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "\nabc\x08\x08x").unwrap();
+
+        for (i, char) in "axc".chars().enumerate() {
+            let real_char;
+            unsafe { real_char = VolatileRef::new(NonNull::new(&mut writer.buffer.chars[BUFFER_HEIGHT - 2][i]).unwrap()).as_ptr().read() };
+            assert_eq!(char::from(real_char.character), char);
+        }
+    });
+}
@@ -0,0 +1,183 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, AddressSpace, MapError, MemoryMapFrameAllocator, RegionKind}, allocator, fs::{page_cache::{self, MappedFile}, tmpfs::TmpFs, vfs, OpenFlags}, loader, process::{self, signal::Signal, ExitStatus}, smp, thread};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::elf::{executable, DATA};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Opens `/file` for reading and writing, maps two pages of it with the flags at offset 0x29,
+/// reads the first byte of the second page, writes `X` at offset 1, calls `msync` and exits with
+/// the byte it read
+const MAP_FILE: [u8; 99] = [
+    0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+    0x48, 0x89, 0xDF,                   // mov rdi, rbx
+    0xBE, 0x05, 0x00, 0x00, 0x00,       // mov esi, 5
+    0xBA, 0x03, 0x00, 0x00, 0x00,       // mov edx, 3 (READ_WRITE)
+    0xB8, 0x07, 0x00, 0x00, 0x00,       // mov eax, 7 (open)
+    0x0F, 0x05,                         // syscall
+    0x49, 0x89, 0xC4,                   // mov r12, rax
+    0x31, 0xFF,                         // xor edi, edi
+    0xBE, 0x00, 0x20, 0x00, 0x00,       // mov esi, 8192
+    0xBA, 0x0D, 0x00, 0x00, 0x00,       // mov edx, 13 (MMAP_WRITE | MMAP_SHARED | MMAP_FILE)
+    0x4D, 0x89, 0xE2,                   // mov r10, r12
+    0x45, 0x31, 0xC0,                   // xor r8d, r8d
+    0xB8, 0x05, 0x00, 0x00, 0x00,       // mov eax, 5 (mmap)
+    0x0F, 0x05,                         // syscall
+    0x49, 0x89, 0xC5,                   // mov r13, rax
+    0x45, 0x0F, 0xB6, 0xB5, 0x00, 0x10, 0x00, 0x00, // movzx r14d, byte ptr [r13 + 4096]
+    0x41, 0xC6, 0x45, 0x01, 0x58,       // mov byte ptr [r13 + 1], 'X'
+    0x4C, 0x89, 0xEF,                   // mov rdi, r13
+    0xBE, 0x00, 0x20, 0x00, 0x00,       // mov esi, 8192
+    0xB8, 0x0F, 0x00, 0x00, 0x00,       // mov eax, 15 (msync)
+    0x0F, 0x05,                         // syscall
+    0x4C, 0x89, 0xF7,                   // mov rdi, r14
+    0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+    0x0F, 0x05                          // syscall
+];
+
+/// Page of `a` followed by 100 bytes of `b`
+fn contents() -> Vec<u8> {
+    let mut contents = vec![b'a'; 0x1000];
+    contents.extend_from_slice(&[b'b'; 100]);
+    contents
+}
+
+fn run_map_file(flags: u8) -> ExitStatus {
+    let mut code = MAP_FILE;
+    code[0x29] = flags;
+    let pid = process::spawn(loader::load(&executable(&code, b"/file"), &["map"], &[]).unwrap(), "map");
+    process::wait(Some(pid)).unwrap().1
+}
+
+#[test_case]
+fn test_reads_go_through_cache() {
+    vfs::write_all("/cached", &contents()).unwrap();
+    let file = vfs::open("/cached", OpenFlags::READ_WRITE).unwrap();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data, contents());
+    assert!(page_cache::cached_pages() >= 2);
+
+    // Writes through another handle show up in cached pages
+    vfs::open("/cached", OpenFlags::WRITE).unwrap().write_all(b"zz").unwrap();
+    assert_eq!(&vfs::read_to_end("/cached").unwrap()[..3], b"zza");
+
+    file.truncate(10).unwrap();
+    file.write_all(b"c").unwrap();
+    assert_eq!(vfs::read_to_end("/cached").unwrap().len(), 0x1001 + 100);
+    file.truncate(3).unwrap();
+    assert_eq!(vfs::read_to_end("/cached").unwrap(), b"zza");
+
+    vfs::unlink("/cached").unwrap();
+}
+
+#[test_case]
+fn test_shrink() {
+    vfs::write_all("/evicted", &contents()).unwrap();
+    let cached = page_cache::cached_pages();
+    vfs::read_to_end("/evicted").unwrap();
+    assert_eq!(page_cache::cached_pages(), cached + 2);
+
+    let before = memory::frames_in_use();
+    assert_eq!(page_cache::shrink(usize::MAX), cached + 2);
+    assert_eq!(page_cache::cached_pages(), 0);
+    assert_eq!(memory::frames_in_use(), before - cached - 2);
+    // Evicted pages are read again
+    assert_eq!(vfs::read_to_end("/evicted").unwrap(), contents());
+    vfs::unlink("/evicted").unwrap();
+}
+
+#[test_case]
+fn test_demand_paging() {
+    vfs::write_all("/paged", &contents()).unwrap();
+    let file = vfs::open("/paged", OpenFlags::READ).unwrap();
+    let address_space = AddressSpace::new().unwrap();
+    let backing = MappedFile::new(file.id(), file.inode().clone());
+    address_space.map_backed(VirtAddr::new(DATA), 0x3000, PageTableFlags::WRITABLE, backing, 0, false).unwrap();
+    assert_eq!(address_space.regions()[0].kind, RegionKind::File);
+
+    // Nothing is mapped until it's touched
+    assert!(address_space.flags(VirtAddr::new(DATA)).is_none());
+    let mut byte = [0u8];
+    address_space.read(VirtAddr::new(DATA + 0x1000), &mut byte).unwrap();
+    assert_eq!(byte, *b"b");
+    assert!(address_space.flags(VirtAddr::new(DATA)).is_none());
+    assert!(address_space.flags(VirtAddr::new(DATA + 0x1000)).unwrap().contains(memory::COPY_ON_WRITE));
+
+    // Private writes don't reach the file
+    address_space.write(VirtAddr::new(DATA), b"q").unwrap();
+    address_space.read(VirtAddr::new(DATA), &mut byte).unwrap();
+    assert_eq!(byte, *b"q");
+    assert_eq!(vfs::read_to_end("/paged").unwrap(), contents());
+
+    // Past the end of the file
+    assert_eq!(address_space.read(VirtAddr::new(DATA + 0x2000), &mut byte), Err(MapError::NotMapped));
+
+    drop(address_space);
+    vfs::unlink("/paged").unwrap();
+}
+
+#[test_case]
+fn test_private_mapping() {
+    vfs::write_all("/file", &contents()).unwrap();
+    // MMAP_WRITE | MMAP_FILE
+    assert_eq!(run_map_file(9), ExitStatus::Exited(b'b' as i32));
+    assert_eq!(vfs::read_to_end("/file").unwrap(), contents());
+}
+
+#[test_case]
+fn test_shared_mapping() {
+    vfs::write_all("/file", &contents()).unwrap();
+    assert_eq!(run_map_file(13), ExitStatus::Exited(b'b' as i32));
+
+    let mut expected = contents();
+    expected[1] = b'X';
+    assert_eq!(vfs::read_to_end("/file").unwrap(), expected);
+    // Size of the file doesn't change
+    assert_eq!(vfs::stat("/file").unwrap().size, 0x1000 + 100);
+}
+
+#[test_case]
+fn test_shared_mapping_needs_writable_file() {
+    vfs::write_all("/file", &contents()).unwrap();
+    let mut code = MAP_FILE;
+    // Opened for reading only, mmap fails and the program faults writing to its result
+    code[0x13] = 1;
+    let pid = process::spawn(loader::load(&executable(&code, b"/file"), &["map"], &[]).unwrap(), "map");
+    assert_eq!(process::wait(Some(pid)).unwrap().1, ExitStatus::Signaled(Signal::SEGV));
+    assert_eq!(vfs::read_to_end("/file").unwrap(), contents());
+}
@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, block::{self, BlockDevice, MemoryDisk, SECTOR_SIZE}, partition::{self, Guid, PartitionError, PartitionType}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+const DISK_SECTORS: u64 = 256;
+
+fn write_mbr_entry(sector: &mut [u8], index: usize, partition_type: u8, start: u32, count: u32) {
+    let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+    entry[4] = partition_type;
+    entry[8..12].copy_from_slice(&start.to_le_bytes());
+    entry[12..16].copy_from_slice(&count.to_le_bytes());
+}
+
+fn write_mbr(disk: &MemoryDisk, lba: u64, entries: &[(u8, u32, u32)]) {
+    let mut sector = [0u8; SECTOR_SIZE];
+
+    for (i, &(partition_type, start, count)) in entries.iter().enumerate() {
+        write_mbr_entry(&mut sector, i, partition_type, start, count);
+    }
+
+    sector[510] = 0x55;
+    sector[511] = 0xAA;
+    disk.write_sectors(lba, &sector).unwrap();
+}
+
+fn write_gpt_header(disk: &MemoryDisk, lba: u64, alternate: u64, entries_lba: u64, entries_crc32: u32) {
+    let mut header = [0u8; SECTOR_SIZE];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x00010000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate.to_le_bytes());
+    header[40..48].copy_from_slice(&34u64.to_le_bytes());
+    header[48..56].copy_from_slice(&(DISK_SECTORS - 34).to_le_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc32.to_le_bytes());
+    let crc = partition::crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    disk.write_sectors(lba, &header).unwrap();
+}
+
+fn make_gpt_disk() -> MemoryDisk {
+    let disk = MemoryDisk::new(DISK_SECTORS);
+    write_mbr(&disk, 0, &[(0xEE, 1, DISK_SECTORS as u32 - 1)]);
+
+    let mut entries = vec![0u8; 128 * 128];
+    entries[0..16].copy_from_slice(&Guid::EFI_SYSTEM.0);
+    entries[32..40].copy_from_slice(&40u64.to_le_bytes());
+    entries[40..48].copy_from_slice(&79u64.to_le_bytes());
+
+    for (i, unit) in "boot".encode_utf16().enumerate() {
+        entries[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+
+    entries[128..144].copy_from_slice(&Guid::LINUX_FILESYSTEM.0);
+    entries[160..168].copy_from_slice(&80u64.to_le_bytes());
+    entries[168..176].copy_from_slice(&199u64.to_le_bytes());
+
+    let entries_crc32 = partition::crc32(&entries);
+    disk.write_sectors(2, &entries).unwrap();
+    disk.write_sectors(DISK_SECTORS - 33, &entries).unwrap();
+    write_gpt_header(&disk, 1, DISK_SECTORS - 1, 2, entries_crc32);
+    write_gpt_header(&disk, DISK_SECTORS - 1, 1, DISK_SECTORS - 33, entries_crc32);
+    disk
+}
+
+#[test_case]
+fn test_crc32() {
+    assert_eq!(partition::crc32(b"123456789"), 0xCBF43926);
+}
+
+#[test_case]
+fn test_guid_display() {
+    assert_eq!(alloc::format!("{}", Guid::EFI_SYSTEM), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+}
+
+#[test_case]
+fn test_no_partition_table() {
+    let disk = MemoryDisk::new(DISK_SECTORS);
+    assert_eq!(partition::read_partitions(&disk), Err(PartitionError::NoPartitionTable));
+}
+
+#[test_case]
+fn test_mbr_with_logical_partitions() {
+    let disk = MemoryDisk::new(DISK_SECTORS);
+    write_mbr(&disk, 0, &[(0x83, 2, 30), (0x05, 100, 100)]);
+    write_mbr(&disk, 100, &[(0x0B, 4, 20), (0x05, 50, 50)]);
+    write_mbr(&disk, 150, &[(0x83, 2, 40)]);
+
+    let partitions = partition::read_partitions(&disk).unwrap();
+    assert_eq!(partitions.len(), 3);
+    assert_eq!((partitions[0].number, partitions[0].start_lba, partitions[0].sector_count), (1, 2, 30));
+    assert_eq!((partitions[1].number, partitions[1].start_lba, partitions[1].sector_count), (5, 104, 20));
+    assert_eq!(partitions[1].partition_type, PartitionType::Mbr(0x0B));
+    assert_eq!((partitions[2].number, partitions[2].start_lba, partitions[2].sector_count), (6, 152, 40));
+}
+
+#[test_case]
+fn test_gpt() {
+    let partitions = partition::read_partitions(&make_gpt_disk()).unwrap();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!(partitions[0].name, "boot");
+    assert_eq!(partitions[0].partition_type, PartitionType::Gpt(Guid::EFI_SYSTEM));
+    assert_eq!((partitions[0].start_lba, partitions[0].sector_count), (40, 40));
+    assert_eq!((partitions[1].start_lba, partitions[1].sector_count), (80, 120));
+}
+
+#[test_case]
+fn test_gpt_backup_header_fallback() {
+    let disk = make_gpt_disk();
+    disk.write_sectors(1, &[0u8; SECTOR_SIZE]).unwrap();
+    assert_eq!(partition::read_partitions(&disk).unwrap().len(), 2);
+
+    let disk = make_gpt_disk();
+    disk.write_sectors(2, &[0xFFu8; SECTOR_SIZE]).unwrap(); // corrupt primary entries only
+    assert_eq!(partition::read_partitions(&disk).unwrap().len(), 2);
+}
+
+#[test_case]
+fn test_scan_registers_slices() {
+    let disk = Arc::new(make_gpt_disk());
+    disk.write_bytes(80 * SECTOR_SIZE as u64 + 3, b"ruin").unwrap();
+    block::register("testdisk", disk);
+    partition::scan("testdisk").unwrap();
+
+    let slice = block::get("testdiskp2").unwrap();
+    assert_eq!(slice.sector_count(), 120);
+    let mut buf = [0u8; 4];
+    slice.read_bytes(3, &mut buf).unwrap();
+    assert_eq!(&buf, b"ruin");
+    assert!(slice.read_sectors(120, &mut [0u8; SECTOR_SIZE]).is_err());
+}
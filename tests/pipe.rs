@@ -0,0 +1,210 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{console::Decoder, memory::{self, MemoryMapFrameAllocator}, allocator, fs::{pipe::{self, PIPE_SIZE}, tmpfs::TmpFs, vfs, FileType, FsError, OpenFlags}, loader, process::{self, files::FileTable, ExitStatus}, smp, thread};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::elf::executable;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_read_write() {
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+    assert_eq!(reader.stat().unwrap().file_type, FileType::Fifo);
+    assert_eq!(writer.write(b"hello").unwrap(), 5);
+    assert_eq!(reader.stat().unwrap().size, 5);
+
+    let mut buf = [0u8; 16];
+    assert_eq!(reader.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(reader.write(b"x").err(), Some(FsError::PermissionDenied));
+    assert_eq!(writer.read(&mut buf).err(), Some(FsError::PermissionDenied));
+}
+
+#[test_case]
+fn test_closed_ends() {
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+    writer.write_all(b"last").unwrap();
+    drop(writer);
+
+    // Data written before the close is still there, then reads see the end
+    let mut buf = [0u8; 16];
+    assert_eq!(reader.read(&mut buf).unwrap(), 4);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+    drop(reader);
+    assert_eq!(writer.write(b"lost").err(), Some(FsError::BrokenPipe));
+}
+
+#[test_case]
+fn test_nonblocking() {
+    let (reader, writer) = pipe::pipe(OpenFlags::NONBLOCK);
+    let mut buf = [0u8; 16];
+    assert_eq!(reader.read(&mut buf).err(), Some(FsError::WouldBlock));
+
+    assert_eq!(writer.write(&[7; PIPE_SIZE + 100]).unwrap(), PIPE_SIZE);
+    assert_eq!(writer.write(b"full").err(), Some(FsError::WouldBlock));
+    assert_eq!(reader.read(&mut buf).unwrap(), 16);
+    assert_eq!(writer.write(b"room for this").unwrap(), 13);
+}
+
+#[test_case]
+fn test_blocking() {
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+
+    // Reader waits for the first write
+    let handle = thread::spawn(move || {
+        thread::sleep(20);
+        writer.write_all(&[1; PIPE_SIZE * 3]).unwrap();
+    });
+
+    let mut data = Vec::new();
+    assert_eq!(reader.read_to_end(&mut data).unwrap(), PIPE_SIZE * 3);
+    assert!(data.iter().all(|&byte| byte == 1));
+    handle.join();
+}
+
+#[test_case]
+fn test_file_table() {
+    let mut files = FileTable::console();
+    assert_eq!(files.len(), 3);
+    assert_eq!(files.get(0).unwrap().path(), "/dev/console");
+
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+    let writer = Arc::new(writer);
+    assert_eq!(files.insert(Arc::new(reader)), Some(3));
+    assert!(files.insert_at(1, writer.clone()).unwrap().is_some());
+    assert!(Arc::ptr_eq(&files.get(1).unwrap(), &writer));
+    assert!(files.remove(0).is_some());
+    assert_eq!(files.insert(writer), Some(0));
+    assert!(files.insert_at(process::files::MAX_FILES, files.get(0).unwrap()).is_none());
+}
+
+#[test_case]
+fn test_decoder() {
+    let mut decoder = Decoder::new();
+    let mut typed = Vec::new();
+
+    // Shift+h, i, ctrl+c, up arrow, then releases that type nothing
+    for scancode in [0x2A, 0x23, 0xA3, 0xAA, 0x17, 0x1D, 0x2E, 0x9D, 0xE0, 0x48, 0xE0, 0xC8, 0x97] {
+        decoder.decode(scancode, |byte| typed.push(byte));
+    }
+
+    assert_eq!(typed, b"Hi\x03\x1B[A");
+}
+
+#[test_case]
+fn test_pipeline() {
+    // Writes "hi" to standard output and exits with 0
+    let writer_code = [
+        0xBF, 0x01, 0x00, 0x00, 0x00,       // mov edi, 1
+        0x48, 0xBE, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA
+        0xBA, 0x02, 0x00, 0x00, 0x00,       // mov edx, 2
+        0xB8, 0x00, 0x00, 0x00, 0x00,       // mov eax, 0 (write)
+        0x0F, 0x05,                         // syscall
+        0x31, 0xFF,                         // xor edi, edi
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    // Reads standard input and exits with the first byte plus the count
+    let reader_code = [
+        0x31, 0xFF,                         // xor edi, edi
+        0x48, 0xBE, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA
+        0xBA, 0x10, 0x00, 0x00, 0x00,       // mov edx, 16
+        0xB8, 0x01, 0x00, 0x00, 0x00,       // mov eax, 1 (read)
+        0x0F, 0x05,                         // syscall
+        0x0F, 0xB6, 0x3E,                   // movzx edi, byte ptr [rsi]
+        0x48, 0x01, 0xC7,                   // add rdi, rax
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let before = memory::frames_in_use();
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+    let mut reader_files = FileTable::console();
+    let mut writer_files = FileTable::console();
+    reader_files.insert_at(0, Arc::new(reader));
+    writer_files.insert_at(1, Arc::new(writer));
+
+    let reading = process::spawn_with_files(loader::load(&executable(&reader_code, &[]), &["reader"], &[]).unwrap(), "reader", reader_files);
+    let writing = process::spawn_with_files(loader::load(&executable(&writer_code, b"hi"), &["writer"], &[]).unwrap(), "writer", writer_files);
+    assert_eq!(process::wait(Some(writing)), Ok((writing, ExitStatus::Exited(0))));
+    assert_eq!(process::wait(Some(reading)), Ok((reading, ExitStatus::Exited(i32::from(b'h') + 2))));
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_pipe_and_dup2() {
+    // Makes a non-blocking pipe and reads it while it's empty, then makes it standard output,
+    // writes "x" there and reads it back. Exits with the byte plus the first result.
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x48, 0x89, 0xDF,                   // mov rdi, rbx
+        0xBE, 0x80, 0x00, 0x00, 0x00,       // mov esi, 0x80 (NONBLOCK)
+        0xB8, 0x10, 0x00, 0x00, 0x00,       // mov eax, 16 (pipe)
+        0x0F, 0x05,                         // syscall
+        0x8B, 0x3B,                         // mov edi, dword ptr [rbx]
+        0x48, 0x8D, 0x73, 0x20,             // lea rsi, [rbx + 32]
+        0xBA, 0x01, 0x00, 0x00, 0x00,       // mov edx, 1
+        0xB8, 0x01, 0x00, 0x00, 0x00,       // mov eax, 1 (read)
+        0x0F, 0x05,                         // syscall
+        0x49, 0x89, 0xC4,                   // mov r12, rax
+        0x8B, 0x7B, 0x04,                   // mov edi, dword ptr [rbx + 4]
+        0xBE, 0x01, 0x00, 0x00, 0x00,       // mov esi, 1
+        0xB8, 0x12, 0x00, 0x00, 0x00,       // mov eax, 18 (dup2)
+        0x0F, 0x05,                         // syscall
+        0xBF, 0x01, 0x00, 0x00, 0x00,       // mov edi, 1
+        0x48, 0x8D, 0x73, 0x10,             // lea rsi, [rbx + 16]
+        0xBA, 0x01, 0x00, 0x00, 0x00,       // mov edx, 1
+        0xB8, 0x00, 0x00, 0x00, 0x00,       // mov eax, 0 (write)
+        0x0F, 0x05,                         // syscall
+        0x8B, 0x3B,                         // mov edi, dword ptr [rbx]
+        0x48, 0x8D, 0x73, 0x20,             // lea rsi, [rbx + 32]
+        0xBA, 0x01, 0x00, 0x00, 0x00,       // mov edx, 1
+        0xB8, 0x01, 0x00, 0x00, 0x00,       // mov eax, 1 (read)
+        0x0F, 0x05,                         // syscall
+        0x0F, 0xB6, 0x7B, 0x20,             // movzx edi, byte ptr [rbx + 32]
+        0x4C, 0x01, 0xE7,                   // add rdi, r12
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let mut data = [0u8; 17];
+    data[16] = b'x';
+    let pid = process::spawn(loader::load(&executable(&code, &data), &["dup2"], &[]).unwrap(), "dup2");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(i32::from(b'x') - 12))));
+}
@@ -0,0 +1,211 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, fs::{tmpfs::TmpFs, vfs}, loader, process::{self, signal::Signal, ExitStatus, Parent, ProcessError}, smp, syscall::Error, thread};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::{elf::{executable, DATA}, memory::read_u64};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Exits with code 7
+const EXIT_7: [u8; 12] = [
+    0xBF, 0x07, 0x00, 0x00, 0x00,       // mov edi, 7
+    0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+    0x0F, 0x05                          // syscall
+];
+
+/// Exits with `argc + 40`
+const EXIT_ARGC: [u8; 15] = [
+    0x48, 0x8B, 0x3C, 0x24,             // mov rdi, [rsp]
+    0x48, 0x83, 0xC7, 0x28,             // add rdi, 40
+    0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+    0x0F, 0x05                          // syscall
+];
+
+#[test_case]
+fn test_exit_status() {
+    let program = loader::load(&executable(&EXIT_7, &[]), &["exit"], &[]).unwrap();
+    let pid = process::spawn(program, "exit");
+    let info = process::info(pid).unwrap();
+    assert_eq!(info.parent, Parent::Kernel);
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(7))));
+    // Zombie is gone once it's waited for
+    assert!(process::info(pid).is_none());
+    assert_eq!(process::wait(Some(pid)), Err(ProcessError::NoChild));
+    assert_eq!(process::wait(None), Err(ProcessError::NoChild));
+    assert_eq!(ExitStatus::Exited(7).wait_status(), 7 << 8);
+}
+
+#[test_case]
+fn test_teardown_frees_memory() {
+    let image = executable(&EXIT_7, &[]);
+    let before = memory::frames_in_use();
+
+    for _ in 0..3 {
+        let pid = process::spawn(loader::load(&image, &["exit"], &[]).unwrap(), "exit");
+        process::wait(Some(pid)).unwrap();
+    }
+
+    assert_eq!(memory::frames_in_use(), before);
+
+    // Killed processes free everything too
+    let pid = process::spawn(loader::load(&executable(&[0x0F, 0x0B], &[]), &[], &[]).unwrap(), "fault");
+    assert_eq!(process::wait(None), Ok((pid, ExitStatus::Signaled(Signal::ILL))));
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_fork() {
+    // Child changes its copy of data and exits with 5 from a callee-saved register, parent waits
+    // and exits with the child's code plus what it sees in its own copy
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x41, 0xBC, 0x05, 0x00, 0x00, 0x00, // mov r12d, 5
+        0xB8, 0x09, 0x00, 0x00, 0x00,       // mov eax, 9 (fork)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x85, 0xC0,                   // test rax, rax
+        0x75, 0x12,                         // jnz parent
+        0x48, 0xC7, 0x43, 0x10, 0x63, 0x00, 0x00, 0x00, // mov qword ptr [rbx + 16], 99
+        0x4C, 0x89, 0xE7,                   // mov rdi, r12
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x18,             // parent: mov [rbx + 24], rax
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0x48, 0x89, 0xDE,                   // mov rsi, rbx
+        0xB8, 0x0B, 0x00, 0x00, 0x00,       // mov eax, 11 (wait)
+        0xCD, 0x80,                         // int 0x80
+        0x48, 0x89, 0x43, 0x20,             // mov [rbx + 32], rax
+        0x48, 0x8B, 0x3B,                   // mov rdi, [rbx]
+        0x48, 0xC1, 0xEF, 0x08,             // shr rdi, 8
+        0x48, 0x03, 0x7B, 0x10,             // add rdi, [rbx + 16]
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let program = loader::load(&executable(&code, &[]), &["fork"], &[]).unwrap();
+    let address_space = program.address_space().clone();
+    let pid = process::spawn(program, "fork");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(5))));
+
+    // Child got a new id and was reaped by the parent, not by the kernel
+    let child = read_u64(&address_space, DATA + 24);
+    assert!(child != 0 && child != pid.as_u64());
+    assert_eq!(read_u64(&address_space, DATA + 32), child);
+    assert_eq!(read_u64(&address_space, DATA), 5 << 8);
+    assert!(process::list().is_empty());
+}
+
+#[test_case]
+fn test_exec() {
+    vfs::mkdir("/bin").unwrap();
+    vfs::write_all("/bin/argc", &executable(&EXIT_ARGC, &[])).unwrap();
+
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x48, 0x89, 0xDF,                   // mov rdi, rbx
+        0x8B, 0x73, 0x08,                   // mov esi, [rbx + 8]
+        0x48, 0x8D, 0x53, 0x10,             // lea rdx, [rbx + 16]
+        0xB8, 0x0A, 0x00, 0x00, 0x00,       // mov eax, 10 (exec)
+        0x0F, 0x05,                         // syscall
+        0x48, 0xF7, 0xD8,                   // neg rax
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    // Path and its length, then `argv` pointing to "argc" and "x"
+    let data = |path: &[u8]| {
+        let mut data = vec![0u8; 64];
+        data[..path.len()].copy_from_slice(path);
+        data[8..16].copy_from_slice(&(path.len() as u64).to_le_bytes());
+        data[16..24].copy_from_slice(&(DATA + 48).to_le_bytes());
+        data[24..32].copy_from_slice(&(DATA + 56).to_le_bytes());
+        data[48..52].copy_from_slice(b"argc");
+        data[56] = b'x';
+        data
+    };
+
+    let pid = process::spawn(loader::load(&executable(&code, &data(b"/bin/argc")), &[], &[]).unwrap(), "exec");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(42))));
+
+    // Failed exec returns to the old program
+    let pid = process::spawn(loader::load(&executable(&code, &data(b"/bin/no")), &[], &[]).unwrap(), "exec");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(-Error::NotFound.code() as i32))));
+}
+
+#[test_case]
+fn test_files() {
+    vfs::write_all("/motd", b"Hello from a file").unwrap();
+
+    // Opens, reads and closes the file, closes it again and exits with the number of bytes read
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x48, 0x89, 0xDF,                   // mov rdi, rbx
+        0x8B, 0x73, 0x08,                   // mov esi, [rbx + 8]
+        0xBA, 0x01, 0x00, 0x00, 0x00,       // mov edx, 1 (read only)
+        0xB8, 0x07, 0x00, 0x00, 0x00,       // mov eax, 7 (open)
+        0x0F, 0x05,                         // syscall
+        0x49, 0x89, 0xC4,                   // mov r12, rax
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0x48, 0x8D, 0x73, 0x40,             // lea rsi, [rbx + 64]
+        0xBA, 0x40, 0x00, 0x00, 0x00,       // mov edx, 64
+        0xB8, 0x01, 0x00, 0x00, 0x00,       // mov eax, 1 (read)
+        0x0F, 0x05,                         // syscall
+        0x49, 0x89, 0xC5,                   // mov r13, rax
+        0x4C, 0x89, 0xE7,                   // mov rdi, r12
+        0xB8, 0x08, 0x00, 0x00, 0x00,       // mov eax, 8 (close)
+        0x0F, 0x05,                         // syscall
+        0x4C, 0x89, 0xE7,                   // mov rdi, r12
+        0xB8, 0x08, 0x00, 0x00, 0x00,       // mov eax, 8 (close)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x10,             // mov [rbx + 16], rax
+        0x4C, 0x89, 0xEF,                   // mov rdi, r13
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let mut data = [0u8; 16];
+    data[..5].copy_from_slice(b"/motd");
+    data[8] = 5;
+    let program = loader::load(&executable(&code, &data), &[], &[]).unwrap();
+    let address_space = program.address_space().clone();
+    let pid = process::spawn(program, "files");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(17))));
+
+    let mut contents = [0u8; 17];
+    address_space.read(VirtAddr::new(DATA + 64), &mut contents).unwrap();
+    assert_eq!(&contents, b"Hello from a file");
+    assert_eq!(read_u64(&address_space, DATA + 16) as i64, Error::BadDescriptor.code());
+}
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use ruin::{serial::COM1, serial_println, exit_qemu, QemuExitCode};
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    // Test still holds COM1, printing only works if the panic path releases it
+    ruin::prepare_panic();
+    serial_println!("Pass");
+    exit_qemu(QemuExitCode::Ok);
+    loop {}
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    test_recursive_lock();
+    serial_println!("Fail");
+    exit_qemu(QemuExitCode::Fail);
+    loop {}
+}
+
+/// Locking a held lock on the same processor is detected instead of spinning forever
+fn test_recursive_lock() {
+    let _first = COM1.lock();
+    let _second = COM1.lock();
+}
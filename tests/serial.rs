@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, fs::{FileType, OpenFlags}, serial::{self, ComPort, SerialError, DEFAULT_BAUD_RATE}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_ports() {
+    let ports = serial::probe();
+    assert_eq!(ports.first(), Some(&ComPort::Com1));
+    assert_eq!((ComPort::Com2.base(), ComPort::Com2.irq(), ComPort::Com2.name()), (0x2F8, 3, "ttyS1"));
+}
+
+#[test_case]
+fn test_baud_rate() {
+    assert_eq!(serial::set_baud_rate(ComPort::Com1, 0), Err(SerialError::InvalidBaudRate));
+    assert_eq!(serial::set_baud_rate(ComPort::Com1, 7), Err(SerialError::InvalidBaudRate));
+    assert_eq!(serial::set_baud_rate(ComPort::Com1, 115200), Ok(()));
+    assert_eq!(serial::set_baud_rate(ComPort::Com1, DEFAULT_BAUD_RATE), Ok(()));
+}
+
+#[test_case]
+fn test_device() {
+    let file = serial::open(ComPort::Com1, OpenFlags::READ_WRITE);
+    assert_eq!(file.path(), "/dev/ttyS0");
+    assert_eq!(file.stat().unwrap().file_type, FileType::CharDevice);
+    assert_eq!(file.write(b"[serial device] ").unwrap(), 16);
+
+    // Nothing is typed while testing
+    assert_eq!(serial::try_read(ComPort::Com1, &mut [0; 8]), 0);
+}
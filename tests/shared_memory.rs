@@ -0,0 +1,192 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, shared, AddressSpace, MemoryMapFrameAllocator, RegionKind, SharedMemory}, allocator, fs::{tmpfs::TmpFs, vfs}, loader, process::{self, ExitStatus}, smp, thread};
+use x86_64::{structures::paging::{PageTableFlags, PhysFrame}, VirtAddr};
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::{elf::{executable, CODE, DATA}, memory::read_u64};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+fn frame(address_space: &AddressSpace, address: u64) -> PhysFrame {
+    PhysFrame::containing_address(address_space.translate(VirtAddr::new(address)).unwrap())
+}
+
+#[test_case]
+fn test_duplicate_copies_on_write() {
+    let before = memory::frames_in_use();
+    let original = AddressSpace::new().unwrap();
+    original.map(VirtAddr::new(DATA), 0x1000, PageTableFlags::WRITABLE, RegionKind::Anonymous).unwrap();
+    original.map(VirtAddr::new(CODE), 0x1000, PageTableFlags::empty(), RegionKind::Image).unwrap();
+    original.write(VirtAddr::new(DATA), &5u64.to_le_bytes()).unwrap();
+
+    let copy = original.duplicate().unwrap();
+    assert_eq!(frame(&original, DATA), frame(&copy, DATA));
+    assert_eq!(memory::frame_references(frame(&copy, DATA)), 2);
+    assert_eq!(frame(&original, CODE), frame(&copy, CODE));
+
+    for address_space in [&original, &copy] {
+        let flags = address_space.flags(VirtAddr::new(DATA)).unwrap();
+        assert!(flags.contains(memory::COPY_ON_WRITE) && !flags.contains(PageTableFlags::WRITABLE));
+        // Read-only pages are just shared
+        assert!(!address_space.flags(VirtAddr::new(CODE)).unwrap().contains(memory::COPY_ON_WRITE));
+    }
+
+    // Writer gets its own frame, the other one keeps the old contents
+    copy.write(VirtAddr::new(DATA), &6u64.to_le_bytes()).unwrap();
+    assert_ne!(frame(&original, DATA), frame(&copy, DATA));
+    assert_eq!(read_u64(&original, DATA), 5);
+    assert_eq!(read_u64(&copy, DATA), 6);
+    assert!(copy.flags(VirtAddr::new(DATA)).unwrap().contains(PageTableFlags::WRITABLE));
+
+    // Last user of the frame just makes it writable again
+    let shared = frame(&original, DATA);
+    original.write(VirtAddr::new(DATA), &7u64.to_le_bytes()).unwrap();
+    assert_eq!(frame(&original, DATA), shared);
+    assert!(original.flags(VirtAddr::new(DATA)).unwrap().contains(PageTableFlags::WRITABLE));
+
+    drop(copy);
+    drop(original);
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_fork_shared_and_private() {
+    // Maps shared memory and forks, child writes 77 there and 11 to its copy of data. Parent
+    // waits, stores what it sees of both and exits with their sum.
+    let code = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0x31, 0xFF,                         // xor edi, edi
+        0xBE, 0x00, 0x10, 0x00, 0x00,       // mov esi, 4096
+        0xBA, 0x05, 0x00, 0x00, 0x00,       // mov edx, 5 (MMAP_WRITE | MMAP_SHARED)
+        0xB8, 0x05, 0x00, 0x00, 0x00,       // mov eax, 5 (mmap)
+        0x0F, 0x05,                         // syscall
+        0x49, 0x89, 0xC4,                   // mov r12, rax
+        0xB8, 0x09, 0x00, 0x00, 0x00,       // mov eax, 9 (fork)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x85, 0xC0,                   // test rax, rax
+        0x75, 0x18,                         // jnz parent
+        0x49, 0xC7, 0x04, 0x24, 0x4D, 0x00, 0x00, 0x00, // mov qword ptr [r12], 77
+        0x48, 0xC7, 0x03, 0x0B, 0x00, 0x00, 0x00, // mov qword ptr [rbx], 11
+        0x31, 0xFF,                         // xor edi, edi
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0xC7,                   // parent: mov rdi, rax
+        0x48, 0x8D, 0x73, 0x08,             // lea rsi, [rbx + 8]
+        0xB8, 0x0B, 0x00, 0x00, 0x00,       // mov eax, 11 (wait)
+        0x0F, 0x05,                         // syscall
+        0x49, 0x8B, 0x04, 0x24,             // mov rax, [r12]
+        0x48, 0x89, 0x43, 0x10,             // mov [rbx + 16], rax
+        0x48, 0x8B, 0x03,                   // mov rax, [rbx]
+        0x48, 0x89, 0x43, 0x18,             // mov [rbx + 24], rax
+        0x49, 0x8B, 0x3C, 0x24,             // mov rdi, [r12]
+        0x48, 0x03, 0x3B,                   // add rdi, [rbx]
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let before = memory::frames_in_use();
+    let program = loader::load(&executable(&code, &[]), &["fork"], &[]).unwrap();
+    let address_space = program.address_space().clone();
+    let pid = process::spawn(program, "fork");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(77))));
+    assert_eq!(read_u64(&address_space, DATA + 8), 0);
+    assert_eq!(read_u64(&address_space, DATA + 16), 77);
+    assert_eq!(read_u64(&address_space, DATA + 24), 0);
+
+    drop(address_space);
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_map_shared() {
+    let before = memory::frames_in_use();
+    let memory = SharedMemory::new(0x1800).unwrap();
+    assert_eq!(memory.size(), 0x2000);
+
+    let first = AddressSpace::new().unwrap();
+    let second = AddressSpace::new().unwrap();
+    first.map_shared(VirtAddr::new(DATA), &memory, PageTableFlags::WRITABLE).unwrap();
+    second.map_shared(VirtAddr::new(CODE), &memory, PageTableFlags::empty()).unwrap();
+    assert_eq!(first.regions()[0].kind, RegionKind::Shared);
+    assert_eq!(memory::frame_references(memory.frames()[1]), 3);
+
+    first.write(VirtAddr::new(DATA + 0x1008), &9u64.to_le_bytes()).unwrap();
+    assert_eq!(read_u64(&second, CODE + 0x1008), 9);
+
+    // Copies share it too instead of copying on write
+    let copy = first.duplicate().unwrap();
+    assert_eq!(copy.flags(VirtAddr::new(DATA)), first.flags(VirtAddr::new(DATA)));
+    copy.write(VirtAddr::new(DATA), &3u64.to_le_bytes()).unwrap();
+    assert_eq!(read_u64(&second, CODE), 3);
+
+    // Frames stay as long as anything maps them
+    drop(memory);
+    drop(first);
+    assert_eq!(read_u64(&copy, DATA + 0x1008), 9);
+    drop(copy);
+    drop(second);
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_named_shared_memory() {
+    // Maps writable shared memory "box", stores 123 at offset 8 and exits with 0
+    let code = [
+        0x48, 0xBF, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rdi, DATA
+        0xBE, 0x03, 0x00, 0x00, 0x00,       // mov esi, 3
+        0xBA, 0x00, 0x10, 0x00, 0x00,       // mov edx, 4096
+        0x41, 0xBA, 0x01, 0x00, 0x00, 0x00, // mov r10d, 1 (MMAP_WRITE)
+        0xB8, 0x0D, 0x00, 0x00, 0x00,       // mov eax, 13 (shm_map)
+        0x0F, 0x05,                         // syscall
+        0x48, 0xC7, 0x40, 0x08, 0x7B, 0x00, 0x00, 0x00, // mov qword ptr [rax + 8], 123
+        0x31, 0xFF,                         // xor edi, edi
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let before = memory::frames_in_use();
+    let pid = process::spawn(loader::load(&executable(&code, b"box"), &["shm"], &[]).unwrap(), "shm");
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(0))));
+
+    // Memory outlives the process while it has a name, size only matters when it's created
+    let memory = shared::open("box", 0x10000).unwrap();
+    assert_eq!(memory.size(), 0x1000);
+    let value = unsafe { *memory::physical_to_virtual(memory.frames()[0].start_address() + 8u64).as_ptr::<u64>() };
+    assert_eq!(value, 123);
+
+    assert!(shared::unlink("box"));
+    assert!(!shared::unlink("box"));
+    drop(memory);
+    assert_eq!(memory::frames_in_use(), before);
+}
@@ -0,0 +1,176 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, fs::{tmpfs::TmpFs, vfs}, rtc, shell::{self, editor::Editor}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+fn no_completions(_: &str, _: bool) -> Vec<String> {
+    Vec::new()
+}
+
+/// Feeds `input` to `editor`, returns the lines it finished and the echo
+fn feed(editor: &mut Editor, input: &[u8], complete: &dyn Fn(&str, bool) -> Vec<String>) -> (Vec<String>, Vec<u8>) {
+    let mut echo = Vec::new();
+    let lines = input.iter().filter_map(|&byte| editor.feed(byte, complete, &mut |bytes| echo.extend_from_slice(bytes))).collect();
+    (lines, echo)
+}
+
+fn run(line: &str) -> String {
+    let mut output = String::new();
+    shell::execute(line, &mut output).unwrap();
+    output
+}
+
+#[test_case]
+fn test_editing() {
+    let mut editor = Editor::new("> ");
+    let (lines, echo) = feed(&mut editor, b"helo", &no_completions);
+    assert!(lines.is_empty());
+    assert_eq!(echo, b"helo");
+
+    // Left twice, insert, then the rest of the line is shown again
+    let (_, echo) = feed(&mut editor, b"\x1B[D\x1B[Dl", &no_completions);
+    assert_eq!(editor.line(), "hello");
+    assert_eq!(editor.cursor(), 3);
+    assert_eq!(echo, b"\x08\x08llo\x08\x08");
+
+    // Backspace in the middle, Home, Delete, End
+    feed(&mut editor, b"\x7F", &no_completions);
+    assert_eq!(editor.line(), "helo");
+    feed(&mut editor, b"\x01\x1B[3~", &no_completions);
+    assert_eq!((editor.line(), editor.cursor()), ("elo", 0));
+    feed(&mut editor, b"\x05!", &no_completions);
+    assert_eq!(editor.line(), "elo!");
+
+    // Enter of a serial terminal is `\r`, maybe with `\n`
+    let (lines, _) = feed(&mut editor, b"\r\nnext\n", &no_completions);
+    assert_eq!(lines, vec![String::from("elo!"), String::from("next")]);
+    assert_eq!((editor.line(), editor.cursor()), ("", 0));
+
+    // Ctrl+U drops the line
+    feed(&mut editor, b"abc\x15", &no_completions);
+    assert_eq!(editor.line(), "");
+}
+
+#[test_case]
+fn test_history() {
+    let mut editor = Editor::new("> ");
+    feed(&mut editor, b"first\nsecond\nsecond\n\n", &no_completions);
+    assert_eq!(editor.history().collect::<Vec<_>>(), ["first", "second"]);
+
+    feed(&mut editor, b"dra", &no_completions);
+    feed(&mut editor, b"\x1B[A", &no_completions);
+    assert_eq!(editor.line(), "second");
+    feed(&mut editor, b"\x1B[A\x1B[A", &no_completions);
+    assert_eq!(editor.line(), "first");
+    feed(&mut editor, b"\x1B[B", &no_completions);
+    assert_eq!(editor.line(), "second");
+
+    // Down past the newest entry gives back the draft
+    let (_, echo) = feed(&mut editor, b"\x1B[Bft", &no_completions);
+    assert_eq!(editor.line(), "draft");
+    assert!(echo.starts_with(b"\x08\x08\x08\x08\x08\x08dra   \x08\x08\x08"));
+
+    let (lines, _) = feed(&mut editor, b"\x1B[A\n", &no_completions);
+    assert_eq!(lines, vec![String::from("second")]);
+}
+
+#[test_case]
+fn test_completion() {
+    let mut editor = Editor::new("> ");
+
+    // One candidate is finished with a space
+    let (_, echo) = feed(&mut editor, b"upt\t", &shell::complete);
+    assert_eq!(editor.line(), "uptime ");
+    assert_eq!(echo, b"uptime ");
+
+    // Several are completed as far as they agree, then listed
+    let mut editor = Editor::new("> ");
+    feed(&mut editor, b"c\t", &shell::complete);
+    assert_eq!(editor.line(), "c");
+    let (_, echo) = feed(&mut editor, b"l\t", &shell::complete);
+    assert_eq!(editor.line(), "clear ");
+    assert_eq!(echo, b"lear ");
+
+    vfs::mkdir("/complete").unwrap();
+    vfs::mkdir("/complete/directory").unwrap();
+    vfs::write_all("/complete/file1", b"1").unwrap();
+    vfs::write_all("/complete/file2", b"2").unwrap();
+
+    let mut editor = Editor::new("> ");
+    feed(&mut editor, b"ls /comp\td\t", &shell::complete);
+    assert_eq!(editor.line(), "ls /complete/directory/");
+
+    let mut editor = Editor::new("> ");
+    let (_, echo) = feed(&mut editor, b"cat /complete/f\t\t", &shell::complete);
+    assert_eq!(editor.line(), "cat /complete/file");
+    assert!(echo.ends_with(b"\nfile1  file2  \n> cat /complete/file"));
+}
+
+#[test_case]
+fn test_commands() {
+    assert_eq!(run(""), "");
+    assert_eq!(run("echo  a b "), "a b\n");
+    assert_eq!(run("nothing"), "nothing: command not found, see help\n");
+    assert_eq!(run("clear"), "\x1B[2J\x1B[H");
+    assert!(run("serial").starts_with("ttyS0 at 0x3f8, IRQ 4\n"));
+    assert_eq!(run("serial ttyS0 7"), "serial: 7: InvalidBaudRate\n");
+    assert_eq!(run("serial ttyS9 9600"), "serial: no port ttyS9\n");
+    assert_eq!(run("help").lines().count(), shell::commands::COMMANDS.len());
+    assert!(run("mem").starts_with("Heap: "));
+    assert!(run("uptime").starts_with("Up 0:"));
+    assert!(run("acpi").contains("APIC"));
+    assert!(!run("lspci").is_empty());
+
+    let date = rtc::now();
+    assert!(date.year >= 2024 && (1..=12).contains(&date.month) && date.hour < 24);
+    assert!(run("date").ends_with(" UTC\n"));
+}
+
+#[test_case]
+fn test_files() {
+    vfs::mkdir("/files").unwrap();
+    vfs::mkdir("/files/sub").unwrap();
+    vfs::write_all("/files/b.txt", b"bee\n").unwrap();
+    vfs::write_all("/files/a.txt", b"ay\n").unwrap();
+
+    assert_eq!(run("ls /files"), "a.txt\nb.txt\nsub/\n");
+    assert_eq!(run("cat /files/a.txt /files/b.txt"), "ay\nbee\n");
+    assert_eq!(run("cat /files/c.txt"), "cat: /files/c.txt: NotFound\n");
+
+    assert_eq!(run("cd /files"), "");
+    assert_eq!(run("pwd"), "/files\n");
+    assert_eq!(run("ls"), "a.txt\nb.txt\nsub/\n");
+    assert_eq!(run("cd a.txt"), "cd: a.txt: NotDirectory\n");
+    assert_eq!(run("cd"), "");
+    assert_eq!(run("pwd"), "/\n");
+}
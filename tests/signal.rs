@@ -0,0 +1,270 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, fs::{tmpfs::TmpFs, vfs}, loader, process::{self, signal::{self, Action, Signal, SignalSet}, ExitStatus, Pid, ProcessError}, smp, syscall::Error, thread};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+mod common;
+
+use common::elf::{executable, CODE};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Handler and restorer offsets in programs with a handler
+const HANDLER: usize = 0xC0;
+const RESTORER: usize = 0xE0;
+
+/// Code of a program that has `main` at the start and `handler` at `HANDLER`, with a restorer
+/// that calls `sigreturn`
+fn with_handler(main: &[u8], handler: &[u8]) -> Vec<u8> {
+    let restorer = [
+        0xB8, 0x16, 0x00, 0x00, 0x00,       // mov eax, 22 (sigreturn)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let mut code = vec![0u8; 0x100];
+    code[..main.len()].copy_from_slice(main);
+    code[HANDLER..HANDLER + handler.len()].copy_from_slice(handler);
+    code[RESTORER..RESTORER + restorer.len()].copy_from_slice(&restorer);
+    code
+}
+
+/// `sigaction` argument with `handler`, `flags`, `restorer` and `mask`
+fn sigaction(handler: u64, restorer: u64, mask: u64) -> Vec<u8> {
+    [handler, 0, restorer, mask].iter().flat_map(|field| field.to_le_bytes()).collect()
+}
+
+fn run(code: &[u8], data: &[u8]) -> ExitStatus {
+    let pid = process::spawn(loader::load(&executable(code, data), &["signal"], &[]).unwrap(), "signal");
+    process::wait(Some(pid)).unwrap().1
+}
+
+/// Spins until process `pid` is stopped or not, as `stopped` says
+fn wait_stopped(pid: Pid, stopped: bool) {
+    while process::info(pid).unwrap().stopped != stopped {
+        thread::sleep(1);
+    }
+}
+
+#[test_case]
+fn test_signal_numbers() {
+    assert_eq!(Signal::new(0), None);
+    assert_eq!(Signal::new(32), None);
+    assert_eq!(Signal::new(15), Some(Signal::TERM));
+    assert!(!Signal::KILL.catchable() && !Signal::STOP.catchable() && Signal::INT.catchable());
+
+    let mut set = SignalSet::EMPTY;
+    set.insert(Signal::USR1);
+    assert_eq!(set, SignalSet(1 << 10));
+    assert!(set.contains(Signal::USR1) && !set.contains(Signal::USR2));
+
+    assert_eq!(ExitStatus::Signaled(Signal::TERM).wait_status(), 15);
+    // Core dump bit for signals that would write one
+    assert_eq!(ExitStatus::Signaled(Signal::SEGV).wait_status(), 11 | 0x80);
+
+    // Kernel threads have no signal state
+    assert_eq!(signal::set_action(Signal::INT, Action::Ignore), Err(ProcessError::NotProcess));
+    assert_eq!(signal::kill(Pid::from_u64(u64::MAX), Signal::TERM), Err(ProcessError::NoProcess));
+}
+
+#[test_case]
+fn test_default_actions() {
+    let before = memory::frames_in_use();
+    let spin = [0xEB, 0xFE];                // jmp $
+
+    let pid = process::spawn(loader::load(&executable(&spin, &[]), &["spin"], &[]).unwrap(), "spin");
+    signal::kill(pid, Signal::TERM).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Signaled(Signal::TERM))));
+
+    // Stopped processes only go on after SIGCONT, but SIGKILL ends them
+    let pid = process::spawn(loader::load(&executable(&spin, &[]), &["spin"], &[]).unwrap(), "spin");
+    signal::kill(pid, Signal::STOP).unwrap();
+    wait_stopped(pid, true);
+    signal::kill(pid, Signal::CONT).unwrap();
+    wait_stopped(pid, false);
+    signal::kill(pid, Signal::TSTP).unwrap();
+    wait_stopped(pid, true);
+    signal::kill(pid, Signal::KILL).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Signaled(Signal::KILL))));
+    assert_eq!(memory::frames_in_use(), before);
+}
+
+#[test_case]
+fn test_divide_error() {
+    let code = [
+        0x31, 0xC9,                         // xor ecx, ecx
+        0xF7, 0xF1                          // div ecx
+    ];
+
+    let status = run(&code, &[]);
+    assert_eq!(status, ExitStatus::Signaled(Signal::FPE));
+    assert_eq!(status.wait_status(), 8 | 0x80);
+}
+
+#[test_case]
+fn test_handler_and_mask() {
+    // Catches SIGUSR1, blocks it and sends it to itself, then unblocks it. Exits with the number
+    // of handler calls plus 8 times the calls while it was blocked, plus 16 times `rbx`, which
+    // the handler changes.
+    let main = [
+        0x48, 0xBB, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rbx, DATA
+        0xBF, 0x0A, 0x00, 0x00, 0x00,       // mov edi, 10 (SIGUSR1)
+        0x48, 0x89, 0xDE,                   // mov rsi, rbx
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x14, 0x00, 0x00, 0x00,       // mov eax, 20 (sigaction)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x8D, 0x73, 0x20,             // lea rsi, [rbx + 32]
+        0xBF, 0x00, 0x00, 0x00, 0x00,       // mov edi, 0 (SIG_BLOCK)
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x15, 0x00, 0x00, 0x00,       // mov eax, 21 (sigprocmask)
+        0x0F, 0x05,                         // syscall
+        0xB8, 0x06, 0x00, 0x00, 0x00,       // mov eax, 6 (getpid)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0xBE, 0x0A, 0x00, 0x00, 0x00,       // mov esi, 10 (SIGUSR1)
+        0xBB, 0x07, 0x00, 0x00, 0x00,       // mov ebx, 7
+        0xB8, 0x13, 0x00, 0x00, 0x00,       // mov eax, 19 (kill)
+        0x0F, 0x05,                         // syscall
+        0x49, 0xBD, 0x40, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs r13, DATA + 64
+        0x45, 0x0F, 0xB6, 0x65, 0x00,       // movzx r12d, byte ptr [r13]
+        0xBF, 0x01, 0x00, 0x00, 0x00,       // mov edi, 1 (SIG_UNBLOCK)
+        0x48, 0xBE, 0x20, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA + 32
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x15, 0x00, 0x00, 0x00,       // mov eax, 21 (sigprocmask)
+        0x0F, 0x05,                         // syscall
+        0x41, 0x0F, 0xB6, 0x7D, 0x00,       // movzx edi, byte ptr [r13]
+        0x42, 0x8D, 0x3C, 0xE7,             // lea edi, [rdi + r12 * 8]
+        0xC1, 0xE3, 0x04,                   // shl ebx, 4
+        0x01, 0xDF,                         // add edi, ebx
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    // Counts its calls at `DATA + 64` and changes `rbx`
+    let handler = [
+        0x48, 0xB8, 0x40, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rax, DATA + 64
+        0xFE, 0x00,                         // inc byte ptr [rax]
+        0xBB, 0x01, 0x00, 0x00, 0x00,       // mov ebx, 1
+        0xC3                                // ret
+    ];
+
+    let mut data = sigaction(CODE + HANDLER as u64, CODE + RESTORER as u64, 0);
+    data.resize(65, 0);
+    data[32..40].copy_from_slice(&(1u64 << 10).to_le_bytes());
+    assert_eq!(run(&with_handler(&main, &handler), &data), ExitStatus::Exited(1 + 7 * 16));
+}
+
+#[test_case]
+fn test_caught_fault() {
+    // Catches SIGSEGV and writes to address 0, the handler exits with the signal number plus 100
+    let main = [
+        0x48, 0xBE, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA
+        0xBF, 0x0B, 0x00, 0x00, 0x00,       // mov edi, 11 (SIGSEGV)
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x14, 0x00, 0x00, 0x00,       // mov eax, 20 (sigaction)
+        0x0F, 0x05,                         // syscall
+        0x31, 0xC0,                         // xor eax, eax
+        0xC6, 0x00, 0x01,                   // mov byte ptr [rax], 1
+        0x0F, 0x0B                          // ud2
+    ];
+
+    let handler = [
+        0x83, 0xC7, 0x64,                   // add edi, 100
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let data = sigaction(CODE + HANDLER as u64, CODE + RESTORER as u64, 0);
+    assert_eq!(run(&with_handler(&main, &handler), &data), ExitStatus::Exited(111));
+
+    // Ignoring it doesn't help, the fault ends the process
+    let data = sigaction(1, 0, 0);
+    assert_eq!(run(&with_handler(&main, &handler), &data), ExitStatus::Signaled(Signal::SEGV));
+}
+
+#[test_case]
+fn test_ignored_and_uncatchable() {
+    // Ignores SIGINT, tries to ignore SIGKILL too and exits with the negated error of that after
+    // sending SIGINT to itself
+    let code = [
+        0x48, 0xBE, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA
+        0xBF, 0x02, 0x00, 0x00, 0x00,       // mov edi, 2 (SIGINT)
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x14, 0x00, 0x00, 0x00,       // mov eax, 20 (sigaction)
+        0x0F, 0x05,                         // syscall
+        0x48, 0xBE, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA
+        0xBF, 0x09, 0x00, 0x00, 0x00,       // mov edi, 9 (SIGKILL)
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x14, 0x00, 0x00, 0x00,       // mov eax, 20 (sigaction)
+        0x0F, 0x05,                         // syscall
+        0x48, 0xF7, 0xD8,                   // neg rax
+        0x49, 0x89, 0xC4,                   // mov r12, rax
+        0xB8, 0x06, 0x00, 0x00, 0x00,       // mov eax, 6 (getpid)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0xBE, 0x02, 0x00, 0x00, 0x00,       // mov esi, 2 (SIGINT)
+        0xB8, 0x13, 0x00, 0x00, 0x00,       // mov eax, 19 (kill)
+        0x0F, 0x05,                         // syscall
+        0x4C, 0x89, 0xE7,                   // mov rdi, r12
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    assert_eq!(run(&code, &sigaction(1, 0, 0)), ExitStatus::Exited(Error::InvalidArgument as i32));
+}
+
+#[test_case]
+fn test_handler_outside_user_memory() {
+    // Tries to catch SIGINT with a non-canonical handler and exits with the negated error after
+    // sending SIGINT to itself
+    let code = [
+        0x48, 0xBE, 0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // movabs rsi, DATA
+        0xBF, 0x02, 0x00, 0x00, 0x00,       // mov edi, 2 (SIGINT)
+        0x31, 0xD2,                         // xor edx, edx
+        0xB8, 0x14, 0x00, 0x00, 0x00,       // mov eax, 20 (sigaction)
+        0x0F, 0x05,                         // syscall
+        0x48, 0xF7, 0xD8,                   // neg rax
+        0x49, 0x89, 0xC4,                   // mov r12, rax
+        0xB8, 0x06, 0x00, 0x00, 0x00,       // mov eax, 6 (getpid)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0xC7,                   // mov rdi, rax
+        0xBE, 0x02, 0x00, 0x00, 0x00,       // mov esi, 2 (SIGINT)
+        0xB8, 0x13, 0x00, 0x00, 0x00,       // mov eax, 19 (kill)
+        0x0F, 0x05,                         // syscall
+        0x4C, 0x89, 0xE7,                   // mov rdi, r12
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let data = sigaction(0x8000_0000_0000_0000, CODE + RESTORER as u64, 0);
+    assert_eq!(run(&code, &data), ExitStatus::Exited(Error::BadAddress as i32));
+}
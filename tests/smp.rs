@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{collections::BTreeSet, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, acpi, allocator, smp, thread};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_madt() {
+    let madt = acpi::find_madt().unwrap();
+    assert_eq!(madt.processors.len(), 4);
+    assert!(madt.processors.iter().any(|processor| processor.apic_id as u32 == smp::current().apic_id()));
+}
+
+#[test_case]
+fn test_all_cpus_online() {
+    // QEMU is started with -smp 4
+    assert_eq!(smp::cpu_count(), 4);
+    assert_eq!(smp::cpu_index(), 0);
+    assert_eq!(thread::list().iter().filter(|info| info.name == "idle").count(), 4);
+}
+
+#[test_case]
+fn test_threads_run_on_every_cpu() {
+    let seen = Arc::new(Mutex::new(BTreeSet::new()));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let handles: Vec<_> = (0..smp::cpu_count() * 2).map(|_| {
+        let (seen, stop) = (seen.clone(), stop.clone());
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                seen.lock().insert(smp::cpu_index());
+                core::hint::spin_loop();
+            }
+        })
+    }).collect();
+
+    thread::sleep(100);
+    let running = thread::list().iter().filter(|info| info.cpu.is_some() && info.name != "idle").count();
+    stop.store(true, Ordering::Relaxed);
+
+    for handle in handles {
+        handle.join();
+    }
+
+    assert_eq!(seen.lock().len(), smp::cpu_count());
+    assert!(running >= smp::cpu_count() - 1, "only {} threads running at once", running);
+}
+
+#[test_case]
+fn test_cross_cpu_wakeups() {
+    // Two threads hand a token back and forth, each wakes the other wherever it runs
+    let turn = Arc::new(AtomicUsize::new(0));
+    let players = Arc::new(Mutex::new([None, None]));
+
+    let handles: Vec<_> = (0..2).map(|player| {
+        let (turn, players) = (turn.clone(), players.clone());
+
+        thread::spawn(move || {
+            players.lock()[player] = thread::current();
+
+            while turn.load(Ordering::SeqCst) < 200 {
+                if turn.load(Ordering::SeqCst) % 2 == player {
+                    turn.fetch_add(1, Ordering::SeqCst);
+
+                    if let Some(other) = players.lock()[1 - player] {
+                        thread::unpark(other);
+                    }
+                } else {
+                    thread::park();
+                }
+            }
+
+            if let Some(other) = players.lock()[1 - player] {
+                thread::unpark(other);
+            }
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join();
+    }
+
+    assert!(turn.load(Ordering::SeqCst) >= 200);
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use ruin::{sync::SpinLock, serial::COM1};
+use x86_64::instructions::interrupts;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_interrupts_restored() {
+    let (outer, inner) = (SpinLock::new(1), SpinLock::new(2));
+    assert!(interrupts::are_enabled());
+
+    {
+        let outer = outer.lock();
+        assert!(!interrupts::are_enabled());
+
+        // Inner guard must not enable interrupts while the outer one is held
+        {
+            let inner = inner.lock();
+            assert_eq!(*outer + *inner, 3);
+        }
+
+        assert!(!interrupts::are_enabled());
+    }
+
+    assert!(interrupts::are_enabled());
+
+    // Interrupts stay disabled when they were disabled before locking
+    interrupts::without_interrupts(|| {
+        drop(outer.lock());
+        assert!(!interrupts::are_enabled());
+    });
+}
+
+#[test_case]
+fn test_try_lock() {
+    let lock = SpinLock::new(0);
+    let guard = lock.lock();
+    assert!(lock.try_lock().is_none());
+    drop(guard);
+    assert!(!lock.is_locked());
+    assert!(lock.try_lock().is_some());
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_holder() {
+    let lock = SpinLock::new(());
+    assert!(lock.holder().is_none());
+    let guard = lock.lock();
+    let line = line!() - 1;
+
+    if cfg!(debug_assertions) {
+        let holder = lock.holder().unwrap();
+        assert_eq!(holder.cpu, 0);
+        assert_eq!((holder.location.file(), holder.location.line()), (file!(), line));
+    }
+
+    drop(guard);
+    assert!(lock.holder().is_none());
+}
+
+#[test_case]
+fn test_force_unlock() {
+    let guard = COM1.lock();
+    core::mem::forget(guard);
+    assert!(COM1.is_locked());
+
+    unsafe { ruin::serial::force_unlock() };
+    assert!(!COM1.is_locked());
+    // Forgotten guard didn't enable interrupts again
+    interrupts::enable();
+}
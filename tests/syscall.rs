@@ -0,0 +1,224 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, smp, syscall::{self, Error}, thread::{self, ThreadId}, timer, usermode};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Each test gets its own code, data and stack page from here
+const USER_BASE: u64 = 0x5100_0000_0000;
+const PAGE_SIZE: u64 = 4096;
+
+/// Maps three user pages at `base` and copies `code` there. Programs start with
+/// `movabs rbx, data`, which is patched to the second page. Returns the data page.
+fn load(base: u64, code: &[u8]) -> *mut u64 {
+    memory::map_user(VirtAddr::new(base), 3 * PAGE_SIZE, PageTableFlags::WRITABLE).unwrap();
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(code.as_ptr(), base as *mut u8, code.len());
+        ((base + 2) as *mut u64).write_unaligned(base + PAGE_SIZE);
+    }
+
+    (base + PAGE_SIZE) as *mut u64
+}
+
+/// Runs user code at `base` on a new thread and waits until it exits
+fn run(base: u64) -> ThreadId {
+    let handle = thread::spawn(move || usermode::enter(VirtAddr::new(base), VirtAddr::new(base + 3 * PAGE_SIZE)));
+    let id = handle.id();
+
+    for _ in 0..200 {
+        if handle.is_finished() {
+            return id;
+        }
+
+        thread::sleep(10);
+    }
+
+    panic!("User thread is still running");
+}
+
+fn result(data: *mut u64, index: usize) -> u64 {
+    unsafe { data.add(index).read_volatile() }
+}
+
+#[test_case]
+fn test_write() {
+    let code = [
+        0x48, 0xBB, 0, 0, 0, 0, 0, 0, 0, 0, // movabs rbx, data
+        0x49, 0xC7, 0xC4, 0x34, 0x12, 0x00, 0x00, // mov r12, 0x1234
+        0xBF, 0x01, 0x00, 0x00, 0x00,       // mov edi, 1
+        0x48, 0x8D, 0x73, 0x20,             // lea rsi, [rbx + 32]
+        0xBA, 0x06, 0x00, 0x00, 0x00,       // mov edx, 6
+        0x31, 0xC0,                         // xor eax, eax (write)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x03,                   // mov [rbx], rax
+        0x4C, 0x89, 0x63, 0x08,             // mov [rbx + 8], r12
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let base = USER_BASE;
+    let data = load(base, &code);
+    unsafe { core::ptr::copy_nonoverlapping(b"Hello\n".as_ptr(), data.add(4) as *mut u8, 6) };
+    run(base);
+    assert_eq!(result(data, 0), 6);
+    // Callee-saved registers survive the call
+    assert_eq!(result(data, 1), 0x1234);
+}
+
+#[test_case]
+fn test_errors() {
+    let code = [
+        0x48, 0xBB, 0, 0, 0, 0, 0, 0, 0, 0, // movabs rbx, data
+        0xBF, 0x01, 0x00, 0x00, 0x00,       // mov edi, 1
+        0x48, 0xBE, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0xFF, 0xFF, // movabs rsi, 0xFFFF800000000000
+        0xBA, 0x04, 0x00, 0x00, 0x00,       // mov edx, 4
+        0x31, 0xC0,                         // xor eax, eax (write)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x03,                   // mov [rbx], rax
+        0xBF, 0x01, 0x00, 0x00, 0x00,       // mov edi, 1
+        0x48, 0xBE, 0x00, 0x00, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, // movabs rsi, 0x700000000000
+        0xBA, 0x04, 0x00, 0x00, 0x00,       // mov edx, 4
+        0x31, 0xC0,                         // xor eax, eax (write)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x08,             // mov [rbx + 8], rax
+        0xBF, 0x07, 0x00, 0x00, 0x00,       // mov edi, 7
+        0x48, 0x8D, 0x73, 0x20,             // lea rsi, [rbx + 32]
+        0xBA, 0x04, 0x00, 0x00, 0x00,       // mov edx, 4
+        0x31, 0xC0,                         // xor eax, eax (write)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x10,             // mov [rbx + 16], rax
+        0xB8, 0xE8, 0x03, 0x00, 0x00,       // mov eax, 1000
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x18,             // mov [rbx + 24], rax
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let base = USER_BASE + 0x10000;
+    let data = load(base, &code);
+    run(base);
+    // Kernel memory and unmapped memory
+    assert_eq!(result(data, 0) as i64, Error::BadAddress.code());
+    assert_eq!(result(data, 1) as i64, Error::BadAddress.code());
+    assert_eq!(result(data, 2) as i64, Error::BadDescriptor.code());
+    assert_eq!(result(data, 3) as i64, Error::InvalidSyscall.code());
+}
+
+#[test_case]
+fn test_interrupt_fallback() {
+    let code = [
+        0x48, 0xBB, 0, 0, 0, 0, 0, 0, 0, 0, // movabs rbx, data
+        0xB8, 0x06, 0x00, 0x00, 0x00,       // mov eax, 6 (getpid)
+        0xCD, 0x80,                         // int 0x80
+        0x48, 0x89, 0x03,                   // mov [rbx], rax
+        0xB8, 0x06, 0x00, 0x00, 0x00,       // mov eax, 6 (getpid)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x08,             // mov [rbx + 8], rax
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0xCD, 0x80                          // int 0x80
+    ];
+
+    let base = USER_BASE + 0x20000;
+    let data = load(base, &code);
+    let id = run(base);
+    assert_eq!(result(data, 0), id.as_u64());
+    assert_eq!(result(data, 1), id.as_u64());
+}
+
+#[test_case]
+fn test_mmap() {
+    let code = [
+        0x48, 0xBB, 0, 0, 0, 0, 0, 0, 0, 0, // movabs rbx, data
+        0x31, 0xFF,                         // xor edi, edi
+        0xBE, 0x00, 0x20, 0x00, 0x00,       // mov esi, 8192
+        0xBA, 0x01, 0x00, 0x00, 0x00,       // mov edx, 1 (writable)
+        0xB8, 0x05, 0x00, 0x00, 0x00,       // mov eax, 5 (mmap)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x03,                   // mov [rbx], rax
+        0x48, 0xC7, 0x80, 0x00, 0x10, 0x00, 0x00, 0x2A, 0x00, 0x00, 0x00, // mov qword ptr [rax + 4096], 42
+        0x31, 0xFF,                         // xor edi, edi
+        0xBE, 0x00, 0x10, 0x00, 0x00,       // mov esi, 4096
+        0xBA, 0x04, 0x00, 0x00, 0x00,       // mov edx, 4 (unknown flag)
+        0xB8, 0x05, 0x00, 0x00, 0x00,       // mov eax, 5 (mmap)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x08,             // mov [rbx + 8], rax
+        0x48, 0xBF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x00, 0x00, // movabs rdi, 0x6000_0000_0000
+        0xBE, 0x00, 0x10, 0x00, 0x00,       // mov esi, 4096
+        0xBA, 0x01, 0x00, 0x00, 0x00,       // mov edx, 1 (writable)
+        0xB8, 0x05, 0x00, 0x00, 0x00,       // mov eax, 5 (mmap)
+        0x0F, 0x05,                         // syscall
+        0x48, 0x89, 0x43, 0x10,             // mov [rbx + 16], rax
+        0xB8, 0x02, 0x00, 0x00, 0x00,       // mov eax, 2 (exit)
+        0x0F, 0x05                          // syscall
+    ];
+
+    let base = USER_BASE + 0x30000;
+    let data = load(base, &code);
+    run(base);
+    let address = result(data, 0);
+    assert_eq!(address % PAGE_SIZE, 0);
+    assert!(address < usermode::USER_END);
+    assert_eq!(unsafe { ((address + PAGE_SIZE) as *const u64).read_volatile() }, 42);
+    assert_eq!(result(data, 1) as i64, Error::InvalidArgument.code());
+    // Without a process the address can't be chosen
+    assert_eq!(result(data, 2) as i64, Error::InvalidArgument.code());
+}
+
+#[test_case]
+fn test_sleep() {
+    let code = [
+        0xB8, 0x03, 0x00, 0x00, 0x00, // mov eax, 3 (yield)
+        0x0F, 0x05,                   // syscall
+        0xBF, 0x64, 0x00, 0x00, 0x00, // mov edi, 100
+        0xB8, 0x04, 0x00, 0x00, 0x00, // mov eax, 4 (sleep)
+        0x0F, 0x05,                   // syscall
+        0xB8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0F, 0x05                    // syscall
+    ];
+
+    let base = USER_BASE + 0x40000;
+    memory::map_user(VirtAddr::new(base), 3 * PAGE_SIZE, PageTableFlags::WRITABLE).unwrap();
+    unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), base as *mut u8, code.len()) };
+    let start = timer::uptime_ms();
+    run(base);
+    assert!(timer::uptime_ms() - start >= 100);
+}
+
+#[test_case]
+fn test_user_pointers() {
+    let base = USER_BASE + 0x50000;
+    memory::map_user(VirtAddr::new(base), PAGE_SIZE, PageTableFlags::empty()).unwrap();
+    assert!(syscall::user::check(base, PAGE_SIZE, false).is_ok());
+    assert_eq!(syscall::user::check(base, PAGE_SIZE, true), Err(Error::BadAddress));
+    assert_eq!(syscall::user::check(base, PAGE_SIZE + 1, false), Err(Error::BadAddress));
+    assert_eq!(syscall::user::check(u64::MAX, 2, false), Err(Error::BadAddress));
+    assert_eq!(syscall::user::read::<u64>(base), Ok(0));
+}
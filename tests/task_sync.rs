@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, smp, thread, task::{self, executor, sync::{mpsc, oneshot, AsyncMutex, Notify, RwLock, Semaphore, SpinLock}}};
+use x86_64::{instructions::interrupts, VirtAddr};
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    executor::global().start(smp::cpu_count(), thread::Priority::Normal);
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_spinlock_disables_interrupts() {
+    let lock = SpinLock::new(0);
+    assert!(interrupts::are_enabled());
+
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+    }
+
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test_case]
+fn test_async_mutex() {
+    let mutex = Arc::new(AsyncMutex::new(0));
+
+    let handles: Vec<_> = (0..50).map(|_| {
+        let mutex = mutex.clone();
+
+        task::spawn(async move {
+            let mut guard = mutex.lock().await;
+            let value = *guard;
+            // Others can't get in while the guard is held across an await
+            task::yield_now().await;
+            *guard = value + 1;
+        })
+    }).collect();
+
+    task::block_on(task::join_all(handles));
+    assert_eq!(*mutex.try_lock().unwrap(), 50);
+}
+
+#[test_case]
+fn test_rwlock() {
+    let lock = RwLock::new(1);
+
+    task::block_on(async {
+        let first = lock.read().await;
+        let second = lock.read().await;
+        assert!(lock.try_write().is_none());
+        assert_eq!(*first + *second, 2);
+        drop((first, second));
+
+        *lock.write().await += 1;
+        let writer = lock.try_write().unwrap();
+        assert!(lock.try_read().is_none());
+        drop(writer);
+
+        assert_eq!(*lock.read().await, 2);
+    });
+}
+
+#[test_case]
+fn test_semaphore() {
+    let semaphore = Arc::new(Semaphore::new(2));
+    let (running, most) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+    let handles: Vec<_> = (0..20).map(|_| {
+        let (semaphore, running, most) = (semaphore.clone(), running.clone(), most.clone());
+
+        task::spawn(async move {
+            let _permit = semaphore.acquire().await;
+            most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            task::yield_now().await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        })
+    }).collect();
+
+    task::block_on(task::join_all(handles));
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[test_case]
+fn test_bounded_channel() {
+    let (sender, mut receiver) = mpsc::channel(2);
+
+    let producers: Vec<_> = (0..4).map(|producer| {
+        let sender = sender.clone();
+
+        task::spawn(async move {
+            for i in 0..25 {
+                sender.send(producer * 25 + i).await.unwrap();
+            }
+        })
+    }).collect();
+
+    drop(sender);
+
+    let received = task::block_on(async move {
+        let mut received = Vec::new();
+
+        while let Some(value) = receiver.recv().await {
+            received.push(value);
+        }
+
+        received
+    });
+
+    task::block_on(task::join_all(producers));
+    assert_eq!(received.len(), 100);
+    assert_eq!(received.iter().sum::<usize>(), (0..100).sum());
+}
+
+#[test_case]
+fn test_unbounded_channel() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    assert_eq!(receiver.try_recv(), Err(mpsc::TryRecvError::Empty));
+
+    for i in 0..100 {
+        sender.send(i).unwrap();
+    }
+
+    assert_eq!(receiver.try_recv(), Ok(0));
+    drop(receiver);
+    assert!(sender.is_closed());
+    assert_eq!(sender.send(1), Err(mpsc::SendError(1)));
+}
+
+#[test_case]
+fn test_oneshot() {
+    let (sender, receiver) = oneshot::channel();
+    task::spawn(async move { sender.send("reply").unwrap() });
+    assert_eq!(task::block_on(receiver), Ok("reply"));
+
+    let (sender, receiver) = oneshot::channel::<()>();
+    drop(sender);
+    assert_eq!(task::block_on(receiver), Err(oneshot::RecvError));
+}
+
+#[test_case]
+fn test_notify() {
+    let notify = Arc::new(Notify::new());
+
+    // Permit is stored when nobody waits
+    notify.notify_one();
+    task::block_on(notify.notified());
+
+    let woken = Arc::new(AtomicUsize::new(0));
+
+    let waiters: Vec<_> = (0..4).map(|_| {
+        let (notify, woken) = (notify.clone(), woken.clone());
+
+        task::spawn(async move {
+            notify.notified().await;
+            woken.fetch_add(1, Ordering::SeqCst);
+        })
+    }).collect();
+
+    thread::sleep(10);
+    assert_eq!(woken.load(Ordering::SeqCst), 0);
+    notify.notify_waiters();
+    task::block_on(task::join_all(waiters));
+    assert_eq!(woken.load(Ordering::SeqCst), 4);
+}
@@ -0,0 +1,257 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{future::Future, pin::Pin, sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, task::{Context, Poll, Waker}};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, thread, timer, task::Executor};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    thread::init();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_spawn_join() {
+    let handles: Vec<_> = (0..8u64).map(|i| thread::spawn(move || i * i)).collect();
+    let results: Vec<u64> = handles.into_iter().map(|handle| handle.join()).collect();
+    assert_eq!(results, [0, 1, 4, 9, 16, 25, 36, 49]);
+}
+
+#[test_case]
+fn test_yield_interleaves() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    let handles: Vec<_> = (0..2).map(|id| {
+        let log = log.clone();
+        thread::spawn(move || {
+            for _ in 0..3 {
+                log.lock().push(id);
+                thread::yield_now();
+            }
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join();
+    }
+
+    // Yielding hands the CPU to the other thread, so neither can run all iterations in a row
+    let log = log.lock();
+    assert_eq!(log.len(), 6);
+    assert!(log.iter().position(|&id| id == 1) < log.iter().rposition(|&id| id == 0));
+}
+
+#[test_case]
+fn test_preemption() {
+    // Spinning thread never yields, only the timer can let the main thread run again
+    let stop = Arc::new(AtomicBool::new(false));
+    let spins = Arc::new(AtomicU64::new(0));
+    let (thread_stop, thread_spins) = (stop.clone(), spins.clone());
+
+    let spinner = thread::Builder::new().name("spinner").spawn(move || {
+        while !thread_stop.load(Ordering::Relaxed) {
+            thread_spins.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+
+    let start = timer::ticks();
+
+    while spins.load(Ordering::Relaxed) == 0 {
+        core::hint::spin_loop();
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    spinner.join();
+    assert!(timer::ticks() > start);
+}
+
+#[test_case]
+fn test_sleep() {
+    let start = timer::uptime_ms();
+    let sleeper = thread::spawn(|| {
+        thread::sleep(50);
+        timer::uptime_ms()
+    });
+
+    thread::sleep(20);
+    assert!(!sleeper.is_finished());
+    assert!(sleeper.join() >= start + 50);
+}
+
+#[test_case]
+fn test_park_unpark() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let thread_counter = counter.clone();
+
+    let parked = thread::spawn(move || {
+        thread::park();
+        thread_counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    thread::sleep(10);
+    assert_eq!(counter.load(Ordering::SeqCst), 0);
+    thread::unpark(parked.id());
+    parked.join();
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // Token left by early unpark makes next park return immediately
+    thread::unpark(thread::current().unwrap());
+    thread::park();
+}
+
+#[test_case]
+fn test_thread_list() {
+    let handle = thread::Builder::new().name("listed").spawn(|| thread::sleep(20));
+    assert!(thread::list().iter().any(|info| info.name == "listed" && info.id == handle.id()));
+    handle.join();
+    assert!(!thread::list().iter().any(|info| info.name == "listed"));
+}
+
+fn spinner(priority: thread::Priority, stop: &Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    let stop = stop.clone();
+    thread::Builder::new().name("spinner").priority(priority).spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            core::hint::spin_loop();
+        }
+    })
+}
+
+#[test_case]
+fn test_priority_share() {
+    let stop = Arc::new(AtomicBool::new(false));
+    let high = spinner(thread::Priority::High, &stop);
+    let low = spinner(thread::Priority::Low, &stop);
+    thread::sleep(300);
+
+    let high_time = thread::info(high.id()).unwrap().cpu_time;
+    let low_time = thread::info(low.id()).unwrap().cpu_time;
+    stop.store(true, Ordering::Relaxed);
+    high.join();
+    low.join();
+
+    // Weights differ 16 times, leave room for rounding to whole ticks
+    assert!(high_time > low_time * 8, "high {} ms, low {} ms", high_time, low_time);
+    assert!(low_time > 0, "low priority thread starved");
+}
+
+#[test_case]
+fn test_accounting() {
+    let busy = thread::Builder::new().name("busy").spawn(|| {
+        let end = timer::uptime_ms() + 30;
+
+        while timer::uptime_ms() < end {
+            core::hint::spin_loop();
+        }
+
+        thread::sleep(100);
+    });
+
+    thread::sleep(50);
+    let info = thread::info(busy.id()).unwrap();
+    assert_eq!(info.state, thread::ThreadState::Sleeping);
+    assert!(info.cpu_time >= 20 && info.cpu_time <= 50, "cpu time {} ms", info.cpu_time);
+    assert!(info.context_switches >= 1);
+    busy.join();
+
+    let idle = thread::list().into_iter().find(|info| info.name == "idle").unwrap();
+    assert!(idle.cpu_time > 0);
+}
+
+#[test_case]
+fn test_sleeper_responsive_under_load() {
+    let stop = Arc::new(AtomicBool::new(false));
+    let load: Vec<_> = (0..3).map(|_| spinner(thread::Priority::Normal, &stop)).collect();
+
+    let interactive = thread::spawn(|| {
+        let mut worst = 0;
+
+        for _ in 0..10 {
+            let expected = timer::uptime_ms() + 5;
+            thread::sleep(5);
+            worst = worst.max(timer::uptime_ms() - expected);
+        }
+
+        worst
+    });
+
+    let worst = interactive.join();
+    stop.store(true, Ordering::Relaxed);
+
+    for handle in load {
+        handle.join();
+    }
+
+    // Woken thread is behind the spinners in virtual runtime, so it preempts them on the next tick
+    assert!(worst <= 3, "woke up {} ms late", worst);
+}
+
+/// Future completed by another thread through its waker
+struct Signal {
+    state: Mutex<(bool, Option<Waker>)>
+}
+
+impl Future for &Signal {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let mut state = self.state.lock();
+
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(context.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[test_case]
+fn test_executor_thread() {
+    static SIGNAL: Signal = Signal { state: Mutex::new((false, None)) };
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    thread::Builder::new().name("executor").spawn(|| {
+        let executor = Executor::new();
+        executor.spawn(async {
+            (&SIGNAL).await;
+            DONE.store(true, Ordering::SeqCst);
+        });
+        executor.run();
+    });
+
+    thread::sleep(20);
+    assert!(!DONE.load(Ordering::SeqCst));
+
+    let waker = {
+        let mut state = SIGNAL.state.lock();
+        state.0 = true;
+        state.1.take()
+    };
+
+    waker.expect("Executor didn't poll the task").wake();
+    thread::sleep(20);
+    assert!(DONE.load(Ordering::SeqCst));
+}
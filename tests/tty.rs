@@ -0,0 +1,193 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use ruin::{console::{self, Decoder}, memory::{self, MemoryMapFrameAllocator}, allocator, fs::{pipe, FsError, OpenFlags}, tty::{LineDiscipline, Mode, Received}, vga::{self, ColorCode, BUFFER_WIDTH}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Feeds `input` to `discipline` in `mode`, returns the echo
+fn feed(discipline: &mut LineDiscipline, mode: Mode, input: &[u8]) -> Vec<u8> {
+    let mut echo = Vec::new();
+
+    for &byte in input {
+        discipline.receive(mode, Received::Byte(byte), &mut echo);
+    }
+
+    echo
+}
+
+/// Text of `row` of console `index`'s screen
+fn screen_row(index: usize, row: usize) -> Vec<u8> {
+    let writer = vga::writer(index).lock();
+    writer.buffer.chars[row].iter().map(|char| char.character).collect()
+}
+
+#[test_case]
+fn test_canonical() {
+    let mut discipline = LineDiscipline::new();
+    let mut buf = [0u8; 64];
+
+    // Erase, then a line ended with `\r\n` that is only one line
+    let echo = feed(&mut discipline, Mode::DEFAULT, b"helo\x7F\x7Flo\r\n");
+    assert_eq!(echo, b"helo\x08 \x08\x08 \x08lo\n");
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf), Some(5));
+    assert_eq!(&buf[..5], b"helo\n");
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf), None);
+
+    // Kill erases control characters shown as two, Ctrl+D ends a line without a newline and
+    // then the input
+    let echo = feed(&mut discipline, Mode::DEFAULT, b"x\x1B\x15ab\x04\x04a\n");
+    assert_eq!(echo, b"x^[\x08 \x08\x08 \x08\x08 \x08aba\n");
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf), Some(2));
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf), Some(0));
+
+    // A line is read a piece at a time
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf[..1]), Some(1));
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf), Some(1));
+    assert_eq!(buf[0], b'\n');
+
+    // Ctrl+C that sent a signal drops the line
+    let mut echo = feed(&mut discipline, Mode::DEFAULT, b"abc");
+    discipline.receive(Mode::DEFAULT, Received::Signal(0x03), &mut echo);
+    assert_eq!(echo, b"abc^C\n");
+    assert_eq!(discipline.read(Mode::DEFAULT, &mut buf), None);
+}
+
+#[test_case]
+fn test_raw() {
+    let mut discipline = LineDiscipline::new();
+    let mut buf = [0u8; 64];
+
+    // Line being edited is kept when leaving canonical mode
+    feed(&mut discipline, Mode::DEFAULT, b"par");
+    discipline.flush_line();
+    let echo = feed(&mut discipline, Mode::RAW, b"t\r\x7F\x03");
+    assert!(echo.is_empty());
+    assert_eq!(discipline.read(Mode::RAW, &mut buf), Some(7));
+    assert_eq!(&buf[..7], b"part\r\x7F\x03");
+
+    // Echo without lines
+    let echo = feed(&mut discipline, Mode::ECHO, b"a\x01");
+    assert_eq!(echo, b"a^A");
+}
+
+#[test_case]
+fn test_console() {
+    let terminal = console::open_virtual(2, OpenFlags::READ_WRITE | OpenFlags::NONBLOCK);
+    let mut buf = [0u8; 16];
+    assert_eq!(terminal.path(), "/dev/tty3");
+    assert_eq!(terminal.terminal_mode(), Ok(Mode::DEFAULT));
+
+    // Input is echoed to the console's screen once it's read
+    console::push_input(2, b"hi\x7Fo");
+    assert_eq!(terminal.read(&mut buf), Err(FsError::WouldBlock));
+    console::push_input(2, b"\n");
+    assert_eq!(terminal.read(&mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"ho\n");
+    let (row, _) = vga::writer(2).lock().position();
+    assert_eq!(&screen_row(2, row - 1)[..3], b"ho ");
+
+    assert_eq!(terminal.set_terminal_mode(Mode::RAW), Ok(Mode::DEFAULT));
+    console::push_input(2, b"\x1B[A");
+    assert_eq!(terminal.read(&mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"\x1B[A");
+
+    // Whether Ctrl+C is a signal is decided as it arrives, not when it's read
+    console::push_input(2, b"\x03");
+    assert_eq!(terminal.set_terminal_mode(Mode::DEFAULT), Ok(Mode::RAW));
+    console::push_input(2, b"\n");
+    assert_eq!(terminal.read(&mut buf), Ok(2));
+    assert_eq!(&buf[..2], b"\x03\n");
+    console::push_input(2, b"ab\x03");
+    assert_eq!(terminal.set_terminal_mode(Mode::RAW), Ok(Mode::DEFAULT));
+    assert_eq!(terminal.read(&mut buf), Ok(2));
+    assert_eq!(&buf[..2], b"ab");
+    assert_eq!(terminal.read(&mut buf), Err(FsError::WouldBlock));
+
+    let (reader, _writer) = pipe::pipe(OpenFlags(0));
+    assert_eq!(reader.set_terminal_mode(Mode::RAW), Err(FsError::NotTerminal));
+}
+
+#[test_case]
+fn test_escapes() {
+    let mut writer = vga::writer(4).lock();
+    writer.write_string("\x1B[2J\x1B[H");
+    assert_eq!(writer.position(), (0, 0));
+
+    writer.write_string("ab\x1B[5;10Hc\x1B[31;1mR\x1B[0m\x1B[2A\x1B[3D");
+    assert_eq!(writer.buffer.chars[4][9].character, b'c');
+    assert_eq!(writer.buffer.chars[4][10].character, b'R');
+    assert_eq!(writer.color(), ColorCode(0x07));
+    assert_eq!(writer.position(), (2, 8));
+
+    // Erase to the end of the line, blue background with bright white, then reversed
+    writer.write_string("\x1B[1;1H\x1B[K\x1B[44;97mx\x1B[7my");
+    assert_eq!(writer.buffer.chars[0][1].character, b'y');
+    assert_eq!(writer.buffer.chars[0][2].character, b' ');
+    assert_eq!(writer.color(), ColorCode(0xF1));
+
+    writer.write_string("\x1B[0m\x1B[?25l\x1B[2Jz");
+    assert!(writer.buffer.chars[4].iter().all(|char| char.character == b' '));
+    assert_eq!(writer.position(), (0, 3));
+}
+
+#[test_case]
+fn test_switch() {
+    vga::writer(1).lock().write_string("\x1B[2J\x1B[Hon the second console");
+    let first = screen_row(0, 0);
+
+    vga::show(1);
+    assert_eq!(vga::visible(), 1);
+    assert_eq!(&screen_row(1, 0)[..21], b"on the second console");
+    assert_eq!(screen_row(0, 0), first);
+
+    // Writing to a hidden console doesn't show
+    vga::writer(0).lock().write_string("\x1B[1;1Hhidden");
+    assert_eq!(&screen_row(1, 0)[..6], b"on the");
+
+    vga::show(0);
+    assert_eq!(&screen_row(0, 0)[..6], b"hidden");
+    assert_eq!(screen_row(0, 0).len(), BUFFER_WIDTH);
+    vga::writer(0).lock().write_string("\x1B[25;1H");
+}
+
+#[test_case]
+fn test_alt_function_keys() {
+    let mut decoder = Decoder::new();
+    let mut typed = Vec::new();
+
+    // F2 alone, then Alt+F3 and Alt+F9 with right alt
+    let switches: Vec<_> = [0x3C, 0xBC, 0x38, 0x3D, 0xBD, 0xB8, 0xE0, 0x38, 0x43, 0xE0, 0xB8]
+        .into_iter()
+        .filter_map(|scancode| decoder.decode(scancode, |byte| typed.push(byte)))
+        .collect();
+
+    assert_eq!(switches, [2]);
+    assert!(typed.is_empty());
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, ata_pio, block, partition, fs::{ext2::Ext2Fs, pipe, tmpfs::TmpFs, vfs, OpenFlags}, loader, process::{self, files::{FileTable, STDOUT}, ExitStatus}, serial_print, smp, thread};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+    vfs::mount("/", TmpFs::new()).unwrap();
+
+    // target/test-disk.img built by tests/images/build.sh has user/hello on its ext2 partition
+    assert!(ata_pio::probe() >= 2, "Test disk is not attached");
+    partition::scan("disk1").unwrap();
+    vfs::mkdir("/disk").unwrap();
+    vfs::mount("/disk", Ext2Fs::new(block::get("disk1p4").unwrap()).unwrap()).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_hello() {
+    let (reader, writer) = pipe::pipe(OpenFlags(0));
+    let mut files = FileTable::console();
+    files.insert_at(STDOUT, Arc::new(writer));
+
+    let program = loader::load_file("/disk/hello", &["hello", "world"], &[]).unwrap();
+    let pid = process::spawn_with_files(program, "hello", files);
+
+    // The pipe ends once the program and its child are gone
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    serial_print!("{}", output);
+
+    assert_eq!(process::wait(Some(pid)), Ok((pid, ExitStatus::Exited(0))));
+    assert_eq!(output, "Hello from hello world!\nSum of squares: 385\nHeap: 200000\nRead back: Written by hello\nChild exited with 7\n");
+    assert_eq!(vfs::read_to_end("/hello.txt").unwrap(), b"Written by hello\n");
+}
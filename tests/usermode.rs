@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, smp, thread, usermode};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    allocator::map_physical(&mut mapper, 0xE0000, 0x1FFFF).unwrap();
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    smp::init().unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+/// Each test gets its own code, data and stack page from here
+const USER_BASE: u64 = 0x5000_0000_0000;
+const PAGE_SIZE: u64 = 4096;
+
+/// Maps three user pages at `base` and copies `code` to the first one, `address` in `code`
+/// is replaced with `patch`. Returns the second page for data.
+fn load(base: u64, code: &[u8], patch: Option<u64>) -> *mut u64 {
+    memory::map_user(VirtAddr::new(base), 3 * PAGE_SIZE, PageTableFlags::WRITABLE).unwrap();
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(code.as_ptr(), base as *mut u8, code.len());
+
+        // `movabs rax, imm64` starts the programs that need an address
+        if let Some(address) = patch {
+            ((base + 2) as *mut u64).write_unaligned(address);
+        }
+    }
+
+    (base + PAGE_SIZE) as *mut u64
+}
+
+/// Runs user code at `base` on a new thread and waits until it's killed
+fn run(base: u64) {
+    let handle = thread::spawn(move || usermode::enter(VirtAddr::new(base), VirtAddr::new(base + 3 * PAGE_SIZE)));
+
+    for _ in 0..200 {
+        if handle.is_finished() {
+            return;
+        }
+
+        thread::sleep(10);
+    }
+
+    panic!("User thread is still running");
+}
+
+#[test_case]
+fn test_user_code_runs() {
+    // Counts to 50 000 000 in data page, long enough to be interrupted by timer, then faults with ud2
+    let code = [
+        0x48, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, // movabs rax, data
+        0x48, 0xFF, 0x00,                   // inc qword ptr [rax]
+        0x48, 0x81, 0x38, 0x80, 0xF0, 0xFA, 0x02, // cmp qword ptr [rax], 50000000
+        0x72, 0xF4,                         // jb inc
+        0x0F, 0x0B                          // ud2
+    ];
+
+    let base = USER_BASE;
+    let data = load(base, &code, Some(base + PAGE_SIZE));
+    run(base);
+    assert_eq!(unsafe { data.read_volatile() }, 50_000_000);
+}
+
+#[test_case]
+fn test_kernel_memory_protected() {
+    static TARGET: AtomicU64 = AtomicU64::new(0);
+
+    let code = [
+        0x48, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0,       // movabs rax, target
+        0x48, 0xC7, 0x00, 0x2A, 0x00, 0x00, 0x00, // mov qword ptr [rax], 42
+        0xEB, 0xFE                                // jmp $
+    ];
+
+    let base = USER_BASE + 0x10000;
+    load(base, &code, Some(&TARGET as *const AtomicU64 as u64));
+    run(base);
+    assert_eq!(TARGET.load(Ordering::SeqCst), 0);
+}
+
+#[test_case]
+fn test_privileged_instruction() {
+    let code = [
+        0xFA,      // cli
+        0xEB, 0xFE // jmp $
+    ];
+
+    let base = USER_BASE + 0x20000;
+    load(base, &code, None);
+    run(base);
+    assert!(x86_64::instructions::interrupts::are_enabled());
+}
+
+#[test_case]
+fn test_kernel_survives_faults() {
+    // Every processor is still scheduling after the faults above
+    let handles: [_; 8] = core::array::from_fn(|i| thread::spawn(move || i * 2));
+    assert_eq!(handles.map(|handle| handle.join()).iter().sum::<usize>(), 56);
+}
@@ -0,0 +1,358 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruin::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use ruin::{memory::{self, MemoryMapFrameAllocator}, allocator, fs::{initramfs, path, tmpfs::TmpFs, vfs, FileSystem, FileType, FsError, Inode, OpenFlags, SeekFrom, Stat}};
+use x86_64::VirtAddr;
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruin::panic_test(info);
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruin::init();
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { MemoryMapFrameAllocator::new(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+
+    test_main();
+
+    loop {}
+}
+
+entry_point!(main);
+
+#[test_case]
+fn test_normalize() {
+    assert_eq!(path::normalize("/"), "/");
+    assert_eq!(path::normalize("/a/./b//c/"), "/a/b/c");
+    assert_eq!(path::normalize("/a/b/../../.."), "/");
+    assert_eq!(path::normalize("/a/../b/./c/.."), "/b");
+}
+
+#[test_case]
+fn test_join() {
+    assert_eq!(path::join("/usr", "bin"), "/usr/bin");
+    assert_eq!(path::join("/", "bin"), "/bin");
+    assert_eq!(path::join("/usr", "/etc"), "/etc");
+}
+
+#[test_case]
+fn test_split_last() {
+    assert_eq!(path::split_last("/a/b"), Some(("/a", "b")));
+    assert_eq!(path::split_last("/a/b/"), Some(("/a", "b")));
+    assert_eq!(path::split_last("/a"), Some(("/", "a")));
+    assert_eq!(path::split_last("a"), Some((".", "a")));
+    assert_eq!(path::split_last("a/b"), Some(("a", "b")));
+    assert_eq!(path::split_last("/"), None);
+}
+
+#[test_case]
+fn test_no_root_mounted() {
+    assert_eq!(vfs::lookup("/").err(), Some(FsError::NotFound));
+    assert_eq!(vfs::open("/file", OpenFlags::READ).err(), Some(FsError::NotFound));
+}
+
+/// Read-only filesystem with a fixed tree, to test path resolution on its own
+struct StubFs {
+    root: Arc<dyn Inode>
+}
+
+impl FileSystem for StubFs {
+    fn name(&self) -> &'static str {
+        "stub"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+enum StubContent {
+    File,
+    Directory(Vec<(String, Arc<dyn Inode>)>),
+    Symlink(String)
+}
+
+struct StubInode {
+    number: u64,
+    content: StubContent
+}
+
+impl Inode for StubInode {
+    fn stat(&self) -> Result<Stat, FsError> {
+        let file_type = match self.content {
+            StubContent::File => FileType::Regular,
+            StubContent::Directory(_) => FileType::Directory,
+            StubContent::Symlink(_) => FileType::Symlink
+        };
+
+        Ok(Stat::new(self.number, file_type, 0))
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match &self.content {
+            StubContent::Directory(entries) => entries.iter().find(|(entry, _)| entry == name).map(|(_, inode)| inode.clone()).ok_or(FsError::NotFound),
+            _ => Err(FsError::NotDirectory)
+        }
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        match &self.content {
+            StubContent::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument)
+        }
+    }
+}
+
+fn stub(number: u64, content: StubContent) -> Arc<dyn Inode> {
+    Arc::new(StubInode { number, content })
+}
+
+fn stub_dir(number: u64, entries: Vec<(&str, Arc<dyn Inode>)>) -> Arc<dyn Inode> {
+    stub(number, StubContent::Directory(entries.into_iter().map(|(name, inode)| (name.to_string(), inode)).collect()))
+}
+
+fn stub_link(number: u64, target: &str) -> Arc<dyn Inode> {
+    stub(number, StubContent::Symlink(target.to_string()))
+}
+
+/// Canonical path and inode number that `target` resolves to from `base`
+fn resolved(base: &str, target: &str, follow_last: bool) -> Result<(String, u64), FsError> {
+    let dentry = vfs::resolve_at(base, target, follow_last)?;
+    Ok((dentry.path, dentry.inode.stat()?.inode))
+}
+
+#[test_case]
+fn test_resolve_at() {
+    // `/chain0` to `/chain40` each link to the next, the last one to `/dir`
+    let mut entries = vec![
+        ("mnt", stub_dir(2, vec![])),
+        ("dir", stub_dir(3, vec![("file", stub(4, StubContent::File))])),
+        ("link", stub_link(5, "dir")),
+        ("absolute", stub_link(6, "/link/file")),
+        ("loop", stub_link(7, "loop"))
+    ];
+    let names: Vec<String> = (0..=40).map(|index| format!("chain{}", index)).collect();
+
+    for (index, name) in names.iter().enumerate() {
+        let target = if index == 40 { String::from("dir") } else { format!("chain{}", index + 1) };
+        entries.push((name, stub_link(100 + index as u64, &target)));
+    }
+
+    let inner = stub_dir(2, vec![("file", stub(3, StubContent::File))]);
+    let mounted = stub_dir(1, vec![("inner", inner), ("out", stub_link(4, "../dir"))]);
+    vfs::mount("/", Arc::new(StubFs { root: stub_dir(1, entries) })).unwrap();
+
+    assert_eq!(resolved("/", "dir/./file", true), Ok((String::from("/dir/file"), 4)));
+    assert_eq!(resolved("/dir", "file", true), Ok((String::from("/dir/file"), 4)));
+    assert_eq!(resolved("/dir", "/dir/../../dir", true), Ok((String::from("/dir"), 3)));
+    assert_eq!(resolved("/", "dir/missing", true), Err(FsError::NotFound));
+    assert_eq!(resolved("/", "dir/file/", true), Err(FsError::NotDirectory));
+    assert_eq!(resolved("/", "", true), Err(FsError::InvalidPath));
+
+    // Symlinks in the middle are always followed, the last one if asked or with a trailing slash
+    assert_eq!(resolved("/", "link/file", false), Ok((String::from("/dir/file"), 4)));
+    assert_eq!(resolved("/", "absolute", true), Ok((String::from("/dir/file"), 4)));
+    assert_eq!(resolved("/", "link", false), Ok((String::from("/link"), 5)));
+    assert_eq!(resolved("/", "link/", false), Ok((String::from("/dir"), 3)));
+    assert_eq!(resolved("/", "link/..", true), Ok((String::from("/"), 1)));
+    assert_eq!(resolved("/", "loop", false), Ok((String::from("/loop"), 7)));
+    assert_eq!(resolved("/", "loop", true), Err(FsError::TooManyLinks));
+
+    // 40 symlinks in a row are followed, 41 are too many
+    assert_eq!(resolved("/", "chain1", true), Ok((String::from("/dir"), 3)));
+    assert_eq!(resolved("/", "chain0", true), Err(FsError::TooManyLinks));
+
+    assert_eq!(vfs::mount("mnt", Arc::new(StubFs { root: mounted.clone() })).err(), Some(FsError::InvalidPath));
+    assert_eq!(vfs::mount("/dir/file", Arc::new(StubFs { root: mounted.clone() })).err(), Some(FsError::NotDirectory));
+    vfs::mount("/link/../mnt", Arc::new(StubFs { root: mounted.clone() })).unwrap();
+    assert_eq!(vfs::mount("/mnt", Arc::new(StubFs { root: mounted })).err(), Some(FsError::Busy));
+    assert_eq!(vfs::mounts(), [(String::from("/"), "stub"), (String::from("/mnt"), "stub")]);
+
+    // The mount point is the root of the mounted filesystem, `..` from it goes back out
+    assert_eq!(resolved("/", "mnt", true), Ok((String::from("/mnt"), 1)));
+    assert_eq!(resolved("/", "mnt/inner/file", true), Ok((String::from("/mnt/inner/file"), 3)));
+    assert_eq!(resolved("/mnt/inner", "../../dir/file", true), Ok((String::from("/dir/file"), 4)));
+    assert_eq!(resolved("/mnt", "..", true), Ok((String::from("/"), 1)));
+    assert_eq!(resolved("/", "mnt/out/file", true), Ok((String::from("/dir/file"), 4)));
+
+    assert_eq!(vfs::unmount("/").err(), Some(FsError::Busy));
+    vfs::unmount("/mnt").unwrap();
+    assert_eq!(resolved("/", "mnt", true), Ok((String::from("/mnt"), 2)));
+    assert_eq!(resolved("/", "mnt/inner", true), Err(FsError::NotFound));
+    vfs::unmount("/").unwrap();
+    assert_eq!(vfs::lookup("/").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn test_tmpfs_root() {
+    vfs::mount("/", TmpFs::new()).unwrap();
+    assert_eq!(vfs::mount("/", TmpFs::new()).err(), Some(FsError::Busy));
+    assert_eq!(vfs::stat("/").unwrap().file_type, FileType::Directory);
+
+    vfs::mkdir("/etc").unwrap();
+    assert_eq!(vfs::mkdir("/etc").err(), Some(FsError::AlreadyExists));
+    vfs::write_all("/etc/hostname", b"ruin\n").unwrap();
+    assert_eq!(vfs::read_to_end("/etc/../etc/./hostname").unwrap(), b"ruin\n");
+    assert_eq!(vfs::stat("/etc/hostname").unwrap().size, 5);
+    assert_eq!(vfs::read_to_end("/etc/hostname/x").err(), Some(FsError::NotDirectory));
+    assert_eq!(vfs::open("/etc", OpenFlags::WRITE).err(), Some(FsError::IsDirectory));
+}
+
+#[test_case]
+fn test_tmpfs_file_handles() {
+    let file = vfs::open("/log", OpenFlags::READ_WRITE | OpenFlags::CREATE).unwrap();
+    file.write_all(b"hello world").unwrap();
+    assert_eq!(file.seek(SeekFrom::Start(6)).unwrap(), 6);
+    file.write_all(b"ruin!").unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"hello ruin!");
+
+    file.truncate(5).unwrap();
+    assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), 5);
+    assert_eq!(vfs::open("/log", OpenFlags::CREATE | OpenFlags::EXCLUSIVE).err(), Some(FsError::AlreadyExists));
+
+    let append = vfs::open("/log", OpenFlags::APPEND).unwrap();
+    append.write_all(b"!").unwrap();
+    assert_eq!(vfs::read_to_end("/log").unwrap(), b"hello!");
+    assert_eq!(append.read(&mut [0u8; 4]).err(), Some(FsError::PermissionDenied));
+    vfs::unlink("/log").unwrap();
+    assert_eq!(vfs::stat("/log").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn test_tmpfs_symlinks_and_cwd() {
+    vfs::mkdir("/a").unwrap();
+    vfs::mkdir("/a/b").unwrap();
+    vfs::write_all("/a/b/file", b"data").unwrap();
+    vfs::symlink("b", "/a/link").unwrap();
+    vfs::symlink("/a/link/file", "/absolute").unwrap();
+    vfs::symlink("loop", "/loop").unwrap();
+
+    assert_eq!(vfs::read_to_end("/a/link/file").unwrap(), b"data");
+    assert_eq!(vfs::read_to_end("/absolute").unwrap(), b"data");
+    assert_eq!(vfs::lookup("/a/link/../b/file").unwrap().path, "/a/b/file");
+    assert_eq!(vfs::readlink("/a/link").unwrap(), "b");
+    assert_eq!(vfs::lstat("/a/link").unwrap().file_type, FileType::Symlink);
+    assert_eq!(vfs::stat("/loop").err(), Some(FsError::TooManyLinks));
+
+    vfs::chdir("/a/link").unwrap();
+    assert_eq!(vfs::cwd(), "/a/b");
+    assert_eq!(vfs::read_to_end("file").unwrap(), b"data");
+    assert_eq!(vfs::chdir("file").err(), Some(FsError::NotDirectory));
+    vfs::chdir("/").unwrap();
+
+    assert_eq!(vfs::unlink("/a").err(), Some(FsError::NotEmpty));
+    vfs::unlink("/a/link").unwrap();
+    vfs::unlink("/a/b/file").unwrap();
+    vfs::unlink("/a/b").unwrap();
+    vfs::unlink("/a").unwrap();
+    vfs::unlink("/absolute").unwrap();
+    vfs::unlink("/loop").unwrap();
+}
+
+#[test_case]
+fn test_nested_mount() {
+    vfs::mkdir("/mnt").unwrap();
+    vfs::mount("/mnt", TmpFs::new()).unwrap();
+    vfs::write_all("/mnt/inner", b"inner").unwrap();
+    assert_eq!(vfs::readdir("/mnt").unwrap().len(), 1);
+    assert_eq!(vfs::read_to_end("/mnt/../mnt/inner").unwrap(), b"inner");
+    assert_eq!(vfs::unlink("/mnt").err(), Some(FsError::Busy));
+    assert_eq!(vfs::unmount("/").err(), Some(FsError::Busy));
+    assert_eq!(vfs::mounts().len(), 2);
+
+    vfs::unmount("/mnt").unwrap();
+    assert_eq!(vfs::stat("/mnt/inner").err(), Some(FsError::NotFound));
+    vfs::unlink("/mnt").unwrap();
+}
+
+fn cpio_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let header = format!("070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+        1, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, name.len() + 1, 0);
+    archive.extend_from_slice(header.as_bytes());
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+fn tar_entry(archive: &mut Vec<u8>, name: &str, type_flag: u8, link: &str, data: &[u8]) {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = type_flag;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(512), 0);
+}
+
+#[test_case]
+fn test_initramfs_cpio() {
+    let mut archive = Vec::new();
+    cpio_entry(&mut archive, ".", 0o040755, b"");
+    cpio_entry(&mut archive, "bin", 0o040755, b"");
+    cpio_entry(&mut archive, "bin/hello", 0o100755, b"hello binary");
+    cpio_entry(&mut archive, "etc/deep/config", 0o100644, b"key=value");
+    cpio_entry(&mut archive, "bin/hi", 0o120777, b"hello");
+    cpio_entry(&mut archive, "dev/null", 0o020666, b"");
+    cpio_entry(&mut archive, "TRAILER!!!", 0, b"");
+
+    vfs::mkdir("/cpio").unwrap();
+    assert_eq!(initramfs::unpack(&archive, "/cpio").unwrap(), 4);
+    assert_eq!(vfs::read_to_end("/cpio/bin/hi").unwrap(), b"hello binary");
+    assert_eq!(vfs::read_to_end("/cpio/etc/deep/config").unwrap(), b"key=value");
+    assert_eq!(vfs::stat("/cpio/dev/null").err(), Some(FsError::NotFound));
+    assert_eq!(initramfs::find(&archive, "/bin/hello").unwrap(), b"hello binary");
+    assert_eq!(initramfs::find(&archive, "bin").err(), Some(FsError::NotFound));
+
+    archive.truncate(200);
+    assert_eq!(initramfs::unpack(&archive, "/cpio").err(), Some(FsError::Corrupted));
+}
+
+#[test_case]
+fn test_initramfs_tar() {
+    let mut archive = Vec::new();
+    let long_name: String = "x".repeat(150);
+    tar_entry(&mut archive, "./usr/", b'5', "", b"");
+    tar_entry(&mut archive, "./usr/share/motd", b'0', "", b"Welcome\n");
+    tar_entry(&mut archive, "./motd", b'2', "usr/share/motd", b"");
+    tar_entry(&mut archive, "././@LongLink", b'L', "", long_name.as_bytes());
+    tar_entry(&mut archive, "truncated", b'0', "", b"long");
+    archive.resize(archive.len() + 1024, 0);
+
+    vfs::mkdir("/tar").unwrap();
+    assert_eq!(initramfs::unpack(&archive, "/tar").unwrap(), 4);
+    assert_eq!(vfs::read_to_end("/tar/motd").unwrap(), b"Welcome\n");
+    assert_eq!(vfs::read_to_end(&format!("/tar/{}", long_name)).unwrap(), b"long");
+    assert_eq!(initramfs::find(&archive, "usr/share/motd").unwrap(), b"Welcome\n");
+
+    archive[0] ^= 1;
+    assert_eq!(initramfs::unpack(&archive, "/tar").err(), Some(FsError::Corrupted));
+    assert_eq!(initramfs::unpack(b"not an archive", "/tar").err(), Some(FsError::InvalidArgument));
+}
+
+#[test_case]
+fn test_embedded_initramfs() {
+    vfs::mkdir("/embedded").unwrap();
+    initramfs::unpack(initramfs::EMBEDDED, "/embedded").unwrap();
+    assert!(!vfs::read_to_end("/embedded/etc/motd").unwrap().is_empty());
+}
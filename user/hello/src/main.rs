@@ -0,0 +1,39 @@
+//! Example program: prints its arguments and uses the heap, a file and a child process. The
+//! kernel's `user_program` test runs it and checks what it prints.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+
+use ruin_rt::{env, fs, println, process::{self, Fork}, Result};
+
+ruin_rt::entry!(main);
+
+fn main() -> Result<()> {
+    let args: Vec<&str> = env::args().collect();
+    println!("Hello from {}!", args.join(" "));
+
+    let squares: Vec<u64> = (1..=10).map(|n| n * n).collect();
+    println!("Sum of squares: {}", squares.iter().sum::<u64>());
+
+    // More than the heap starts with, so it has to grow
+    let big = vec![1u8; 200_000];
+    println!("Heap: {}", big.iter().map(|&byte| byte as usize).sum::<usize>());
+
+    fs::write("/hello.txt", b"Written by hello\n")?;
+    let text = fs::read("/hello.txt")?;
+    println!("Read back: {}", core::str::from_utf8(&text).unwrap_or("?").trim_end());
+
+    match process::fork()? {
+        Fork::Child => process::exit(7),
+        Fork::Parent(child) => {
+            let (_, status) = process::wait(Some(child))?;
+            println!("Child exited with {}", status.code().unwrap_or(-1));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Heap of the program. It starts empty at `HEAP_START` and grows upwards with `mmap` at fixed
+//! addresses, at least `GROWTH` bytes at a time, so it stays one contiguous region.
+
+use core::{alloc::{GlobalAlloc, Layout}, ptr::{self, NonNull}};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use crate::syscall::{self, MMAP_WRITE};
+
+/// Below where the kernel puts `mmap` regions without an address
+const HEAP_START: u64 = 0x5000_0000_0000;
+const GROWTH: u64 = 64 * 1024;
+const PAGE_SIZE: u64 = 4096;
+
+struct Allocator {
+    heap: Mutex<Heap>
+}
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator { heap: Mutex::new(Heap::empty()) };
+
+impl Allocator {
+    /// Maps enough memory after the heap for `layout`, false if the kernel has none
+    fn grow(heap: &mut Heap, layout: Layout) -> bool {
+        let needed = (layout.size() + layout.align()) as u64;
+        let size = needed.max(GROWTH).next_multiple_of(PAGE_SIZE);
+        let bottom = if heap.size() == 0 { HEAP_START } else { heap.top() as u64 };
+
+        if syscall::mmap(bottom, size, MMAP_WRITE).is_err() {
+            return false;
+        }
+
+        unsafe {
+            if heap.size() == 0 {
+                heap.init(bottom as *mut u8, size as usize);
+            } else {
+                heap.extend(size as usize);
+            }
+        }
+
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+
+        if let Ok(allocation) = heap.allocate_first_fit(layout) {
+            return allocation.as_ptr();
+        }
+
+        if !Allocator::grow(&mut heap, layout) {
+            return ptr::null_mut();
+        }
+
+        heap.allocate_first_fit(layout).map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
@@ -0,0 +1,21 @@
+//! Arguments the program was started with
+
+use core::{ffi::CStr, slice, sync::atomic::{AtomicPtr, AtomicUsize, Ordering}};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Keeps `argc` and `argv` from the start of the stack
+pub(crate) fn init(argc: usize, argv: *const *const u8) {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv as *mut *const u8, Ordering::Relaxed);
+}
+
+/// Arguments, the first is the program name. Arguments that aren't UTF-8 are skipped.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    let argv = ARGV.load(Ordering::Relaxed);
+    let pointers: &'static [*const u8] = if argv.is_null() { &[] } else { unsafe { slice::from_raw_parts(argv, ARGC.load(Ordering::Relaxed)) } };
+
+    // The strings are on the stack above `argv` for as long as the program runs
+    pointers.iter().filter_map(|&pointer| unsafe { CStr::from_ptr(pointer as *const _) }.to_str().ok())
+}
@@ -0,0 +1,128 @@
+//! Files by descriptor
+
+use alloc::vec::Vec;
+
+use crate::{io, syscall::{self, Result, CLOSE, DUP, DUP2, OPEN, PIPE}};
+
+/// Flags of `File::open`, the same as the kernel's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags(1 << 0);
+    pub const WRITE: OpenFlags = OpenFlags(1 << 1);
+    pub const READ_WRITE: OpenFlags = OpenFlags(Self::READ.0 | Self::WRITE.0);
+    pub const CREATE: OpenFlags = OpenFlags(1 << 2);
+    pub const EXCLUSIVE: OpenFlags = OpenFlags(1 << 3);
+    pub const TRUNCATE: OpenFlags = OpenFlags(1 << 4);
+    pub const APPEND: OpenFlags = OpenFlags(1 << 5);
+    pub const DIRECTORY: OpenFlags = OpenFlags(1 << 6);
+    /// Reads and writes of pipes and the console give `WouldBlock` instead of waiting
+    pub const NONBLOCK: OpenFlags = OpenFlags(1 << 7);
+}
+
+impl core::ops::BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, other: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | other.0)
+    }
+}
+
+/// Open file, closed when dropped
+#[derive(Debug)]
+pub struct File {
+    descriptor: u32
+}
+
+impl File {
+    pub fn open(path: &str, flags: OpenFlags) -> Result<File> {
+        let descriptor = unsafe { syscall::syscall(OPEN, [path.as_ptr() as u64, path.len() as u64, flags.0 as u64, 0, 0, 0])? };
+        Ok(File { descriptor: descriptor as u32 })
+    }
+
+    /// Opens `path` for writing, creating it or emptying it
+    pub fn create(path: &str) -> Result<File> {
+        File::open(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)
+    }
+
+    /// File of an open `descriptor`, which it now owns
+    pub fn from_raw(descriptor: u32) -> File {
+        File { descriptor }
+    }
+
+    /// Descriptor of the file, which stays open
+    pub fn into_raw(self) -> u32 {
+        let descriptor = self.descriptor;
+        core::mem::forget(self);
+        descriptor
+    }
+
+    pub fn descriptor(&self) -> u32 {
+        self.descriptor
+    }
+
+    /// Reads up to `buf.len()` bytes, 0 is the end of the file
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        io::read(self.descriptor, buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        io::write(self.descriptor, buf)
+    }
+
+    pub fn write_all(&self, buf: &[u8]) -> Result<()> {
+        io::write_all(self.descriptor, buf)
+    }
+
+    /// Reads until the end of the file and appends it to `data`, returns how many bytes were read
+    pub fn read_to_end(&self, data: &mut Vec<u8>) -> Result<usize> {
+        let start = data.len();
+        let mut buf = [0u8; 512];
+
+        loop {
+            match self.read(&mut buf)? {
+                0 => return Ok(data.len() - start),
+                count => data.extend_from_slice(&buf[..count])
+            }
+        }
+    }
+
+    /// Same file under the lowest free descriptor
+    pub fn duplicate(&self) -> Result<File> {
+        let descriptor = unsafe { syscall::syscall(DUP, [self.descriptor as u64, 0, 0, 0, 0, 0])? };
+        Ok(File { descriptor: descriptor as u32 })
+    }
+
+    /// Makes `descriptor` refer to this file too, closing what it referred to before
+    pub fn duplicate_to(&self, descriptor: u32) -> Result<()> {
+        unsafe { syscall::syscall(DUP2, [self.descriptor as u64, descriptor as u64, 0, 0, 0, 0])? };
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // Nothing to be done if the descriptor is gone already
+        let _ = unsafe { syscall::syscall(CLOSE, [self.descriptor as u64, 0, 0, 0, 0, 0]) };
+    }
+}
+
+/// Whole content of file at `path`
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path, OpenFlags::READ)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Makes `data` the content of file at `path`, creating it if needed
+pub fn write(path: &str, data: &[u8]) -> Result<()> {
+    File::create(path)?.write_all(data)
+}
+
+/// New pipe as its read end and its write end
+pub fn pipe() -> Result<(File, File)> {
+    let mut descriptors = [0u32; 2];
+    unsafe { syscall::syscall(PIPE, [descriptors.as_mut_ptr() as u64, 0, 0, 0, 0, 0])? };
+    Ok((File { descriptor: descriptors[0] }, File { descriptor: descriptors[1] }))
+}
@@ -0,0 +1,108 @@
+//! Standard input and output, descriptors 0, 1 and 2
+
+use core::{fmt, ops::BitOr};
+
+use crate::{fs::File, syscall::{self, Result, READ, SET_TERMINAL_MODE, TERMINAL_MODE, WRITE}};
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// How a terminal treats input, like the kernel's `tty::Mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalMode(pub u8);
+
+impl TerminalMode {
+    /// Bytes are read as they arrive
+    pub const RAW: TerminalMode = TerminalMode(0);
+    /// Input is read in lines edited with backspace and Ctrl+U, Ctrl+D ends the input
+    pub const CANONICAL: TerminalMode = TerminalMode(1 << 0);
+    pub const ECHO: TerminalMode = TerminalMode(1 << 1);
+    /// Ctrl+C, Ctrl+\ and Ctrl+Z send signals
+    pub const SIGNALS: TerminalMode = TerminalMode(1 << 2);
+    pub const DEFAULT: TerminalMode = TerminalMode(Self::CANONICAL.0 | Self::ECHO.0 | Self::SIGNALS.0);
+
+    pub fn contains(self, other: TerminalMode) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for TerminalMode {
+    type Output = TerminalMode;
+
+    fn bitor(self, rhs: TerminalMode) -> TerminalMode {
+        TerminalMode(self.0 | rhs.0)
+    }
+}
+
+/// Reads up to `buf.len()` bytes from `descriptor`, 0 is the end of the file
+pub fn read(descriptor: u32, buf: &mut [u8]) -> Result<usize> {
+    unsafe { syscall::syscall(READ, [descriptor as u64, buf.as_mut_ptr() as u64, buf.len() as u64, 0, 0, 0]).map(|count| count as usize) }
+}
+
+/// Writes up to `buf.len()` bytes to `descriptor`, returns how many were written
+pub fn write(descriptor: u32, buf: &[u8]) -> Result<usize> {
+    unsafe { syscall::syscall(WRITE, [descriptor as u64, buf.as_ptr() as u64, buf.len() as u64, 0, 0, 0]).map(|count| count as usize) }
+}
+
+/// Writes all of `buf` to `descriptor`
+pub fn write_all(descriptor: u32, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+        let count = write(descriptor, buf)?;
+        buf = &buf[count..];
+    }
+
+    Ok(())
+}
+
+/// Mode of terminal `descriptor`
+pub fn terminal_mode(descriptor: u32) -> Result<TerminalMode> {
+    unsafe { syscall::syscall(TERMINAL_MODE, [descriptor as u64, 0, 0, 0, 0, 0]).map(|mode| TerminalMode(mode as u8)) }
+}
+
+/// Switches terminal `descriptor` to `mode`, returns the previous one
+pub fn set_terminal_mode(descriptor: u32, mode: TerminalMode) -> Result<TerminalMode> {
+    unsafe { syscall::syscall(SET_TERMINAL_MODE, [descriptor as u64, mode.0 as u64, 0, 0, 0, 0]).map(|mode| TerminalMode(mode as u8)) }
+}
+
+/// Descriptor written with `fmt::Write`, it isn't closed when this is dropped
+pub struct Writer(pub u32);
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        write_all(self.0, text.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Standard input as a `File` that closes it when dropped
+pub fn stdin() -> File {
+    File::from_raw(STDIN)
+}
+
+#[doc(hidden)]
+pub fn _print(descriptor: u32, args: fmt::Arguments) {
+    // Output nobody reads is not an error of the program
+    let _ = fmt::Write::write_fmt(&mut Writer(descriptor), args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
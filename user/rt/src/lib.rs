@@ -0,0 +1,64 @@
+//! Runtime of programs that run on ruin: the `_start` entry point, a panic handler, a heap that
+//! grows with `mmap`, `print!` and wrappers of the system calls for files and processes.
+//!
+//! Programs are `no_std` and `no_main`, name their main function with `entry!` and are built for
+//! `user/x86_64-ruin.json`. Main may return nothing, an exit code or a `Result`.
+
+#![no_std]
+
+extern crate alloc;
+
+mod allocator;
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod process;
+pub mod syscall;
+
+use core::{arch::global_asm, panic::PanicInfo};
+
+pub use syscall::{Error, Result};
+
+/// Exit code of programs that panic
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+// The kernel starts programs with `rsp` pointing to `argc`, aligned to 16 bytes. `call` pushes
+// the return address the way the ABI expects at function entry.
+global_asm!(
+    ".global _start",
+    "_start:",
+    "xor ebp, ebp",
+    "mov rdi, rsp",
+    "and rsp, -16",
+    "call {start}",
+    "ud2",
+    start = sym start
+);
+
+extern "Rust" {
+    /// Defined by `entry!`
+    fn __ruin_main() -> i32;
+}
+
+unsafe extern "C" fn start(stack: *const u64) -> ! {
+    let argc = *stack as usize;
+    env::init(argc, stack.add(1) as *const *const u8);
+    process::exit(__ruin_main())
+}
+
+/// Makes `$main` the main function of the program
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        fn __ruin_main() -> i32 {
+            $crate::process::Termination::report($main())
+        }
+    };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{}", info);
+    process::exit(PANIC_EXIT_CODE)
+}
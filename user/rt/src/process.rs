@@ -0,0 +1,138 @@
+//! Processes: exiting, starting others and waiting for them
+
+use alloc::{ffi::CString, vec::Vec};
+use core::{fmt::Debug, ptr};
+
+use crate::{eprintln, syscall::{self, Error, Result, EXEC, EXIT, FORK, GETPID, GETPPID, KILL, SLEEP, WAIT, YIELD}};
+
+/// Signal numbers, the same as the kernel's
+pub mod signal {
+    pub const HUP: u64 = 1;
+    pub const INT: u64 = 2;
+    pub const QUIT: u64 = 3;
+    pub const KILL: u64 = 9;
+    pub const SEGV: u64 = 11;
+    pub const PIPE: u64 = 13;
+    pub const TERM: u64 = 15;
+    pub const CONT: u64 = 18;
+    pub const STOP: u64 = 19;
+}
+
+/// Ends the process with exit code `code`
+pub fn exit(code: i32) -> ! {
+    unsafe {
+        let _ = syscall::syscall(EXIT, [code as u64, 0, 0, 0, 0, 0]);
+    }
+
+    unreachable!("Process continued after exit")
+}
+
+/// Id of the process
+pub fn id() -> u64 {
+    unsafe { syscall::syscall(GETPID, [0; 6]).unwrap_or(0) }
+}
+
+/// Id of the process that started this one
+pub fn parent_id() -> u64 {
+    unsafe { syscall::syscall(GETPPID, [0; 6]).unwrap_or(0) }
+}
+
+/// Lets other threads run
+pub fn yield_now() {
+    let _ = unsafe { syscall::syscall(YIELD, [0; 6]) };
+}
+
+/// Waits for at least `ms` milliseconds
+pub fn sleep(ms: u64) {
+    let _ = unsafe { syscall::syscall(SLEEP, [ms, 0, 0, 0, 0, 0]) };
+}
+
+/// Which side of `fork` continues
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fork {
+    Child,
+    /// Id of the child
+    Parent(u64)
+}
+
+/// Starts a copy of the process that continues from here as well
+pub fn fork() -> Result<Fork> {
+    match unsafe { syscall::syscall(FORK, [0; 6])? } {
+        0 => Ok(Fork::Child),
+        pid => Ok(Fork::Parent(pid))
+    }
+}
+
+/// Replaces the process with executable at `path` started with `args`, which usually begin with
+/// the program name. Only returns on failure.
+pub fn exec(path: &str, args: &[&str]) -> Error {
+    let Ok(strings) = args.iter().map(|&arg| CString::new(arg)).collect::<core::result::Result<Vec<_>, _>>() else {
+        return Error::InvalidArgument;
+    };
+
+    let mut argv: Vec<*const u8> = strings.iter().map(|string| string.as_ptr() as *const u8).collect();
+    argv.push(ptr::null());
+
+    match unsafe { syscall::syscall(EXEC, [path.as_ptr() as u64, path.len() as u64, argv.as_ptr() as u64, 0, 0, 0]) } {
+        Ok(_) => unreachable!("Exec returned without an error"),
+        Err(error) => error
+    }
+}
+
+/// How a child ended, as `wait` reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus(pub u64);
+
+impl ExitStatus {
+    /// Exit code if the child exited
+    pub fn code(self) -> Option<i32> {
+        (self.0 & 0x7F == 0).then_some((self.0 >> 8 & 0xFF) as i32)
+    }
+
+    /// Signal that ended the child
+    pub fn signal(self) -> Option<u64> {
+        (self.0 & 0x7F != 0).then_some(self.0 & 0x7F)
+    }
+}
+
+/// Waits until child `pid`, or any child, ends. Returns its id and how it ended.
+pub fn wait(pid: Option<u64>) -> Result<(u64, ExitStatus)> {
+    let mut status = 0u64;
+    let pid = unsafe { syscall::syscall(WAIT, [pid.unwrap_or(0), &mut status as *mut u64 as u64, 0, 0, 0, 0])? };
+    Ok((pid, ExitStatus(status)))
+}
+
+/// Sends `signal` to process `pid`
+pub fn kill(pid: u64, signal: u64) -> Result<()> {
+    unsafe { syscall::syscall(KILL, [pid, signal, 0, 0, 0, 0])? };
+    Ok(())
+}
+
+/// What main returns, turned into the exit code
+pub trait Termination {
+    fn report(self) -> i32;
+}
+
+impl Termination for () {
+    fn report(self) -> i32 {
+        0
+    }
+}
+
+impl Termination for i32 {
+    fn report(self) -> i32 {
+        self
+    }
+}
+
+impl<E: Debug> Termination for core::result::Result<(), E> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(()) => 0,
+            Err(error) => {
+                eprintln!("Error: {:?}", error);
+                1
+            }
+        }
+    }
+}
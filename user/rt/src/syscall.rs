@@ -0,0 +1,132 @@
+//! Raw system calls with the numbers, flags and error codes of the kernel's `syscall` module
+
+use core::arch::asm;
+
+pub const WRITE: u64 = 0;
+pub const READ: u64 = 1;
+pub const EXIT: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const MMAP: u64 = 5;
+pub const GETPID: u64 = 6;
+pub const OPEN: u64 = 7;
+pub const CLOSE: u64 = 8;
+pub const FORK: u64 = 9;
+pub const EXEC: u64 = 10;
+pub const WAIT: u64 = 11;
+pub const GETPPID: u64 = 12;
+pub const SHM_MAP: u64 = 13;
+pub const SHM_UNLINK: u64 = 14;
+pub const MSYNC: u64 = 15;
+pub const PIPE: u64 = 16;
+pub const DUP: u64 = 17;
+pub const DUP2: u64 = 18;
+pub const KILL: u64 = 19;
+pub const SIGACTION: u64 = 20;
+pub const SIGPROCMASK: u64 = 21;
+pub const SIGRETURN: u64 = 22;
+pub const CHANNEL: u64 = 23;
+pub const CLOSE_HANDLE: u64 = 24;
+pub const SEND: u64 = 25;
+pub const RECEIVE: u64 = 26;
+pub const CALL: u64 = 27;
+pub const WAIT_HANDLES: u64 = 28;
+pub const TERMINAL_MODE: u64 = 29;
+pub const SET_TERMINAL_MODE: u64 = 30;
+
+/// `mmap` flags
+pub const MMAP_WRITE: u64 = 1;
+pub const MMAP_EXECUTE: u64 = 2;
+pub const MMAP_SHARED: u64 = 4;
+pub const MMAP_FILE: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidSyscall,
+    BadAddress,
+    BadDescriptor,
+    InvalidArgument,
+    OutOfMemory,
+    NotFound,
+    NoChild,
+    BadExecutable,
+    TooManyFiles,
+    Io,
+    AccessDenied,
+    WouldBlock,
+    BrokenPipe,
+    NoProcess,
+    Interrupted,
+    PeerClosed,
+    NotTerminal,
+    /// Code this runtime doesn't know
+    Unknown(u64)
+}
+
+impl Error {
+    pub fn from_code(code: u64) -> Error {
+        match code {
+            1 => Error::InvalidSyscall,
+            2 => Error::BadAddress,
+            3 => Error::BadDescriptor,
+            4 => Error::InvalidArgument,
+            5 => Error::OutOfMemory,
+            6 => Error::NotFound,
+            7 => Error::NoChild,
+            8 => Error::BadExecutable,
+            9 => Error::TooManyFiles,
+            10 => Error::Io,
+            11 => Error::AccessDenied,
+            12 => Error::WouldBlock,
+            13 => Error::BrokenPipe,
+            14 => Error::NoProcess,
+            15 => Error::Interrupted,
+            16 => Error::PeerClosed,
+            17 => Error::NotTerminal,
+            code => Error::Unknown(code)
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Splits what a system call returned into its value and its error, errors are negated codes
+pub fn result(value: u64) -> Result<u64> {
+    if (value as i64) < 0 {
+        return Err(Error::from_code(value.wrapping_neg()));
+    }
+
+    Ok(value)
+}
+
+/// System call `number` with arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`, unused ones
+/// may be anything
+///
+/// # Safety
+///
+/// Arguments must be what the system call expects, memory it writes must be the caller's to give.
+pub unsafe fn syscall(number: u64, arguments: [u64; 6]) -> Result<u64> {
+    let value: u64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number => value,
+        in("rdi") arguments[0],
+        in("rsi") arguments[1],
+        in("rdx") arguments[2],
+        in("r10") arguments[3],
+        in("r8") arguments[4],
+        in("r9") arguments[5],
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    result(value)
+}
+
+/// Maps `length` bytes of zeroed memory at page aligned `address`, or wherever there's room if
+/// it's 0. Returns the address.
+pub fn mmap(address: u64, length: u64, flags: u64) -> Result<u64> {
+    unsafe { syscall(MMAP, [address, length, flags, 0, 0, 0]) }
+}